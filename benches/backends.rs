@@ -0,0 +1,175 @@
+// GNU GPL v3 License
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use polldough::{Completion, CompletionBuilder, Nop, Op, Read, SubmissionStatus, Write};
+use std::{
+    fs::File,
+    io::Write as _,
+    net::{TcpListener, TcpStream},
+};
+
+const CHUNK: usize = 4096;
+
+/// The backends available on this platform.
+fn backends() -> Vec<(&'static str, Completion)> {
+    let mut backends = vec![(
+        "polling",
+        CompletionBuilder::new(64)
+            .disable_io_uring()
+            .build()
+            .unwrap(),
+    )];
+
+    if cfg!(target_os = "linux") {
+        backends.push(("default", Completion::new(64).unwrap()));
+        backends.push((
+            "hybrid",
+            CompletionBuilder::new(64).hybrid(true).build().unwrap(),
+        ));
+    }
+
+    backends
+}
+
+/// Run an operation to completion.
+fn run<O: Op>(completion: &Completion, mut op: O) -> (usize, O::Captured) {
+    let status = unsafe { completion.submit(&mut op, 0).unwrap() };
+    let result = match status {
+        SubmissionStatus::AlreadyComplete(result) => result,
+        SubmissionStatus::Submitted => {
+            let mut events = Vec::with_capacity(1);
+            while events.is_empty() {
+                completion.wait(None, &mut events).unwrap();
+            }
+            events.pop().unwrap().result
+        }
+    };
+
+    (result.unwrap(), unsafe { op.into_captured() })
+}
+
+fn socket_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    client.set_nodelay(true).unwrap();
+    server.set_nodelay(true).unwrap();
+    (client, server)
+}
+
+fn submit_wait_latency(c: &mut Criterion) {
+    let mut group = c.benchmark_group("submit_wait_latency");
+
+    for (name, completion) in backends() {
+        let (client, mut server) = socket_pair();
+        completion.register(&client).unwrap();
+        let mut buf = vec![0u8; 1];
+
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                server.write_all(b"x").unwrap();
+                let (n, out) = run(&completion, Read::new(&client, std::mem::take(&mut buf)));
+                assert_eq!(n, 1);
+                buf = out;
+            })
+        });
+
+        completion.deregister(&client).unwrap();
+        eprintln!("{}: {:?}", name, completion.counters());
+    }
+}
+
+fn nop_round_trip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("nop_round_trip");
+
+    for (name, completion) in backends() {
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| run(&completion, Nop::new()))
+        });
+
+        eprintln!("{}: {:?}", name, completion.counters());
+    }
+}
+
+fn echo_throughput(c: &mut Criterion) {
+    let mut group = c.benchmark_group("echo_throughput");
+    group.throughput(Throughput::Bytes(CHUNK as u64));
+
+    for (name, completion) in backends() {
+        let (client, server) = socket_pair();
+        completion.register(&client).unwrap();
+        completion.register(&server).unwrap();
+        let mut send = vec![0xAAu8; CHUNK];
+        let mut recv = vec![0u8; CHUNK];
+
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                let (n, out) = run(&completion, Write::new(&client, std::mem::take(&mut send)));
+                assert_eq!(n, CHUNK);
+                send = out;
+
+                let mut received = 0;
+                while received < CHUNK {
+                    let (n, out) = run(&completion, Read::new(&server, std::mem::take(&mut recv)));
+                    received += n;
+                    recv = out;
+                }
+            })
+        });
+
+        completion.deregister(&client).unwrap();
+        completion.deregister(&server).unwrap();
+        eprintln!("{}: {:?}", name, completion.counters());
+    }
+}
+
+fn file_read_iops(c: &mut Criterion) {
+    const BLOCKS: u64 = 256;
+
+    let path = std::env::temp_dir().join(format!("polldough-bench-{}", std::process::id()));
+    File::create(&path)
+        .unwrap()
+        .write_all(&vec![0x55u8; CHUNK * BLOCKS as usize])
+        .unwrap();
+
+    let mut group = c.benchmark_group("file_read_iops");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, completion) in backends() {
+        // the polling backend only supports files on Linux
+        if cfg!(not(target_os = "linux")) {
+            continue;
+        }
+
+        let file = File::open(&path).unwrap();
+        completion.register(&file).unwrap();
+        let mut buf = vec![0u8; CHUNK];
+        let mut block = 0;
+
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                let mut op = Read::new(&file, std::mem::take(&mut buf));
+                op.offset(block * CHUNK as u64).unwrap();
+                block = (block + 97) % BLOCKS;
+
+                let (n, out) = run(&completion, op);
+                assert_eq!(n, CHUNK);
+                buf = out;
+            })
+        });
+
+        completion.deregister(&file).unwrap();
+        eprintln!("{}: {:?}", name, completion.counters());
+    }
+
+    std::fs::remove_file(&path).ok();
+}
+
+criterion_group!(
+    benches,
+    submit_wait_latency,
+    nop_round_trip,
+    echo_throughput,
+    file_read_iops
+);
+criterion_main!(benches);
@@ -0,0 +1,263 @@
+// GNU GPL v3 License
+
+//! A server that echoes back everything its clients send.
+//!
+//! Every connection is accepted along with the first data it sends, then
+//! alternates between writing back what it received and reading more,
+//! all on one thread. Clients that go quiet for too long are hung up on.
+//!
+//! Run it with `cargo run --example echo [ADDRESS]` and talk to it with
+//! something like `nc 127.0.0.1 8080`.
+
+use polldough::{AcceptAndRecv, Completion, Op, Raw, Read, Source, SubmissionStatus, Write};
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    io::Result,
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// How much to read at once.
+const BUF_LEN: usize = 4096;
+
+/// How often to check whether the server should stop.
+const TICK: Duration = Duration::from_millis(50);
+
+/// How long a client may stay quiet before it's hung up on.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An operation in flight, and the connection it belongs to.
+enum Conn {
+    Accepting(AcceptAndRecv<Vec<u8>>),
+    Reading(TcpStream, Read<Vec<u8>>),
+    Writing(TcpStream, Write<Vec<u8>>),
+}
+
+impl Conn {
+    /// The connection, once it's accepted.
+    fn stream(&self) -> Option<&TcpStream> {
+        match self {
+            Conn::Accepting(_) => None,
+            Conn::Reading(stream, _) => Some(stream),
+            Conn::Writing(stream, _) => Some(stream),
+        }
+    }
+}
+
+/// The connections of the server.
+struct Server<'a> {
+    listener: &'a TcpListener,
+    completion: &'a Completion,
+    /// The operations in flight, boxed so that they stay put.
+    conns: HashMap<u64, Box<Conn>>,
+    /// Operations that completed as they were submitted.
+    ready: VecDeque<(u64, Result<usize>)>,
+    /// How long a client may stay quiet.
+    idle: Duration,
+    /// The connections with an idle timer, by the key of its event.
+    timers: HashMap<u64, Raw>,
+    next_key: u64,
+}
+
+impl Server<'_> {
+    /// A key that no operation or timer uses yet.
+    fn next_key(&mut self) -> u64 {
+        let key = self.next_key;
+        self.next_key += 1;
+        key
+    }
+
+    /// Submit an operation for a connection.
+    fn submit(&mut self, mut conn: Box<Conn>) -> Result<()> {
+        let key = self.next_key();
+
+        // SAFETY: the operation is boxed and kept until it completes
+        let status = unsafe {
+            match &mut *conn {
+                Conn::Accepting(op) => self.completion.submit(op, key)?,
+                Conn::Reading(_, op) => self.completion.submit(op, key)?,
+                Conn::Writing(_, op) => self.completion.submit(op, key)?,
+            }
+        };
+
+        if let SubmissionStatus::AlreadyComplete(result) = status {
+            self.ready.push_back((key, result));
+        }
+        self.conns.insert(key, conn);
+        Ok(())
+    }
+
+    /// Wait for the next connection.
+    fn accept(&mut self) -> Result<()> {
+        let op = AcceptAndRecv::new(self.listener, vec![0; BUF_LEN]);
+        self.submit(Box::new(Conn::Accepting(op)))
+    }
+
+    /// Start the idle timer of a new connection.
+    ///
+    /// Every operation on it that completes restarts the timer.
+    fn watch(&mut self, stream: &TcpStream) -> Result<()> {
+        let key = self.next_key();
+        self.completion.set_idle_timeout(stream, self.idle, key)?;
+        self.timers.insert(key, stream.as_raw());
+        Ok(())
+    }
+
+    /// Cancel the operation of a connection whose timer expired, which
+    /// then hangs up.
+    fn expire(&mut self, source: Raw) -> Result<()> {
+        let key = self
+            .conns
+            .iter()
+            .find(|(_, conn)| conn.stream().map(Source::as_raw) == Some(source))
+            .map(|(&key, _)| key);
+
+        match key {
+            Some(key) => self.completion.cancel(key),
+            None => Ok(()),
+        }
+    }
+
+    /// Hang up on a client.
+    fn hang_up(&mut self, stream: TcpStream) -> Result<()> {
+        let source = stream.as_raw();
+        self.timers.retain(|_, timer| *timer != source);
+        // this stops the timer too
+        self.completion.deregister(&stream)
+    }
+
+    /// Send data back to the client.
+    fn echo(&mut self, stream: TcpStream, mut buf: Vec<u8>, len: usize) -> Result<()> {
+        if len == 0 {
+            // the client hung up
+            return self.hang_up(stream);
+        }
+
+        buf.truncate(len);
+        let op = Write::new(&stream, buf);
+        self.submit(Box::new(Conn::Writing(stream, op)))
+    }
+
+    /// Move a connection along once its operation completes.
+    fn complete(&mut self, key: u64, result: Result<usize>) -> Result<()> {
+        // the client went quiet for too long
+        if let Some(source) = self.timers.remove(&key) {
+            return self.expire(source);
+        }
+
+        let conn = match self.conns.remove(&key) {
+            Some(conn) => *conn,
+            None => return Ok(()),
+        };
+
+        match conn {
+            Conn::Accepting(op) => {
+                // keep accepting, even if this one failed
+                self.accept()?;
+
+                match result {
+                    Ok(n) => {
+                        // SAFETY: the operation is complete
+                        let (stream, _peer, len, buf) = unsafe { op.complete(n) };
+                        self.completion.register(&stream)?;
+                        self.watch(&stream)?;
+                        self.echo(stream, buf, len)
+                    }
+                    Err(_) => Ok(()),
+                }
+            }
+            Conn::Reading(stream, op) => match result {
+                Ok(n) => {
+                    let (len, buf) = unsafe { op.complete(n) };
+                    self.echo(stream, buf, len)
+                }
+                Err(_) => self.hang_up(stream),
+            },
+            Conn::Writing(stream, op) => match result {
+                Ok(n) => {
+                    let (written, mut buf) = unsafe { op.complete(n) };
+                    buf.drain(..written);
+
+                    if buf.is_empty() {
+                        // all of it went out, read some more
+                        buf.resize(BUF_LEN, 0);
+                        let op = Read::new(&stream, buf);
+                        self.submit(Box::new(Conn::Reading(stream, op)))
+                    } else {
+                        let len = buf.len();
+                        self.echo(stream, buf, len)
+                    }
+                }
+                Err(_) => self.hang_up(stream),
+            },
+        }
+    }
+}
+
+/// Echo data back to every client that connects to `listener`, hanging up
+/// on those that are quiet for `idle`, until `stop` is set.
+pub fn serve(
+    listener: &TcpListener,
+    completion: &Completion,
+    idle: Duration,
+    stop: &AtomicBool,
+) -> Result<()> {
+    completion.register(listener)?;
+
+    let mut server = Server {
+        listener,
+        completion,
+        conns: HashMap::new(),
+        ready: VecDeque::new(),
+        idle,
+        timers: HashMap::new(),
+        next_key: 0,
+    };
+    server.accept()?;
+
+    let mut events = Vec::new();
+    while !stop.load(Ordering::Acquire) {
+        while let Some((key, result)) = server.ready.pop_front() {
+            server.complete(key, result)?;
+        }
+
+        completion.wait(Some(TICK), &mut events)?;
+        for event in events.drain(..) {
+            server.complete(event.key, event.result)?;
+        }
+    }
+
+    // cancel whatever is still in flight, and wait for it to finish
+    for (key, _) in server.ready.drain(..) {
+        server.conns.remove(&key);
+    }
+    for key in server.conns.keys() {
+        completion.cancel(*key)?;
+    }
+    while !server.conns.is_empty() {
+        completion.wait(Some(TICK), &mut events)?;
+        for event in events.drain(..) {
+            server.conns.remove(&event.key);
+        }
+    }
+
+    completion.deregister(listener)
+}
+
+fn main() -> Result<()> {
+    let addr = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:8080".into());
+    let listener = TcpListener::bind(&addr)?;
+    println!("Listening on {}", listener.local_addr()?);
+
+    let completion = Completion::new(64)?;
+    serve(
+        &listener,
+        &completion,
+        IDLE_TIMEOUT,
+        &AtomicBool::new(false),
+    )
+}
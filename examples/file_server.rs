@@ -0,0 +1,381 @@
+// GNU GPL v3 License
+
+//! A bare-bones HTTP server for the files in a directory.
+//!
+//! Every connection is accepted along with its request, then gets the
+//! response header and the file, a chunk at a time, all on one thread. On
+//! Linux, the chunks are spliced into a pipe and from there into the
+//! socket, without being copied into userspace; elsewhere, they're read
+//! and written. Connections are closed once the file has been sent. To
+//! keep it short, the request line has to arrive along with the
+//! connection.
+//!
+//! Run it with `cargo run --example file_server [DIRECTORY] [ADDRESS]` and
+//! fetch files with something like `curl 127.0.0.1:8080/Cargo.toml`.
+
+#[cfg(not(target_os = "linux"))]
+use polldough::Read;
+use polldough::{AcceptAndRecv, Completion, Op, SubmissionStatus, Write};
+#[cfg(target_os = "linux")]
+use polldough::{Raw, Source, SourceType, Splice};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    fs::File,
+    io::Result,
+    net::{TcpListener, TcpStream},
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// How much of the request, and then of the file, to read at once.
+const BUF_LEN: usize = 4096;
+
+/// How much of the file to splice into the pipe at once, which is as much
+/// as a pipe holds by default.
+#[cfg(target_os = "linux")]
+const PIPE_LEN: usize = 64 * 1024;
+
+/// How often to check whether the server should stop.
+const TICK: Duration = Duration::from_millis(50);
+
+/// One end of a pipe.
+#[cfg(target_os = "linux")]
+struct PipeEnd(OwnedFd);
+
+// SAFETY: the descriptor is open for as long as the `PipeEnd` is
+#[cfg(target_os = "linux")]
+unsafe impl Source for PipeEnd {
+    const SOURCE_TYPE: SourceType = SourceType::Tty;
+
+    fn as_raw(&self) -> Raw {
+        self.0.as_raw_fd()
+    }
+}
+
+/// Create a pipe, returning its read and write ends.
+#[cfg(target_os = "linux")]
+fn pipe() -> Result<(PipeEnd, PipeEnd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    // SAFETY: we just created them
+    unsafe {
+        Ok((
+            PipeEnd(OwnedFd::from_raw_fd(fds[0])),
+            PipeEnd(OwnedFd::from_raw_fd(fds[1])),
+        ))
+    }
+}
+
+/// A file being sent, and where the next chunk starts.
+struct Body {
+    file: File,
+    offset: u64,
+    /// The pipe that the chunks go through, read from and written to.
+    #[cfg(target_os = "linux")]
+    pipe: (PipeEnd, PipeEnd),
+}
+
+/// An operation in flight, and the connection it belongs to.
+enum Conn {
+    Accepting(AcceptAndRecv<Vec<u8>>),
+    #[cfg(not(target_os = "linux"))]
+    ReadingFile(TcpStream, Body, Read<Vec<u8>>),
+    /// Splicing the next chunk of the file into the pipe.
+    #[cfg(target_os = "linux")]
+    Filling(TcpStream, Body, Splice),
+    /// Splicing the chunk out of the pipe into the socket, with how much of
+    /// it is still in the pipe.
+    #[cfg(target_os = "linux")]
+    Draining(TcpStream, Body, usize, Splice),
+    Writing(TcpStream, Option<Body>, Write<Vec<u8>>),
+}
+
+/// The connections of the server.
+struct Server<'a> {
+    listener: &'a TcpListener,
+    completion: &'a Completion,
+    root: &'a Path,
+    /// The operations in flight, boxed so that they stay put.
+    conns: HashMap<u64, Box<Conn>>,
+    /// Operations that completed as they were submitted.
+    ready: VecDeque<(u64, Result<usize>)>,
+    next_key: u64,
+}
+
+impl Server<'_> {
+    /// Submit an operation for a connection.
+    fn submit(&mut self, mut conn: Box<Conn>) -> Result<()> {
+        let key = self.next_key;
+        self.next_key += 1;
+
+        // SAFETY: the operation is boxed and kept until it completes
+        let status = unsafe {
+            match &mut *conn {
+                Conn::Accepting(op) => self.completion.submit(op, key)?,
+                #[cfg(not(target_os = "linux"))]
+                Conn::ReadingFile(_, _, op) => self.completion.submit(op, key)?,
+                #[cfg(target_os = "linux")]
+                Conn::Filling(_, _, op) => self.completion.submit(op, key)?,
+                #[cfg(target_os = "linux")]
+                Conn::Draining(_, _, _, op) => self.completion.submit(op, key)?,
+                Conn::Writing(_, _, op) => self.completion.submit(op, key)?,
+            }
+        };
+
+        if let SubmissionStatus::AlreadyComplete(result) = status {
+            self.ready.push_back((key, result));
+        }
+        self.conns.insert(key, conn);
+        Ok(())
+    }
+
+    /// Wait for the next connection.
+    fn accept(&mut self) -> Result<()> {
+        let op = AcceptAndRecv::new(self.listener, vec![0; BUF_LEN]);
+        self.submit(Box::new(Conn::Accepting(op)))
+    }
+
+    /// Find the file that a request asks for.
+    fn open(&self, request: &[u8]) -> Option<File> {
+        let line = request.split(|&b| b == b'\n').next()?;
+        let line = std::str::from_utf8(line).ok()?;
+        let mut parts = line.split_whitespace();
+        if parts.next()? != "GET" {
+            return None;
+        }
+
+        // stay inside of the root
+        let path = Path::new(parts.next()?.trim_start_matches('/'));
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
+            return None;
+        }
+
+        let file = File::open(self.root.join(path)).ok()?;
+        if file.metadata().ok()?.is_file() {
+            Some(file)
+        } else {
+            None
+        }
+    }
+
+    /// Answer a request with the header, and then the file.
+    fn respond(&mut self, stream: TcpStream, request: &[u8]) -> Result<()> {
+        let (header, body) = match self.open(request) {
+            Some(file) => {
+                let len = file.metadata()?.len();
+                let header = format!(
+                    "HTTP/1.0 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    len
+                );
+                self.completion.register(&file)?;
+                let body = Body {
+                    file,
+                    offset: 0,
+                    #[cfg(target_os = "linux")]
+                    pipe: pipe()?,
+                };
+                // the chunks are spliced into the pipe, and out into the
+                // socket, which is registered already
+                #[cfg(target_os = "linux")]
+                self.completion.register(&body.pipe.1)?;
+                (header, Some(body))
+            }
+            None => (
+                "HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".into(),
+                None,
+            ),
+        };
+
+        let op = Write::new(&stream, header.into_bytes());
+        self.submit(Box::new(Conn::Writing(stream, body, op)))
+    }
+
+    /// Read the next chunk of the file, or hang up once it's all sent.
+    #[cfg(not(target_os = "linux"))]
+    fn next_chunk(
+        &mut self,
+        stream: TcpStream,
+        body: Option<Body>,
+        mut buf: Vec<u8>,
+    ) -> Result<()> {
+        match body {
+            Some(body) => {
+                buf.resize(BUF_LEN, 0);
+                let mut op = Read::new(&body.file, buf);
+                op.offset(body.offset)?;
+                self.submit(Box::new(Conn::ReadingFile(stream, body, op)))
+            }
+            None => self.completion.deregister(&stream),
+        }
+    }
+
+    /// Splice the next chunk of the file into the pipe, or hang up once
+    /// it's all sent.
+    ///
+    /// The buffer that the header was written from isn't needed for that.
+    #[cfg(target_os = "linux")]
+    fn next_chunk(&mut self, stream: TcpStream, body: Option<Body>, _buf: Vec<u8>) -> Result<()> {
+        match body {
+            Some(body) => {
+                let mut op = Splice::new(&body.file, &body.pipe.1, PIPE_LEN);
+                op.from_offset(body.offset)?;
+                self.submit(Box::new(Conn::Filling(stream, body, op)))
+            }
+            None => self.completion.deregister(&stream),
+        }
+    }
+
+    /// Splice what's left of the chunk out of the pipe into the socket.
+    #[cfg(target_os = "linux")]
+    fn drain(&mut self, stream: TcpStream, body: Body, len: usize) -> Result<()> {
+        let op = Splice::new(&body.pipe.0, &stream, len);
+        self.submit(Box::new(Conn::Draining(stream, body, len, op)))
+    }
+
+    /// Stop sending the file and hang up.
+    fn close(&mut self, stream: TcpStream, body: Option<Body>) -> Result<()> {
+        if let Some(body) = body {
+            self.completion.deregister(&body.file)?;
+            #[cfg(target_os = "linux")]
+            self.completion.deregister(&body.pipe.1)?;
+        }
+        self.completion.deregister(&stream)
+    }
+
+    /// Move a connection along once its operation completes.
+    fn complete(&mut self, key: u64, result: Result<usize>) -> Result<()> {
+        let conn = match self.conns.remove(&key) {
+            Some(conn) => *conn,
+            None => return Ok(()),
+        };
+
+        match conn {
+            Conn::Accepting(op) => {
+                // keep accepting, even if this one failed
+                self.accept()?;
+
+                match result {
+                    Ok(n) => {
+                        // SAFETY: the operation is complete
+                        let (stream, _peer, len, buf) = unsafe { op.complete(n) };
+                        self.completion.register(&stream)?;
+                        self.respond(stream, &buf[..len])
+                    }
+                    Err(_) => Ok(()),
+                }
+            }
+            #[cfg(not(target_os = "linux"))]
+            Conn::ReadingFile(stream, mut body, op) => match result {
+                // the whole file has been sent
+                Ok(0) => self.close(stream, Some(body)),
+                Ok(n) => {
+                    let (len, mut buf) = unsafe { op.complete(n) };
+                    body.offset += len as u64;
+                    buf.truncate(len);
+
+                    let op = Write::new(&stream, buf);
+                    self.submit(Box::new(Conn::Writing(stream, Some(body), op)))
+                }
+                Err(_) => self.close(stream, Some(body)),
+            },
+            #[cfg(target_os = "linux")]
+            Conn::Filling(stream, mut body, _) => match result {
+                // the whole file has been sent
+                Ok(0) => self.close(stream, Some(body)),
+                Ok(n) => {
+                    body.offset += n as u64;
+                    self.drain(stream, body, n)
+                }
+                Err(_) => self.close(stream, Some(body)),
+            },
+            #[cfg(target_os = "linux")]
+            Conn::Draining(stream, body, len, _) => match result {
+                Ok(n) if n < len => self.drain(stream, body, len - n),
+                Ok(_) => self.next_chunk(stream, Some(body), Vec::new()),
+                Err(_) => self.close(stream, Some(body)),
+            },
+            Conn::Writing(stream, body, op) => match result {
+                Ok(n) => {
+                    let (written, mut buf) = unsafe { op.complete(n) };
+                    buf.drain(..written);
+
+                    if buf.is_empty() {
+                        self.next_chunk(stream, body, buf)
+                    } else {
+                        // the socket took part of it, send the rest
+                        let op = Write::new(&stream, buf);
+                        self.submit(Box::new(Conn::Writing(stream, body, op)))
+                    }
+                }
+                Err(_) => self.close(stream, body),
+            },
+        }
+    }
+}
+
+/// Serve the files in `root` to every client that connects to `listener`,
+/// until `stop` is set.
+pub fn serve(
+    listener: &TcpListener,
+    completion: &Completion,
+    root: &Path,
+    stop: &AtomicBool,
+) -> Result<()> {
+    completion.register(listener)?;
+
+    let mut server = Server {
+        listener,
+        completion,
+        root,
+        conns: HashMap::new(),
+        ready: VecDeque::new(),
+        next_key: 0,
+    };
+    server.accept()?;
+
+    let mut events = Vec::new();
+    while !stop.load(Ordering::Acquire) {
+        while let Some((key, result)) = server.ready.pop_front() {
+            server.complete(key, result)?;
+        }
+
+        completion.wait(Some(TICK), &mut events)?;
+        for event in events.drain(..) {
+            server.complete(event.key, event.result)?;
+        }
+    }
+
+    // cancel whatever is still in flight, and wait for it to finish
+    for (key, _) in server.ready.drain(..) {
+        server.conns.remove(&key);
+    }
+    for key in server.conns.keys() {
+        completion.cancel(*key)?;
+    }
+    while !server.conns.is_empty() {
+        completion.wait(Some(TICK), &mut events)?;
+        for event in events.drain(..) {
+            server.conns.remove(&event.key);
+        }
+    }
+
+    completion.deregister(listener)
+}
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    let root = PathBuf::from(args.next().unwrap_or_else(|| ".".into()));
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:8080".into());
+    let listener = TcpListener::bind(&addr)?;
+    println!("Serving {} on {}", root.display(), listener.local_addr()?);
+
+    let completion = Completion::new(64)?;
+    serve(&listener, &completion, &root, &AtomicBool::new(false))
+}
@@ -0,0 +1,217 @@
+// GNU GPL v3 License
+
+use crate::{Completion, Op, Read, Source, SourceType, SubmissionStatus, Write};
+use std::{
+    io::{self, Result, Seek, SeekFrom},
+    mem,
+};
+
+/// The default size of the intermediate buffer.
+const DEFAULT_BUF_SIZE: usize = 8 * 1024;
+
+/// A blocking `std::io::Read` implementation on top of a `Completion`.
+///
+/// Every call to `read` submits a `Read` operation with the given key and
+/// waits for it to complete. This allows synchronous code to share the
+/// same `Completion` as the rest of the program.
+///
+/// The source must already be registered with the `Completion`. The
+/// adaptor waits with `Completion::wait_for_key`, so events for other
+/// operations are set aside for the next `Completion::wait`, and other
+/// threads may wait for their own keys at the same time. A thread that
+/// calls `Completion::wait` meanwhile may take the adaptor's event,
+/// though, and the call would never return. If waiting fails, the
+/// operation is still in flight, so its buffer is leaked and a new one is
+/// allocated for the next call.
+#[derive(Debug)]
+pub struct CompletionRead<'a, S> {
+    inner: Adaptor<'a, S>,
+}
+
+/// A blocking `std::io::Write` implementation on top of a `Completion`.
+///
+/// Every call to `write` submits a `Write` operation with the given key
+/// and waits for it to complete. This allows synchronous code to share
+/// the same `Completion` as the rest of the program.
+///
+/// The source must already be registered with the `Completion`, and the
+/// same restrictions as for `CompletionRead` apply.
+#[derive(Debug)]
+pub struct CompletionWrite<'a, S> {
+    inner: Adaptor<'a, S>,
+}
+
+#[derive(Debug)]
+struct Adaptor<'a, S> {
+    /// The completion to submit operations to.
+    completion: &'a Completion,
+    /// The source to read from or write to.
+    source: &'a S,
+    /// The key used for submitted operations.
+    key: u64,
+    /// The current position in the file.
+    position: u64,
+    /// The intermediate buffer that operations use.
+    buf: Vec<u8>,
+}
+
+impl<'a, S: Source> CompletionRead<'a, S> {
+    /// Create a new `CompletionRead` that submits operations with `key`.
+    pub fn new(completion: &'a Completion, source: &'a S, key: u64) -> Self {
+        CompletionRead {
+            inner: Adaptor::new(completion, source, key),
+        }
+    }
+}
+
+impl<'a, S: Source> CompletionWrite<'a, S> {
+    /// Create a new `CompletionWrite` that submits operations with `key`.
+    pub fn new(completion: &'a Completion, source: &'a S, key: u64) -> Self {
+        CompletionWrite {
+            inner: Adaptor::new(completion, source, key),
+        }
+    }
+}
+
+impl<S: Source> io::Read for CompletionRead<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let inner = &mut self.inner;
+        let len = buf.len().min(DEFAULT_BUF_SIZE);
+        let mut data = mem::take(&mut inner.buf);
+        data.resize(len, 0);
+
+        let mut op = Read::new(inner.source, data);
+        op.offset(inner.position)?;
+        let (n, data) = inner.run(op)?;
+
+        buf[..n].copy_from_slice(&data[..n]);
+        inner.buf = data;
+        inner.advance(n);
+        Ok(n)
+    }
+}
+
+impl<S: Source> io::Write for CompletionWrite<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let inner = &mut self.inner;
+        let len = buf.len().min(DEFAULT_BUF_SIZE);
+        let mut data = mem::take(&mut inner.buf);
+        data.clear();
+        data.extend_from_slice(&buf[..len]);
+
+        let mut op = Write::new(inner.source, data);
+        op.offset(inner.position)?;
+        let (n, data) = inner.run(op)?;
+
+        inner.buf = data;
+        inner.advance(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // every write is submitted immediately
+        Ok(())
+    }
+}
+
+impl<S: Source> Seek for CompletionRead<'_, S> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<S: Source> Seek for CompletionWrite<'_, S> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<'a, S: Source> Adaptor<'a, S> {
+    fn new(completion: &'a Completion, source: &'a S, key: u64) -> Self {
+        Adaptor {
+            completion,
+            source,
+            key,
+            position: 0,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Submit an operation and wait for it to complete.
+    fn run<O: Op>(&mut self, mut op: O) -> Result<(usize, O::Captured)> {
+        // SAFETY: the operation is owned by this function, and isn't
+        // dropped until it completes
+        let result = match unsafe { self.completion.submit(&mut op, self.key)? } {
+            SubmissionStatus::AlreadyComplete(result) => result,
+            SubmissionStatus::Submitted => loop {
+                match self.completion.wait_for_key(self.key, None) {
+                    Ok(event) => break event.result,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        // the operation is still in flight, so we can't drop
+                        // it; this leaks its buffer, as the type documents
+                        mem::forget(op);
+                        return Err(e);
+                    }
+                }
+            },
+        };
+
+        // SAFETY: the operation is complete
+        let captured = unsafe { op.into_captured() };
+        result.map(|n| (n, captured))
+    }
+
+    /// Advance the position after a transfer of `n` bytes.
+    fn advance(&mut self, n: usize) {
+        if S::SOURCE_TYPE == SourceType::File {
+            self.position += n as u64;
+        }
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        if S::SOURCE_TYPE != SourceType::File {
+            return Err(io::Error::from(io::ErrorKind::Unsupported));
+        }
+
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => (n, 0),
+            SeekFrom::Current(n) => (self.position, n),
+            SeekFrom::End(n) => (file_len(self.source.as_raw())?, n),
+        };
+
+        // the OS can't go past the largest signed offset
+        match base.checked_add_signed(offset) {
+            Some(n) if n <= i64::MAX as u64 => {
+                self.position = n;
+                Ok(n)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+/// Get the length of a file.
+#[cfg(unix)]
+fn file_len(fd: crate::Raw) -> Result<u64> {
+    let mut stat = mem::MaybeUninit::<libc::stat>::uninit();
+    syscall!(fstat(fd, stat.as_mut_ptr()))?;
+    Ok(unsafe { stat.assume_init() }.st_size as u64)
+}
+
+/// Get the length of a file.
+#[cfg(windows)]
+fn file_len(handle: crate::Raw) -> Result<u64> {
+    let mut size = 0;
+    let res =
+        unsafe { windows_sys::Win32::Storage::FileSystem::GetFileSizeEx(handle as _, &mut size) };
+
+    if res == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(size as u64)
+    }
+}
@@ -0,0 +1,248 @@
+// GNU GPL v3 License
+
+//! Readiness polling for sockets on Windows, using the AFD driver.
+//!
+//! This is the same approach as `wepoll` and `polling`: a poll request is
+//! sent to the driver behind Winsock, and it completes through the
+//! completion port once the socket is ready. Unlike overlapped I/O, this
+//! works on any socket, even ones that weren't opened in overlapped mode.
+
+#![cfg(windows)]
+
+use std::{
+    io::{self, Result},
+    mem::{size_of, zeroed},
+    ptr::null_mut,
+};
+use windows_sys::Win32::{
+    Foundation::{
+        CloseHandle, RtlNtStatusToDosError, HANDLE, INVALID_HANDLE_VALUE, NTSTATUS, UNICODE_STRING,
+    },
+    Networking::WinSock::{WSAIoctl, SIO_BASE_HANDLE, SOCKET_ERROR},
+    Storage::FileSystem::{
+        NtCreateFile, SetFileCompletionNotificationModes, FILE_OPEN, FILE_SHARE_READ,
+        FILE_SHARE_WRITE, SYNCHRONIZE,
+    },
+    System::{
+        WindowsProgramming::{NtDeviceIoControlFile, IO_STATUS_BLOCK, OBJECT_ATTRIBUTES},
+        IO::{CancelIoEx, CreateIoCompletionPort, OVERLAPPED},
+    },
+};
+
+pub(crate) const AFD_POLL_RECEIVE: u32 = 0x0001;
+pub(crate) const AFD_POLL_SEND: u32 = 0x0004;
+pub(crate) const AFD_POLL_DISCONNECT: u32 = 0x0008;
+pub(crate) const AFD_POLL_ABORT: u32 = 0x0010;
+pub(crate) const AFD_POLL_LOCAL_CLOSE: u32 = 0x0020;
+pub(crate) const AFD_POLL_ACCEPT: u32 = 0x0080;
+pub(crate) const AFD_POLL_CONNECT_FAIL: u32 = 0x0100;
+
+/// The events that mean a socket is readable, including hanging up and
+/// errors.
+pub(crate) const READABLE: u32 = AFD_POLL_RECEIVE
+    | AFD_POLL_ACCEPT
+    | AFD_POLL_DISCONNECT
+    | AFD_POLL_ABORT
+    | AFD_POLL_LOCAL_CLOSE
+    | AFD_POLL_CONNECT_FAIL;
+
+/// The events that mean a socket is writable, including errors.
+pub(crate) const WRITABLE: u32 =
+    AFD_POLL_SEND | AFD_POLL_ABORT | AFD_POLL_LOCAL_CLOSE | AFD_POLL_CONNECT_FAIL;
+
+const IOCTL_AFD_POLL: u32 = 0x0001_2024;
+const STATUS_PENDING: NTSTATUS = 0x0000_0103;
+const FILE_SKIP_SET_EVENT_ON_HANDLE: u8 = 0x2;
+
+/// The name of the device we open, the suffix is arbitrary.
+const AFD_NAME: &[u16] = &[
+    '\\' as _, 'D' as _, 'e' as _, 'v' as _, 'i' as _, 'c' as _, 'e' as _, '\\' as _, 'A' as _,
+    'f' as _, 'd' as _, '\\' as _, 'p' as _, 'o' as _, 'l' as _, 'l' as _, 'd' as _, 'o' as _,
+    'u' as _, 'g' as _, 'h' as _,
+];
+
+/// A handle to the AFD driver, associated with a completion port.
+#[derive(Debug)]
+pub(crate) struct Afd {
+    handle: HANDLE,
+}
+
+unsafe impl Send for Afd {}
+unsafe impl Sync for Afd {}
+
+/// The state of a single socket in a poll request.
+#[repr(C)]
+#[derive(Debug)]
+struct AfdPollHandleInfo {
+    handle: HANDLE,
+    events: u32,
+    status: NTSTATUS,
+}
+
+/// A poll request, which must stay at the same address until it
+/// completes.
+#[repr(C)]
+#[derive(Debug)]
+pub(crate) struct AfdPollInfo {
+    timeout: i64,
+    number_of_handles: u32,
+    exclusive: u32,
+    handles: [AfdPollHandleInfo; 1],
+}
+
+impl AfdPollInfo {
+    /// Create a request for a single socket and the given events.
+    pub(crate) fn new(socket: HANDLE, events: u32) -> Self {
+        AfdPollInfo {
+            timeout: i64::MAX,
+            number_of_handles: 1,
+            exclusive: 0,
+            handles: [AfdPollHandleInfo {
+                handle: socket,
+                events,
+                status: 0,
+            }],
+        }
+    }
+
+    /// The events that were reported once the request completed.
+    pub(crate) fn events(&self) -> u32 {
+        if self.number_of_handles == 0 {
+            0
+        } else {
+            self.handles[0].events
+        }
+    }
+}
+
+impl Afd {
+    /// Open the driver and associate it with the completion port.
+    pub(crate) fn new(port: HANDLE) -> Result<Self> {
+        let mut name = UNICODE_STRING {
+            Length: (AFD_NAME.len() * size_of::<u16>()) as _,
+            MaximumLength: (AFD_NAME.len() * size_of::<u16>()) as _,
+            Buffer: AFD_NAME.as_ptr() as *mut _,
+        };
+        let mut attributes = OBJECT_ATTRIBUTES {
+            Length: size_of::<OBJECT_ATTRIBUTES>() as _,
+            RootDirectory: 0,
+            ObjectName: &mut name,
+            Attributes: 0,
+            SecurityDescriptor: null_mut(),
+            SecurityQualityOfService: null_mut(),
+        };
+        let mut handle = INVALID_HANDLE_VALUE;
+        let mut iosb: IO_STATUS_BLOCK = unsafe { zeroed() };
+
+        let status = unsafe {
+            NtCreateFile(
+                &mut handle,
+                SYNCHRONIZE,
+                &mut attributes,
+                &mut iosb,
+                null_mut(),
+                0,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                FILE_OPEN,
+                0,
+                null_mut(),
+                0,
+            )
+        };
+        if status != 0 {
+            return Err(nt_error(status));
+        }
+
+        let afd = Afd { handle };
+
+        if unsafe { CreateIoCompletionPort(handle, port, 0, 0) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { SetFileCompletionNotificationModes(handle, FILE_SKIP_SET_EVENT_ON_HANDLE) } == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(afd)
+    }
+
+    /// Start a poll request, which completes through the port with
+    /// `overlapped` once the socket is ready.
+    ///
+    /// # Safety
+    ///
+    /// `info` and `overlapped` must stay valid until the request completes.
+    pub(crate) unsafe fn poll(
+        &self,
+        info: *mut AfdPollInfo,
+        overlapped: *mut OVERLAPPED,
+    ) -> Result<()> {
+        // the start of an OVERLAPPED has the same layout as IO_STATUS_BLOCK
+        let status = NtDeviceIoControlFile(
+            self.handle,
+            0,
+            None,
+            overlapped.cast(),
+            overlapped.cast(),
+            IOCTL_AFD_POLL,
+            info.cast(),
+            size_of::<AfdPollInfo>() as _,
+            info.cast(),
+            size_of::<AfdPollInfo>() as _,
+        );
+
+        match status {
+            0 | STATUS_PENDING => Ok(()),
+            status => Err(nt_error(status)),
+        }
+    }
+
+    /// Cancel a poll request started with `overlapped`.
+    ///
+    /// This fails if the request already completed, which is fine, so
+    /// nothing is returned.
+    pub(crate) fn cancel(&self, overlapped: *const OVERLAPPED) {
+        unsafe {
+            CancelIoEx(self.handle, overlapped);
+        }
+    }
+}
+
+impl Drop for Afd {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.handle);
+        }
+    }
+}
+
+/// Get the base socket under any layered service providers, since the
+/// driver only knows about that one.
+pub(crate) fn base_socket(socket: HANDLE) -> Result<HANDLE> {
+    let mut base: HANDLE = 0;
+    let mut bytes = 0;
+
+    let res = unsafe {
+        WSAIoctl(
+            socket as _,
+            SIO_BASE_HANDLE,
+            null_mut(),
+            0,
+            &mut base as *mut _ as *mut _,
+            size_of::<HANDLE>() as _,
+            &mut bytes,
+            null_mut(),
+            None,
+        )
+    };
+
+    if res == SOCKET_ERROR {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(base)
+    }
+}
+
+fn nt_error(status: NTSTATUS) -> io::Error {
+    io::Error::from_raw_os_error(unsafe { RtlNtStatusToDosError(status) } as _)
+}
@@ -0,0 +1,135 @@
+// GNU GPL v3 License
+
+use crate::{Completion, SharedBuf, Source, SubmissionStatus, Write};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Result},
+    mem,
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+/// The writes of one buffer to several sources, waited on together.
+///
+/// This is created by `Completion::broadcast`. The writes use consecutive
+/// keys, one per source, so those keys shouldn't be used by anything else
+/// while the handle is alive. If the handle is dropped while writes are
+/// still in flight, they're leaked, since the OS may still be using them.
+pub struct BroadcastHandle<'a> {
+    /// The completion the writes were submitted to.
+    completion: &'a Completion,
+    /// The key of the write to the first source.
+    first_key: u64,
+    /// The number of sources.
+    len: usize,
+    /// The writes in flight, by key.
+    writes: HashMap<u64, Box<Write<SharedBuf>>>,
+    /// The result for each source, once it's known.
+    results: Vec<Option<Result<usize>>>,
+}
+
+impl fmt::Debug for BroadcastHandle<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BroadcastHandle")
+            .field("keys", &self.keys())
+            .field("in_flight", &self.writes.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> BroadcastHandle<'a> {
+    /// Submit a write of `buf` to every source.
+    ///
+    /// # Safety
+    ///
+    /// See `Completion::broadcast`.
+    pub(crate) unsafe fn submit<S: Source>(
+        completion: &'a Completion,
+        buf: SharedBuf,
+        sources: &[S],
+        first_key: u64,
+    ) -> Self {
+        let mut handle = BroadcastHandle {
+            completion,
+            first_key,
+            len: sources.len(),
+            writes: HashMap::with_capacity(sources.len()),
+            results: Vec::with_capacity(sources.len()),
+        };
+
+        for (i, source) in sources.iter().enumerate() {
+            let key = first_key.wrapping_add(i as u64);
+            let mut write = Box::new(Write::new(source, buf.clone()));
+
+            // we own the write, so it can't be submitted twice, and it's
+            // boxed so it won't move while in flight
+            let result = match completion.submit(&mut *write, key) {
+                Ok(SubmissionStatus::AlreadyComplete(result)) => Some(result),
+                Ok(SubmissionStatus::Submitted) => {
+                    handle.writes.insert(key, write);
+                    None
+                }
+                // the other sources still get the data
+                Err(e) => Some(Err(e)),
+            };
+
+            handle.results.push(result);
+        }
+
+        handle
+    }
+
+    /// The keys used by the writes, in the order of the sources.
+    pub fn keys(&self) -> Range<u64> {
+        self.first_key..self.first_key.wrapping_add(self.len as u64)
+    }
+
+    /// Tell whether every write has completed.
+    pub fn is_complete(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Wait for every write to complete.
+    ///
+    /// Returns the number of bytes written to each source, in the order
+    /// the sources were given. Returns an error of kind `TimedOut` if the
+    /// timeout expires first; writes that completed in the meantime are
+    /// kept, so this can be called again. Once the results are returned,
+    /// later calls return an empty list.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Vec<Result<usize>>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        while !self.writes.is_empty() {
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) => Some(timeout),
+                    None => return Err(io::ErrorKind::TimedOut.into()),
+                },
+                None => None,
+            };
+
+            let writes = &self.writes;
+            let event = self
+                .completion
+                .wait_matching(timeout, |key| writes.contains_key(&key))?;
+
+            // the write is complete, so its reference to the buffer can go
+            self.writes.remove(&event.key);
+
+            let index = event.key.wrapping_sub(self.first_key) as usize;
+            self.results[index] = Some(event.result);
+        }
+
+        Ok(self.results.drain(..).flatten().collect())
+    }
+}
+
+impl Drop for BroadcastHandle<'_> {
+    fn drop(&mut self) {
+        // the OS may still read from these
+        for (_, write) in self.writes.drain() {
+            mem::forget(write);
+        }
+    }
+}
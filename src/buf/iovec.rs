@@ -21,6 +21,10 @@ use windows_sys::Win32::Networking::WinSock::WSABUF;
 #[repr(transparent)]
 pub struct OwnedIoSlice(Sys);
 
+// SAFETY: the slice owns its storage, like a Box<[u8]>
+unsafe impl Send for OwnedIoSlice {}
+unsafe impl Sync for OwnedIoSlice {}
+
 #[cfg(windows)]
 #[repr(transparent)]
 struct Sys {
@@ -69,30 +73,12 @@ impl OwnedIoSlice {
 
     /// Get the `IoSlice` used in this `IoVec`.
     pub fn io_slice(&self) -> IoSlice<'_> {
-        cfg_if! {
-            if #[cfg(any(unix, windows))] {
-                // SAFETY: iovec is ABI-compatible with WSABUF/iovec, so we
-                // can do a transmute here
-                unsafe { mem::transmute(self.0.buf) }
-            } else {
-                // just borrow the inside
-                IoSlice::new(&self.0.buf)
-            }
-        }
+        IoSlice::new(self.as_ref())
     }
 
     /// Get the `IoSliceMut` used in this `IoVec`.
     pub fn io_slice_mut(&mut self) -> IoSliceMut<'_> {
-        cfg_if! {
-            if #[cfg(any(unix, windows))] {
-                // SAFETY: iovec is ABI-compatible with WSABUF/iovec, so we
-                // can do a transmute here
-                unsafe { mem::transmute(self.0.buf) }
-            } else {
-                // just borrow the inside
-                IoSliceMut::new(&mut self.0.buf)
-            }
-        }
+        IoSliceMut::new(self.as_mut())
     }
 
     /// Convert this `OwnedIoSlice` into a `Box<[u8]>`.
@@ -105,7 +91,7 @@ impl OwnedIoSlice {
         cfg_if! {
             if #[cfg(windows)] {
                 let ptr = self.0.buf.buf as *mut u8;
-                let len = self.0.buf.buf as usize;
+                let len = self.0.buf.len as usize;
                 Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len))
             } else if #[cfg(unix)] {
                 let ptr = self.0.buf.iov_base as *mut u8;
@@ -117,6 +103,102 @@ impl OwnedIoSlice {
         }
     }
 
+    /// Create a zeroed `OwnedIoSlice` of `len` bytes, backed by huge pages
+    /// where possible.
+    ///
+    /// On Linux, this asks for transparent huge pages with
+    /// `madvise(MADV_HUGEPAGE)`, which cuts down on TLB misses for large
+    /// buffers. Only the parts of the buffer that cover whole huge pages
+    /// can use them, so this is meant for buffers of several megabytes.
+    /// Elsewhere, this is a plain zeroed buffer.
+    ///
+    /// This is only available with the `hugepages` feature.
+    #[cfg(feature = "hugepages")]
+    pub fn with_hugepages(len: usize) -> Self {
+        let slice = Self::from(vec![0u8; len]);
+
+        // large zeroed allocations are fresh mappings that haven't been
+        // touched yet, so the advice applies before the pages are faulted in
+        #[cfg(target_os = "linux")]
+        if let Some((ptr, len)) = slice.whole_pages() {
+            if let Err(e) = syscall!(madvise(ptr, len, libc::MADV_HUGEPAGE)) {
+                tracing::debug!("Failed to ask for huge pages: {:?}", e);
+            }
+        }
+
+        slice
+    }
+
+    /// Lock the buffer into memory, so that it's never paged out.
+    ///
+    /// This uses `mlock` on Unix and `VirtualLock` on Windows, and fails
+    /// if the process may not lock that much memory. Locks aren't
+    /// counted: whole pages are locked, and unlocking the buffer also
+    /// unlocks the pages it shares with other memory. Unlock it before
+    /// dropping it, or those pages stay locked until the allocator
+    /// returns them to the OS.
+    ///
+    /// This is only available with the `hugepages` feature.
+    #[cfg(all(feature = "hugepages", any(unix, windows)))]
+    pub fn lock_in_memory(&self) -> std::io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        cfg_if! {
+            if #[cfg(windows)] {
+                use windows_sys::Win32::System::Memory::VirtualLock;
+
+                if unsafe { VirtualLock(self.as_ptr() as *const _, self.len()) } == 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            } else {
+                syscall!(mlock(self.as_ptr().cast(), self.len()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Undo `lock_in_memory`.
+    ///
+    /// This is only available with the `hugepages` feature.
+    #[cfg(all(feature = "hugepages", any(unix, windows)))]
+    pub fn unlock_in_memory(&self) -> std::io::Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        cfg_if! {
+            if #[cfg(windows)] {
+                use windows_sys::Win32::System::Memory::VirtualUnlock;
+
+                if unsafe { VirtualUnlock(self.as_ptr() as *const _, self.len()) } == 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            } else {
+                syscall!(munlock(self.as_ptr().cast(), self.len()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The part of the buffer made up of whole pages, if any.
+    #[cfg(all(feature = "hugepages", target_os = "linux"))]
+    fn whole_pages(&self) -> Option<(*mut libc::c_void, usize)> {
+        let page = syscall!(sysconf(libc::_SC_PAGESIZE)).ok()? as usize;
+        let start = self.as_ptr() as usize;
+        let first = (start + page - 1) & !(page - 1);
+        let end = (start + self.len()) & !(page - 1);
+
+        if end > first {
+            Some((first as *mut _, end - first))
+        } else {
+            None
+        }
+    }
+
     /// Convert this `OwnedIoSlice` into a `Box<[u8]>`.
     pub fn into_boxed_slice(self) -> Box<[u8]> {
         // SAFETY: only called once, since we suppress destructor
@@ -164,7 +246,7 @@ impl AsRef<[u8]> for OwnedIoSlice {
                 unsafe {
                     slice::from_raw_parts(
                         self.0.buf.iov_base as *const u8,
-                        self.0.buf.iov_len as usize,
+                        self.0.buf.iov_len,
                     )
                 }
             } else {
@@ -188,7 +270,7 @@ impl AsMut<[u8]> for OwnedIoSlice {
                 unsafe {
                     slice::from_raw_parts_mut(
                         self.0.buf.iov_base as *mut u8,
-                        self.0.buf.iov_len as usize,
+                        self.0.buf.iov_len,
                     )
                 }
             } else {
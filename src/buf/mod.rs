@@ -9,6 +9,15 @@ use std::{
 mod iovec;
 pub use iovec::OwnedIoSlice;
 
+mod pool;
+pub use pool::{BufPool, PooledBuf};
+
+mod ring;
+pub use ring::RingBuf;
+
+mod shared;
+pub use shared::SharedBuf;
+
 /// A buffer type that can be used to write data of some kind
 /// to a source.
 ///
@@ -108,28 +117,49 @@ unsafe impl BufMut for IoSliceMut<'static> {}
 unsafe impl BufMut for Vec<u8> {}
 unsafe impl BufMut for OwnedIoSlice {}
 
-/// A buffer type that is ABI compatible with `std::io::IoSlice`.
+/// A buffer type that can be used in vectored I/O.
 ///
 /// # Safety
 ///
-/// In addition to fulfilling the same contracts as `Buf`, `IoBuf`
-/// must also be able to be safely transmuted to `IoSlice`.
-pub unsafe trait IoBuf: Buf {}
+/// Same contract as `Buf`, and `as_io_slice` must refer to the same
+/// memory as `pointer`.
+pub unsafe trait IoBuf: Buf {
+    /// Get this buffer as an `IoSlice`.
+    fn as_io_slice(&self) -> IoSlice<'_> {
+        // SAFETY: the pointer is valid for as long as we're borrowed
+        IoSlice::new(unsafe { &*self.pointer().as_ptr() })
+    }
+}
 
 unsafe impl IoBuf for IoSlice<'static> {}
 unsafe impl IoBuf for IoSliceMut<'static> {}
-unsafe impl IoBuf for OwnedIoSlice {}
+unsafe impl IoBuf for OwnedIoSlice {
+    fn as_io_slice(&self) -> IoSlice<'_> {
+        self.io_slice()
+    }
+}
 
-/// A mutable buffer type that is ABI compatible with `std::io::IoSliceMut`.
+/// A mutable buffer type that can be used in vectored I/O.
 ///
 /// # Safety
 ///
-/// In addition to fulfilling the same contracts as `BufMut`, `IoBufMut`
-/// must also be able to be safely transmuted to `IoSliceMut`.
-pub unsafe trait IoBufMut: BufMut + IoBuf {}
+/// Same contract as `BufMut`, and `as_io_slice_mut` must refer to the
+/// same memory as `pointer`.
+pub unsafe trait IoBufMut: BufMut + IoBuf {
+    /// Get this buffer as an `IoSliceMut`.
+    fn as_io_slice_mut(&mut self) -> IoSliceMut<'_> {
+        // SAFETY: the pointer is valid for as long as we're borrowed, and
+        // `BufMut` allows using it mutably
+        IoSliceMut::new(unsafe { &mut *self.pointer().as_ptr() })
+    }
+}
 
 unsafe impl IoBufMut for IoSliceMut<'static> {}
-unsafe impl IoBufMut for OwnedIoSlice {}
+unsafe impl IoBufMut for OwnedIoSlice {
+    fn as_io_slice_mut(&mut self) -> IoSliceMut<'_> {
+        self.io_slice_mut()
+    }
+}
 
 /// A buffer made up of I/O slices, for vectored I/O.
 ///
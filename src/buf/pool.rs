@@ -0,0 +1,180 @@
+// GNU GPL v3 License
+
+use crate::PoisonPolicy;
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+
+/// The most free buffers kept for each size.
+const MAX_FREE: usize = 64;
+
+/// Buffers of a few sizes, kept around to be reused.
+///
+/// The sizes start at `min_size` and double until they reach `max_size`.
+/// This is cheap to clone; the clones share the same buffers.
+#[derive(Clone)]
+pub struct BufPool {
+    inner: Arc<Inner>,
+    /// What to do when the free buffers are found poisoned.
+    poison: PoisonPolicy,
+}
+
+struct Inner {
+    /// The size of the buffers in each class, from small to large.
+    sizes: Vec<usize>,
+    /// The free buffers of each class.
+    free: Mutex<Vec<Vec<Vec<u8>>>>,
+}
+
+impl fmt::Debug for BufPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufPool")
+            .field("sizes", &self.inner.sizes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl BufPool {
+    /// Create a new `BufPool` with buffers from `min_size` to `max_size`
+    /// bytes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `min_size` is zero or larger than `max_size`.
+    #[track_caller]
+    pub fn new(min_size: usize, max_size: usize) -> Self {
+        assert!(min_size > 0, "buffers can't be empty");
+        assert!(
+            min_size <= max_size,
+            "minimum size ({}) must be less than or equal to maximum size ({})",
+            min_size,
+            max_size,
+        );
+
+        let mut sizes = vec![min_size];
+        while let Some(&size) = sizes.last().filter(|&&size| size < max_size) {
+            sizes.push(size.saturating_mul(2).min(max_size));
+        }
+
+        BufPool {
+            inner: Arc::new(Inner {
+                free: Mutex::new(vec![Vec::new(); sizes.len()]),
+                sizes,
+            }),
+            poison: PoisonPolicy::default(),
+        }
+    }
+
+    /// Set what to do when the free buffers are found poisoned.
+    ///
+    /// By default, the poisoning is logged and the buffers are used anyway.
+    /// Buffers are taken and given back where no error can be returned,
+    /// so `PropagateError` aborts the process, like `Abort`. Clones made
+    /// afterwards follow the same policy.
+    pub fn on_poison(&mut self, policy: PoisonPolicy) -> &mut Self {
+        self.poison = policy;
+        self
+    }
+
+    /// The number of size classes.
+    pub(crate) fn classes(&self) -> usize {
+        self.inner.sizes.len()
+    }
+
+    /// The smallest class that holds `len` bytes, or the largest one.
+    pub(crate) fn class_for(&self, len: usize) -> usize {
+        let sizes = &self.inner.sizes;
+        sizes
+            .iter()
+            .position(|&size| size >= len)
+            .unwrap_or(sizes.len() - 1)
+    }
+
+    /// Take a buffer of the given class, which is as long as the class's
+    /// size.
+    pub(crate) fn take(&self, class: usize) -> Vec<u8> {
+        let size = self.inner.sizes[class];
+        lock!(self.inner.free, self.poison, infallible)[class]
+            .pop()
+            .unwrap_or_else(|| vec![0; size])
+    }
+
+    /// Give a buffer back, unless enough of its class are already free.
+    pub(crate) fn put(&self, buf: Vec<u8>) {
+        let class = match self.inner.sizes.iter().position(|&size| size == buf.len()) {
+            Some(class) => class,
+            None => return,
+        };
+
+        let mut free = lock!(self.inner.free, self.poison, infallible);
+        if free[class].len() < MAX_FREE {
+            free[class].push(buf);
+        }
+    }
+}
+
+/// A buffer from a `BufPool`, holding the data that was read into it.
+///
+/// The buffer goes back to the pool when this is dropped.
+pub struct PooledBuf {
+    buf: Vec<u8>,
+    len: usize,
+    pool: BufPool,
+}
+
+impl fmt::Debug for PooledBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledBuf")
+            .field("len", &self.len)
+            .field("capacity", &self.buf.len())
+            .finish()
+    }
+}
+
+impl PooledBuf {
+    /// Wrap a buffer from `pool`, of which the first `len` bytes are
+    /// filled.
+    pub(crate) fn new(buf: Vec<u8>, len: usize, pool: BufPool) -> Self {
+        PooledBuf {
+            len: len.min(buf.len()),
+            buf,
+            pool,
+        }
+    }
+
+    /// The size of the buffer, including the part that wasn't filled.
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Take the data out, without giving the buffer back to the pool.
+    pub fn into_vec(mut self) -> Vec<u8> {
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.truncate(self.len);
+        buf
+    }
+}
+
+impl Deref for PooledBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl DerefMut for PooledBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf[..self.len]
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            self.pool.put(std::mem::take(&mut self.buf));
+        }
+    }
+}
@@ -0,0 +1,314 @@
+// GNU GPL v3 License
+
+use super::{VectoredBuf, VectoredBufMut};
+use std::{fmt, io::IoSliceMut, ptr::NonNull, slice};
+
+/// A circular buffer that vectored reads fill without copying.
+///
+/// Data is read into the free space after the data already in the buffer,
+/// and consumed from the front. As a `VectoredBufMut`, the buffer is the
+/// free space: one slice, or two when it wraps around the end. After a
+/// read completes, `advance_tail` makes the bytes it read part of the
+/// data, and `advance_head` drops the data that was parsed.
+///
+/// On Linux, the storage is mapped twice, back to back, so the free space
+/// and the data are always contiguous and reads never need to be split.
+/// Elsewhere, or if the mapping fails, this is a plain heap buffer.
+pub struct RingBuf {
+    storage: Storage,
+    /// Where the data starts.
+    head: usize,
+    /// How much data there is.
+    len: usize,
+    /// The free space, as handed out by `pointer`.
+    free: [IoSliceMut<'static>; 2],
+    /// How many of the slices in `free` are in use.
+    segments: usize,
+}
+
+// SAFETY: the slices point into storage that we own
+unsafe impl Send for RingBuf {}
+unsafe impl Sync for RingBuf {}
+
+/// The memory behind a `RingBuf`.
+enum Storage {
+    Heap(Box<[u8]>),
+    /// The same pages, mapped twice in a row.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Mirrored {
+        ptr: NonNull<u8>,
+        capacity: usize,
+    },
+}
+
+impl Storage {
+    /// Map `capacity` bytes twice, rounding up to whole pages.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn mirrored(capacity: usize) -> std::io::Result<Self> {
+        use std::ptr;
+
+        let page = syscall!(sysconf(libc::_SC_PAGESIZE))? as usize;
+        let capacity = capacity.max(1).checked_add(page - 1).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "capacity is too large")
+        })? & !(page - 1);
+
+        let fd = syscall!(memfd_create(
+            b"polldough-ringbuf\0".as_ptr().cast(),
+            libc::MFD_CLOEXEC
+        ))?;
+
+        let result = (|| {
+            syscall!(ftruncate(fd, capacity as _))?;
+
+            // reserve room for both halves, then put the pages in each
+            let base = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    capacity * 2,
+                    libc::PROT_NONE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                    -1,
+                    0,
+                )
+            };
+            if base == libc::MAP_FAILED {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            for half in 0..2 {
+                let addr = unsafe { base.cast::<u8>().add(half * capacity) };
+                let mapped = unsafe {
+                    libc::mmap(
+                        addr.cast(),
+                        capacity,
+                        libc::PROT_READ | libc::PROT_WRITE,
+                        libc::MAP_SHARED | libc::MAP_FIXED,
+                        fd,
+                        0,
+                    )
+                };
+                if mapped == libc::MAP_FAILED {
+                    let err = std::io::Error::last_os_error();
+                    unsafe { libc::munmap(base, capacity * 2) };
+                    return Err(err);
+                }
+            }
+
+            Ok(Storage::Mirrored {
+                ptr: NonNull::new(base.cast()).expect("mmap returned null"),
+                capacity,
+            })
+        })();
+
+        unsafe { libc::close(fd) };
+        result
+    }
+
+    fn as_ptr(&self) -> *mut u8 {
+        match self {
+            Storage::Heap(buf) => buf.as_ptr() as *mut u8,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Storage::Mirrored { ptr, .. } => ptr.as_ptr(),
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        match self {
+            Storage::Heap(buf) => buf.len(),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Storage::Mirrored { capacity, .. } => *capacity,
+        }
+    }
+
+    fn is_mirrored(&self) -> bool {
+        !matches!(self, Storage::Heap(_))
+    }
+}
+
+impl Drop for Storage {
+    fn drop(&mut self) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        if let Storage::Mirrored { ptr, capacity } = self {
+            unsafe { libc::munmap(ptr.as_ptr().cast(), *capacity * 2) };
+        }
+    }
+}
+
+impl RingBuf {
+    /// Create a ring buffer that holds at least `capacity` bytes.
+    ///
+    /// When the storage is mapped twice, the capacity is rounded up to
+    /// whole pages.
+    pub fn new(capacity: usize) -> Self {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let storage = match Storage::mirrored(capacity) {
+            Ok(storage) => storage,
+            Err(e) => {
+                tracing::debug!("Failed to map a mirrored ring buffer: {:?}", e);
+                Storage::Heap(vec![0; capacity].into_boxed_slice())
+            }
+        };
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let storage = Storage::Heap(vec![0; capacity].into_boxed_slice());
+
+        let mut ring = RingBuf {
+            storage,
+            head: 0,
+            len: 0,
+            free: [IoSliceMut::new(&mut []), IoSliceMut::new(&mut [])],
+            segments: 0,
+        };
+        ring.update_free();
+        ring
+    }
+
+    /// The most data the buffer holds.
+    pub fn capacity(&self) -> usize {
+        self.storage.capacity()
+    }
+
+    /// How much data is in the buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Is the buffer empty?
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How much more data fits into the buffer.
+    pub fn remaining(&self) -> usize {
+        self.capacity() - self.len
+    }
+
+    /// Is the storage mapped twice, so that the data is always in one
+    /// slice?
+    pub fn is_mirrored(&self) -> bool {
+        self.storage.is_mirrored()
+    }
+
+    /// The data in the buffer, in order.
+    ///
+    /// The second slice is only used when the data wraps around the end
+    /// of a buffer that isn't mirrored.
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        let (first, second) = self.split(self.head, self.len);
+        // SAFETY: the data is initialized, and only written to through
+        // `&mut self`
+        unsafe {
+            (
+                slice::from_raw_parts(first.0, first.1),
+                slice::from_raw_parts(second.0, second.1),
+            )
+        }
+    }
+
+    /// Make the next `n` bytes of free space part of the data.
+    ///
+    /// Call this with the number of bytes a read into the buffer returned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is more than the free space.
+    #[track_caller]
+    pub fn advance_tail(&mut self, n: usize) {
+        assert!(
+            n <= self.remaining(),
+            "advanced the tail by {} bytes, but only {} are free",
+            n,
+            self.remaining()
+        );
+
+        self.len += n;
+        self.update_free();
+    }
+
+    /// Drop the first `n` bytes of data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is more than the data in the buffer.
+    #[track_caller]
+    pub fn advance_head(&mut self, n: usize) {
+        assert!(
+            n <= self.len,
+            "advanced the head by {} bytes, but there are only {}",
+            n,
+            self.len
+        );
+
+        self.head = match self.capacity() {
+            0 => 0,
+            capacity => (self.head + n) % capacity,
+        };
+        self.len -= n;
+
+        // start over at the beginning, so that reads aren't split
+        if self.len == 0 {
+            self.head = 0;
+        }
+
+        self.update_free();
+    }
+
+    /// Drop all of the data.
+    pub fn clear(&mut self) {
+        self.advance_head(self.len);
+    }
+
+    /// The pointers and lengths of the one or two parts of `len` bytes
+    /// starting at `start`.
+    fn split(&self, start: usize, len: usize) -> ((*mut u8, usize), (*mut u8, usize)) {
+        let base = self.storage.as_ptr();
+        let capacity = self.capacity();
+        let start = if capacity == 0 { 0 } else { start % capacity };
+
+        // SAFETY: `start` is in bounds, and mirrored storage is mapped
+        // for twice its capacity
+        let first = unsafe { base.add(start) };
+        if self.storage.is_mirrored() {
+            return ((first, len), (base, 0));
+        }
+
+        let first_len = len.min(capacity - start);
+        ((first, first_len), (base, len - first_len))
+    }
+
+    /// Point the free slices at the free space.
+    fn update_free(&mut self) {
+        let (first, second) = self.split(self.head + self.len, self.remaining());
+
+        // SAFETY: the slices are only handed out as raw pointers, and
+        // are replaced whenever the free space moves
+        let mut segments = 0;
+        for (ptr, len) in [first, second] {
+            if len > 0 {
+                self.free[segments] =
+                    IoSliceMut::new(unsafe { slice::from_raw_parts_mut(ptr, len) });
+                segments += 1;
+            }
+        }
+        self.segments = segments;
+    }
+}
+
+impl fmt::Debug for RingBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RingBuf")
+            .field("capacity", &self.capacity())
+            .field("len", &self.len)
+            .field("mirrored", &self.is_mirrored())
+            .finish()
+    }
+}
+
+unsafe impl VectoredBuf for RingBuf {
+    type InnerBuf = IoSliceMut<'static>;
+
+    fn pointer(&self) -> NonNull<[Self::InnerBuf]> {
+        NonNull::from(&self.free[..self.segments])
+    }
+}
+
+unsafe impl VectoredBufMut for RingBuf {}
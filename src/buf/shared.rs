@@ -0,0 +1,48 @@
+// GNU GPL v3 License
+
+use std::{ops::Deref, ptr::NonNull, sync::Arc};
+
+/// An immutable buffer that can be written to several sources at once.
+///
+/// Cloning this only clones the reference, so every clone points to the
+/// same data.
+#[derive(Debug, Clone)]
+pub struct SharedBuf(Arc<[u8]>);
+
+unsafe impl super::Buf for SharedBuf {
+    fn pointer(&self) -> NonNull<[u8]> {
+        NonNull::from(&*self.0)
+    }
+}
+
+impl From<Arc<[u8]>> for SharedBuf {
+    fn from(data: Arc<[u8]>) -> Self {
+        SharedBuf(data)
+    }
+}
+
+impl From<Vec<u8>> for SharedBuf {
+    fn from(data: Vec<u8>) -> Self {
+        SharedBuf(data.into())
+    }
+}
+
+impl From<&[u8]> for SharedBuf {
+    fn from(data: &[u8]) -> Self {
+        SharedBuf(data.into())
+    }
+}
+
+impl AsRef<[u8]> for SharedBuf {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Deref for SharedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
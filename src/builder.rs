@@ -0,0 +1,727 @@
+// GNU GPL v3 License
+
+use crate::{
+    coalesce::Coalescer, fence::Fences, memory::Memory, ordering::Sequencer, pending::Pending,
+    platform, pool::PoolConfig, BlockingExecutor, Completion, OrderingMode, PoisonPolicy,
+};
+use std::{
+    collections::VecDeque,
+    env,
+    io::{self, Result},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A builder for configuring a `Completion`.
+#[derive(Debug, Clone)]
+pub struct CompletionBuilder {
+    /// The number of events that can be processed at once.
+    pub(crate) capacity: usize,
+    /// Whether we manage `O_NONBLOCK` on registered sources.
+    pub(crate) nonblocking: bool,
+    /// Whether that includes the inherited standard I/O.
+    pub(crate) nonblocking_stdio: bool,
+    /// Whether we only use `io_uring` for files.
+    pub(crate) hybrid: bool,
+    /// Whether we try to use `io_uring` at all.
+    pub(crate) io_uring: bool,
+    /// Whether files and sockets go through POSIX AIO.
+    #[cfg(target_os = "freebsd")]
+    pub(crate) aio: bool,
+    /// Whether readiness polling uses edge-triggered notifications.
+    pub(crate) edge_triggered: bool,
+    /// Whether we keep track of every operation in flight.
+    pub(crate) track_pending: bool,
+    /// How long operations may be in flight before they're flagged, if
+    /// they're watched.
+    pub(crate) watchdog: Option<Duration>,
+    /// The key of the events delivered for flagged operations, if any.
+    pub(crate) watchdog_key: Option<u64>,
+    /// The order in which events are delivered.
+    pub(crate) ordering: OrderingMode,
+    /// What to do when an internal mutex is poisoned.
+    pub(crate) poison: PoisonPolicy,
+    /// Whether waits interrupted by a signal are retried.
+    pub(crate) retry_interrupted: bool,
+    /// How the blocking pool is set up.
+    pub(crate) blocking: PoolConfig,
+    /// The most bytes that operations in flight may pin, if limited.
+    pub(crate) memory_limit: Option<usize>,
+    /// The largest write that is merged with others, if they're merged.
+    pub(crate) coalesce_writes: Option<usize>,
+    /// Whether operations are kept in order around barriers.
+    pub(crate) barriers: bool,
+    /// How long `wait` spins before sleeping, if at all.
+    pub(crate) busy_poll: Option<Duration>,
+    /// The CPU that the kernel's submission polling thread runs on.
+    pub(crate) busy_poll_cpu: Option<u32>,
+    /// The number of fixed buffer slots registered with `io_uring`.
+    pub(crate) fixed_buffers: u16,
+    /// The number of fixed file slots registered with `io_uring`.
+    pub(crate) fixed_files: u32,
+    /// How often `wait` gives back memory, if at all.
+    pub(crate) shrink_interval: Option<Duration>,
+    /// Whether handles skip the completion port when I/O completes
+    /// right away.
+    #[cfg(windows)]
+    pub(crate) skip_completion_on_success: bool,
+    /// Whether completions are picked up by the Win32 thread pool.
+    #[cfg(windows)]
+    pub(crate) thread_pool_io: bool,
+    /// Whether every operation runs on its own thread.
+    #[cfg(feature = "fallback-threads")]
+    pub(crate) fallback_threads: bool,
+    /// The error that setting up `io_uring` fails with, if any.
+    #[cfg(feature = "fault-injection")]
+    pub(crate) uring_failure: Option<io::ErrorKind>,
+    /// The key whose `io_uring` entries seem to be refused, if any.
+    #[cfg(feature = "fault-injection")]
+    pub(crate) refused_key: Option<u64>,
+}
+
+impl CompletionBuilder {
+    /// Create a new `CompletionBuilder` with the specified capacity.
+    pub fn new(capacity: usize) -> Self {
+        CompletionBuilder {
+            capacity,
+            nonblocking: true,
+            nonblocking_stdio: false,
+            hybrid: false,
+            io_uring: true,
+            #[cfg(target_os = "freebsd")]
+            aio: true,
+            edge_triggered: false,
+            track_pending: false,
+            watchdog: None,
+            watchdog_key: None,
+            ordering: OrderingMode::Unordered,
+            poison: PoisonPolicy::Recover,
+            retry_interrupted: true,
+            blocking: PoolConfig::default(),
+            memory_limit: None,
+            coalesce_writes: None,
+            barriers: false,
+            busy_poll: None,
+            busy_poll_cpu: None,
+            fixed_buffers: 0,
+            fixed_files: 0,
+            shrink_interval: None,
+            #[cfg(windows)]
+            skip_completion_on_success: false,
+            #[cfg(windows)]
+            thread_pool_io: false,
+            #[cfg(feature = "fallback-threads")]
+            fallback_threads: false,
+            #[cfg(feature = "fault-injection")]
+            uring_failure: None,
+            #[cfg(feature = "fault-injection")]
+            refused_key: None,
+        }
+    }
+
+    /// Create a new `CompletionBuilder`, tuned by environment variables.
+    ///
+    /// This lets operators change the backend without a rebuild. The
+    /// variables are read once, here, and any that aren't set leave the
+    /// defaults alone:
+    ///
+    /// - `POLLDOUGH_BACKEND`: `auto` to pick the best backend, `polling`
+    ///   to disable `io_uring` and POSIX AIO, `hybrid` for
+    ///   `CompletionBuilder::hybrid` or, with the `fallback-threads`
+    ///   feature, `threads` for `CompletionBuilder::fallback_threads`.
+    /// - `POLLDOUGH_SQPOLL`: the idle time of `CompletionBuilder::busy_poll`
+    ///   in milliseconds, or `0` to not busy poll.
+    /// - `POLLDOUGH_CAPACITY`: the capacity, instead of `capacity`.
+    /// - `POLLDOUGH_NO_URING`: `1`, `true`, `yes` or `on` for
+    ///   `CompletionBuilder::disable_io_uring`, or `0`, `false`, `no`, `off`
+    ///   or nothing to leave `io_uring` alone.
+    /// - `POLLDOUGH_NO_AIO`: the same, for `CompletionBuilder::disable_aio`.
+    ///
+    /// Fails with `InvalidInput` if a variable has a value that isn't
+    /// recognized.
+    pub fn from_env(capacity: usize) -> Result<Self> {
+        let capacity = env_var("POLLDOUGH_CAPACITY")?.unwrap_or(capacity);
+        let mut builder = CompletionBuilder::new(capacity);
+
+        if let Some(backend) = env_var::<String>("POLLDOUGH_BACKEND")? {
+            match backend.as_str() {
+                "auto" => {}
+                "polling" => {
+                    builder.disable_io_uring().disable_aio();
+                }
+                "hybrid" => {
+                    builder.hybrid(true);
+                }
+                #[cfg(feature = "fallback-threads")]
+                "threads" => {
+                    builder.fallback_threads();
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("unknown backend in POLLDOUGH_BACKEND: {:?}", backend),
+                    ))
+                }
+            }
+        }
+
+        if env_flag("POLLDOUGH_NO_URING")? {
+            builder.disable_io_uring();
+        }
+
+        if env_flag("POLLDOUGH_NO_AIO")? {
+            builder.disable_aio();
+        }
+
+        match env_var("POLLDOUGH_SQPOLL")? {
+            None | Some(0) => {}
+            Some(millis) => {
+                builder.busy_poll(Duration::from_millis(millis));
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// Set whether registered sources are put into non-blocking mode.
+    ///
+    /// The polling backend relies on every source being non-blocking;
+    /// a blocking source stalls the entire wait loop. By default,
+    /// `register` sets `O_NONBLOCK` on the source, and `deregister` or
+    /// dropping the `Completion` restores its original flags; see `nonblocking_stdio` for the
+    /// standard I/O. Disable this if you manage the flags yourself.
+    ///
+    /// This has no effect on backends that don't poll for readiness.
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut Self {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// Set whether the standard input, output and error that the process
+    /// inherited are put into non-blocking mode too.
+    ///
+    /// Their flags are shared with every other process that has them
+    /// open, such as the shell, which may not expect `O_NONBLOCK`. By
+    /// default, `register` leaves them alone, and the polling backend
+    /// runs their operations on the blocking pool if they're blocking.
+    /// Enable this if nothing else uses them while they're registered.
+    ///
+    /// This has no effect unless `nonblocking` is enabled.
+    pub fn nonblocking_stdio(&mut self, enabled: bool) -> &mut Self {
+        self.nonblocking_stdio = enabled;
+        self
+    }
+
+    /// Set whether to only use `io_uring` for file operations.
+    ///
+    /// When enabled, file operations are submitted to `io_uring` while
+    /// everything else uses readiness polling, all within the same
+    /// `Completion`. This is useful on kernels where `io_uring` exists
+    /// but is too restricted to be used for sockets. If `io_uring` is
+    /// not available at all, readiness polling is used for everything.
+    ///
+    /// This only has an effect on Linux.
+    pub fn hybrid(&mut self, hybrid: bool) -> &mut Self {
+        self.hybrid = hybrid;
+        self
+    }
+
+    /// Set whether operations that complete right away skip the
+    /// completion port.
+    ///
+    /// By default, an I/O operation that completes during `submit` still
+    /// queues a completion, and is reported as `Submitted`. When enabled,
+    /// `register` sets `FILE_SKIP_COMPLETION_PORT_ON_SUCCESS` on each
+    /// handle, and these operations are reported as `AlreadyComplete`
+    /// instead, saving a trip through the port. Sockets behind non-IFS
+    /// layered service providers may not support this.
+    ///
+    /// This only has an effect on Windows. See also
+    /// `os::windows::CompletionExt::skip_completion_on_success`.
+    pub fn skip_completion_on_success(&mut self, skip: bool) -> &mut Self {
+        cfg_if::cfg_if! {
+            if #[cfg(windows)] {
+                self.skip_completion_on_success = skip;
+            } else {
+                let _ = skip;
+            }
+        }
+
+        self
+    }
+
+    /// Set whether registered handles are bound to the Win32 thread pool.
+    ///
+    /// By default, handles are associated with our own completion port,
+    /// and `wait` wakes up for every batch of completions. When enabled,
+    /// `register` binds them with `CreateThreadpoolIo` instead, and the
+    /// thread pool picks up their completions and hands them to `wait`,
+    /// which is only woken up once for all the completions that arrive
+    /// while it's busy. This suits applications that drive everything
+    /// from callbacks on the thread pool already. Handles must not be
+    /// deregistered while operations on them are in flight.
+    ///
+    /// This only has an effect on Windows.
+    pub fn thread_pool_io(&mut self, enabled: bool) -> &mut Self {
+        cfg_if::cfg_if! {
+            if #[cfg(windows)] {
+                self.thread_pool_io = enabled;
+            } else {
+                let _ = enabled;
+            }
+        }
+
+        self
+    }
+
+    /// Never try to use `io_uring`, and use readiness polling instead.
+    ///
+    /// Many container runtimes block `io_uring` via seccomp, and probing
+    /// for it in such an environment is wasted effort. With
+    /// `CompletionBuilder::from_env`, setting the `POLLDOUGH_NO_URING`
+    /// environment variable to `1` has the same effect.
+    ///
+    /// This only has an effect on Linux.
+    pub fn disable_io_uring(&mut self) -> &mut Self {
+        self.io_uring = false;
+        self
+    }
+
+    /// Never use POSIX AIO, and use the blocking pool for files and
+    /// readiness polling for sockets instead.
+    ///
+    /// By default, reads and writes on files and sockets are handed to
+    /// the kernel with `aio_read` and `aio_write`, and their completions
+    /// are received through kqueue, while everything else is polled for
+    /// readiness. Operations that AIO refuses, because the file system
+    /// doesn't support it or too many requests are in flight, fall back
+    /// to the latter. With `CompletionBuilder::from_env`, setting the
+    /// `POLLDOUGH_NO_AIO` environment variable to `1` has the same effect.
+    ///
+    /// This only has an effect on FreeBSD.
+    pub fn disable_aio(&mut self) -> &mut Self {
+        #[cfg(target_os = "freebsd")]
+        {
+            self.aio = false;
+        }
+        self
+    }
+
+    /// Set whether readiness polling uses edge-triggered notifications.
+    ///
+    /// By default, interest in a source is re-armed every time an
+    /// operation on it blocks, which costs a system call per event. In
+    /// edge-triggered mode, interest is installed once when the source is
+    /// registered, and operations are polled until they block whenever
+    /// the source becomes ready. At most a fixed number of operations per
+    /// source are polled in a single `wait`, so that one busy source can't
+    /// starve the others.
+    ///
+    /// If the OS doesn't support edge-triggered polling, this is ignored.
+    /// This has no effect on backends that don't poll for readiness.
+    pub fn edge_triggered(&mut self, edge_triggered: bool) -> &mut Self {
+        self.edge_triggered = edge_triggered;
+        self
+    }
+
+    /// Set whether to keep track of every operation in flight.
+    ///
+    /// This enables `Completion::pending_snapshot`, which lists the key,
+    /// source and age of every operation in flight. It's useful for
+    /// dashboards and for finding operations that never complete, but it
+    /// adds some overhead to every submission.
+    pub fn track_pending(&mut self, track_pending: bool) -> &mut Self {
+        self.track_pending = track_pending;
+        self
+    }
+
+    /// Flag operations that are in flight for longer than `threshold`.
+    ///
+    /// This helps find operations whose events never arrive. Every
+    /// operation that goes over the threshold is logged once with
+    /// `tracing`, along with its key, type and source, and `wait` wakes up
+    /// to do so if needed. This enables `track_pending`.
+    pub fn watchdog(&mut self, threshold: Duration) -> &mut Self {
+        self.watchdog = Some(threshold);
+        self
+    }
+
+    /// Deliver an event with `key` for every operation that the watchdog
+    /// flags.
+    ///
+    /// Its result is an error of kind `TimedOut` that describes the
+    /// operation. The operation itself stays in flight. This only has an
+    /// effect along with `watchdog`.
+    pub fn watchdog_key(&mut self, key: u64) -> &mut Self {
+        self.watchdog_key = Some(key);
+        self
+    }
+
+    /// Set the order in which events are delivered.
+    ///
+    /// See `OrderingMode` for the guarantees of each mode. Enforcing an
+    /// order adds some overhead to every submission and event.
+    pub fn ordering(&mut self, ordering: OrderingMode) -> &mut Self {
+        self.ordering = ordering;
+        self
+    }
+
+    /// Set what to do when an internal mutex is found poisoned.
+    ///
+    /// By default, the poisoning is logged and the data is used anyway.
+    /// See `PoisonPolicy` for the alternatives.
+    pub fn on_poison(&mut self, policy: PoisonPolicy) -> &mut Self {
+        self.poison = policy;
+        self
+    }
+
+    /// Set whether waits interrupted by a signal are retried.
+    ///
+    /// By default, a wait that a signal interrupts is restarted with
+    /// whatever is left of its timeout. Disable this to have `wait` return
+    /// an `Interrupted` error instead, for example to check a flag set by
+    /// a signal handler.
+    ///
+    /// Waits on Windows can't be interrupted by signals.
+    pub fn retry_interrupted(&mut self, retry: bool) -> &mut Self {
+        self.retry_interrupted = retry;
+        self
+    }
+
+    /// Set the most threads the blocking pool runs at once.
+    ///
+    /// Operations that the OS can't run asynchronously, such as file I/O
+    /// on the polling backend, are finished on a pool of threads. Threads
+    /// are only spawned when there's work for them, up to this many. The
+    /// default is 16.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max_threads` is zero.
+    #[track_caller]
+    pub fn blocking_threads(&mut self, max_threads: usize) -> &mut Self {
+        assert!(max_threads > 0, "the blocking pool needs a thread");
+        self.blocking.max_threads = max_threads;
+        self
+    }
+
+    /// Set how many jobs may wait for a thread of the blocking pool.
+    ///
+    /// Once every thread is busy and this many jobs are waiting, operations
+    /// that need the pool fail instead of being queued. By default, the
+    /// queue is unbounded.
+    pub fn blocking_queue_limit(&mut self, max_queued: usize) -> &mut Self {
+        self.blocking.max_queued = Some(max_queued);
+        self
+    }
+
+    /// Set how long an idle thread of the blocking pool waits for more
+    /// work before exiting.
+    ///
+    /// The default is ten seconds.
+    pub fn blocking_keep_alive(&mut self, keep_alive: Duration) -> &mut Self {
+        self.blocking.keep_alive = keep_alive;
+        self
+    }
+
+    /// Run the jobs of the blocking pool on another executor.
+    ///
+    /// The `Completion` then doesn't spawn any threads of its own, and the
+    /// other settings for the blocking pool are ignored.
+    pub fn blocking_executor(&mut self, executor: impl BlockingExecutor + 'static) -> &mut Self {
+        self.blocking.executor = Some(Arc::new(executor));
+        self
+    }
+
+    /// Limit the memory that operations in flight may pin.
+    ///
+    /// The buffers of an operation can't be reused until it completes, so
+    /// a slow disk or peer can make them pile up. With a limit, the buffer
+    /// sizes of the operations in flight are added up, and a submission
+    /// that would go over the limit fails with `WouldBlock` instead; submit
+    /// it again once some events have been received. An operation is always
+    /// accepted when nothing else is in flight, even if it's larger than
+    /// the limit. A rearmed operation counts from its first submission
+    /// until its last event. See `Op::pinned_bytes` for what is counted.
+    pub fn memory_limit(&mut self, limit: usize) -> &mut Self {
+        self.memory_limit = Some(limit);
+        self
+    }
+
+    /// Merge small writes to the same stream into vectored writes.
+    ///
+    /// Writes of at most `max_len` bytes to a socket or pipe are held back
+    /// until the next `wait`, or until another operation is submitted on
+    /// the same source, and are then written with a single system call. A
+    /// thread already blocked in `wait` is woken up to write them.
+    /// This saves many calls for workloads that send lots of small
+    /// messages, such as logging. Every write still delivers its own
+    /// event, with the number of its bytes that were written; if a merged
+    /// write comes up short, the writes it didn't reach are held back
+    /// again. Exact and high priority writes aren't merged.
+    ///
+    /// A merged write is submitted with the key of its first write, so
+    /// keys of writes shouldn't be shared with other operations in flight.
+    /// Cancelling a write that was already merged cancels the writes it
+    /// was merged with.
+    pub fn coalesce_writes(&mut self, max_len: usize) -> &mut Self {
+        self.coalesce_writes = Some(max_len);
+        self
+    }
+
+    /// Set whether `Barrier`s can be submitted.
+    ///
+    /// This keeps track of the source of every operation in flight, so
+    /// that a barrier knows what it waits for and what waits for it.
+    /// Without it, submitting a barrier fails with `InvalidInput`.
+    pub fn barriers(&mut self, barriers: bool) -> &mut Self {
+        self.barriers = barriers;
+        self
+    }
+
+    /// Spin for up to `spin` in `wait` before going to sleep.
+    ///
+    /// This trades CPU time for latency: `wait` checks for events
+    /// without blocking over and over, and only sleeps once `spin` has
+    /// passed without any. With `io_uring`, the kernel also polls the
+    /// submission queue on a thread of its own (`IORING_SETUP_SQPOLL`),
+    /// which goes to sleep after being idle for `spin`, so submitting
+    /// doesn't need a system call. Before Linux 5.11, that thread only
+    /// takes operations on fixed files, so a plain ring is used instead.
+    pub fn busy_poll(&mut self, spin: Duration) -> &mut Self {
+        self.busy_poll = Some(spin);
+        self
+    }
+
+    /// Pin the kernel's submission polling thread to a CPU.
+    ///
+    /// This only has an effect with `io_uring` and `busy_poll`.
+    pub fn busy_poll_cpu(&mut self, cpu: u32) -> &mut Self {
+        self.busy_poll_cpu = Some(cpu);
+        self
+    }
+
+    /// Set aside `slots` fixed buffers with `io_uring`.
+    ///
+    /// The slots start out empty, and `Completion::register_buffer` and
+    /// `Completion::unregister_buffer` fill them in and empty them one at
+    /// a time, while operations are in flight. Reads and writes use a
+    /// fixed buffer with `Read::fixed_buffer` and `Write::fixed_buffer`,
+    /// which saves the kernel from pinning the memory for every
+    /// operation. Setting the slots aside empty needs Linux 5.13, which
+    /// `Capabilities::sparse_buffers` reports, and this only has an
+    /// effect with `io_uring`; check `Capabilities::fixed_buffers`.
+    pub fn fixed_buffers(&mut self, slots: u16) -> &mut Self {
+        self.fixed_buffers = slots;
+        self
+    }
+
+    /// Set aside `slots` fixed files with `io_uring`.
+    ///
+    /// The slots start out empty. `Completion::register_with_hints` puts a
+    /// file into one, and `Completion::deregister` takes it out again.
+    /// Reads, writes and syncs on a file in a slot then skip looking up
+    /// its descriptor, which adds up for heavy readers. Files that don't
+    /// fit work as usual. A file must be deregistered before it's closed,
+    /// since its slot keeps it open, and see `Completion::register_with_hints`
+    /// for what happens to its descriptor otherwise. This only has an effect
+    /// with `io_uring`; check `Capabilities::fixed_files`.
+    pub fn fixed_files(&mut self, slots: u32) -> &mut Self {
+        self.fixed_files = slots;
+        self
+    }
+
+    /// Give back the memory left over from bursts of load every
+    /// `interval`.
+    ///
+    /// Once `interval` has passed since the last time, `wait` calls
+    /// `Completion::shrink_to_fit` before it returns, so that a
+    /// long-running process goes back to its usual footprint after a
+    /// spike. This is off by default.
+    pub fn shrink_interval(&mut self, interval: Duration) -> &mut Self {
+        self.shrink_interval = Some(interval);
+        self
+    }
+
+    /// Run every operation on its own thread, even where the OS has a
+    /// better way.
+    ///
+    /// This backend is used on platforms that have no way to wait on many
+    /// sources at once. Every operation in flight holds a thread of the
+    /// blocking pool, which waits for the source with `poll()`, so the
+    /// limit on the number of threads doesn't apply. It's slow, but it
+    /// works everywhere; this is meant for testing it.
+    ///
+    /// This only has an effect on Linux, and is only available with the
+    /// `fallback-threads` feature.
+    #[cfg(feature = "fallback-threads")]
+    pub fn fallback_threads(&mut self) -> &mut Self {
+        self.fallback_threads = true;
+        self
+    }
+
+    /// Make setting up `io_uring` fail with an error of this kind.
+    ///
+    /// This behaves as if the kernel rejected `io_uring`, so the fallback
+    /// to readiness polling can be tested on machines that support it.
+    ///
+    /// This is semver-exempt, and only available with the
+    /// `fault-injection` feature.
+    #[cfg(feature = "fault-injection")]
+    pub fn fail_io_uring(&mut self, kind: io::ErrorKind) -> &mut Self {
+        self.uring_failure = Some(kind);
+        self
+    }
+
+    /// Make the kernel seem to refuse the `io_uring` entries of the
+    /// operation with this key.
+    ///
+    /// Submitting fails with `EINVAL` while one of them is at the head of
+    /// the submission queue, the way older kernels refuse entries they
+    /// can't take, so that the error can be seen to reach the operation's
+    /// own event.
+    ///
+    /// This is semver-exempt, and only available with the
+    /// `fault-injection` feature.
+    #[cfg(feature = "fault-injection")]
+    pub fn refuse_io_uring_key(&mut self, key: u64) -> &mut Self {
+        self.refused_key = Some(key);
+        self
+    }
+
+    /// The error to fail setting up `io_uring` with, if one was injected.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn injected_uring_failure(&self) -> Option<io::Error> {
+        #[cfg(feature = "fault-injection")]
+        if let Some(kind) = self.uring_failure {
+            return Some(io::Error::new(kind, "injected io_uring failure"));
+        }
+
+        None
+    }
+
+    /// Create the `Completion`.
+    pub fn build(&self) -> Result<Completion> {
+        self.finish(platform::Completion::new(self)?)
+    }
+
+    /// Create the `Completion` around an `io_uring` instance that was set
+    /// up elsewhere, such as with registered personalities or restricted
+    /// opcodes.
+    ///
+    /// The `Completion` takes over the ring, so the options that set one
+    /// up, such as `busy_poll`, have no effect. Rings set up with
+    /// `IORING_SETUP_IOPOLL` are refused with `InvalidInput`, and rings
+    /// without `IORING_FEAT_EXT_ARG`, from before Linux 5.11, with
+    /// `Unsupported`. The ring has to allow `IORING_OP_READ`, which the
+    /// `Completion` uses to wake itself up, besides the opcodes of the
+    /// operations submitted to it. If the ring can't be probed, the kernel
+    /// is left to reject opcodes that it doesn't support, and
+    /// `Capabilities::uring_cmd` is `false`.
+    ///
+    /// This is semver-exempt, and only available with the
+    /// `unstable-uring` feature.
+    ///
+    /// # Safety
+    ///
+    /// The ring must be fresh: no entries may ever have been pushed into
+    /// its submission queue, even if they were submitted and completed
+    /// since. A ring that was evidently used is refused with
+    /// `InvalidInput`, but that can't always be told. While the
+    /// `Completion` exists, nothing else may submit to the ring or reap
+    /// its completions, such as through a copy of its file descriptor.
+    #[cfg(all(target_os = "linux", feature = "unstable-uring"))]
+    pub unsafe fn build_from_uring(&self, uring: io_uring::IoUring) -> Result<Completion> {
+        self.finish(platform::Completion::from_raw_uring(uring, self)?)
+    }
+
+    /// Create the `Completion` around an I/O completion port that was
+    /// created elsewhere, such as with particular security attributes.
+    ///
+    /// The `Completion` takes ownership of the port, and closes it when
+    /// it's dropped.
+    ///
+    /// # Safety
+    ///
+    /// `port` must be a valid I/O completion port. While the `Completion`
+    /// exists, nothing else may dequeue packets from the port or close it,
+    /// and only the sources registered with the `Completion` may post to
+    /// it.
+    #[cfg(windows)]
+    pub unsafe fn build_from_iocp(
+        &self,
+        port: std::os::windows::io::RawHandle,
+    ) -> Result<Completion> {
+        self.finish(platform::Completion::from_raw_iocp(port as _, self))
+    }
+
+    /// Set up the parts of the `Completion` around its backend.
+    fn finish(&self, inner: platform::Completion) -> Result<Completion> {
+        let mut completion: Completion = inner.into();
+        completion.pending =
+            Pending::new(self.track_pending || self.watchdog.is_some(), self.poison);
+        if let Some(threshold) = self.watchdog {
+            completion
+                .pending
+                .set_watchdog(threshold, self.watchdog_key);
+        }
+        completion.poison = self.poison;
+        completion.sequencer = match self.ordering {
+            OrderingMode::Unordered => None,
+            OrderingMode::SubmissionOrderPerSource => Some(Mutex::new(Sequencer::default())),
+        };
+        completion.memory = self
+            .memory_limit
+            .map(|limit| Mutex::new(Memory::new(limit)));
+        completion.coalescer = self
+            .coalesce_writes
+            .map(|max_len| Mutex::new(Coalescer::new(max_len)));
+        completion.fences = if self.barriers {
+            Some(Mutex::new(Fences::default()))
+        } else {
+            None
+        };
+        completion.busy_poll = self.busy_poll;
+        completion.shrink_interval = self.shrink_interval;
+        completion.stash = Mutex::new(VecDeque::with_capacity(self.capacity));
+        completion.scratch = Mutex::new(vec![Vec::with_capacity(self.capacity)]);
+        Ok(completion)
+    }
+}
+
+/// Read a boolean environment variable, which is off if it isn't set.
+fn env_flag(name: &str) -> Result<bool> {
+    let value = match env_var::<String>(name)? {
+        Some(value) => value,
+        None => return Ok(false),
+    };
+
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "" | "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid value for {}: {:?}", name, value),
+        )),
+    }
+}
+
+/// Read and parse an environment variable, if it's set.
+fn env_var<T: FromStr>(name: &str) -> Result<Option<T>> {
+    let value = match env::var_os(name) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    value
+        .to_str()
+        .and_then(|value| value.trim().parse().ok())
+        .map(Some)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid value for {}: {:?}", name, value),
+            )
+        })
+}
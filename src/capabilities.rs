@@ -0,0 +1,26 @@
+// GNU GPL v3 License
+
+/// What the backend in use supports, beyond the operations that work
+/// everywhere.
+///
+/// This is returned by `Completion::capabilities`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// `UringCmd` operations can be submitted.
+    pub uring_cmd: bool,
+    /// Fixed buffers can be registered, see
+    /// `CompletionBuilder::fixed_buffers`.
+    pub fixed_buffers: bool,
+    /// The kernel can set aside empty fixed buffer slots, which
+    /// `Completion::register_buffer` and `Completion::unregister_buffer`
+    /// fill in and empty one at a time.
+    ///
+    /// This is `IORING_REGISTER_BUFFERS2`, from Linux 5.13, and
+    /// `CompletionBuilder::fixed_buffers` needs it. Without it, this and
+    /// `fixed_buffers` are both `false`.
+    pub sparse_buffers: bool,
+    /// Files can be put into fixed file slots, see
+    /// `CompletionBuilder::fixed_files`.
+    pub fixed_files: bool,
+}
@@ -0,0 +1,259 @@
+// GNU GPL v3 License
+
+use crate::{Event, Raw, SourceType, WriteVectored};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    io::{self, IoSlice, Result},
+    ptr::NonNull,
+};
+
+/// Merges small writes to the same source into vectored writes.
+#[derive(Debug)]
+pub(crate) struct Coalescer {
+    /// The largest write that is merged.
+    max_len: usize,
+    /// Writes waiting to be merged, by source.
+    held: HashMap<Raw, Held>,
+    /// Merged writes in flight, by the key they were submitted with.
+    in_flight: HashMap<u64, VecDeque<Box<Batch>>>,
+    /// Events for writes that were cancelled or completed during
+    /// submission, to be handed out by the next `wait`.
+    ready: Vec<Event>,
+}
+
+/// The writes waiting to be merged on a source.
+#[derive(Debug)]
+struct Held {
+    variant: SourceType,
+    writes: VecDeque<Logical>,
+}
+
+/// A write that is merged with others.
+#[derive(Debug, Clone, Copy)]
+struct Logical {
+    key: u64,
+    buf: IoSlice<'static>,
+}
+
+/// Several writes, merged into one.
+pub(crate) struct Batch {
+    /// The vectored write that they're merged into.
+    pub(crate) op: WriteVectored<Vec<IoSlice<'static>>>,
+    source: Raw,
+    variant: SourceType,
+    writes: Vec<Logical>,
+}
+
+// SAFETY: the buffers are only used by the write, which is only touched by
+// whoever holds the `Coalescer`
+unsafe impl Send for Batch {}
+
+impl Batch {
+    /// The key that the merged write is submitted with.
+    ///
+    /// This is the key of its first write.
+    pub(crate) fn key(&self) -> u64 {
+        self.writes[0].key
+    }
+}
+
+impl fmt::Debug for Batch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Batch")
+            .field("source", &self.source)
+            .field("writes", &self.writes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Coalescer {
+    pub(crate) fn new(max_len: usize) -> Self {
+        Coalescer {
+            max_len,
+            held: HashMap::new(),
+            in_flight: HashMap::new(),
+            ready: Vec::new(),
+        }
+    }
+
+    /// Hold back a write to be merged with the next ones on its source.
+    ///
+    /// Returns `false` if it's too large to be merged.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must stay valid until the write's event is handed out.
+    pub(crate) unsafe fn hold(
+        &mut self,
+        source: Raw,
+        variant: SourceType,
+        key: u64,
+        buf: NonNull<[u8]>,
+    ) -> bool {
+        if buf.len() > self.max_len {
+            return false;
+        }
+
+        let buf = IoSlice::new(&*buf.as_ptr());
+        self.held
+            .entry(source)
+            .or_insert_with(|| Held {
+                variant,
+                writes: VecDeque::new(),
+            })
+            .writes
+            .push_back(Logical { key, buf });
+        true
+    }
+
+    /// Are any writes waiting to be merged?
+    pub(crate) fn has_held(&self) -> bool {
+        !self.held.is_empty()
+    }
+
+    /// Are any events waiting to be handed out?
+    pub(crate) fn has_ready(&self) -> bool {
+        !self.ready.is_empty()
+    }
+
+    /// Merge the writes held back on `source`, or on every source.
+    pub(crate) fn take(&mut self, source: Option<Raw>) -> Vec<Batch> {
+        let held: Vec<_> = match source {
+            Some(source) => self.held.remove_entry(&source).into_iter().collect(),
+            None => self.held.drain().collect(),
+        };
+
+        held.into_iter()
+            .map(|(source, held)| {
+                let writes: Vec<_> = held.writes.into();
+                let bufs = writes.iter().map(|write| write.buf).collect();
+
+                Batch {
+                    op: WriteVectored::from_raw(source, held.variant, bufs),
+                    source,
+                    variant: held.variant,
+                    writes,
+                }
+            })
+            .collect()
+    }
+
+    /// Keep track of a merged write that is about to be submitted.
+    pub(crate) fn submitted(&mut self, batch: Box<Batch>) {
+        self.in_flight
+            .entry(batch.key())
+            .or_default()
+            .push_back(batch);
+    }
+
+    /// Forget a merged write that won't deliver an event, giving it back.
+    pub(crate) fn unsubmitted(&mut self, key: u64, batch: *const Batch) -> Option<Box<Batch>> {
+        let queue = self.in_flight.get_mut(&key)?;
+        let index = queue.iter().position(|b| std::ptr::eq(&**b, batch))?;
+        let batch = queue.remove(index);
+        if queue.is_empty() {
+            self.in_flight.remove(&key);
+        }
+        batch
+    }
+
+    /// Remove a write that is still held back, if any.
+    pub(crate) fn cancel(&mut self, key: u64) -> bool {
+        let mut found = None;
+        for (&source, held) in &mut self.held {
+            if let Some(index) = held.writes.iter().position(|write| write.key == key) {
+                held.writes.remove(index);
+                found = Some((source, held.writes.is_empty()));
+                break;
+            }
+        }
+
+        match found {
+            Some((source, empty)) => {
+                if empty {
+                    self.held.remove(&source);
+                }
+                self.ready
+                    .push(Event::new(key, Err(io::ErrorKind::Interrupted.into())));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Hand out an event for every write of a merged write, given its
+    /// result.
+    ///
+    /// Every write gets its share of the bytes written. Those that weren't
+    /// reached are held back again, ahead of any newer writes.
+    pub(crate) fn completed(&mut self, batch: Box<Batch>, result: Result<usize>) {
+        let mut written = match result {
+            Ok(written) => written,
+            Err(e) => {
+                for write in &batch.writes {
+                    self.ready.push(Event::new(write.key, Err(copy_error(&e))));
+                }
+                return;
+            }
+        };
+
+        let mut rest = VecDeque::new();
+        for (i, write) in batch.writes.iter().enumerate() {
+            if written == 0 && i > 0 && !write.buf.is_empty() {
+                rest.push_back(*write);
+                continue;
+            }
+
+            let done = written.min(write.buf.len());
+            written -= done;
+            self.ready.push(Event::new(write.key, Ok(done)));
+        }
+
+        if !rest.is_empty() {
+            let held = self.held.entry(batch.source).or_insert_with(|| Held {
+                variant: batch.variant,
+                writes: VecDeque::new(),
+            });
+            rest.append(&mut held.writes);
+            held.writes = rest;
+        }
+    }
+
+    /// Replace the events of merged writes in `out[start..]` with events
+    /// for their writes, and hand out the other events that are ready.
+    pub(crate) fn expand(&mut self, out: &mut Vec<Event>, start: usize) {
+        let mut i = start;
+        while i < out.len() && !self.in_flight.is_empty() {
+            let key = out[i].key;
+            let batch = match self.in_flight.get_mut(&key) {
+                Some(queue) => {
+                    let batch = queue.pop_front();
+                    if queue.is_empty() {
+                        self.in_flight.remove(&key);
+                    }
+                    batch
+                }
+                None => None,
+            };
+
+            match batch {
+                Some(batch) => {
+                    let event = out.remove(i);
+                    self.completed(batch, event.result);
+                }
+                None => i += 1,
+            }
+        }
+
+        out.append(&mut self.ready);
+    }
+}
+
+/// Copy an error, for every write that shares it.
+fn copy_error(e: &io::Error) -> io::Error {
+    match e.raw_os_error() {
+        Some(code) => io::Error::from_raw_os_error(code),
+        None => io::Error::new(e.kind(), e.to_string()),
+    }
+}
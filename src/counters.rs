@@ -0,0 +1,53 @@
+// GNU GPL v3 License
+
+#![cfg(feature = "benchmark-internals")]
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Internal counters kept by a `Completion`.
+///
+/// These are used to interpret benchmark results, and are only available
+/// with the `benchmark-internals` feature. They are not semver-stable.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    submissions: AtomicU64,
+    immediate: AtomicU64,
+    waits: AtomicU64,
+    events: AtomicU64,
+}
+
+/// A snapshot of the internal counters of a `Completion`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CounterSnapshot {
+    /// The number of operations submitted.
+    pub submissions: u64,
+    /// The number of operations that completed during submission.
+    pub immediate: u64,
+    /// The number of times `wait` was called.
+    pub waits: u64,
+    /// The number of events returned from `wait`.
+    pub events: u64,
+}
+
+impl Counters {
+    pub(crate) fn submitted(&self, immediate: bool) {
+        self.submissions.fetch_add(1, Ordering::Relaxed);
+        if immediate {
+            self.immediate.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn waited(&self, events: usize) {
+        self.waits.fetch_add(1, Ordering::Relaxed);
+        self.events.fetch_add(events as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> CounterSnapshot {
+        CounterSnapshot {
+            submissions: self.submissions.load(Ordering::Relaxed),
+            immediate: self.immediate.load(Ordering::Relaxed),
+            waits: self.waits.load(Ordering::Relaxed),
+            events: self.events.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+// GNU GPL v3 License
+
+use crate::{ops::Op, Completion, Event, Priority, Raw, SubmissionStatus};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Result},
+};
+
+/// Submits an operation that was held back, given a pointer to it.
+type SubmitFn = unsafe fn(*mut (), &Completion, u64, Priority) -> Result<SubmissionStatus>;
+
+/// Keeps the operations on each source in order around barriers, see
+/// `Barrier`.
+#[derive(Debug, Default)]
+pub(crate) struct Fences {
+    /// The source of every operation that went to the backend, by key.
+    in_flight: HashMap<u64, Raw>,
+    /// The sources with operations in flight or held back.
+    sources: HashMap<Raw, Fence>,
+    /// Operations that can go now, in order.
+    ready: Vec<(u64, Held)>,
+}
+
+/// The operations on a source.
+#[derive(Debug, Default)]
+struct Fence {
+    /// The number of operations that went to the backend and haven't
+    /// completed.
+    in_flight: usize,
+    /// The key of the barrier that went to the backend, if any.
+    barrier: Option<u64>,
+    /// Operations waiting for the barrier before them.
+    held: VecDeque<(u64, Held)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Held {
+    /// The operation, which the user keeps in place until its event.
+    op: *mut (),
+    submit: SubmitFn,
+    priority: Priority,
+    barrier: bool,
+}
+
+// SAFETY: the operation is only touched by `submit`, while the user
+// isn't allowed to use it
+unsafe impl Send for Held {}
+
+impl Held {
+    /// Keep track of an operation.
+    ///
+    /// # Safety
+    ///
+    /// `op` must stay in place and unused until its event.
+    pub(crate) unsafe fn new<O: Op>(op: &mut O, priority: Priority) -> Self {
+        unsafe fn submit<O: Op>(
+            op: *mut (),
+            completion: &Completion,
+            key: u64,
+            priority: Priority,
+        ) -> Result<SubmissionStatus> {
+            completion.submit_now(&mut *op.cast::<O>(), key, priority)
+        }
+
+        Held {
+            op: (op as *mut O).cast(),
+            submit: submit::<O>,
+            priority,
+            barrier: op.is_barrier(),
+        }
+    }
+
+    /// Submit the operation.
+    ///
+    /// # Safety
+    ///
+    /// The operation's event must not have been handed out yet.
+    pub(crate) unsafe fn submit(
+        &self,
+        completion: &Completion,
+        key: u64,
+    ) -> Result<SubmissionStatus> {
+        (self.submit)(self.op, completion, key, self.priority)
+    }
+}
+
+impl Fences {
+    /// Is anything in flight or held back?
+    pub(crate) fn is_empty(&self) -> bool {
+        self.sources.is_empty() && self.ready.is_empty()
+    }
+
+    /// The number of operations held back, which haven't gone to the
+    /// backend yet.
+    pub(crate) fn held(&self) -> usize {
+        let held: usize = self.sources.values().map(|fence| fence.held.len()).sum();
+        held + self.ready.len()
+    }
+
+    /// Give back the memory that isn't needed for the operations in
+    /// flight or held back.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.in_flight.shrink_to_fit();
+        self.sources.shrink_to_fit();
+        self.ready.shrink_to_fit();
+    }
+
+    /// Look at an operation that's about to be submitted.
+    ///
+    /// Returns `true` if it has to wait for a barrier, in which case it's
+    /// held back. Otherwise, it's counted as in flight, and has to be
+    /// passed to `done` if it doesn't end up delivering its event
+    /// through `wait`.
+    pub(crate) fn hold(&mut self, key: u64, source: Raw, held: Held) -> bool {
+        let fence = self.sources.entry(source).or_default();
+
+        let waits = fence.barrier.is_some()
+            || !fence.held.is_empty()
+            || (held.barrier && fence.in_flight > 0);
+        if waits {
+            fence.held.push_back((key, held));
+            return true;
+        }
+
+        fence.in_flight += 1;
+        if held.barrier {
+            fence.barrier = Some(key);
+        }
+        self.in_flight.insert(key, source);
+        false
+    }
+
+    /// Look at events that are being handed out.
+    pub(crate) fn completed(&mut self, events: &[Event]) {
+        for event in events {
+            self.done(event.key);
+        }
+    }
+
+    /// Forget an operation that went to the backend, and let the ones
+    /// that were waiting on it go.
+    pub(crate) fn done(&mut self, key: u64) {
+        let source = match self.in_flight.remove(&key) {
+            Some(source) => source,
+            None => return,
+        };
+
+        if let Some(fence) = self.sources.get_mut(&source) {
+            fence.in_flight -= 1;
+            if fence.barrier == Some(key) {
+                fence.barrier = None;
+            }
+        }
+
+        self.release(source);
+    }
+
+    /// Move the operations on a source that no longer wait to `ready`.
+    fn release(&mut self, source: Raw) {
+        let fence = match self.sources.get_mut(&source) {
+            Some(fence) => fence,
+            None => return,
+        };
+
+        while fence.barrier.is_none() {
+            let barrier = match fence.held.front() {
+                Some((_, held)) => held.barrier,
+                None => break,
+            };
+
+            // a barrier also waits for everything before it
+            if barrier && fence.in_flight > 0 {
+                break;
+            }
+
+            let (key, held) = fence.held.pop_front().unwrap();
+            fence.in_flight += 1;
+            if barrier {
+                fence.barrier = Some(key);
+            }
+            self.in_flight.insert(key, source);
+            self.ready.push((key, held));
+        }
+
+        if fence.in_flight == 0 && fence.held.is_empty() {
+            self.sources.remove(&source);
+        }
+    }
+
+    /// Take the operations that can be submitted now.
+    ///
+    /// They're already counted as in flight.
+    pub(crate) fn take_ready(&mut self) -> Vec<(u64, Held)> {
+        std::mem::take(&mut self.ready)
+    }
+
+    /// Cancel an operation.
+    ///
+    /// If it's held back, it's forgotten, and its event is returned.
+    pub(crate) fn cancel(&mut self, key: u64) -> Option<Event> {
+        if let Some(i) = self.ready.iter().position(|&(ready, _)| ready == key) {
+            self.ready.remove(i);
+            self.done(key);
+            return Some(Event::new(key, Err(io::ErrorKind::Interrupted.into())));
+        }
+
+        let source = self.sources.iter().find_map(|(&source, fence)| {
+            fence
+                .held
+                .iter()
+                .position(|&(held, _)| held == key)
+                .map(|i| (source, i))
+        });
+
+        let (source, i) = source?;
+        self.sources.get_mut(&source)?.held.remove(i);
+
+        // the operations after a cancelled barrier may go
+        self.release(source);
+        Some(Event::new(key, Err(io::ErrorKind::Interrupted.into())))
+    }
+}
@@ -0,0 +1,261 @@
+// GNU GPL v3 License
+
+use crate::{CompletionBuilder, Event, PoisonPolicy, Raw, SubmissionStatus};
+use std::{
+    collections::{hash_map, HashMap},
+    fmt,
+    io::{self, Result},
+    mem,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    ptr::{self, NonNull},
+    sync::Mutex,
+};
+
+/// A read or write that POSIX AIO performs.
+#[doc(hidden)]
+pub struct Request {
+    write: bool,
+    fd: Raw,
+    buf: NonNull<u8>,
+    len: usize,
+    offset: i64,
+}
+
+// SAFETY: the buffer is owned by the operation, which is `Send`
+unsafe impl Send for Request {}
+
+impl Request {
+    /// Read `len` bytes at `offset` into `buf`.
+    pub(crate) fn read(fd: Raw, buf: NonNull<u8>, len: usize, offset: i64) -> Self {
+        Request {
+            write: false,
+            fd,
+            buf,
+            len,
+            offset,
+        }
+    }
+
+    /// Write `len` bytes at `offset` from `buf`.
+    pub(crate) fn write(fd: Raw, buf: NonNull<u8>, len: usize, offset: i64) -> Self {
+        Request {
+            write: true,
+            fd,
+            buf,
+            len,
+            offset,
+        }
+    }
+}
+
+/// An `aiocb`, boxed so that its address stays stable while the kernel
+/// uses it.
+struct ControlBlock(libc::aiocb);
+
+// SAFETY: the buffer behind it is owned by the operation
+unsafe impl Send for ControlBlock {}
+
+/// A completion-oriented I/O interface based on POSIX AIO.
+///
+/// Completions are posted as `EVFILT_AIO` events to a kqueue of our own,
+/// which the poller watches.
+pub(crate) struct Completion {
+    /// The kqueue that completions are posted to.
+    kqueue: OwnedFd,
+    /// The requests in flight.
+    in_flight: Mutex<InFlight>,
+    /// A buffer for holding events.
+    events: Mutex<Vec<libc::kevent>>,
+    /// What to do when one of our mutexes is poisoned.
+    poison: PoisonPolicy,
+}
+
+/// The control blocks of the requests in flight.
+struct InFlight {
+    /// The control blocks, by key.
+    blocks: HashMap<u64, Box<ControlBlock>>,
+    /// The keys, by the address of their control block.
+    ///
+    /// Events carry the address, since a pointer can't hold a 64-bit key
+    /// on 32-bit targets.
+    keys: HashMap<usize, u64>,
+}
+
+impl InFlight {
+    /// Stop tracking the request with the given key.
+    fn remove(&mut self, key: u64) -> Option<Box<ControlBlock>> {
+        let block = self.blocks.remove(&key)?;
+        self.keys.remove(&(&block.0 as *const libc::aiocb as usize));
+        Some(block)
+    }
+}
+
+impl fmt::Debug for Completion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Completion")
+            .field("kqueue", &self.kqueue)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Completion {
+    pub(crate) fn new(builder: &CompletionBuilder) -> Result<Self> {
+        let kqueue = syscall!(kqueue())?;
+        // SAFETY: we just created it
+        let kqueue = unsafe { OwnedFd::from_raw_fd(kqueue) };
+
+        Ok(Completion {
+            kqueue,
+            in_flight: Mutex::new(InFlight {
+                blocks: HashMap::with_capacity(builder.capacity),
+                keys: HashMap::with_capacity(builder.capacity),
+            }),
+            // SAFETY: `kevent` is plain data
+            events: Mutex::new(vec![unsafe { mem::zeroed() }; builder.capacity.max(1)]),
+            poison: builder.poison,
+        })
+    }
+
+    /// Start a request.
+    pub(crate) fn submit(&self, request: Request, key: u64) -> Result<SubmissionStatus> {
+        // SAFETY: `aiocb` is plain data
+        let mut block: libc::aiocb = unsafe { mem::zeroed() };
+        block.aio_fildes = request.fd;
+        block.aio_buf = request.buf.as_ptr().cast();
+        block.aio_nbytes = request.len;
+        block.aio_offset = request.offset;
+        block.aio_sigevent.sigev_notify = libc::SIGEV_KEVENT;
+        // `sigev_notify_kqueue` is an alias for `sigev_signo`
+        block.aio_sigevent.sigev_signo = self.kqueue.as_raw_fd();
+
+        // keep track of it first, its event may arrive at any time
+        let mut in_flight = lock!(self.in_flight, self.poison);
+        let in_flight = &mut *in_flight;
+        let block = match in_flight.blocks.entry(key) {
+            hash_map::Entry::Occupied(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "key is already in flight with AIO",
+                ))
+            }
+            hash_map::Entry::Vacant(entry) => entry.insert(Box::new(ControlBlock(block))),
+        };
+        let block: *mut libc::aiocb = &mut block.0;
+        in_flight.keys.insert(block as usize, key);
+
+        let result = if request.write {
+            syscall!(aio_write(block))
+        } else {
+            syscall!(aio_read(block))
+        };
+
+        match result {
+            Ok(_) => Ok(SubmissionStatus::Submitted),
+            Err(e) => {
+                in_flight.remove(key);
+                Err(e)
+            }
+        }
+    }
+
+    /// Cancel the request with the given key.
+    ///
+    /// Returns whether it was in flight. If so, its event still arrives.
+    pub(crate) fn cancel(&self, key: u64) -> Result<bool> {
+        let mut in_flight = lock!(self.in_flight, self.poison);
+        let block = match in_flight.blocks.get_mut(&key) {
+            Some(block) => block,
+            None => return Ok(false),
+        };
+
+        let fd = block.0.aio_fildes;
+        syscall!(aio_cancel(fd, &mut block.0))?;
+        Ok(true)
+    }
+
+    /// Give back the memory that isn't needed for the requests in flight.
+    pub(crate) fn shrink_to_fit(&self) -> Result<()> {
+        let mut in_flight = lock!(self.in_flight, self.poison);
+        in_flight.blocks.shrink_to_fit();
+        in_flight.keys.shrink_to_fit();
+        Ok(())
+    }
+
+    /// Collect the events of requests that are done, without blocking.
+    pub(crate) fn harvest(&self, out: &mut Vec<Event>) -> Result<usize> {
+        let mut events = lock!(self.events, self.poison);
+        let timeout = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        let n = syscall!(kevent(
+            self.kqueue.as_raw_fd(),
+            ptr::null(),
+            0,
+            events.as_mut_ptr(),
+            events.len() as _,
+            &timeout
+        ))?;
+
+        let mut in_flight = lock!(self.in_flight, self.poison);
+        let mut count = 0;
+        for event in &events[..n as usize] {
+            if event.filter != libc::EVFILT_AIO {
+                continue;
+            }
+
+            // the event identifies the request by its control block
+            let key = match in_flight.keys.get(&event.ident) {
+                Some(&key) => key,
+                None => continue,
+            };
+            let mut block = match in_flight.remove(key) {
+                Some(block) => block,
+                None => continue,
+            };
+
+            let result = match syscall!(aio_return(&mut block.0)) {
+                Ok(n) => Ok(n as usize),
+                Err(e) if e.raw_os_error() == Some(libc::ECANCELED) => {
+                    Err(io::ErrorKind::Interrupted.into())
+                }
+                Err(e) => Err(e),
+            };
+
+            out.push(Event::new(key, result));
+            count += 1;
+        }
+
+        Ok(count)
+    }
+}
+
+impl AsRawFd for Completion {
+    fn as_raw_fd(&self) -> RawFd {
+        self.kqueue.as_raw_fd()
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        // the kernel uses the control blocks until the requests are done
+        let in_flight = match self.in_flight.get_mut() {
+            Ok(in_flight) => in_flight,
+            Err(e) => e.into_inner(),
+        };
+
+        for block in in_flight.blocks.values_mut() {
+            let fd = block.0.aio_fildes;
+            let _ = syscall!(aio_cancel(fd, &mut block.0));
+        }
+
+        for block in in_flight.blocks.values_mut() {
+            let list = [&block.0 as *const libc::aiocb];
+            while matches!(syscall!(aio_error(&block.0)), Ok(libc::EINPROGRESS)) {
+                let _ = syscall!(aio_suspend(list.as_ptr(), 1, ptr::null()));
+            }
+            let _ = syscall!(aio_return(&mut block.0));
+        }
+    }
+}
@@ -0,0 +1,172 @@
+// GNU GPL v3 License
+
+#![cfg(target_os = "freebsd")]
+
+pub(crate) mod aio;
+
+use std::{io::Result, os::unix::io::AsRawFd, time::Duration};
+
+use crate::{
+    ops::Op, polling, Backend, CompletionBuilder, Event, Priority, Source, SourceGroup, SourceType,
+    SubmissionStatus,
+};
+
+#[doc(hidden)]
+pub use crate::polling::OpData;
+
+#[derive(Debug)]
+pub(crate) enum Completion {
+    Polling(polling::Completion),
+    /// Reads and writes on files and sockets go through POSIX AIO,
+    /// everything else is polled.
+    Aio(aio::Completion, Box<polling::Completion>),
+}
+
+macro_rules! defer {
+    ($self: ident . $fnname: ident $($arg: tt)*) => {{
+        match $self {
+            Self::Polling(po) => po.$fnname $($arg)*,
+            Self::Aio(_, po) => po.$fnname $($arg)*,
+        }
+    }}
+}
+
+impl Completion {
+    pub(crate) fn new(builder: &CompletionBuilder) -> Result<Self> {
+        if !builder.aio {
+            tracing::debug!("AIO is disabled, using polling");
+            return polling::Completion::new(builder).map(Completion::Polling);
+        }
+
+        match aio::Completion::new(builder) {
+            Ok(ao) => {
+                // wake up the poller whenever AIO has events
+                let mut po = polling::Completion::new(builder)?;
+                po.watch(ao.as_raw_fd())?;
+                Ok(Completion::Aio(ao, Box::new(po)))
+            }
+            Err(e) => {
+                tracing::error!("Failed to create AIO completion: {:?}", e);
+                polling::Completion::new(builder).map(Completion::Polling)
+            }
+        }
+    }
+
+    pub(crate) fn register(&self, source: &impl Source) -> Result<()> {
+        defer!(self.register(source))
+    }
+
+    pub(crate) fn deregister(&self, source: &impl Source) -> Result<()> {
+        defer!(self.deregister(source))
+    }
+
+    pub(crate) fn register_group(&self, group: &SourceGroup) -> Result<()> {
+        defer!(self.register_group(group))
+    }
+
+    pub(crate) fn deregister_group(&self, group: &SourceGroup) -> Result<()> {
+        defer!(self.deregister_group(group))
+    }
+
+    pub(crate) fn cancel_group(&self, group: &SourceGroup) -> Result<()> {
+        defer!(self.cancel_group(group))
+    }
+
+    pub(crate) fn cancel(&self, key: u64) -> Result<()> {
+        match self {
+            Self::Polling(po) => po.cancel(key),
+            // we don't know which one has it
+            Self::Aio(ao, po) => {
+                if !po.try_cancel(key)? {
+                    ao.cancel(key)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn submit(
+        &self,
+        op: &mut impl Op,
+        key: u64,
+        priority: Priority,
+    ) -> Result<SubmissionStatus> {
+        let (ao, po) = match self {
+            Self::Aio(ao, po) if uses_aio(op.variant()) => (ao, po),
+            _ => return defer!(self.submit(op, key, priority)),
+        };
+
+        let mut op_data = OpData::new();
+        op.run(&mut op_data)?;
+
+        if let Some(request) = op_data.aio.take() {
+            match ao.submit(request, key) {
+                // the file system doesn't support AIO, or the system is
+                // out of AIO requests for now, so poll instead
+                Err(e) if matches!(e.raw_os_error(), Some(libc::EOPNOTSUPP | libc::EAGAIN)) => {}
+                status => return status,
+            }
+        }
+
+        po.submit_data(op_data, op.source(), op.variant(), key, priority)
+    }
+
+    pub(crate) fn wait(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<usize> {
+        match self {
+            Self::Aio(ao, po) => {
+                // if requests are already done, don't block
+                let mut count = ao.harvest(out)?;
+                let timeout = if count > 0 {
+                    Some(Duration::from_secs(0))
+                } else {
+                    timeout
+                };
+
+                count += po.wait(timeout, out)?;
+                count += ao.harvest(out)?;
+                Ok(count)
+            }
+            _ => defer!(self.wait(timeout, out)),
+        }
+    }
+
+    /// The backend that performs operations on this kind of source.
+    pub(crate) fn backend_for(&self, variant: SourceType) -> Backend {
+        match self {
+            Self::Aio(..) if uses_aio(variant) => Backend::Aio,
+            _ => Backend::Polling,
+        }
+    }
+
+    pub(crate) fn notify(&self) -> Result<()> {
+        defer!(self.notify())
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        defer!(self.capacity())
+    }
+
+    pub(crate) fn reserve(&self, additional: usize) -> Result<()> {
+        defer!(self.reserve(additional))
+    }
+
+    pub(crate) fn shrink_to_fit(&self) -> Result<()> {
+        match self {
+            Self::Polling(po) => po.shrink_to_fit(),
+            Self::Aio(aio, po) => {
+                aio.shrink_to_fit()?;
+                po.shrink_to_fit()
+            }
+        }
+    }
+
+    pub(crate) fn notifiers(&self) -> Result<Vec<crate::Raw>> {
+        // the poller watches the AIO kqueue
+        defer!(self.notifiers())
+    }
+}
+
+/// Whether operations on this kind of source try AIO first.
+fn uses_aio(variant: SourceType) -> bool {
+    matches!(variant, SourceType::File | SourceType::Socket)
+}
@@ -0,0 +1,251 @@
+// GNU GPL v3 License
+
+use crate::{
+    ops::{Op, OpBase, OpenAt},
+    Completion, OpData, Raw, SourceType,
+};
+use std::{
+    fs::File,
+    io::{self, Result},
+    path::Path,
+};
+
+/// Options for opening a file, mirroring `std::fs::OpenOptions`.
+///
+/// Instead of opening the file directly, `open` creates an `Open`
+/// operation that opens the file once submitted to a `Completion`. The
+/// resulting `File` is opened in a mode usable with the `Completion`, and
+/// is registered with it.
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+    custom_flags: u32,
+    #[cfg(unix)]
+    mode: u32,
+}
+
+impl OpenOptions {
+    /// Create a blank set of options.
+    pub fn new() -> Self {
+        OpenOptions {
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+            custom_flags: 0,
+            #[cfg(unix)]
+            mode: 0o666,
+        }
+    }
+
+    /// Set the option for read access.
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    /// Set the option for write access.
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Set the option for append mode.
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    /// Set the option for truncating an existing file.
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Set the option to create the file if it doesn't exist.
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Set the option to create the file, failing if it already exists.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Pass custom flags to the underlying open call.
+    ///
+    /// On Unix, these are `O_*` flags. On Windows, these are the
+    /// `FILE_FLAG_*` and `FILE_ATTRIBUTE_*` flags.
+    pub fn custom_flags(&mut self, flags: u32) -> &mut Self {
+        self.custom_flags = flags;
+        self
+    }
+
+    /// Set the permissions of a newly created file.
+    #[cfg(unix)]
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Create an operation that opens the file at `path`, and registers it
+    /// with `completion`.
+    pub fn open<'a>(&self, completion: &'a Completion, path: impl AsRef<Path>) -> Result<Open<'a>> {
+        Ok(Open {
+            inner: self.open_inner(path.as_ref())?,
+            completion,
+        })
+    }
+
+    /// Make sure the options are consistent, like `std` does.
+    fn check(&self) -> Result<()> {
+        let writable = self.write || self.append;
+
+        if !self.read && !writable {
+            return Err(invalid("must open the file for reading or writing"));
+        }
+
+        if (self.truncate || self.create || self.create_new) && !writable {
+            return Err(invalid("creating or truncating requires write access"));
+        }
+
+        if self.truncate && self.append && !self.create_new {
+            return Err(invalid("cannot truncate a file opened for appending"));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn open_inner(&self, path: &Path) -> Result<OpenAt> {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+        self.check()?;
+
+        let access = match (self.read, self.write || self.append) {
+            (true, false) => libc::O_RDONLY,
+            (false, true) => libc::O_WRONLY,
+            _ => libc::O_RDWR,
+        };
+
+        let append = if self.append { libc::O_APPEND } else { 0 };
+
+        let creation = match (self.create, self.truncate, self.create_new) {
+            (_, _, true) => libc::O_CREAT | libc::O_EXCL,
+            (true, true, false) => libc::O_CREAT | libc::O_TRUNC,
+            (true, false, false) => libc::O_CREAT,
+            (false, true, false) => libc::O_TRUNC,
+            (false, false, false) => 0,
+        };
+
+        let flags = access | append | creation | libc::O_CLOEXEC | self.custom_flags as libc::c_int;
+        let path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|_| invalid("path contains a null byte"))?;
+
+        Ok(OpenAt::new(path, flags, self.mode as _))
+    }
+
+    #[cfg(windows)]
+    fn open_inner(&self, path: &Path) -> Result<OpenAt> {
+        use std::os::windows::ffi::OsStrExt;
+        use windows_sys::Win32::{
+            Foundation::{GENERIC_READ, GENERIC_WRITE},
+            Storage::FileSystem::{
+                CREATE_ALWAYS, CREATE_NEW, FILE_APPEND_DATA, FILE_ATTRIBUTE_NORMAL,
+                FILE_FLAG_OVERLAPPED, FILE_GENERIC_WRITE, FILE_WRITE_DATA, OPEN_ALWAYS,
+                OPEN_EXISTING, TRUNCATE_EXISTING,
+            },
+        };
+
+        self.check()?;
+
+        let mut access = 0;
+        if self.read {
+            access |= GENERIC_READ;
+        }
+        if self.write {
+            access |= GENERIC_WRITE;
+        }
+        if self.append && !self.write {
+            access |= (FILE_GENERIC_WRITE & !FILE_WRITE_DATA) | FILE_APPEND_DATA;
+        }
+
+        let disposition = match (self.create, self.truncate, self.create_new) {
+            (_, _, true) => CREATE_NEW,
+            (true, true, false) => CREATE_ALWAYS,
+            (true, false, false) => OPEN_ALWAYS,
+            (false, true, false) => TRUNCATE_EXISTING,
+            (false, false, false) => OPEN_EXISTING,
+        };
+
+        // the file needs to be overlapped to use it with the port
+        let attributes = FILE_ATTRIBUTE_NORMAL | FILE_FLAG_OVERLAPPED | self.custom_flags;
+
+        let mut path: Vec<u16> = path.as_os_str().encode_wide().collect();
+        if path.contains(&0) {
+            return Err(invalid("path contains a null character"));
+        }
+        path.push(0);
+
+        Ok(OpenAt::new(path, access, disposition, attributes))
+    }
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Open a file, and register it with a `Completion`.
+///
+/// This is created by `OpenOptions::open`. The output is the opened
+/// `File`, registered once the operation's output is built. If it can't
+/// be registered, the file is closed and the output is the error; either
+/// way, the operation itself failing is reported by its event.
+pub struct Open<'a> {
+    inner: OpenAt,
+    completion: &'a Completion,
+}
+
+unsafe impl OpBase for Open<'_> {
+    fn run(&mut self, op_data: &mut OpData<'_>) -> Result<()> {
+        self.inner.run(op_data)
+    }
+}
+
+unsafe impl<'a> Op for Open<'a> {
+    type Captured = (<OpenAt as Op>::Captured, &'a Completion);
+    type Output = Result<File>;
+
+    fn source(&self) -> Raw {
+        self.inner.source()
+    }
+
+    fn variant(&self) -> SourceType {
+        self.inner.variant()
+    }
+
+    unsafe fn into_captured(self) -> Self::Captured {
+        (self.inner.into_captured(), self.completion)
+    }
+
+    fn decode(result: usize, (path, completion): Self::Captured) -> Result<File> {
+        let file = OpenAt::decode(result, path);
+        completion.register(&file)?;
+        Ok(file)
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
@@ -0,0 +1,129 @@
+// GNU GPL v3 License
+
+use crate::{Completion, Op, SubmissionStatus};
+use std::{fmt, io::Result, mem, time::Duration};
+
+/// A single operation in flight, owned by the handle.
+///
+/// The operation is boxed so that it stays in place while the OS uses it.
+/// Its event must only be received through the handle, so its key can't
+/// be used by anything else while the handle is alive.
+///
+/// If the handle is dropped while the operation is still in flight, the
+/// operation is leaked, since the OS may still be using its buffers. Use
+/// `recover` to get them back instead.
+pub struct OpHandle<'a, O: Op> {
+    /// The completion the operation was submitted to.
+    completion: &'a Completion,
+    /// The key of the operation.
+    key: u64,
+    /// The operation itself.
+    op: Option<Box<O>>,
+    /// The result of the operation, once its event was received.
+    result: Option<Result<usize>>,
+}
+
+impl<O: Op> fmt::Debug for OpHandle<'_, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpHandle")
+            .field("key", &self.key)
+            .field("result", &self.result)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, O: Op> OpHandle<'a, O> {
+    /// Submit an operation with the given key.
+    ///
+    /// # Safety
+    ///
+    /// No other operation in flight on `completion` may use `key` while the
+    /// handle is alive, and its event must not be received by anything but
+    /// the handle. Otherwise the handle may take another operation's event
+    /// and free its buffers while the OS is still using them.
+    pub unsafe fn submit(completion: &'a Completion, op: O, key: u64) -> Result<Self> {
+        let mut op = Box::new(op);
+
+        // SAFETY: we own the operation, so it can't be submitted twice, and
+        // it's boxed so it won't move while in flight
+        let result = match unsafe { completion.submit(&mut *op, key)? } {
+            SubmissionStatus::AlreadyComplete(result) => Some(result),
+            SubmissionStatus::Submitted => None,
+        };
+
+        Ok(OpHandle {
+            completion,
+            key,
+            op: Some(op),
+            result,
+        })
+    }
+
+    /// The key of the operation.
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    /// Tell whether the operation's event was received.
+    pub fn is_complete(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// Wait for the operation to complete.
+    ///
+    /// Returns an error of kind `TimedOut` if the timeout expires first, in
+    /// which case the operation is still in flight; wait again, or call
+    /// `recover` to give up on it.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<()> {
+        if self.result.is_none() {
+            let event = self.completion.wait_for_key(self.key, timeout)?;
+            self.result = Some(event.result);
+        }
+
+        Ok(())
+    }
+
+    /// Ask the OS to cancel the operation.
+    ///
+    /// The operation still has to complete; see `Completion::cancel`.
+    pub fn cancel(&self) -> Result<()> {
+        if self.result.is_some() {
+            return Ok(());
+        }
+
+        self.completion.cancel(self.key)
+    }
+
+    /// Wait for the operation to complete, and get its output.
+    pub fn output(mut self) -> Result<O::Output> {
+        self.wait(None)?;
+        let result = self.result.take().unwrap();
+        let op = self.op.take().unwrap();
+
+        // SAFETY: the operation is complete
+        result.map(|result| unsafe { op.complete(result) })
+    }
+
+    /// Cancel the operation if it's still in flight, and get its captured
+    /// variables back once it completes.
+    ///
+    /// The result of the operation is discarded, even if it managed to
+    /// complete. If waiting fails, the operation is leaked.
+    pub fn recover(mut self) -> Result<O::Captured> {
+        self.cancel()?;
+        self.wait(None)?;
+        let op = self.op.take().unwrap();
+
+        // SAFETY: the operation is complete
+        Ok(unsafe { op.into_captured() })
+    }
+}
+
+impl<O: Op> Drop for OpHandle<'_, O> {
+    fn drop(&mut self) {
+        // the OS may still write into it
+        if self.result.is_none() {
+            mem::forget(self.op.take());
+        }
+    }
+}
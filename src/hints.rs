@@ -0,0 +1,67 @@
+// GNU GPL v3 License
+
+use crate::Raw;
+
+/// Hints about how a file is going to be accessed.
+///
+/// These are passed to `Completion::register_with_hints`, and let the OS
+/// tune its readahead for the file. They're only advice; the OS is free to
+/// ignore them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Hints {
+    /// The file is going to be read from start to end.
+    pub sequential: bool,
+    /// The file is going to be read in no particular order.
+    ///
+    /// This disables readahead, and is ignored if `sequential` is set.
+    pub random: bool,
+    /// The number of bytes from the start of the file that are going to
+    /// be read soon.
+    ///
+    /// The OS may start reading them into its cache right away.
+    pub size_hint: Option<u64>,
+}
+
+impl Hints {
+    /// Pass the hints on to the OS.
+    ///
+    /// This fails if the OS refuses them, for example because the file is
+    /// really a pipe.
+    pub(crate) fn apply(&self, raw: Raw) -> std::io::Result<()> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "dragonfly",
+                target_os = "illumos",
+            ))] {
+                let advise = |offset: u64, len: u64, advice| {
+                    // this returns the error instead of setting errno
+                    let err = unsafe {
+                        libc::posix_fadvise(raw, offset as _, len as _, advice)
+                    };
+                    match err {
+                        0 => Ok(()),
+                        err => Err(std::io::Error::from_raw_os_error(err)),
+                    }
+                };
+
+                if self.sequential {
+                    advise(0, 0, libc::POSIX_FADV_SEQUENTIAL)?;
+                } else if self.random {
+                    advise(0, 0, libc::POSIX_FADV_RANDOM)?;
+                }
+
+                if let Some(size) = self.size_hint {
+                    advise(0, size, libc::POSIX_FADV_WILLNEED)?;
+                }
+                Ok(())
+            } else {
+                // the OS has no way to take hints for an open file
+                let _ = raw;
+                Ok(())
+            }
+        }
+    }
+}
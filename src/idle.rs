@@ -0,0 +1,125 @@
+// GNU GPL v3 License
+
+use crate::{Event, Raw};
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+    time::{Duration, Instant},
+};
+
+/// Keeps track of idle deadlines for sources.
+#[derive(Debug, Default)]
+pub(crate) struct IdleTimers {
+    /// The timer for each source.
+    timers: HashMap<Raw, Timer>,
+    /// The deadlines, ordered so the next one is first.
+    deadlines: BTreeSet<(Instant, Raw)>,
+    /// Sources of in-flight operations on sources with timers, by key.
+    in_flight: HashMap<u64, Raw>,
+}
+
+#[derive(Debug)]
+struct Timer {
+    /// How long the source may be idle.
+    timeout: Duration,
+    /// When the source is considered idle.
+    deadline: Instant,
+    /// The key of the event delivered when the source is idle.
+    key: u64,
+}
+
+impl IdleTimers {
+    /// Is there any timer at all?
+    pub(crate) fn is_empty(&self) -> bool {
+        self.timers.is_empty()
+    }
+
+    /// Start or replace the timer for a source.
+    pub(crate) fn set(&mut self, source: Raw, timeout: Duration, key: u64) {
+        self.clear(source);
+
+        let deadline = Instant::now() + timeout;
+        self.deadlines.insert((deadline, source));
+        self.timers.insert(
+            source,
+            Timer {
+                timeout,
+                deadline,
+                key,
+            },
+        );
+    }
+
+    /// Stop the timer for a source.
+    pub(crate) fn clear(&mut self, source: Raw) {
+        if let Some(timer) = self.timers.remove(&source) {
+            self.deadlines.remove(&(timer.deadline, source));
+            self.in_flight.retain(|_, raw| *raw != source);
+        }
+    }
+
+    /// Note that an operation is being submitted on a source.
+    pub(crate) fn submitted(&mut self, source: Raw, key: u64) {
+        if self.timers.contains_key(&source) {
+            self.in_flight.insert(key, source);
+        }
+    }
+
+    /// Take back `submitted` for an operation that never went in flight.
+    ///
+    /// If it completed right away, that counts as activity on its source.
+    pub(crate) fn cancelled(&mut self, key: u64, complete: bool) {
+        if let Some(source) = self.in_flight.remove(&key) {
+            if complete {
+                self.reset(source);
+            }
+        }
+    }
+
+    /// Reset the timers for the sources of these events.
+    pub(crate) fn completed(&mut self, events: &[Event]) {
+        if self.in_flight.is_empty() {
+            return;
+        }
+
+        for event in events {
+            if let Some(source) = self.in_flight.remove(&event.key) {
+                self.reset(source);
+            }
+        }
+    }
+
+    /// The next time a timer expires.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.deadlines.iter().next().map(|&(deadline, _)| deadline)
+    }
+
+    /// Deliver events for expired timers, returning how many there were.
+    ///
+    /// Expired timers are removed.
+    pub(crate) fn expire(&mut self, now: Instant, out: &mut Vec<Event>) -> usize {
+        let mut count = 0;
+
+        while let Some(&(deadline, source)) = self.deadlines.iter().next() {
+            if deadline > now {
+                break;
+            }
+
+            let timer = self.timers.get(&source).unwrap();
+            out.push(Event::new(timer.key, Err(io::ErrorKind::TimedOut.into())));
+            count += 1;
+            self.clear(source);
+        }
+
+        count
+    }
+
+    /// Push back the deadline for a source.
+    fn reset(&mut self, source: Raw) {
+        if let Some(timer) = self.timers.get_mut(&source) {
+            self.deadlines.remove(&(timer.deadline, source));
+            timer.deadline = Instant::now() + timer.timeout;
+            self.deadlines.insert((timer.deadline, source));
+        }
+    }
+}
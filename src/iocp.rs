@@ -2,37 +2,109 @@
 
 #![cfg(windows)]
 
-use crate::{ops::Op, Event, Source, SubmissionStatus};
+use crate::{
+    afd::{self, Afd, AfdPollInfo},
+    ops::Op,
+    pool::BlockingPool,
+    threadpool::{ThreadPool, THREAD_POOL_KEY},
+    CompletionBuilder, Event, PoisonPolicy, PollingFn, Priority, Source, SourceGroup, SourceType,
+    SubmissionStatus,
+};
 use slab::Slab;
 use std::{
-    cell::UnsafeCell,
-    collections::HashMap,
+    cell::{Cell, UnsafeCell},
+    collections::{HashMap, HashSet},
     fmt,
     io::{self, Result},
     marker::PhantomData,
     mem::{zeroed, MaybeUninit},
     ptr::{self, null_mut},
-    sync::{atomic::AtomicBool, Arc, Mutex, MutexGuard, Weak},
+    sync::{atomic::AtomicBool, Arc, Mutex, MutexGuard, OnceLock, Weak},
     time::Duration,
 };
 use windows_sys::Win32::{
-    Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE},
-    System::IO::{
-        CreateIoCompletionPort, PostQueuedCompletionStatus, OVERLAPPED, OVERLAPPED_ENTRY,
+    Foundation::{
+        CloseHandle, RtlNtStatusToDosError, HANDLE, INVALID_HANDLE_VALUE, NTSTATUS,
+        STATUS_CONNECTION_RESET, STATUS_END_OF_FILE, STATUS_LOCAL_DISCONNECT,
+        STATUS_REMOTE_DISCONNECT,
+    },
+    Networking::WinSock::WSAECONNRESET,
+    Storage::FileSystem::SetFileCompletionNotificationModes,
+    System::{
+        WindowsProgramming::{FILE_SKIP_COMPLETION_PORT_ON_SUCCESS, FILE_SKIP_SET_EVENT_ON_HANDLE},
+        IO::{
+            CancelIoEx, CreateIoCompletionPort, PostQueuedCompletionStatus, OVERLAPPED,
+            OVERLAPPED_ENTRY,
+        },
     },
 };
 
+/// The completion key that `notify` wakes up the port with.
 const NOTIFY_KEY: u64 = u64::MAX;
 
+/// Placed in `OVERLAPPED::Internal` by `complete_on_thread` to indicate
+/// that the operation failed.
+///
+/// In this case, `InternalHigh` holds the error code.
+const THREAD_ERROR: usize = usize::MAX;
+
 /// This `PollData` exposes an `OVERLAPPED` structure, which is used to
 /// coordinate I/O operations with the OS.
 #[doc(hidden)]
 pub struct OpData<'a> {
     pub(crate) overlapped: *mut OVERLAPPED,
+    pub(crate) port: HANDLE,
     pub(crate) immediate_result: Option<Result<usize>>,
+    afd: &'a OnceLock<Afd>,
+    /// Did the operation go through the AFD driver, rather than the
+    /// handle itself?
+    used_afd: Cell<bool>,
+    pool: &'a BlockingPool,
     _marker: PhantomData<&'a ()>,
 }
 
+impl OpData<'_> {
+    /// Get the handle to the AFD driver, opening it if needed.
+    pub(crate) fn afd(&self) -> Result<&Afd> {
+        self.used_afd.set(true);
+        if let Some(afd) = self.afd.get() {
+            return Ok(afd);
+        }
+
+        let afd = Afd::new(self.port)?;
+        Ok(self.afd.get_or_init(|| afd))
+    }
+
+    /// Call `poll` every time `socket` is ready for some of `events`, until
+    /// it stops returning `WouldBlock`, and complete with its result.
+    ///
+    /// The socket is waited on with the AFD driver, so it doesn't need to
+    /// be overlapped.
+    pub(crate) fn poll_ready(
+        &mut self,
+        socket: crate::Raw,
+        events: u32,
+        poll: PollingFn,
+    ) -> Result<Option<usize>> {
+        let socket = afd::base_socket(socket as _)?;
+        let afd = self.afd()?;
+
+        // SAFETY: every OVERLAPPED we hand out is the start of an OpEntry
+        let entry = unsafe { &mut *self.overlapped.cast::<OpEntry>() };
+        let repoll = entry.repoll.insert(Repoll {
+            poll,
+            info: Box::new(AfdPollInfo::new(socket, events)),
+            socket,
+            events,
+        });
+
+        // SAFETY: the request and the OVERLAPPED live in the entry until
+        // the operation completes
+        unsafe { afd.poll(&mut *repoll.info, self.overlapped)? };
+        Ok(None)
+    }
+}
+
 pub(crate) struct Completion {
     /// The handle to the IOCP port.
     iocp_port: HANDLE,
@@ -41,6 +113,8 @@ pub(crate) struct Completion {
     /// Holding this mutex implies the exclusive right to poll for
     /// completion events.
     result_buffer: Mutex<Box<[MaybeUninit<OVERLAPPED_ENTRY>]>>,
+    /// The length of `result_buffer`.
+    capacity: usize,
     /// A buffer for holding active operations.
     ///
     /// This is implied to be stable. Empty slots are owned by the
@@ -48,9 +122,9 @@ pub(crate) struct Completion {
     /// by the in-progress operation. Changing a slot from empty to
     /// full or vice versa requires locking the mutex.
     ///
-    /// The capacity should never change, since `OpEntry` does not
-    /// have a stable deref.
-    active_ops: UnsafeCell<Slab<OpEntry>>,
+    /// The capacity of each chunk should never change, since `OpEntry`
+    /// does not have a stable deref.
+    active_ops: UnsafeCell<ActiveOps>,
     /// An exclusion lock for changes to the `active_ops` slab.
     ///
     /// This doesn't protect `active_ops` directly because calling
@@ -61,6 +135,20 @@ pub(crate) struct Completion {
     notification: UnsafeCell<OpEntry>,
     /// Is the completion object notified?
     notified: AtomicBool,
+    /// The AFD driver, used to poll sockets for readiness.
+    ///
+    /// This is only opened once it's needed.
+    afd: OnceLock<Afd>,
+    /// Threads used to run operations on handles that can't be overlapped.
+    pool: BlockingPool,
+    /// What to do when one of our mutexes is poisoned.
+    poison: PoisonPolicy,
+    /// Whether `register` makes handles skip the port on success.
+    skip_on_register: bool,
+    /// The handles that skip the port when I/O completes right away.
+    skipping: Mutex<HashSet<crate::Raw>>,
+    /// Picks up completions on the Win32 thread pool, if enabled.
+    thread_pool: Option<ThreadPool>,
 }
 
 unsafe impl Send for Completion {}
@@ -70,7 +158,7 @@ impl fmt::Debug for Completion {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         struct ActiveOpsLen<'a> {
             not_mutating: Option<MutexGuard<'a, ()>>,
-            ops: *mut Slab<OpEntry>,
+            ops: *mut ActiveOps,
         }
 
         impl fmt::Debug for ActiveOpsLen<'_> {
@@ -97,6 +185,83 @@ impl fmt::Debug for Completion {
     }
 }
 
+/// The list of active operations.
+///
+/// This is a list of slabs that never reallocate, so that the address of
+/// an `OpEntry` stays the same for as long as it's in the list. When every
+/// slab is full, another one is added.
+struct ActiveOps {
+    /// The slabs containing the entries.
+    chunks: Vec<Slab<OpEntry>>,
+    /// The capacity of each slab.
+    chunk_size: usize,
+}
+
+impl ActiveOps {
+    fn new(chunk_size: usize) -> Self {
+        let chunk_size = chunk_size.max(1);
+        ActiveOps {
+            chunks: vec![Slab::with_capacity(chunk_size)],
+            chunk_size,
+        }
+    }
+
+    /// The number of active operations.
+    fn len(&self) -> usize {
+        self.chunks.iter().map(Slab::len).sum()
+    }
+
+    /// Insert a new entry, returning its index and a reference to it.
+    fn insert(&mut self, entry: OpEntry) -> (usize, &mut OpEntry) {
+        let chunk_size = self.chunk_size;
+        let chunk_index = match self.chunks.iter().position(|c| c.len() < chunk_size) {
+            Some(i) => i,
+            None => {
+                tracing::debug!("Growing active operation list");
+                self.chunks.push(Slab::with_capacity(chunk_size));
+                self.chunks.len() - 1
+            }
+        };
+
+        let chunk = &mut self.chunks[chunk_index];
+        let slot = chunk.insert(entry);
+        let index = chunk_index * chunk_size + slot;
+        let entry = chunk.get_mut(slot).unwrap();
+        entry.index = index;
+        (index, entry)
+    }
+
+    /// Iterate over the entries.
+    fn iter(&self) -> impl Iterator<Item = &OpEntry> + '_ {
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.iter().map(|(_, entry)| entry))
+    }
+
+    /// Get the entry at the given index.
+    fn get_mut(&mut self, index: usize) -> Option<&mut OpEntry> {
+        let chunk = self.chunks.get_mut(index / self.chunk_size)?;
+        chunk.get_mut(index % self.chunk_size)
+    }
+
+    /// Remove the entry at the given index.
+    fn remove(&mut self, index: usize) -> Option<OpEntry> {
+        let chunk = self.chunks.get_mut(index / self.chunk_size)?;
+        chunk.try_remove(index % self.chunk_size)
+    }
+
+    /// Free the empty slabs at the end, keeping at least one.
+    ///
+    /// Slabs in the middle stay, since the indices after them would move.
+    fn shrink(&mut self) {
+        while self.chunks.len() > 1 && matches!(self.chunks.last(), Some(chunk) if chunk.is_empty())
+        {
+            self.chunks.pop();
+        }
+        self.chunks.shrink_to_fit();
+    }
+}
+
 /// An entry in the active ops list used to keep track of the
 /// state of an operation.
 #[repr(C)]
@@ -109,24 +274,95 @@ struct OpEntry {
     overlapped: OVERLAPPED,
     /// The event ID for this operation.
     key: u64,
-    /// The index of the operation in the `active_ops` slab.
+    /// The handle the operation runs on, for cancelling it.
+    source: crate::Raw,
+    /// The index of the operation in the `active_ops` list.
     index: usize,
     /// The type of the source.
     ///
     /// This determines what we determine is the error code.
     source_type: SourceType,
+    /// Was this operation run by `complete_on_thread`?
+    ///
+    /// If so, `THREAD_ERROR` in `Internal` indicates an error.
+    completed_on_thread: bool,
+    /// The function polled whenever the socket is ready, if the operation
+    /// went through `OpData::poll_ready`.
+    repoll: Option<Repoll>,
+}
+
+/// A function that is polled every time the AFD driver reports that its
+/// socket is ready.
+struct Repoll {
+    poll: PollingFn,
+    /// The poll request, which stays in place while it's in flight.
+    info: Box<AfdPollInfo>,
+    /// The base socket, and the events that are waited for.
+    socket: HANDLE,
+    events: u32,
+}
+
+impl OpEntry {
+    /// Call the polling function of an operation whose socket is ready.
+    ///
+    /// Returns `None` if it would block, once the socket is waited on
+    /// again.
+    fn repoll(&mut self, afd: &OnceLock<Afd>) -> Option<Result<usize>> {
+        let repoll = self.repoll.as_mut()?;
+        match overlapped_result(&self.overlapped).and_then(|_| repoll.poll.call()) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            result => return Some(result),
+        }
+
+        let afd = afd.get().expect("the AFD driver started the first request");
+        *repoll.info = AfdPollInfo::new(repoll.socket, repoll.events);
+        self.overlapped = unsafe { zeroed() };
+
+        // SAFETY: the request and the OVERLAPPED live in the entry until
+        // the operation completes
+        match unsafe { afd.poll(&mut *repoll.info, &mut self.overlapped) } {
+            Ok(()) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Cancel the operation, if it's still running.
+    fn cancel(&self, afd: &OnceLock<Afd>) {
+        // the request is on the driver, not on the socket
+        if let (Some(_), Some(afd)) = (&self.repoll, afd.get()) {
+            afd.cancel(&self.overlapped);
+            return;
+        }
+
+        // fails if the operation already completed, which is fine
+        unsafe {
+            CancelIoEx(self.source as _, &self.overlapped);
+        }
+    }
 }
 
 impl Completion {
     /// Create a new completion object.
-    pub(crate) fn new(capacity: usize) -> Result<Self> {
+    pub(crate) fn new(builder: &CompletionBuilder) -> Result<Self> {
         let iocp_port = unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, 0, 0, 1) };
 
         if iocp_port == INVALID_HANDLE_VALUE {
             return Err(io::Error::last_os_error());
         }
 
-        Ok(Completion {
+        // SAFETY: the port is new, and only ours
+        Ok(unsafe { Self::from_raw_iocp(iocp_port, builder) })
+    }
+
+    /// Wrap a completion port that was created elsewhere.
+    ///
+    /// # Safety
+    ///
+    /// See `CompletionBuilder::build_from_iocp`.
+    pub(crate) unsafe fn from_raw_iocp(iocp_port: HANDLE, builder: &CompletionBuilder) -> Self {
+        let capacity = builder.capacity;
+
+        Completion {
             iocp_port,
             source_type: HashMap::new(),
             result_buffer: Mutex::new({
@@ -134,83 +370,233 @@ impl Completion {
                 buffer.resize(capacity, MaybeUninit::zeroed());
                 buffer
             }),
-            active_ops: UnsafeCell::new(Slab::with_capacity(capacity)),
+            capacity,
+            active_ops: UnsafeCell::new(ActiveOps::new(capacity)),
             mutation_lock: Mutex::new(()),
             notification: UnsafeCell::new(OpEntry {
                 overlapped: unsafe { zeroed() },
                 key: NOTIFY_KEY,
+                // posted to the port directly, so there's no handle to cancel
+                source: null_mut(),
                 index: usize::MAX,
                 source_type: SourceType::File,
+                completed_on_thread: false,
+                repoll: None,
             }),
             notified: AtomicBool::new(false),
-        })
+            afd: OnceLock::new(),
+            pool: BlockingPool::new(&builder.blocking, builder.poison),
+            poison: builder.poison,
+            skip_on_register: builder.skip_completion_on_success,
+            skipping: Mutex::new(HashSet::new()),
+            thread_pool: if builder.thread_pool_io {
+                Some(ThreadPool::new(iocp_port, builder.poison))
+            } else {
+                None
+            },
+        }
     }
 
-    pub(crate) fn register(&self, source: &impl Source) -> Result<()> {
-        // register using the CreateIoCompletionPort function
-        let result = unsafe { CreateIoCompletionPort(source.as_raw() as _, self.iocp_port, 0, 0) };
+    pub(crate) fn register<S: Source>(&self, source: &S) -> Result<()> {
+        self.register_raw(source.as_raw(), S::SOURCE_TYPE)
+    }
 
-        if result == INVALID_HANDLE_VALUE {
-            return Err(io::Error::last_os_error());
+    pub(crate) fn deregister(&self, source: &impl Source) -> Result<()> {
+        // the handle stays associated with the port, but its value may be
+        // reused once it's closed
+        lock!(self.skipping, self.poison).remove(&source.as_raw());
+        match &self.thread_pool {
+            Some(pool) => pool.unbind(source.as_raw()),
+            None => Ok(()),
         }
+    }
+
+    pub(crate) fn register_group(&self, group: &SourceGroup) -> Result<()> {
+        // handles can't be disassociated from a port, so there is
+        // nothing to roll back on failure
+        group
+            .sources
+            .iter()
+            .try_for_each(|&(raw, source_type)| self.register_raw(raw, source_type))
+    }
 
+    pub(crate) fn deregister_group(&self, _group: &SourceGroup) -> Result<()> {
         Ok(())
     }
 
-    pub(crate) fn deregister(&self, _source: &impl Source) -> Result<()> {
-        // entirely likely that this doesn't matter
+    pub(crate) fn cancel_group(&self, group: &SourceGroup) -> Result<()> {
+        for &(raw, _) in &group.sources {
+            // fails if there was nothing to cancel, which is fine
+            unsafe {
+                CancelIoEx(raw as _, null_mut());
+            }
+        }
+
+        // readiness is polled through the driver, so those are cancelled
+        // one by one
+        if let Some(afd) = self.afd.get() {
+            let _guard = lock!(self.mutation_lock, self.poison);
+            let active_ops = unsafe { &*self.active_ops.get() };
+            let polled = active_ops.iter().filter(|entry| entry.repoll.is_some());
+            for entry in polled {
+                if group.sources.iter().any(|&(raw, _)| raw == entry.source) {
+                    afd.cancel(&entry.overlapped);
+                }
+            }
+        }
+
         Ok(())
     }
 
-    pub(crate) fn submit(&self, op: &mut impl Op, key: u64) -> Result<SubmissionStatus> {
+    /// Cancel the operation with `key`.
+    ///
+    /// It completes with `ERROR_OPERATION_ABORTED`, or as usual if it was
+    /// too late. Operations run on a separate thread can't be cancelled.
+    pub(crate) fn cancel(&self, key: u64) -> Result<()> {
+        let _guard = lock!(self.mutation_lock, self.poison);
+        let active_ops = unsafe { &*self.active_ops.get() };
+
+        for entry in active_ops.iter() {
+            if entry.key == key && !entry.completed_on_thread {
+                entry.cancel(&self.afd);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn register_raw(&self, raw: crate::Raw, source_type: SourceType) -> Result<()> {
+        // console handles can't be associated with a completion port,
+        // operations on them are run on a separate thread instead
+        if source_type == SourceType::Tty {
+            return Ok(());
+        }
+
+        match &self.thread_pool {
+            Some(pool) => pool.bind(raw)?,
+            None => {
+                // register using the CreateIoCompletionPort function
+                let result = unsafe { CreateIoCompletionPort(raw as _, self.iocp_port, 0, 0) };
+
+                if result == INVALID_HANDLE_VALUE {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+
+        if self.skip_on_register {
+            self.skip_completion_on_success(raw)
+        } else {
+            // a closed handle with the same value may have skipped
+            lock!(self.skipping, self.poison).remove(&raw);
+            Ok(())
+        }
+    }
+
+    /// Stop operations on `raw` that complete right away from also
+    /// queueing a completion.
+    pub(crate) fn skip_completion_on_success(&self, raw: crate::Raw) -> Result<()> {
+        let flags = (FILE_SKIP_COMPLETION_PORT_ON_SUCCESS | FILE_SKIP_SET_EVENT_ON_HANDLE) as u8;
+        if unsafe { SetFileCompletionNotificationModes(raw as _, flags) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        lock!(self.skipping, self.poison).insert(raw);
+        Ok(())
+    }
+
+    pub(crate) fn submit(
+        &self,
+        op: &mut impl Op,
+        key: u64,
+        _priority: Priority,
+    ) -> Result<SubmissionStatus> {
+        // the port hands out completions as they come, so only the order
+        // of events within a `wait` can be changed
+
         // acquire the lock to add a new entry
-        let mut _guard = lock!(self.mutation_lock);
+        let mut _guard = lock!(self.mutation_lock, self.poison);
         let mut active_ops = unsafe { &mut *self.active_ops.get() };
 
-        // see if we are able to add a new entry
-        if active_ops.len() == active_ops.capacity() {
-            return Err(io::Error::new(
-                io::ErrorKind::OutOfMemory,
-                "too many active operations",
-            ));
-        }
+        let source = op.source();
+        let skips = lock!(self.skipping, self.poison).contains(&source);
 
-        // add a new entry to the slab
-        let entry = OpEntry {
+        // the thread pool expects a completion for the handle from here on
+        let tp_io = match &self.thread_pool {
+            Some(pool) => pool.start(source)?,
+            None => None,
+        };
+
+        // add a new entry to the active ops, growing if necessary
+        let (index, entry) = active_ops.insert(OpEntry {
             overlapped: unsafe { zeroed() },
             key,
-            index: active_ops.vacant_entry(),
+            source,
+            index: usize::MAX,
             source_type: op.variant(),
-        };
-        let index = active_ops.insert(entry);
-        let mut entry = active_ops.get_mut(index).unwrap();
+            completed_on_thread: false,
+            repoll: None,
+        });
 
         // submit the operation
         // from this point on, the operation owns the entry
         let mut op_data = OpData {
             overlapped: &mut entry.overlapped,
+            port: self.iocp_port,
             immediate_result: None,
+            afd: &self.afd,
+            used_afd: Cell::new(false),
+            pool: &self.pool,
             _marker: PhantomData,
         };
-        op.run(&mut op_data)?;
+        let result = op.run(&mut op_data);
+
+        // operations that went elsewhere complete through our own port, and
+        // those that fail right away or skip the port don't complete at all
+        if let (Some(io), Some(pool)) = (tp_io, &self.thread_pool) {
+            let on_handle = !op_data.used_afd.get() && !entry_completed_on_thread(&op_data);
+            let completes = match &op_data.immediate_result {
+                None => result.is_ok(),
+                Some(Err(_)) => false,
+                Some(Ok(_)) => !skips,
+            };
+            if !on_handle || !completes {
+                pool.cancel(io);
+            }
+        }
+
+        if let Err(e) = result {
+            active_ops.remove(index);
+            return Err(e);
+        }
 
-        // the operation may complete immediately; in this case,
-        // we propogate the result upwards
+        // the operation may complete immediately; failures never reach the
+        // port, and successes only skip it if the handle is set up to
         Ok(match op_data.immediate_result {
-            Some(result) => SubmissionStatus::AlreadyComplete(result),
-            None => {
+            None => SubmissionStatus::Submitted,
+            Some(Err(e)) => {
                 active_ops.remove(index);
-                SubmissionStatus::Submitted
+                SubmissionStatus::AlreadyComplete(Err(e))
+            }
+            Some(Ok(_)) if skips => {
+                // the number of bytes is in the OVERLAPPED, even when the
+                // function itself doesn't return it
+                let entry = active_ops.remove(index).unwrap();
+                SubmissionStatus::AlreadyComplete(overlapped_result(&entry.overlapped))
             }
+            // the completion is delivered through `wait`
+            Some(Ok(_)) => SubmissionStatus::Submitted,
         })
     }
 
     pub(crate) fn wait(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<usize> {
         // wait for an event
-        let mut buffer = lock!(self.result_buffer);
+        let mut buffer = lock!(self.result_buffer, self.poison);
         let mut entries_removed = 0;
 
         // preform the IOCP wait
+        //
+        // this wait isn't alertable, so signals and APCs can't interrupt it
         unsafe {
             GetQueuedCompletionStatusEx(
                 self.iocp_port as _,
@@ -223,54 +609,106 @@ impl Completion {
         }
 
         let entries_removed = entries_removed as usize;
+        let mut completed = Vec::with_capacity(entries_removed);
+        for entry in buffer.iter().take(entries_removed) {
+            // SAFETY: entry is initialized
+            let entry = unsafe { ptr::read(entry.as_ptr()) };
+
+            // the thread pool woke us up, its completions are taken below
+            if entry.lpCompletionKey == THREAD_POOL_KEY as usize {
+                continue;
+            }
+
+            completed.push(entry.lpOverlapped);
+        }
+        if let Some(pool) = &self.thread_pool {
+            completed.extend(pool.take_completed()?);
+        }
 
         // process the results in the buffer
         // since every entry in the buffer basically contains
         // a reference to the active_ops slab, we have to lock it
         // every entry we grab is owned by us now
-        let _guard = lock!(self.mutation_lock);
+        let _guard = lock!(self.mutation_lock, self.poison);
         let mut ops = unsafe { &mut *self.active_ops.get() };
-        let mut process_notify = false;
-
-        out.extend(
-            buffer
-                .iter()
-                .take(entries_removed)
-                .map(|entry| {
-                    // SAFETY: entry is initialized
-                    unsafe { ptr::read(entry.as_ptr()) }
-                })
-                .filter_map(|entry| {
-                    // cast back to an OpEntry and remove it from the slab
-                    let op_entry: *mut OpEntry = entry.lpOverlapped.cast();
-                    let op_entry = unsafe { &*op_entry };
-
-                    // if this is a notification, flip the switch back
-                    // and return
-                    if op_entry.key == NOTIFY_KEY {
-                        self.notified.store(false, Ordering::SeqCst);
-                        process_notify = true;
-                        return None;
-                    }
+        let start = out.len();
+
+        for overlapped in completed {
+            // if this is a notification, flip the switch back; it's told
+            // apart by its address, so that every key is free for operations
+            if overlapped == self.notification.get().cast() {
+                self.notified.store(false, Ordering::SeqCst);
+                continue;
+            }
 
-                    let index = op_entry.index;
-                    ops.remove(index)
-                })
-                .map(|op| {
-                    // convert to an event
-                    Event {
-                        key: op.key,
-                        result: match (op.source_type, op.overlapped.Internal as isize) {
-                            (SourceType::File, 0) | (SourceType::Socket, -1) => {
-                                Err(io::Error::last_os_error())
-                            }
-                            (_, code) => Ok(code as _),
-                        },
-                    }
-                }),
-        );
+            // cast back to an OpEntry and remove it from the slab
+            let index = unsafe { (*overlapped.cast::<OpEntry>()).index };
 
-        Ok(entries_removed - (process_notify as usize))
+            // a polled function goes on until it stops blocking
+            let polled = match ops.get_mut(index) {
+                Some(op) if op.repoll.is_some() => match op.repoll(&self.afd) {
+                    Some(result) => Some(result),
+                    None => continue,
+                },
+                Some(_) => None,
+                None => continue,
+            };
+
+            let op = match ops.remove(index) {
+                Some(op) => op,
+                None => continue,
+            };
+
+            // convert to an event
+            let result = if let Some(result) = polled {
+                result
+            } else if op.completed_on_thread {
+                match op.overlapped.Internal {
+                    THREAD_ERROR => Err(io::Error::from_raw_os_error(
+                        op.overlapped.InternalHigh as _,
+                    )),
+                    n => Ok(n),
+                }
+            } else {
+                overlapped_result(&op.overlapped)
+            };
+            out.push(Event::new(op.key, result));
+        }
+
+        Ok(out.len() - start)
+    }
+
+    /// The handles that are signalled when `wait` has something to do.
+    pub(crate) fn notifiers(&self) -> Result<Vec<crate::Raw>> {
+        Ok(vec![self.iocp_port as _])
+    }
+
+    /// The backend that performs operations on this kind of source.
+    pub(crate) fn backend_for(&self, _variant: SourceType) -> crate::Backend {
+        crate::Backend::Iocp
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn reserve(&self, _additional: usize) -> Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the completion port's buffers can't be resized",
+        ))
+    }
+
+    pub(crate) fn shrink_to_fit(&self) -> Result<()> {
+        {
+            let _guard = lock!(self.mutation_lock, self.poison);
+            // SAFETY: we hold the lock, and the slabs that are freed have
+            // no entries for the port to point to
+            unsafe { &mut *self.active_ops.get() }.shrink();
+        }
+
+        lock!(self.skipping, self.poison).shrink_to_fit();
+        Ok(())
     }
 
     pub(crate) fn notify(&self) -> Result<()> {
@@ -302,6 +740,86 @@ impl Drop for Completion {
     }
 }
 
+/// Was the operation handed to `complete_on_thread`?
+fn entry_completed_on_thread(op_data: &OpData<'_>) -> bool {
+    // SAFETY: every OVERLAPPED we hand out is the start of an OpEntry
+    unsafe { (*op_data.overlapped.cast::<OpEntry>()).completed_on_thread }
+}
+
+/// Run a blocking operation on the blocking pool, then post its result to
+/// the completion port once it's done.
+///
+/// This is used for handles that can't be used with overlapped I/O, like
+/// console handles.
+pub(crate) fn complete_on_thread(
+    op_data: &mut OpData<'_>,
+    f: impl FnOnce() -> Result<usize> + Send + 'static,
+) -> Result<Option<usize>> {
+    let port = op_data.port as usize;
+    let overlapped = op_data.overlapped as usize;
+
+    // SAFETY: every OVERLAPPED we hand out is the start of an OpEntry
+    unsafe {
+        (*op_data.overlapped.cast::<OpEntry>()).completed_on_thread = true;
+    }
+
+    op_data.pool.spawn(move || {
+        let overlapped = overlapped as *mut OVERLAPPED;
+        let transferred = match f() {
+            Ok(n) => {
+                unsafe {
+                    (*overlapped).Internal = n;
+                }
+                n
+            }
+            Err(e) => {
+                unsafe {
+                    (*overlapped).Internal = THREAD_ERROR;
+                    (*overlapped).InternalHigh = e.raw_os_error().unwrap_or(0) as usize;
+                }
+                0
+            }
+        };
+
+        // the waiter now owns the overlapped entry
+        let res =
+            unsafe { PostQueuedCompletionStatus(port as HANDLE, transferred as _, 0, overlapped) };
+        if res == 0 {
+            tracing::error!(
+                "Failed to post blocking completion: {:?}",
+                io::Error::last_os_error()
+            );
+        }
+    })?;
+
+    Ok(None)
+}
+
+/// Get the result of a completed overlapped operation.
+///
+/// `Internal` holds the status of the operation, and `InternalHigh` holds
+/// the number of bytes transferred.
+fn overlapped_result(overlapped: &OVERLAPPED) -> Result<usize> {
+    let status = overlapped.Internal as NTSTATUS;
+
+    if status >= 0 {
+        Ok(overlapped.InternalHigh)
+    } else if status == STATUS_END_OF_FILE {
+        // other platforms report end-of-file as reading nothing
+        Ok(0)
+    } else if matches!(
+        status,
+        STATUS_CONNECTION_RESET | STATUS_LOCAL_DISCONNECT | STATUS_REMOTE_DISCONNECT
+    ) {
+        // these would become ERROR_NETNAME_DELETED, which isn't recognized
+        // as a reset connection
+        Err(io::Error::from_raw_os_error(WSAECONNRESET))
+    } else {
+        let code = unsafe { RtlNtStatusToDosError(status) };
+        Err(io::Error::from_raw_os_error(code as _))
+    }
+}
+
 fn timeout_to_ms(timeout: Option<Duration>) -> u32 {
     match timeout {
         Some(timeout) => {
@@ -0,0 +1,25 @@
+// GNU GPL v3 License
+
+use std::convert::TryFrom;
+
+/// A type that keys are made of.
+///
+/// Keys are `u64`s, which are often packed from something more
+/// meaningful, such as a connection ID and the kind of operation. Any type
+/// that converts to a `u64` and back can be used instead, with
+/// `Completion::submit_keyed` and `Event::key_as`. The conversions must
+/// round-trip; debug builds check that they do when submitting.
+pub trait EventKey: Into<u64> + TryFrom<u64> {}
+
+impl<K: Into<u64> + TryFrom<u64>> EventKey for K {}
+
+/// Convert the key into a `u64`, checking that it converts back.
+pub(crate) fn pack<K: EventKey>(key: K) -> u64 {
+    let raw = key.into();
+    debug_assert_eq!(
+        K::try_from(raw).ok().map(Into::into),
+        Some(raw),
+        "key does not round-trip through u64"
+    );
+    raw
+}
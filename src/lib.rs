@@ -12,38 +12,177 @@ macro_rules! syscall {
 }
 
 macro_rules! lock {
-    ($mtx: expr) => {{
-        match ($mtx).lock() {
+    // follow the policy, returning an error if it says so
+    ($mtx: expr, $policy: expr) => {{
+        $policy.handle(($mtx).lock())?
+    }};
+    // follow the policy, aborting if it wants an error returned
+    ($mtx: expr, $policy: expr, infallible) => {{
+        match $policy.handle(($mtx).lock()) {
             Ok(lk) => lk,
-            Err(e) => {
-                tracing::error!("Mutex was poisoned: {:?}", &e);
-                e.into_inner()
-            }
+            Err(_) => std::process::abort(),
         }
     }};
 }
 
 // modules
 
+mod adaptor;
+pub use adaptor::{CompletionRead, CompletionWrite};
+
+mod broadcast;
+pub use broadcast::BroadcastHandle;
+
 mod buf;
-pub use buf::{Buf, BufMut, IoBuf, IoBufMut, OwnedIoSlice, VectoredBuf, VectoredBufMut};
+pub use buf::{
+    Buf, BufMut, BufPool, IoBuf, IoBufMut, OwnedIoSlice, PooledBuf, RingBuf, SharedBuf,
+    VectoredBuf, VectoredBufMut,
+};
+
+mod builder;
+pub use builder::CompletionBuilder;
+
+mod capabilities;
+pub use capabilities::Capabilities;
+
+mod coalesce;
+
+mod fence;
+
+mod handle;
+pub use handle::OpHandle;
+
+mod hints;
+pub use hints::Hints;
+
+mod idle;
+
+mod key;
+pub use key::EventKey;
+
+mod ordering;
+pub use ordering::OrderingMode;
+
+mod pending;
+pub use pending::{Backend, OpDebugInfo, PendingOp, PendingSnapshot};
+
+mod memory;
+
+mod multi;
+pub use multi::CompletionSet;
+
+mod poison;
+pub use poison::PoisonPolicy;
+
+mod poll_fn;
+use poll_fn::PollingFn;
+
+mod priority;
+pub use priority::Priority;
+
+mod readiness;
+pub use readiness::Readiness;
+
+mod rearm;
+
+mod set;
+pub use set::OpSet;
+
+mod sink;
+pub use sink::EventSink;
+
+mod spans;
+
+#[cfg(feature = "benchmark-internals")]
+mod counters;
+#[cfg(feature = "benchmark-internals")]
+pub use counters::CounterSnapshot;
+
+pub mod fs;
+pub mod os;
 
 mod ops;
-pub use ops::{Op, Read, Write};
+#[cfg(feature = "tracing-spans")]
+pub use ops::Traced;
+#[cfg(target_os = "linux")]
+pub use ops::{
+    is_ktls, GetXattr, RecvMsgGro, RecvTlsRecord, SendMsgGso, SendTlsRecord, SetXattr, Splice,
+    TlsRecordType, UringCmd, URING_CMD_LEN,
+};
+pub use ops::{
+    Accept, AcceptAndRecv, AnyOp, Barrier, CompletionKind, CopyFileRange, Custom, CustomFn,
+    CustomOp, InlineBuf, Nop, Op, OpenAt, PollReadable, PollWritable, Read, ReadInline, ReadStream,
+    ReadVectored, Resolve, Write, WriteVectored,
+};
+#[cfg(unix)]
+pub use ops::{
+    Frame, ReadAdaptive, ReadFrame, ReadUntil, RecvFrom, RecvFromFiltered, RecvMMsg, RecvMeta,
+    SendMMsg,
+};
 
 #[cfg(unix)]
 mod polling;
 
+mod pool;
+pub use pool::{BlockingExecutor, BlockingJob};
+
+mod timed;
+pub use timed::TimedEvent;
+
+mod try_io;
+pub use try_io::TryIo;
+
+mod unpark;
+
+#[cfg(unix)]
+mod retry;
+
 #[cfg(target_os = "linux")]
 mod linux;
 
+#[cfg(target_os = "freebsd")]
+mod freebsd;
+
+#[cfg(all(unix, feature = "fallback-threads"))]
+mod threads;
+
 #[cfg(windows)]
 mod iocp;
 
+#[cfg(windows)]
+mod threadpool;
+
+#[cfg(windows)]
+mod afd;
+
 cfg_if::cfg_if! {
     if #[cfg(target_os = "linux")] {
         use linux as platform;
+    } else if #[cfg(all(
+        feature = "fallback-threads",
+        unix,
+        not(any(
+            target_os = "android",
+            target_os = "illumos",
+            target_os = "solaris",
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "tvos",
+            target_os = "watchos",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd",
+            target_os = "dragonfly",
+        ))
+    ))] {
+        // nothing to poll many sources with, such as on Fuchsia
+        use threads as platform;
+    } else if #[cfg(target_os = "freebsd")] {
+        // kqueue, with POSIX AIO for files and sockets
+        use freebsd as platform;
     } else if #[cfg(unix)] {
+        // kqueue on the BSDs and Apple platforms, event ports on illumos
+        // and Solaris, and poll() everywhere else
         use polling as platform;
     } else if #[cfg(windows)] {
         use iocp as platform;
@@ -52,15 +191,29 @@ cfg_if::cfg_if! {
     }
 }
 
+#[cfg(feature = "rustls")]
+mod tls;
+#[cfg(feature = "rustls")]
+pub use tls::TlsAdapter;
+
 mod source;
-pub use source::{AsSource, Raw, Source, SourceType};
-use std::{fmt, io::Result, time::Duration};
+pub use source::{AsSource, Raw, Source, SourceGroup, SourceType};
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Result},
+    mem::MaybeUninit,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Condvar, Mutex,
+    },
+    thread::Thread,
+    time::{Duration, Instant},
+};
 
 #[doc(hidden)]
 pub use platform::OpData;
 
-type PollingFn = Box<dyn FnMut() -> Result<usize> + Send + Sync + 'static>;
-
 /// The events output from waiting.
 #[derive(Debug)]
 pub struct Event {
@@ -68,6 +221,34 @@ pub struct Event {
     pub result: Result<usize>,
 }
 
+impl Event {
+    /// Create a new `Event`.
+    pub fn new(key: u64, result: Result<usize>) -> Self {
+        Event { key, result }
+    }
+
+    /// Get the typed output of the operation this event belongs to.
+    ///
+    /// If the operation failed, its captured variables are dropped. Use
+    /// `Op::complete` directly to keep them around.
+    ///
+    /// # Safety
+    ///
+    /// `op` must be the operation that was submitted with this event's
+    /// key.
+    pub unsafe fn complete<O: Op>(self, op: O) -> Result<O::Output> {
+        let result = self.result?;
+        Ok(op.complete(result))
+    }
+
+    /// Get the key as the type it was submitted with.
+    ///
+    /// See `EventKey`.
+    pub fn key_as<K: EventKey>(&self) -> std::result::Result<K, K::Error> {
+        K::try_from(self.key)
+    }
+}
+
 /// When submitting an event, there is a chance that it completes
 /// before the event is submitted.
 ///
@@ -84,7 +265,71 @@ pub enum SubmissionStatus {
 /// The interface to system faculties for polling for completion on
 /// certain events.
 pub struct Completion {
+    /// Unparks a thread when the backend wakes up, if there is one.
+    ///
+    /// This is dropped before `inner`, since it watches its handles.
+    #[cfg(unix)]
+    unpark: Mutex<Option<unpark::Unparker>>,
     inner: platform::Completion,
+    /// Events received by `wait_for_key` that belong to other operations,
+    /// and events that didn't fit into the buffer given to `wait_into`.
+    ///
+    /// The latter go back in at the front, so this is a ring that starts
+    /// out with room for the capacity given to `CompletionBuilder::new`.
+    stash: Mutex<VecDeque<Event>>,
+    /// Wakes up `wait_for_key` callers when events are set aside.
+    stashed: Condvar,
+    /// Whether a `wait_for_key` caller is waiting on the backend.
+    ///
+    /// The others wait on `stashed` for it instead. This is only changed
+    /// while `stash` is locked.
+    stash_leader: AtomicBool,
+    /// Buffers reused by `wait_into`, `wait_extend` and `wait_sink` to
+    /// collect events.
+    ///
+    /// Each call takes one out while it waits, so that concurrent waiters
+    /// don't have to wait for each other.
+    scratch: Mutex<Vec<Vec<Event>>>,
+    /// Idle deadlines for sources.
+    idle: Mutex<idle::IdleTimers>,
+    /// Are there any idle deadlines?
+    has_idle: AtomicBool,
+    /// Operations that are submitted again every time they complete.
+    rearmed: Mutex<rearm::Rearmed>,
+    /// Are there any rearmed operations?
+    has_rearmed: AtomicBool,
+    /// The operations in flight.
+    pending: pending::Pending,
+    /// Puts events into submission order, if enabled.
+    sequencer: Option<Mutex<ordering::Sequencer>>,
+    /// High priority operations in flight.
+    urgent: Mutex<priority::Urgent>,
+    /// Are there any high priority operations in flight?
+    has_urgent: AtomicBool,
+    /// The memory pinned by operations in flight, if it's limited.
+    memory: Option<Mutex<memory::Memory>>,
+    /// Merges small writes, if enabled.
+    coalescer: Option<Mutex<coalesce::Coalescer>>,
+    /// Keeps operations in order around barriers, if enabled.
+    fences: Option<Mutex<fence::Fences>>,
+    /// How long `wait` spins before sleeping, if at all.
+    busy_poll: Option<Duration>,
+    /// How often `wait` gives back memory, if at all.
+    shrink_interval: Option<Duration>,
+    /// When `wait` last gave back memory.
+    last_shrink: Mutex<Instant>,
+    /// The spans of traced operations in flight.
+    #[cfg(feature = "tracing-spans")]
+    spans: Mutex<spans::Spans>,
+    /// Was `notify` called since the last wait, when busy polling?
+    notified: AtomicBool,
+    /// Is there a thread to unpark?
+    #[cfg(unix)]
+    has_unpark: AtomicBool,
+    /// What to do when one of our mutexes is poisoned.
+    poison: PoisonPolicy,
+    #[cfg(feature = "benchmark-internals")]
+    counters: counters::Counters,
 }
 
 impl fmt::Debug for Completion {
@@ -95,44 +340,1600 @@ impl fmt::Debug for Completion {
 
 impl Completion {
     /// Create a new `Completion` instance with the specified capacity.
+    ///
+    /// The capacity is only a starting point; if more operations are
+    /// in flight at once, the `Completion` grows to accommodate them.
+    ///
+    /// The exception is `io_uring`, whose queues never grow past the
+    /// capacity. A full submission queue is handed to the kernel to make
+    /// room, so submissions still go through, but events that don't fit
+    /// into the completion queue are only kept by kernels with
+    /// `IORING_FEAT_NODROP`, from Linux 5.5 on. Older kernels drop them,
+    /// so there the capacity has to cover the most operations that are
+    /// ever in flight at once.
     pub fn new(capacity: usize) -> Result<Self> {
-        platform::Completion::new(capacity).map(Into::into)
+        CompletionBuilder::new(capacity).build()
+    }
+
+    /// Create a new `Completion` around an `io_uring` instance that was
+    /// set up elsewhere, with the default options.
+    ///
+    /// The capacity is the size of the ring's submission queue. See
+    /// `CompletionBuilder::build_from_uring` for details.
+    ///
+    /// This is semver-exempt, and only available with the
+    /// `unstable-uring` feature.
+    ///
+    /// # Safety
+    ///
+    /// See `CompletionBuilder::build_from_uring`.
+    #[cfg(all(target_os = "linux", feature = "unstable-uring"))]
+    pub unsafe fn from_raw_uring(uring: io_uring::IoUring) -> Result<Self> {
+        let capacity = uring.params().sq_entries() as usize;
+        CompletionBuilder::new(capacity).build_from_uring(uring)
+    }
+
+    /// Create a new `Completion` with the specified capacity around an
+    /// I/O completion port that was created elsewhere, with the default
+    /// options.
+    ///
+    /// See `CompletionBuilder::build_from_iocp` for details.
+    ///
+    /// # Safety
+    ///
+    /// See `CompletionBuilder::build_from_iocp`.
+    #[cfg(windows)]
+    pub unsafe fn from_raw_iocp(
+        port: std::os::windows::io::RawHandle,
+        capacity: usize,
+    ) -> Result<Self> {
+        CompletionBuilder::new(capacity).build_from_iocp(port)
     }
 
     /// Register a source with the completion.
     pub fn register(&self, source: &impl Source) -> Result<()> {
+        // a file that was closed without being deregistered may still be
+        // in a fixed file slot under the same descriptor
+        #[cfg(target_os = "linux")]
+        if let Some(uo) = self.inner.uring() {
+            uo.deregister_file(source.as_raw())?;
+        }
+
         self.inner.register(source)
     }
 
+    /// Register a source with the completion, passing hints about how it's
+    /// going to be accessed on to the OS.
+    ///
+    /// For files, this tunes the OS's readahead, which can make heavy
+    /// sequential readers noticeably faster, and puts the file into a
+    /// fixed file slot if any were set aside with
+    /// `CompletionBuilder::fixed_files`. The hints are ignored for other
+    /// sources, and on platforms that can't take them, such as Windows,
+    /// where they have to be given when the file is opened.
+    ///
+    /// If the OS refuses the hints, the source is deregistered again and
+    /// the error is returned.
+    ///
+    /// A file in a fixed file slot must be deregistered before it's
+    /// closed. The slot is found by descriptor, so until then, operations
+    /// on a file that's opened with the same descriptor go to the old file
+    /// instead, unless the new one is registered first.
+    pub fn register_with_hints<S: Source>(&self, source: &S, hints: &Hints) -> Result<()> {
+        self.register(source)?;
+        if S::SOURCE_TYPE != SourceType::File {
+            return Ok(());
+        }
+
+        let prepared = hints.apply(source.as_raw()).and_then(|()| {
+            #[cfg(target_os = "linux")]
+            if let Some(uo) = self.inner.uring() {
+                uo.register_file(source.as_raw())?;
+            }
+            Ok(())
+        });
+
+        if let Err(e) = prepared {
+            if let Err(e) = self.deregister(source) {
+                tracing::error!(
+                    "Failed to deregister a source after its hints failed: {:?}",
+                    e
+                );
+            }
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
     /// Deregister a source from the completion.
+    ///
+    /// This also stops its idle timer, if any. Operations on the source
+    /// that are waiting for readiness can't complete once it's
+    /// deregistered, so they complete with an `Interrupted` error, and
+    /// their buffers can be reclaimed. Operations that the OS is already
+    /// performing complete as usual.
     pub fn deregister(&self, source: &impl Source) -> Result<()> {
-        self.inner.deregister(source)
+        self.clear_idle_timeout(source)?;
+
+        // the source is deregistered even if its slot can't be emptied
+        #[cfg(target_os = "linux")]
+        let emptied = match self.inner.uring() {
+            Some(uo) => uo.deregister_file(source.as_raw()),
+            None => Ok(()),
+        };
+        #[cfg(not(target_os = "linux"))]
+        let emptied = Ok(());
+
+        emptied.and(self.inner.deregister(source))
+    }
+
+    /// Deliver an event when no operation on `source` completes for
+    /// `timeout`.
+    ///
+    /// The event has the given `key` and a `TimedOut` error. Every
+    /// operation on the source that completes pushes the deadline back.
+    /// The timer is stopped once it fires; call this again to restart it.
+    /// Calling this for a source that already has a timer replaces it.
+    pub fn set_idle_timeout(
+        &self,
+        source: &impl Source,
+        timeout: Duration,
+        key: u64,
+    ) -> Result<()> {
+        let mut idle = lock!(self.idle, self.poison);
+        idle.set(source.as_raw(), timeout, key);
+        self.has_idle.store(true, Ordering::Release);
+        drop(idle);
+
+        // the deadline may be earlier than what we're waiting for
+        self.notify()
+    }
+
+    /// Stop the idle timer for `source`, if any.
+    pub fn clear_idle_timeout(&self, source: &impl Source) -> Result<()> {
+        if !self.has_idle.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        let mut idle = lock!(self.idle, self.poison);
+        idle.clear(source.as_raw());
+        self.has_idle.store(!idle.is_empty(), Ordering::Release);
+        Ok(())
+    }
+
+    /// Register every source in a group with the completion.
+    ///
+    /// The completion's locks are taken once for the entire group, but
+    /// with readiness polling, every source is still added to the poller
+    /// with a system call of its own, since there's no batched form of
+    /// `epoll_ctl` or its counterparts.
+    ///
+    /// If registering one of them fails, the sources registered before
+    /// it are deregistered again, except on Windows, where handles can't
+    /// be taken back out of the completion port and stay registered.
+    /// With `io_uring`, registering does nothing and can't fail.
+    pub fn register_group(&self, group: &SourceGroup) -> Result<()> {
+        self.inner.register_group(group)
+    }
+
+    /// Deregister every source in a group from the completion.
+    pub fn deregister_group(&self, group: &SourceGroup) -> Result<()> {
+        self.inner.deregister_group(group)
+    }
+
+    /// Cancel every pending operation on the sources in a group.
+    ///
+    /// The cancelled operations complete through `wait` with an error.
+    /// With readiness polling, this is an `Interrupted` error, and the
+    /// poller stops watching the sources for the readiness that only the
+    /// cancelled operations needed. This isn't
+    /// supported with `io_uring`, and operations on files aren't cancelled
+    /// in hybrid mode.
+    pub fn cancel_group(&self, group: &SourceGroup) -> Result<()> {
+        self.inner.cancel_group(group)
+    }
+
+    /// Cancel the operation with `key`.
+    ///
+    /// Like every submitted operation, a cancelled operation still
+    /// delivers exactly one event through `wait`. If it was cancelled in
+    /// time, its result is an error (`Interrupted` with readiness polling,
+    /// `ECANCELED` with `io_uring` and `ERROR_OPERATION_ABORTED` on
+    /// Windows); otherwise, it's the usual result. Operations running on
+    /// the blocking pool can't be cancelled and complete as usual. Until
+    /// the event is received, the OS may still be using the operation's
+    /// buffers; once it is, they can be taken back with
+    /// `Op::into_captured`.
+    pub fn cancel(&self, key: u64) -> Result<()> {
+        if self.has_rearmed.load(Ordering::Acquire) {
+            // a rearmed operation may be between submissions
+            let event = lock!(self.rearmed, self.poison).cancel(key);
+            if let Some(event) = event {
+                self.rearm_done(key)?;
+                self.stash(event)?;
+                return self.notify();
+            }
+        }
+
+        if let Some(coalescer) = &self.coalescer {
+            // a write may still be held back to be merged
+            if lock!(coalescer, self.poison).cancel(key) {
+                return self.notify();
+            }
+        }
+
+        if let Some(fences) = &self.fences {
+            // an operation may still be held back by a barrier
+            let event = lock!(fences, self.poison).cancel(key);
+            if let Some(event) = event {
+                self.stash(event)?;
+                return self.notify();
+            }
+        }
+
+        self.inner.cancel(key)
+    }
+
+    /// Write the same buffer to every source.
+    ///
+    /// The writes use consecutive keys starting at `first_key`, and are
+    /// waited on together through the returned handle, which reports the
+    /// result for each source. A source whose write can't be submitted
+    /// gets the error as its result, and the others are still written to.
+    /// Each write is a single `Write`, so it may come up short.
+    ///
+    /// # Safety
+    ///
+    /// None of the keys may be used by another operation in flight while
+    /// the handle is alive, and their events must not be received by
+    /// anything but the handle.
+    pub unsafe fn broadcast<S: Source>(
+        &self,
+        buf: SharedBuf,
+        sources: &[S],
+        first_key: u64,
+    ) -> BroadcastHandle<'_> {
+        BroadcastHandle::submit(self, buf, sources, first_key)
     }
 
     /// Submit an operation to the completion queue.
     ///
+    /// Any key can be used, except that `io_uring` keeps the four highest
+    /// keys, `u64::MAX - 3` and up, for itself. Operations that it would
+    /// perform with one of those fail with an `InvalidInput` error.
+    ///
     /// # Safety
     ///
     /// Cannot submit the same `op` more than once.
     pub unsafe fn submit(&self, op: &mut impl Op, key: u64) -> Result<SubmissionStatus> {
-        self.inner.submit(op, key)
+        self.submit_with_priority(op, key, Priority::Normal)
+    }
+
+    /// Submit an operation again, once it's complete.
+    ///
+    /// This reuses its buffers instead of building a new operation, which
+    /// suits reads that are kept around for the lifetime of a connection:
+    /// look at the data with `Read::buf`, then resubmit. The operation is
+    /// reset first, see `Op::reset`.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete, meaning that its event was
+    /// received or it completed during submission.
+    pub unsafe fn resubmit(&self, op: &mut impl Op, key: u64) -> Result<SubmissionStatus> {
+        op.reset();
+        self.submit(op, key)
+    }
+
+    /// Submit an operation to the completion queue, with a key of another
+    /// type.
+    ///
+    /// See `EventKey`.
+    ///
+    /// # Safety
+    ///
+    /// Cannot submit the same `op` more than once.
+    pub unsafe fn submit_keyed<K: EventKey>(
+        &self,
+        op: &mut impl Op,
+        key: K,
+    ) -> Result<SubmissionStatus> {
+        self.submit(op, key::pack(key))
+    }
+
+    /// Submit an operation to the completion queue in the given lane.
+    ///
+    /// See `Priority` for what this changes.
+    ///
+    /// # Safety
+    ///
+    /// Cannot submit the same `op` more than once.
+    pub unsafe fn submit_with_priority<O: Op>(
+        &self,
+        op: &mut O,
+        key: u64,
+        priority: Priority,
+    ) -> Result<SubmissionStatus> {
+        if !op.is_rearmed() {
+            return self.submit_once(op, key, priority);
+        }
+
+        // its buffer stays pinned until its last event
+        if let Some(memory) = &self.memory {
+            lock!(memory, self.poison).rearmed(key, op.pinned_bytes())?;
+        }
+
+        // keep track of it first, its event may arrive at any time
+        let mut rearmed = lock!(self.rearmed, self.poison);
+        rearmed.submitted(key, rearm::Rearm::new(op, priority));
+        self.has_rearmed.store(true, Ordering::Release);
+        drop(rearmed);
+
+        let status = self.submit_once(op, key, priority);
+        let done = match &status {
+            Err(_) => {
+                lock!(self.rearmed, self.poison).remove(key);
+                true
+            }
+            // the next `wait` submits it again if it goes on
+            Ok(SubmissionStatus::AlreadyComplete(result)) => {
+                lock!(self.rearmed, self.poison).complete(key, result)
+            }
+            Ok(SubmissionStatus::Submitted) => false,
+        };
+        if done {
+            self.rearm_done(key)?;
+        }
+
+        status
+    }
+
+    /// Submit an operation once, whether or not it's rearmed.
+    pub(crate) unsafe fn submit_once<O: Op>(
+        &self,
+        op: &mut O,
+        key: u64,
+        priority: Priority,
+    ) -> Result<SubmissionStatus> {
+        let fences = match &self.fences {
+            Some(fences) => fences,
+            None if op.is_barrier() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "barriers need `CompletionBuilder::barriers`",
+                ))
+            }
+            None => return self.submit_now(op, key, priority),
+        };
+
+        // wait for the barrier before it, if there is one
+        let held = fence::Held::new(op, priority);
+        if lock!(fences, self.poison).hold(key, op.source(), held) {
+            return Ok(SubmissionStatus::Submitted);
+        }
+
+        let status = self.submit_now(op, key, priority);
+        if !matches!(status, Ok(SubmissionStatus::Submitted)) {
+            // its event won't come through `wait`
+            let mut fences = lock!(fences, self.poison);
+            fences.done(key);
+            if !fences.is_empty() {
+                drop(fences);
+                self.notify()?;
+            }
+        }
+
+        status
+    }
+
+    /// Submit an operation right away.
+    pub(crate) unsafe fn submit_now(
+        &self,
+        op: &mut impl Op,
+        key: u64,
+        priority: Priority,
+    ) -> Result<SubmissionStatus> {
+        let source = op.source();
+
+        // hold back operations that would pin too much memory, rearmed
+        // ones were counted when they were first submitted
+        let memory = self.memory.as_ref().filter(|_| !op.is_rearmed());
+        if let Some(memory) = memory {
+            lock!(memory, self.poison).submitted(key, op.pinned_bytes())?;
+        }
+
+        // record the operation first, its event may arrive at any time
+        if let Some(sequencer) = &self.sequencer {
+            lock!(sequencer, self.poison).submitted(key, source);
+        }
+        if priority.is_high() {
+            let mut urgent = lock!(self.urgent, self.poison);
+            urgent.submitted(key);
+            self.has_urgent.store(true, Ordering::Release);
+        }
+
+        self.pending.submitted(
+            key,
+            source,
+            pending::op_kind(op),
+            self.inner.backend_for(op.variant()),
+        )?;
+        if self.has_idle.load(Ordering::Acquire) {
+            lock!(self.idle, self.poison).submitted(source, key);
+        }
+
+        #[cfg(feature = "tracing-spans")]
+        let span = op.op_span().cloned();
+        #[cfg(feature = "tracing-spans")]
+        if let Some(span) = &span {
+            lock!(self.spans, self.poison).submitted(key, span.clone());
+        }
+        #[cfg(feature = "tracing-spans")]
+        let _enter = span.as_ref().map(tracing::Span::enter);
+
+        let status = match self.submit_or_hold(op, key, priority) {
+            Ok(status) => status,
+            Err(e) => {
+                self.unrecord(key, false)?;
+                if let Some(sequencer) = &self.sequencer {
+                    lock!(sequencer, self.poison).cancelled(key, source);
+                }
+                if priority.is_high() {
+                    lock!(self.urgent, self.poison).cancelled(key);
+                }
+                if let Some(memory) = memory {
+                    lock!(memory, self.poison).cancelled(key);
+                }
+                return Err(e);
+            }
+        };
+
+        if priority.is_high() && matches!(status, SubmissionStatus::AlreadyComplete(_)) {
+            lock!(self.urgent, self.poison).cancelled(key);
+        }
+
+        let status = match (status, &self.sequencer) {
+            (SubmissionStatus::AlreadyComplete(result), Some(sequencer)) => {
+                match lock!(sequencer, self.poison).completed_early(key, source, result) {
+                    Some(result) => SubmissionStatus::AlreadyComplete(result),
+                    // delivered through `wait` after the operations before it
+                    None => SubmissionStatus::Submitted,
+                }
+            }
+            (status, _) => status,
+        };
+
+        if let SubmissionStatus::AlreadyComplete(_) = status {
+            if let Some(memory) = memory {
+                lock!(memory, self.poison).cancelled(key);
+            }
+            self.unrecord(key, true)?;
+        }
+
+        // a thread that's parked instead of waiting won't hand the entry
+        // to the kernel, so it's done here
+        #[cfg(target_os = "linux")]
+        if self.has_unpark.load(Ordering::Acquire) && matches!(status, SubmissionStatus::Submitted)
+        {
+            if let Err(e) = self.inner.flush() {
+                // the operation is in flight either way, and the next
+                // harvest tries again
+                tracing::error!("Failed to flush the submission: {:?}", e);
+            }
+        }
+
+        #[cfg(feature = "benchmark-internals")]
+        self.counters
+            .submitted(matches!(status, SubmissionStatus::AlreadyComplete(_)));
+
+        Ok(status)
+    }
+
+    /// Take back what `submit_now` recorded about an operation before
+    /// submitting it, once it's clear that it never went in flight.
+    ///
+    /// It's recorded first, since another thread may receive its event
+    /// before `submit_now` returns. `complete` is whether it completed
+    /// right away.
+    fn unrecord(&self, key: u64, complete: bool) -> Result<()> {
+        self.pending.cancelled(key)?;
+        if self.has_idle.load(Ordering::Acquire) {
+            lock!(self.idle, self.poison).cancelled(key, complete);
+        }
+        #[cfg(feature = "tracing-spans")]
+        lock!(self.spans, self.poison).cancelled(key);
+        Ok(())
+    }
+
+    /// Submit an operation to the backend, or hold it back to be merged
+    /// with other writes.
+    unsafe fn submit_or_hold(
+        &self,
+        op: &mut impl Op,
+        key: u64,
+        priority: Priority,
+    ) -> Result<SubmissionStatus> {
+        let coalescer = match &self.coalescer {
+            Some(coalescer) => coalescer,
+            None => return self.inner.submit(op, key, priority),
+        };
+
+        let source = op.source();
+        let mut held = lock!(coalescer, self.poison);
+        if !priority.is_high() {
+            if let Some(buf) = op.coalescable() {
+                let first = !held.has_held();
+                if held.hold(source, op.variant(), key, buf) {
+                    drop(held);
+
+                    // a thread blocked in `wait` wouldn't flush it otherwise
+                    if first {
+                        self.notify()?;
+                    }
+                    return Ok(SubmissionStatus::Submitted);
+                }
+            }
+        }
+
+        // the writes held back on the source go first
+        let batches = held.take(Some(source));
+        drop(held);
+        if self.submit_batches(batches)? {
+            self.notify()?;
+        }
+
+        self.inner.submit(op, key, priority)
+    }
+
+    /// Submit writes that were merged.
+    ///
+    /// Returns whether any events are ready to be handed out, for the
+    /// writes that completed right away or couldn't be submitted.
+    ///
+    /// # Safety
+    ///
+    /// The buffers of the writes must stay valid until their events are
+    /// handed out.
+    unsafe fn submit_batches(&self, batches: Vec<coalesce::Batch>) -> Result<bool> {
+        let coalescer = match &self.coalescer {
+            Some(coalescer) => coalescer,
+            None => return Ok(false),
+        };
+
+        let mut ready = false;
+        for batch in batches {
+            // the write has to stay in place while it's in flight
+            let mut batch = Box::new(batch);
+            let key = batch.key();
+            let op: *mut WriteVectored<_> = &mut batch.op;
+            let batch_ptr: *const coalesce::Batch = &*batch;
+
+            // keep track of it first, its event may arrive at any time
+            lock!(coalescer, self.poison).submitted(batch);
+
+            let result = match self.inner.submit(&mut *op, key, Priority::Normal) {
+                Ok(SubmissionStatus::Submitted) => continue,
+                Ok(SubmissionStatus::AlreadyComplete(result)) => result,
+                Err(e) => Err(e),
+            };
+
+            let mut coalescer = lock!(coalescer, self.poison);
+            if let Some(batch) = coalescer.unsubmitted(key, batch_ptr) {
+                coalescer.completed(batch, result);
+                ready = true;
+            }
+        }
+
+        Ok(ready)
+    }
+
+    /// Merge and submit the writes held back since the last wait.
+    ///
+    /// Returns whether any events are ready to be handed out.
+    fn flush_writes(&self) -> Result<bool> {
+        let coalescer = match &self.coalescer {
+            Some(coalescer) => coalescer,
+            None => return Ok(false),
+        };
+
+        let batches = {
+            let mut coalescer = lock!(coalescer, self.poison);
+            if !coalescer.has_held() {
+                return Ok(coalescer.has_ready());
+            }
+            coalescer.take(None)
+        };
+
+        // SAFETY: the events of the writes haven't been handed out, so the
+        // user keeps their buffers in place
+        unsafe { self.submit_batches(batches)? };
+        Ok(lock!(coalescer, self.poison).has_ready())
     }
 
     /// Wait for events to be available.
     pub fn wait(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<usize> {
+        // hand out events that were set aside by `wait_for_key` first
+        let stashed = {
+            let mut stash = lock!(self.stash, self.poison);
+            let len = stash.len();
+            out.extend(stash.drain(..));
+            len
+        };
+
+        let timeout = if stashed > 0 {
+            Some(Duration::ZERO)
+        } else {
+            timeout
+        };
+
+        Ok(stashed + self.wait_inner(timeout, out)?)
+    }
+
+    /// Wait for events to be available, writing them into `out`.
+    ///
+    /// Unlike `wait`, this doesn't allocate once the internal buffers have
+    /// grown large enough. Returns the number of events written to the
+    /// start of `out`. If more events are available than fit into `out`,
+    /// the rest are returned by the next call to `wait` or `wait_into`.
+    pub fn wait_into(
+        &self,
+        timeout: Option<Duration>,
+        out: &mut [MaybeUninit<Event>],
+    ) -> Result<usize> {
+        self.with_scratch(|scratch| {
+            self.wait(timeout, scratch)?;
+
+            let count = scratch.len().min(out.len());
+            for (slot, event) in out.iter_mut().zip(scratch.drain(..count)) {
+                slot.write(event);
+            }
+
+            Ok(count)
+        })
+    }
+
+    /// Wait for events to be available, pushing them into any collection.
+    ///
+    /// This allows delivering events straight into a fixed-size buffer, a
+    /// channel or a custom queue. Like `wait_into`, this doesn't allocate
+    /// once the internal buffers have grown large enough.
+    pub fn wait_extend(
+        &self,
+        timeout: Option<Duration>,
+        out: &mut impl Extend<Event>,
+    ) -> Result<usize> {
+        self.with_scratch(|scratch| {
+            let count = self.wait(timeout, scratch)?;
+            out.extend(scratch.drain(..));
+            Ok(count)
+        })
+    }
+
+    /// Wait for events to be available, reporting each one to `sink`.
+    ///
+    /// This is meant for executors that can't allocate while waiting.
+    /// The internal buffer that events are collected into is allocated
+    /// with room for the capacity given to `CompletionBuilder::new`, so
+    /// this doesn't allocate as long as no more events than that are
+    /// received at once. Errors that the OS reports don't allocate either.
+    pub fn wait_sink(&self, timeout: Option<Duration>, sink: &mut impl EventSink) -> Result<usize> {
+        self.with_scratch(|scratch| {
+            let count = self.wait(timeout, scratch)?;
+            for event in scratch.drain(..) {
+                sink.on_event(event);
+            }
+            Ok(count)
+        })
+    }
+
+    /// Wait for events to be available, along with the time they were
+    /// received.
+    ///
+    /// Subtracting the time an operation was submitted from its event's
+    /// timestamp gives its latency, give or take the time `wait` took to
+    /// get to it. The monotonic clock is read once for every batch of
+    /// events rather than for every event, so events received together
+    /// share a timestamp. Events set aside by `wait_for_key` are stamped
+    /// when they're handed out. Timestamps from the kernel, such as
+    /// `SO_TIMESTAMPING`, aren't used.
+    pub fn wait_timed(
+        &self,
+        timeout: Option<Duration>,
+        out: &mut Vec<TimedEvent>,
+    ) -> Result<usize> {
+        self.with_scratch(|scratch| {
+            let count = self.wait(timeout, scratch)?;
+            let timestamp = Instant::now();
+            out.extend(
+                scratch
+                    .drain(..)
+                    .map(|event| TimedEvent { event, timestamp }),
+            );
+            Ok(count)
+        })
+    }
+
+    /// Collect events into a scratch buffer, without holding a lock while
+    /// waiting.
+    ///
+    /// Events that `f` leaves in the buffer are returned by the next call
+    /// to `wait`, ahead of any newer events.
+    fn with_scratch<R>(&self, f: impl FnOnce(&mut Vec<Event>) -> Result<R>) -> Result<R> {
+        let mut scratch = lock!(self.scratch, self.poison)
+            .pop()
+            .unwrap_or_else(|| Vec::with_capacity(self.capacity()));
+        let result = f(&mut scratch);
+
+        if !scratch.is_empty() {
+            // they go ahead of the events that were set aside meanwhile
+            let mut stash = lock!(self.stash, self.poison);
+            for event in scratch.drain(..).rev() {
+                stash.push_front(event);
+            }
+            self.stashed.notify_all();
+        }
+
+        lock!(self.scratch, self.poison).push(scratch);
+        result
+    }
+
+    /// Wait until the operation submitted with `key` completes.
+    ///
+    /// Events for other operations received in the meantime are set aside
+    /// and returned by later calls to `wait`. Threads that call this at
+    /// the same time take turns waiting on the backend, and hand each
+    /// other the events they receive. If another thread calls
+    /// `wait` at the same time, it may receive this operation's event
+    /// instead. Returns an error of kind `TimedOut` if the timeout expires
+    /// first.
+    pub fn wait_for_key(&self, key: u64, timeout: Option<Duration>) -> Result<Event> {
+        self.wait_matching(timeout, |k| k == key)
+    }
+
+    /// Wait until an operation with a key accepted by `matches` completes.
+    ///
+    /// See `wait_for_key`.
+    pub(crate) fn wait_matching(
+        &self,
+        timeout: Option<Duration>,
+        matches: impl Fn(u64) -> bool,
+    ) -> Result<Event> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut events = Vec::new();
+        let mut stash = lock!(self.stash, self.poison);
+
+        loop {
+            let found = stash.iter().position(|event| matches(event.key));
+            if let Some(event) = found.and_then(|i| stash.remove(i)) {
+                return Ok(event);
+            }
+
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) => Some(timeout),
+                    None => return Err(io::ErrorKind::TimedOut.into()),
+                },
+                None => None,
+            };
+
+            // only one of us waits on the backend, and sets aside what it
+            // receives for the others
+            if self.stash_leader.load(Ordering::Relaxed) {
+                stash = match timeout {
+                    Some(timeout) => {
+                        let waited = self.stashed.wait_timeout(stash, timeout);
+                        self.poison.handle(waited)?.0
+                    }
+                    None => self.poison.handle(self.stashed.wait(stash))?,
+                };
+                continue;
+            }
+
+            self.stash_leader.store(true, Ordering::Relaxed);
+            let leader = StashLeader(self);
+            drop(stash);
+
+            let waited = self.wait_inner(timeout, &mut events);
+            lock!(self.stash, self.poison).extend(events.drain(..));
+            drop(leader);
+            waited?;
+
+            stash = lock!(self.stash, self.poison);
+        }
+    }
+
+    /// Set an event aside for `wait_for_key`.
+    fn stash(&self, event: Event) -> Result<()> {
+        lock!(self.stash, self.poison).push_back(event);
+        self.stashed.notify_all();
+        Ok(())
+    }
+
+    /// Wait for events from the backend, and run them through the
+    /// layers around it, such as write coalescing and ordering.
+    fn wait_backend(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<usize> {
+        let rearm_start = out.len();
+        let rearmed = self.rearm_parked(out)? + self.release_fenced(out)?;
+        let coalesced = self.flush_writes()?;
+        let timeout = if rearmed > 0 || coalesced {
+            Some(Duration::ZERO)
+        } else {
+            timeout
+        };
+
+        let start = out.len();
+        let mut count = match self.busy_poll {
+            Some(spin) => self.spin_wait(spin, timeout, out)?,
+            None => self.inner.wait(timeout, out)?,
+        };
+        self.harvested()?;
+
+        // hand out an event for every write that was merged
+        if let Some(coalescer) = &self.coalescer {
+            lock!(coalescer, self.poison).expand(out, start);
+            count = out.len() - start;
+        }
+
+        if self.has_urgent.load(Ordering::Acquire) {
+            let mut urgent = lock!(self.urgent, self.poison);
+            urgent.prioritize(out, start);
+            self.has_urgent.store(!urgent.is_empty(), Ordering::Release);
+        }
+
+        let count = match &self.sequencer {
+            Some(sequencer) => {
+                lock!(sequencer, self.poison).reorder(out, start);
+                out.len() - start
+            }
+            None => count,
+        };
+
+        if let Some(memory) = &self.memory {
+            lock!(memory, self.poison).completed(&out[start..]);
+        }
+
+        if let Some(fences) = &self.fences {
+            lock!(fences, self.poison).completed(&out[start..]);
+        }
+
+        if self.has_rearmed.load(Ordering::Acquire) {
+            let mut rearmed = lock!(self.rearmed, self.poison);
+            let done = rearmed.completed(&out[rearm_start..]);
+            self.has_rearmed
+                .store(!rearmed.is_empty(), Ordering::Release);
+            drop(rearmed);
+
+            for key in done {
+                self.rearm_done(key)?;
+            }
+        }
+
+        self.decay()?;
+        Ok(rearmed + count)
+    }
+
+    /// Give back memory if it's time to, see
+    /// `CompletionBuilder::shrink_interval`.
+    fn decay(&self) -> Result<()> {
+        let interval = match self.shrink_interval {
+            Some(interval) => interval,
+            None => return Ok(()),
+        };
+
+        {
+            let mut last_shrink = lock!(self.last_shrink, self.poison);
+            if last_shrink.elapsed() < interval {
+                return Ok(());
+            }
+            *last_shrink = Instant::now();
+        }
+
+        // the events are already out, so don't lose them over this
+        if let Err(e) = self.shrink_to_fit() {
+            tracing::debug!("Failed to give back memory: {:?}", e);
+        }
+        Ok(())
+    }
+
+    /// Release the memory of a rearmed operation that won't be submitted
+    /// again.
+    fn rearm_done(&self, key: u64) -> Result<()> {
+        if let Some(memory) = &self.memory {
+            lock!(memory, self.poison).rearm_done(key);
+        }
+        Ok(())
+    }
+
+    /// Submit the rearmed operations whose events were handed out again.
+    ///
+    /// Returns the number of events pushed for the ones that completed
+    /// right away or couldn't be submitted.
+    fn rearm_parked(&self, out: &mut Vec<Event>) -> Result<usize> {
+        if !self.has_rearmed.load(Ordering::Acquire) {
+            return Ok(0);
+        }
+
+        let parked = lock!(self.rearmed, self.poison).take_parked();
+        let mut count = 0;
+
+        for (key, rearm) in parked {
+            // SAFETY: its last event hasn't been handed out, so the user
+            // keeps it in place
+            let result = match unsafe { rearm.resubmit(self, key) } {
+                Ok(SubmissionStatus::Submitted) => continue,
+                Ok(SubmissionStatus::AlreadyComplete(result)) => result,
+                Err(e) => {
+                    lock!(self.rearmed, self.poison).remove(key);
+                    self.rearm_done(key)?;
+                    Err(e)
+                }
+            };
+
+            out.push(Event::new(key, result));
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Submit the operations that were waiting for a barrier.
+    ///
+    /// Returns the number of events pushed for the ones that completed
+    /// right away or couldn't be submitted.
+    fn release_fenced(&self, out: &mut Vec<Event>) -> Result<usize> {
+        let fences = match &self.fences {
+            Some(fences) => fences,
+            None => return Ok(0),
+        };
+
+        let mut count = 0;
+        loop {
+            let ready = lock!(fences, self.poison).take_ready();
+            if ready.is_empty() {
+                return Ok(count);
+            }
+
+            for (key, held) in ready {
+                // SAFETY: its event hasn't been handed out, so the user
+                // keeps it in place
+                let result = match unsafe { held.submit(self, key) } {
+                    Ok(SubmissionStatus::Submitted) => continue,
+                    Ok(SubmissionStatus::AlreadyComplete(result)) => result,
+                    Err(e) => Err(e),
+                };
+
+                // this may let the operations after it go
+                lock!(fences, self.poison).done(key);
+                out.push(Event::new(key, result));
+                count += 1;
+            }
+        }
+    }
+
+    /// Check the backend for events without blocking for up to `spin`,
+    /// then wait for the rest of the timeout.
+    fn spin_wait(
+        &self,
+        spin: Duration,
+        timeout: Option<Duration>,
+        out: &mut Vec<Event>,
+    ) -> Result<usize> {
+        let started = Instant::now();
+        let spin = timeout.map_or(spin, |timeout| timeout.min(spin));
+
+        loop {
+            // the backend swallows its own wakeups when it doesn't block
+            if self.notified.swap(false, Ordering::AcqRel) {
+                return Ok(0);
+            }
+
+            let count = self.inner.wait(Some(Duration::ZERO), out)?;
+            if count > 0 {
+                return Ok(count);
+            }
+
+            if started.elapsed() >= spin {
+                break;
+            }
+            std::hint::spin_loop();
+        }
+
+        if self.notified.swap(false, Ordering::AcqRel) {
+            return Ok(0);
+        }
+
+        let timeout = timeout.map(|timeout| timeout.saturating_sub(started.elapsed()));
         self.inner.wait(timeout, out)
     }
 
+    /// Wait for events from the backend, until there are some or the
+    /// timeout expires.
+    fn wait_inner(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<usize> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        let mut timeout = timeout;
+
+        loop {
+            let (count, cut_short) = self.wait_once(timeout, out)?;
+            if count > 0 || !cut_short {
+                return Ok(count);
+            }
+
+            // only an internal deadline passed, so keep waiting for the
+            // caller's
+            timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) if !timeout.is_zero() => Some(timeout),
+                    _ => return Ok(0),
+                },
+                None => timeout,
+            };
+        }
+    }
+
+    /// Wait for events from the backend once, until the timeout or an
+    /// internal deadline, such as the watchdog's next check or an idle
+    /// timer, whichever comes first.
+    ///
+    /// Also returns whether an internal deadline passed before the
+    /// timeout, rather than the backend waking up for another reason.
+    fn wait_once(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<(usize, bool)> {
+        let (requested, started) = (timeout, Instant::now());
+        let cut_short = |timeout: Option<Duration>| match timeout {
+            Some(timeout) if Some(timeout) != requested => started.elapsed() >= timeout,
+            _ => false,
+        };
+
+        // wake up once the next operation goes over the watchdog's threshold
+        let timeout = match self.pending.next_watchdog_check()? {
+            Some(check) => {
+                let until = check.saturating_duration_since(Instant::now());
+                Some(timeout.map_or(until, |timeout| timeout.min(until)))
+            }
+            None => timeout,
+        };
+
+        if !self.has_idle.load(Ordering::Acquire) {
+            let start = out.len();
+            let mut count = self.wait_backend(timeout, out)?;
+            self.pending.completed(&out[start..])?;
+            #[cfg(feature = "tracing-spans")]
+            self.trace_completed(&out[start..])?;
+            count += self.pending.watch(out)?;
+
+            #[cfg(feature = "benchmark-internals")]
+            self.counters.waited(count);
+
+            return Ok((count, cut_short(timeout)));
+        }
+
+        // don't sleep past the next idle deadline
+        let next_deadline = lock!(self.idle, self.poison).next_deadline();
+        let timeout = match next_deadline {
+            Some(deadline) => {
+                let until = deadline.saturating_duration_since(Instant::now());
+                Some(timeout.map_or(until, |timeout| timeout.min(until)))
+            }
+            None => timeout,
+        };
+
+        let start = out.len();
+        let mut count = self.wait_backend(timeout, out)?;
+        self.pending.completed(&out[start..])?;
+        #[cfg(feature = "tracing-spans")]
+        self.trace_completed(&out[start..])?;
+
+        let mut idle = lock!(self.idle, self.poison);
+        idle.completed(&out[start..]);
+        count += idle.expire(Instant::now(), out);
+        self.has_idle.store(!idle.is_empty(), Ordering::Release);
+        drop(idle);
+        count += self.pending.watch(out)?;
+
+        #[cfg(feature = "benchmark-internals")]
+        self.counters.waited(count);
+
+        Ok((count, cut_short(timeout)))
+    }
+
+    /// Log the new events of traced operations within their spans.
+    #[cfg(feature = "tracing-spans")]
+    fn trace_completed(&self, events: &[Event]) -> Result<()> {
+        let mut spans = lock!(self.spans, self.poison);
+        if !spans.is_empty() {
+            spans.completed(events);
+        }
+        Ok(())
+    }
+
+    /// The next idle deadline, if any.
+    pub(crate) fn next_idle_deadline(&self) -> Result<Option<Instant>> {
+        if !self.has_idle.load(Ordering::Acquire) {
+            return Ok(None);
+        }
+
+        Ok(lock!(self.idle, self.poison).next_deadline())
+    }
+
+    /// Wait for events to be available, or for `predicate` to return
+    /// `true`.
+    ///
+    /// The predicate is checked before waiting and every time the
+    /// completion is woken up, so setting a flag and then calling
+    /// `notify` will cause this function to return. Returns the number
+    /// of events received, which is zero if the predicate was satisfied
+    /// or the timeout expired first.
+    pub fn wait_until(
+        &self,
+        timeout: Option<Duration>,
+        predicate: impl Fn() -> bool,
+        out: &mut Vec<Event>,
+    ) -> Result<usize> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        loop {
+            if predicate() {
+                return Ok(0);
+            }
+
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) => Some(timeout),
+                    None => return Ok(0),
+                },
+                None => None,
+            };
+
+            let start = out.len();
+            self.wait(timeout, out)?;
+            if out.len() > start {
+                return Ok(out.len() - start);
+            }
+        }
+    }
+
+    /// Wait until every operation in flight has completed.
+    ///
+    /// This is meant for shutting down cleanly: the events are added to
+    /// `out`, and the number received is returned. Operations submitted by
+    /// other threads in the meantime are waited for as well. Returns an
+    /// error of kind `TimedOut` if the timeout expires first; the events
+    /// received until then are still in `out`.
+    pub fn drain(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<usize> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let start = out.len();
+
+        while self.len_in_flight() > 0 {
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) => Some(timeout),
+                    None => return Err(io::ErrorKind::TimedOut.into()),
+                },
+                None => None,
+            };
+
+            self.wait(timeout, out)?;
+        }
+
+        Ok(out.len() - start)
+    }
+
+    /// Whether any operations go through `io_uring`.
+    ///
+    /// This is semver-exempt, and only available with the
+    /// `fault-injection` feature.
+    #[cfg(feature = "fault-injection")]
+    pub fn uses_io_uring(&self) -> bool {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                self.inner.uses_io_uring()
+            } else {
+                false
+            }
+        }
+    }
+
+    /// Get the underlying `io_uring` instance, if one is used.
+    ///
+    /// This is semver-exempt, and only available with the
+    /// `unstable-uring` feature.
+    #[cfg(all(target_os = "linux", feature = "unstable-uring"))]
+    pub fn as_uring(&self) -> Option<&io_uring::IoUring> {
+        self.inner.uring().map(|uo| uo.raw())
+    }
+
+    /// Push entries directly into the `io_uring` submission queue.
+    ///
+    /// This allows submitting operations that this crate doesn't wrap.
+    /// The internal locks are held while `f` runs, and the entries are
+    /// submitted to the kernel afterwards. Their completions are returned
+    /// by `wait` like any other event, with the entry's user data as the
+    /// key. Returns an error of kind `Unsupported` if `io_uring` isn't
+    /// being used.
+    ///
+    /// This is semver-exempt, and only available with the
+    /// `unstable-uring` feature.
+    ///
+    /// # Safety
+    ///
+    /// Every entry pushed must stay valid until it completes, and must
+    /// not use one of the keys reserved by `io_uring`, `u64::MAX - 3` and
+    /// up, as its user data.
+    #[cfg(all(target_os = "linux", feature = "unstable-uring"))]
+    pub unsafe fn with_submission<R>(
+        &self,
+        f: impl FnOnce(&mut io_uring::SubmissionQueue<'_>) -> R,
+    ) -> Result<R> {
+        match self.inner.uring() {
+            Some(uo) => uo.with_submission(f),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "io_uring is not being used",
+            )),
+        }
+    }
+
+    /// Read from a source right away, or hand back a `Read` to submit if
+    /// that would block.
+    ///
+    /// This saves submitting an operation when data is already waiting,
+    /// which is common for protocols that read in small steps. Sockets are
+    /// read with `MSG_DONTWAIT`, and pipes and terminals only if they're
+    /// in non-blocking mode; otherwise, and on Windows, the operation is
+    /// always handed back. It isn't submitted, so submit it as usual,
+    /// such as with `OpHandle::submit`. If reading fails with another
+    /// error, the buffer is dropped.
+    pub fn try_read<S: Source, B: BufMut + Send>(
+        &self,
+        source: &S,
+        buf: B,
+    ) -> Result<TryIo<Read<B>>> {
+        match try_io::try_recv(source.as_raw(), S::SOURCE_TYPE, buf.pointer()) {
+            Some(Err(e)) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+            Some(Ok(n)) => {
+                // SAFETY: the operation was never submitted
+                Ok(TryIo::Done(unsafe { Read::new(source, buf).complete(n) }))
+            }
+            _ => Ok(TryIo::WouldBlock(Read::new(source, buf))),
+        }
+    }
+
+    /// Write to a source right away, or hand back a `Write` to submit if
+    /// that would block.
+    ///
+    /// See `try_read`.
+    pub fn try_write<S: Source, B: Buf + Send>(
+        &self,
+        source: &S,
+        buf: B,
+    ) -> Result<TryIo<Write<B>>> {
+        match try_io::try_send(source.as_raw(), S::SOURCE_TYPE, buf.pointer()) {
+            Some(Err(e)) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+            Some(Ok(n)) => {
+                // SAFETY: the operation was never submitted
+                Ok(TryIo::Done(unsafe { Write::new(source, buf).complete(n) }))
+            }
+            _ => Ok(TryIo::WouldBlock(Write::new(source, buf))),
+        }
+    }
+
+    /// Check whether a source is ready for I/O, without blocking.
+    ///
+    /// This is a non-blocking `poll()`, or `WSAPoll()` on Windows, where
+    /// only sockets are supported. It lets protocol code decide whether to
+    /// try a non-blocking call right away before submitting an operation.
+    /// The source doesn't have to be registered.
+    pub fn poll_ready<S: Source>(&self, source: &S) -> Result<Readiness> {
+        readiness::poll_ready(source.as_raw(), S::SOURCE_TYPE)
+    }
+
+    /// What the backend in use supports.
+    pub fn capabilities(&self) -> Capabilities {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                self.inner.capabilities()
+            } else {
+                Capabilities::default()
+            }
+        }
+    }
+
+    /// Register a buffer in one of the fixed buffer slots set aside by
+    /// `CompletionBuilder::fixed_buffers`, and return its index.
+    ///
+    /// Reads and writes into the buffer, or any part of it, can then use
+    /// it with `Read::fixed_buffer` and `Write::fixed_buffer`. This fails
+    /// with `Unsupported` if no slots were set aside, which is always the
+    /// case without `io_uring`, and with `OutOfMemory` if they're all
+    /// taken.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must stay valid until it's unregistered with
+    /// `unregister_buffer` and the operations using it have completed, or
+    /// the `Completion` is dropped.
+    pub unsafe fn register_buffer(&self, buf: &mut [u8]) -> Result<u16> {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                if let Some(uo) = self.inner.uring() {
+                    return uo.register_buffer(buf.as_mut_ptr(), buf.len());
+                }
+            } else {
+                let _ = buf;
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "fixed buffers are only supported by io_uring",
+        ))
+    }
+
+    /// Empty the fixed buffer slot at `index`, so that another buffer can
+    /// be registered.
+    ///
+    /// Operations in flight that use the buffer still complete as usual.
+    pub fn unregister_buffer(&self, index: u16) -> Result<()> {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                if let Some(uo) = self.inner.uring() {
+                    return uo.unregister_buffer(index);
+                }
+            } else {
+                let _ = index;
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "fixed buffers are only supported by io_uring",
+        ))
+    }
+
+    /// The number of operations in flight.
+    ///
+    /// Operations that completed during submission aren't counted, and
+    /// operations held back by a `Barrier` are. With
+    /// `PoisonPolicy::PropagateError`, a poisoned lock aborts the process,
+    /// since there's no error to return.
+    pub fn len_in_flight(&self) -> usize {
+        let held = match &self.fences {
+            Some(fences) => lock!(fences, self.poison, infallible).held(),
+            None => 0,
+        };
+        self.pending.count() + held
+    }
+
+    /// The number of events that a single `wait` receives without
+    /// allocating.
+    ///
+    /// This starts out as the capacity given to `CompletionBuilder::new`,
+    /// rounded up by the OS for some backends.
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// Make room for `additional` more sources than are registered now,
+    /// and for an event from each of them per `wait`.
+    ///
+    /// This allocates ahead of time, before a burst of connections, rather
+    /// than while handling it. Like `Vec::reserve`, nothing changes if
+    /// there's room already, so the capacity only grows to the number of
+    /// sources plus `additional`. If a `wait` is in progress, its buffer grows
+    /// once it returns. Only readiness polling can grow; other backends
+    /// fail with `Unsupported`. With `CompletionBuilder::hybrid`, this
+    /// grows the polled side that sockets use and succeeds, while the
+    /// `io_uring` queues that files use keep their size.
+    pub fn reserve(&self, additional: usize) -> Result<()> {
+        self.inner.reserve(additional)
+    }
+
+    /// Give back the memory that isn't needed for the sources and
+    /// operations there are now.
+    ///
+    /// After a burst of connections, the `Completion` holds on to the
+    /// memory it needed at the peak. This releases what it can without
+    /// moving operations in flight, while keeping room for `capacity`
+    /// events. The queues of `io_uring` and the completion port keep their
+    /// size. With readiness polling, the sources are also moved to fill
+    /// the gaps left by the ones that were deregistered, unless a `wait`
+    /// is in progress. `CompletionBuilder::shrink_interval` has `wait` do
+    /// this regularly.
+    pub fn shrink_to_fit(&self) -> Result<()> {
+        // these are in use during `wait`, so leave them alone then
+        if let Ok(mut stash) = self.stash.try_lock() {
+            stash.shrink_to(self.capacity());
+        }
+        if let Ok(mut scratch) = self.scratch.try_lock() {
+            scratch.truncate(1);
+            for scratch in scratch.iter_mut() {
+                scratch.shrink_to(self.capacity());
+            }
+            scratch.shrink_to_fit();
+        }
+
+        self.pending.shrink_to_fit()?;
+        if let Some(sequencer) = &self.sequencer {
+            lock!(sequencer, self.poison).shrink_to_fit();
+        }
+        if let Some(fences) = &self.fences {
+            lock!(fences, self.poison).shrink_to_fit();
+        }
+
+        self.inner.shrink_to_fit()
+    }
+
+    /// Get a snapshot of the operations in flight, with their sources
+    /// and ages.
+    ///
+    /// This returns `None` unless tracking was enabled with
+    /// `CompletionBuilder::track_pending`. Like `len_in_flight`, it
+    /// aborts on a poisoned lock with `PoisonPolicy::PropagateError`.
+    pub fn pending_snapshot(&self) -> Option<PendingSnapshot> {
+        self.pending.snapshot()
+    }
+
+    /// The number of bytes pinned by the operations in flight.
+    ///
+    /// This returns `None` unless a limit was set with
+    /// `CompletionBuilder::memory_limit`. Like `len_in_flight`, it aborts
+    /// on a poisoned lock with `PoisonPolicy::PropagateError`.
+    pub fn memory_in_use(&self) -> Option<usize> {
+        self.memory
+            .as_ref()
+            .map(|memory| lock!(memory, self.poison, infallible).in_use())
+    }
+
+    /// Describe the operation in flight with this key.
+    ///
+    /// This is meant for figuring out why an event never arrived. If the
+    /// key isn't in flight, the operation either completed or was never
+    /// submitted. If several operations in flight share the key, the
+    /// oldest one is described.
+    ///
+    /// This returns `None` unless tracking was enabled with
+    /// `CompletionBuilder::track_pending`. Like `len_in_flight`, it
+    /// aborts on a poisoned lock with `PoisonPolicy::PropagateError`.
+    pub fn debug_lookup(&self, key: u64) -> Option<OpDebugInfo> {
+        self.pending.lookup(key)
+    }
+
     /// Notify the completion, either interrupting a wait cycle or
     /// pre-empting the next wait cycle.
     pub fn notify(&self) -> Result<()> {
-        self.inner.notify()
+        if self.busy_poll.is_some() {
+            self.notified.store(true, Ordering::Release);
+        }
+        self.inner.notify()?;
+
+        #[cfg(unix)]
+        if self.has_unpark.load(Ordering::Acquire) {
+            if let Some(unparker) = &*lock!(self.unpark, self.poison) {
+                unparker.thread().unpark();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Unpark `thread` whenever there are events to harvest or `notify`
+    /// is called, or stop unparking with `None`.
+    ///
+    /// This is for executors that sleep with `thread::park` in their own
+    /// scheduler loop instead of in `wait`. Such a loop takes events with
+    /// `harvest_nonblocking` after every unpark. A helper thread waits on
+    /// the backend for as long as this is set, and unparks `thread` once
+    /// the backend wakes up; it waits for the next harvest before it looks
+    /// again, so the thread isn't unparked over and over for the same
+    /// events. Unparks can be spurious, so the loop must check for work
+    /// either way.
+    ///
+    /// This fails with `Unsupported` on Windows and with the thread per
+    /// operation backend, which can't be waited on from outside.
+    pub fn unpark_on_notify(&self, thread: Option<Thread>) -> Result<()> {
+        #[cfg(unix)]
+        {
+            let unparker = match thread {
+                Some(thread) => Some(unpark::Unparker::new(
+                    thread,
+                    self.inner.notifiers()?,
+                    self.poison,
+                )?),
+                None => None,
+            };
+
+            // the old watcher stops when it's dropped
+            let mut unpark = lock!(self.unpark, self.poison);
+            self.has_unpark.store(unparker.is_some(), Ordering::Release);
+            *unpark = unparker;
+            Ok(())
+        }
+
+        #[cfg(not(unix))]
+        match thread {
+            Some(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the backend can't be watched on this platform",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Let the watcher of `unpark_on_notify` know that the events were
+    /// harvested.
+    fn harvested(&self) -> Result<()> {
+        #[cfg(unix)]
+        if self.has_unpark.load(Ordering::Acquire) {
+            if let Some(unparker) = &*lock!(self.unpark, self.poison) {
+                unparker.harvested();
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the events that are ready, without blocking.
+    ///
+    /// This is `wait` with a timeout of zero, for loops that sleep
+    /// somewhere else; see `unpark_on_notify`. Taking the events lets the
+    /// thread be unparked again for the next ones.
+    pub fn harvest_nonblocking(&self, out: &mut Vec<Event>) -> Result<usize> {
+        self.wait(Some(Duration::ZERO), out)
+    }
+
+    /// Get a snapshot of the internal counters.
+    ///
+    /// This is only intended for interpreting benchmark results.
+    #[cfg(feature = "benchmark-internals")]
+    pub fn counters(&self) -> CounterSnapshot {
+        self.counters.snapshot()
     }
 }
 
 impl From<platform::Completion> for Completion {
     fn from(inner: platform::Completion) -> Self {
-        Completion { inner }
+        Completion {
+            #[cfg(unix)]
+            unpark: Mutex::new(None),
+            inner,
+            stash: Mutex::new(VecDeque::new()),
+            stashed: Condvar::new(),
+            stash_leader: AtomicBool::new(false),
+            scratch: Mutex::new(Vec::new()),
+            idle: Mutex::new(idle::IdleTimers::default()),
+            has_idle: AtomicBool::new(false),
+            rearmed: Mutex::new(rearm::Rearmed::default()),
+            has_rearmed: AtomicBool::new(false),
+            pending: pending::Pending::new(false, PoisonPolicy::Recover),
+            sequencer: None,
+            urgent: Mutex::new(priority::Urgent::default()),
+            has_urgent: AtomicBool::new(false),
+            memory: None,
+            coalescer: None,
+            fences: None,
+            busy_poll: None,
+            shrink_interval: None,
+            last_shrink: Mutex::new(Instant::now()),
+            #[cfg(feature = "tracing-spans")]
+            spans: Mutex::new(spans::Spans::default()),
+            notified: AtomicBool::new(false),
+            #[cfg(unix)]
+            has_unpark: AtomicBool::new(false),
+            poison: PoisonPolicy::Recover,
+            #[cfg(feature = "benchmark-internals")]
+            counters: Default::default(),
+        }
+    }
+}
+
+/// Lets another `wait_for_key` caller wait on the backend once dropped.
+struct StashLeader<'a>(&'a Completion);
+
+impl Drop for StashLeader<'_> {
+    fn drop(&mut self) {
+        // if the lock is poisoned, the others find out once they wake up
+        let _stash = self.0.stash.lock();
+        self.0.stash_leader.store(false, Ordering::Relaxed);
+        self.0.stashed.notify_all();
     }
 }
 
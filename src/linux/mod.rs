@@ -2,11 +2,14 @@
 
 #![cfg(target_os = "linux")]
 
-mod uring;
+pub(crate) mod uring;
 
-use std::{io::Result, time::Duration};
+use std::{io::Result, os::unix::io::AsRawFd, time::Duration};
 
-use crate::{ops::Op, polling, Event, Source};
+use crate::{
+    ops::Op, polling, Backend, Capabilities, CompletionBuilder, Event, Priority, Source,
+    SourceGroup, SourceType,
+};
 use io_uring::squeue::Entry as SEntry;
 
 /// This `OpData` is either a wrapper around the `polling`
@@ -15,13 +18,40 @@ use io_uring::squeue::Entry as SEntry;
 #[doc(hidden)]
 pub enum OpData<'a> {
     Polling(polling::OpData<'a>),
-    Entry(Option<SEntry>),
+    /// The entries for the operation, linked if there are several.
+    Entry(Vec<SEntry>),
+    /// An entry that's submitted again until the operation is done.
+    Resubmit(Resubmit),
+    /// A function run on the blocking pool, for operations that the
+    /// kernel can't perform.
+    Blocking(Blocking),
 }
 
+/// A function run on the blocking pool.
+#[doc(hidden)]
+pub struct Blocking(pub(crate) crate::PollingFn);
+
+/// An entry that's submitted again until the operation is done.
+#[doc(hidden)]
+pub struct Resubmit {
+    pub(crate) entry: SEntry,
+    /// Called with the result of every completion. Returns the result of
+    /// the operation once it's done, or `None` to submit the entry again,
+    /// which it may replace to move on to another stage.
+    pub(crate) done: Box<ResubmitFn>,
+}
+
+type ResubmitFn = dyn FnMut(i32, &mut SEntry) -> Option<Result<usize>> + Send;
+
 #[derive(Debug)]
 pub(crate) enum Completion {
     Polling(polling::Completion),
     Uring(uring::Completion),
+    /// Files go through `io_uring`, everything else is polled.
+    Hybrid(uring::Completion, Box<polling::Completion>),
+    /// Every operation runs on its own thread.
+    #[cfg(feature = "fallback-threads")]
+    Threads(crate::threads::Completion),
 }
 
 macro_rules! defer {
@@ -29,21 +59,66 @@ macro_rules! defer {
         match $self {
             Self::Polling(po) => po.$fnname $($arg)*,
             Self::Uring(uo) => uo.$fnname $($arg)*,
+            Self::Hybrid(_, po) => po.$fnname $($arg)*,
+            #[cfg(feature = "fallback-threads")]
+            Self::Threads(to) => to.$fnname $($arg)*,
         }
     }}
 }
 
 impl Completion {
-    pub(crate) fn new(capacity: usize) -> Result<Self> {
-        match uring::Completion::new(capacity) {
-            Ok(ur) => Ok(Completion::Uring(ur)),
+    pub(crate) fn new(builder: &CompletionBuilder) -> Result<Self> {
+        #[cfg(feature = "fallback-threads")]
+        if builder.fallback_threads {
+            return crate::threads::Completion::new(builder).map(Completion::Threads);
+        }
+
+        if !builder.io_uring {
+            tracing::debug!("io_uring is disabled, using polling");
+            return polling::Completion::new(builder).map(Completion::Polling);
+        }
+
+        let uring = match builder.injected_uring_failure() {
+            Some(e) => Err(e),
+            None => uring::Completion::new(builder),
+        };
+
+        match uring {
+            Ok(ur) => Self::with_uring(ur, builder),
             Err(e) => {
                 tracing::error!("Failed to create uring completion: {:?}", e);
-                polling::Completion::new(capacity).map(Completion::Polling)
+                polling::Completion::new(builder).map(Completion::Polling)
             }
         }
     }
 
+    /// Wrap a ring that was set up elsewhere.
+    ///
+    /// # Safety
+    ///
+    /// See `CompletionBuilder::build_from_uring`.
+    #[cfg(feature = "unstable-uring")]
+    pub(crate) unsafe fn from_raw_uring(
+        uring: io_uring::IoUring,
+        builder: &CompletionBuilder,
+    ) -> Result<Self> {
+        let ur = uring::Completion::adopt(uring, builder)?;
+        Self::with_uring(ur, builder)
+    }
+
+    /// Use the ring, along with a poller for everything else if the
+    /// backend is hybrid.
+    fn with_uring(ur: uring::Completion, builder: &CompletionBuilder) -> Result<Self> {
+        if !builder.hybrid {
+            return Ok(Completion::Uring(ur));
+        }
+
+        // wake up the poller whenever the ring has events
+        let mut po = polling::Completion::new(builder)?;
+        po.watch(ur.as_raw_fd())?;
+        Ok(Completion::Hybrid(ur, Box::new(po)))
+    }
+
     pub(crate) fn register(&self, source: &impl Source) -> Result<()> {
         defer!(self.register(source))
     }
@@ -52,15 +127,141 @@ impl Completion {
         defer!(self.deregister(source))
     }
 
-    pub(crate) fn submit(&self, op: &mut impl Op, key: u64) -> Result<crate::SubmissionStatus> {
-        defer!(self.submit(op, key))
+    pub(crate) fn register_group(&self, group: &SourceGroup) -> Result<()> {
+        defer!(self.register_group(group))
+    }
+
+    pub(crate) fn deregister_group(&self, group: &SourceGroup) -> Result<()> {
+        defer!(self.deregister_group(group))
+    }
+
+    pub(crate) fn cancel_group(&self, group: &SourceGroup) -> Result<()> {
+        defer!(self.cancel_group(group))
+    }
+
+    pub(crate) fn cancel(&self, key: u64) -> Result<()> {
+        match self {
+            Self::Polling(po) => po.cancel(key),
+            Self::Uring(uo) => uo.cancel(key),
+            #[cfg(feature = "fallback-threads")]
+            Self::Threads(to) => to.cancel(key),
+            // we don't know which one has it
+            Self::Hybrid(uo, po) => {
+                if !po.try_cancel(key)? {
+                    uo.cancel(key)?;
+                    uo.flush()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn submit(
+        &self,
+        op: &mut impl Op,
+        key: u64,
+        priority: Priority,
+    ) -> Result<crate::SubmissionStatus> {
+        match self {
+            Self::Hybrid(uo, _) if op.variant() == SourceType::File => {
+                // the waiter may be blocked on the poller, so submit
+                // the entry to the kernel right away
+                let status = uo.submit(op, key, priority)?;
+                uo.flush()?;
+                Ok(status)
+            }
+            _ => defer!(self.submit(op, key, priority)),
+        }
     }
 
     pub(crate) fn wait(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<usize> {
-        defer!(self.wait(timeout, out))
+        match self {
+            Self::Hybrid(uo, po) => {
+                // if the ring already has events, don't block
+                let mut count = uo.harvest(out)?;
+                let timeout = if count > 0 {
+                    Some(Duration::from_secs(0))
+                } else {
+                    timeout
+                };
+
+                count += po.wait(timeout, out)?;
+                count += uo.harvest(out)?;
+                Ok(count)
+            }
+            _ => defer!(self.wait(timeout, out)),
+        }
+    }
+
+    /// Whether any operations go through `io_uring`.
+    #[cfg(feature = "fault-injection")]
+    pub(crate) fn uses_io_uring(&self) -> bool {
+        matches!(self, Self::Uring(_) | Self::Hybrid(..))
+    }
+
+    /// The backend that performs operations on this kind of source.
+    pub(crate) fn backend_for(&self, variant: SourceType) -> Backend {
+        match self {
+            Self::Polling(_) => Backend::Polling,
+            Self::Uring(_) => Backend::IoUring,
+            Self::Hybrid(..) if variant == SourceType::File => Backend::IoUring,
+            Self::Hybrid(..) => Backend::Polling,
+            #[cfg(feature = "fallback-threads")]
+            Self::Threads(_) => Backend::Threads,
+        }
+    }
+
+    pub(crate) fn capabilities(&self) -> Capabilities {
+        match self {
+            Self::Uring(uo) | Self::Hybrid(uo, _) => uo.capabilities(),
+            _ => Capabilities::default(),
+        }
+    }
+
+    /// Get the `io_uring` part of this completion, if any.
+    pub(crate) fn uring(&self) -> Option<&uring::Completion> {
+        match self {
+            Self::Uring(uo) | Self::Hybrid(uo, _) => Some(uo),
+            _ => None,
+        }
     }
 
     pub(crate) fn notify(&self) -> Result<()> {
         defer!(self.notify())
     }
+
+    pub(crate) fn capacity(&self) -> usize {
+        defer!(self.capacity())
+    }
+
+    pub(crate) fn reserve(&self, additional: usize) -> Result<()> {
+        // in hybrid mode, sockets are polled, and only their side can grow
+        defer!(self.reserve(additional))
+    }
+
+    pub(crate) fn shrink_to_fit(&self) -> Result<()> {
+        match self {
+            Self::Polling(po) => po.shrink_to_fit(),
+            Self::Uring(uo) => uo.shrink_to_fit(),
+            #[cfg(feature = "fallback-threads")]
+            Self::Threads(to) => to.shrink_to_fit(),
+            Self::Hybrid(uo, po) => {
+                uo.shrink_to_fit()?;
+                po.shrink_to_fit()
+            }
+        }
+    }
+
+    /// Hand the entries staged for `io_uring` to the kernel.
+    pub(crate) fn flush(&self) -> Result<()> {
+        match self {
+            Self::Uring(uo) | Self::Hybrid(uo, _) => uo.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn notifiers(&self) -> Result<Vec<crate::Raw>> {
+        // in hybrid mode, the poller watches the ring
+        defer!(self.notifiers())
+    }
 }
@@ -1,26 +1,106 @@
 // GNU GPL v3 License
 
-use crate::{ops::Op, Event, Raw, Source, SubmissionStatus};
+use super::Resubmit;
+use crate::{
+    ops::{Op, IORING_OP_URING_CMD},
+    polling,
+    pool::BlockingPool,
+    retry::retry_interrupted,
+    Capabilities, CompletionBuilder, Event, PoisonPolicy, PollingFn, Priority, Raw, Source,
+    SourceGroup, SubmissionStatus,
+};
 use io_uring::{
     cqueue::Entry as CEvent,
+    opcode,
+    squeue::{Entry as SEntry, Flags},
     types::{Fd, SubmitArgs, Timespec},
-    IoUring,
+    IoUring, Probe, SubmissionQueue,
 };
 use std::{
     cell::UnsafeCell,
+    collections::{hash_map, HashMap},
     fmt,
     io::{self, Result},
-    mem::MaybeUninit,
-    ptr,
+    iter, mem,
+    os::unix::io::{AsRawFd, FromRawFd, OwnedFd},
+    ptr::{self, NonNull},
     sync::{
-        atomic::{AtomicBool, Ordering},
-        Mutex,
+        atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, TryLockError,
     },
-    time::{Duration, SystemTime},
+    time::Duration,
 };
 
+/// The user data for reads from the notification event FD.
 const ENTRY_KEY: u64 = u64::MAX;
 
+/// The user data for cancellation requests, whose own completions are
+/// discarded.
+const CANCEL_KEY: u64 = u64::MAX - 1;
+
+/// The user data for reads from the event FD of the blocking pool, see
+/// `BlockingOps`.
+const BLOCKING_KEY: u64 = u64::MAX - 2;
+
+/// The user data for the `NOP`s that take the place of entries the kernel
+/// rejected, whose completions are discarded.
+const REJECTED_KEY: u64 = u64::MAX - 3;
+
+/// The lowest of the keys above, which operations can't use.
+const FIRST_RESERVED_KEY: u64 = REJECTED_KEY;
+
+/// The offset to map the submission queue entries at, `IORING_OFF_SQES`.
+const OFF_SQES: libc::off_t = 0x1000_0000;
+
+/// `IORING_REGISTER_BUFFERS2` and `IORING_REGISTER_BUFFERS_UPDATE`, which
+/// `io-uring` doesn't wrap.
+const REGISTER_BUFFERS2: libc::c_uint = 15;
+const REGISTER_BUFFERS_UPDATE: libc::c_uint = 16;
+
+/// `struct io_uring_rsrc_register`.
+#[repr(C)]
+struct RsrcRegister {
+    nr: u32,
+    flags: u32,
+    resv2: u64,
+    data: u64,
+    tags: u64,
+}
+
+/// `struct io_uring_rsrc_update2`.
+#[repr(C)]
+struct RsrcUpdate {
+    offset: u32,
+    resv: u32,
+    data: u64,
+    tags: u64,
+    nr: u32,
+    resv2: u32,
+}
+
+/// Where the opcode is in a submission queue entry.
+const OPCODE_OFFSET: usize = 0;
+
+/// Where the flags are in a submission queue entry.
+const FLAGS_OFFSET: usize = 1;
+
+/// Where the file descriptor is in a submission queue entry.
+const FD_OFFSET: usize = 4;
+
+/// Where the user data is in a submission queue entry.
+const USER_DATA_OFFSET: usize = 32;
+
+/// The number of staging buffers that submitting threads are spread over.
+const STAGING_SHARDS: usize = 16;
+
+thread_local! {
+    /// The staging buffer that this thread submits to.
+    static STAGING_SHARD: usize = {
+        static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+        NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % STAGING_SHARDS
+    };
+}
+
 /// A completion-oriented I/O interface based on io_uring.
 pub(crate) struct Completion {
     /// The underlying interface to `io_uring`.
@@ -30,12 +110,42 @@ pub(crate) struct Completion {
     /// Holding this mutex grants exclusive access to the
     /// submission queue.
     submit_lock: Mutex<()>,
-    /// A buffer containing a buffer used for completion
-    /// events.
+    /// Entries waiting to be moved into the submission queue.
     ///
-    /// Holding this mutex grants exclusive access to the
-    /// completion queue.
-    complete_buffer: Mutex<Box<[MaybeUninit<CEvent>]>>,
+    /// Threads push their entries here instead of contending on
+    /// `submit_lock`, and whoever holds the lock moves them over in
+    /// batches. The holder checks for new entries after releasing it, so
+    /// an entry is in the submission queue by the time its `submit`
+    /// returns, or is moved there by the thread that was draining. Either
+    /// way, like any entry in the queue, it only reaches the kernel on the
+    /// next `wait` or `flush`, when the queue fills up, or right away if a
+    /// thread is blocked in `wait`.
+    staging: Box<[Mutex<Vec<SEntry>>]>,
+    /// Entries of high priority operations waiting to be moved into the
+    /// submission queue, ahead of those in `staging`.
+    urgent: Mutex<Vec<SEntry>>,
+    /// The number of entries in `staging` and `urgent`.
+    staged: AtomicUsize,
+    /// The number of threads in `wait`.
+    ///
+    /// A thread blocked in the kernel doesn't see entries pushed after it
+    /// went in, so submitting hands them over while this isn't zero.
+    waiting: AtomicUsize,
+    /// The number of entries pushed into the submission queue, which is
+    /// where its tail is. Only changed with `submit_lock` held.
+    ///
+    /// This starts at zero, so an adopted ring must never have been
+    /// submitted to.
+    sq_tail: AtomicU32,
+    /// The submission queue entries, if they could be mapped again.
+    sqes: Option<Sqes>,
+    /// Entries that the kernel refused to take, by their key, with the
+    /// negated error code.
+    rejected: Mutex<Vec<(u64, i32)>>,
+    /// The key whose entries the kernel seems to refuse, if one was
+    /// injected.
+    #[cfg(feature = "fault-injection")]
+    refused_key: Option<u64>,
     /// A file descriptor for the event FD, used to wake up the
     /// `uring` waiting.
     wakeup_fd: Raw,
@@ -46,6 +156,172 @@ pub(crate) struct Completion {
     wakeup_buffer: UnsafeCell<[u8; 8]>,
     /// A flag indicating whether this system has already been notified.
     notified: AtomicBool,
+    /// Operations made up of several linked entries, by their key.
+    chains: Mutex<HashMap<u64, Chain>>,
+    /// Operations whose entry is submitted again until they're done, by
+    /// their key.
+    resubmits: Mutex<HashMap<u64, Repeating>>,
+    /// Events taken from the completion queue that haven't been processed
+    /// yet, such as while submitting to make room for more.
+    ///
+    /// Holding this mutex grants exclusive access to the
+    /// completion queue.
+    reaped: Mutex<Vec<CEvent>>,
+    /// Threads for operations that the kernel can't perform.
+    pool: BlockingPool,
+    /// Operations that ran on `pool`.
+    blocking: Arc<BlockingOps>,
+    /// A buffer for the reads from the blocking event FD.
+    ///
+    /// Its contents are never looked at.
+    blocking_buffer: UnsafeCell<[u8; 8]>,
+    /// What to do when one of our mutexes is poisoned.
+    poison: PoisonPolicy,
+    /// Do we retry waits interrupted by a signal?
+    retry_interrupted: bool,
+    /// The optional operations that the kernel supports.
+    capabilities: Capabilities,
+    /// Whether the kernel supports each opcode.
+    opcodes: Box<[bool]>,
+    /// Whether each of the fixed buffer slots is filled in.
+    buffer_slots: Mutex<Vec<bool>>,
+    /// The fixed file slots, and the files in them.
+    ///
+    /// This is locked after the submission lock, when both are held.
+    file_slots: Mutex<FileSlots>,
+    /// The number of files in `file_slots`, so that submitting doesn't
+    /// lock it when there are none.
+    fixed_files: AtomicUsize,
+}
+
+/// The fixed file slots set aside by `CompletionBuilder::fixed_files`.
+#[derive(Default)]
+struct FileSlots {
+    /// The indices of the empty slots.
+    free: Vec<u32>,
+    /// The slot that each file is in.
+    files: HashMap<Raw, u32>,
+}
+
+/// Counts the current thread as waiting, until it's dropped.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn new(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::SeqCst);
+        Waiting(waiting)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The progress of an operation made up of several linked entries.
+struct Chain {
+    /// The number of entries that haven't completed yet.
+    remaining: usize,
+    /// The combined result so far.
+    result: Result<usize>,
+}
+
+impl Chain {
+    /// Account for the result of one of the entries.
+    fn complete(&mut self, result: i32) {
+        self.remaining -= 1;
+
+        match (&mut self.result, cqe_result(result)) {
+            // the rest of the chain is cancelled after a short transfer
+            (_, Err(e)) if e.raw_os_error() == Some(libc::ECANCELED) => {}
+            (Ok(total), Ok(n)) => *total += n,
+            (Ok(0), Err(e)) => self.result = Err(e),
+            _ => {}
+        }
+    }
+}
+
+/// The submission queue entries, mapped a second time so that an entry
+/// the kernel refuses can be taken out of the way.
+struct Sqes {
+    ptr: NonNull<SEntry>,
+    entries: u32,
+}
+
+impl Sqes {
+    fn map(uring: &IoUring) -> Result<Self> {
+        let entries = uring.params().sq_entries();
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                entries as usize * mem::size_of::<SEntry>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                uring.as_raw_fd(),
+                OFF_SQES,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Sqes {
+            ptr: NonNull::new(ptr.cast()).expect("mmap returned null"),
+            entries,
+        })
+    }
+
+    /// Has nothing ever been pushed into the ring?
+    ///
+    /// The kernel hands out the entries zeroed, and pushing one writes to
+    /// it, so a ring that was already used almost always has entries that
+    /// aren't zeroed anymore.
+    #[cfg(feature = "unstable-uring")]
+    fn untouched(&self) -> bool {
+        let len = self.entries as usize * mem::size_of::<SEntry>();
+
+        // SAFETY: the entries are mapped, and nothing is pushing to them yet
+        let bytes = unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().cast::<u8>(), len) };
+        bytes.iter().all(|&byte| byte == 0)
+    }
+
+    /// The entry that `position` in the ring refers to.
+    fn get(&self, position: u32) -> *mut SEntry {
+        // the entries are a power of two, and the ring's array maps each
+        // position to the entry with the same index
+        unsafe {
+            self.ptr
+                .as_ptr()
+                .add((position & (self.entries - 1)) as usize)
+        }
+    }
+}
+
+impl Drop for Sqes {
+    fn drop(&mut self) {
+        let len = self.entries as usize * mem::size_of::<SEntry>();
+        unsafe { libc::munmap(self.ptr.as_ptr().cast(), len) };
+    }
+}
+
+/// Operations that finished on the blocking pool.
+///
+/// Every submitted operation also submits a read from `fd`, an event FD in
+/// semaphore mode that's written once for every finished operation. Each
+/// completed read hands out one of the finished events.
+struct BlockingOps {
+    fd: OwnedFd,
+    finished: Mutex<Vec<Event>>,
+}
+
+/// An operation whose entry is submitted again until it's done.
+struct Repeating {
+    resubmit: Resubmit,
+    /// Was the operation cancelled while its entry wasn't in the kernel?
+    cancelled: bool,
+    /// The lane the entry is submitted in every time.
+    priority: Priority,
 }
 
 unsafe impl Send for Completion {}
@@ -61,23 +337,365 @@ impl fmt::Debug for Completion {
 }
 
 impl Completion {
-    pub(crate) fn new(capacity: usize) -> Result<Self> {
+    pub(crate) fn new(builder: &CompletionBuilder) -> Result<Self> {
+        let capacity = builder.capacity;
+        let uring = match builder.busy_poll {
+            Some(spin) => {
+                let mut sqpoll = IoUring::builder();
+                sqpoll.setup_sqpoll(spin.as_millis().min(u32::MAX as u128) as u32);
+                if let Some(cpu) = builder.busy_poll_cpu {
+                    sqpoll.setup_sqpoll_cpu(cpu);
+                }
+
+                match sqpoll.build(capacity as _) {
+                    // before Linux 5.11, the polling thread only takes
+                    // entries that use fixed files
+                    Ok(uring) if uring.params().is_feature_sqpoll_nonfixed() => uring,
+                    Ok(_) => {
+                        tracing::debug!("Submission polling needs fixed files, not using it");
+                        IoUring::new(capacity as _)?
+                    }
+                    Err(e) => {
+                        tracing::debug!("Failed to set up submission polling: {:?}", e);
+                        IoUring::new(capacity as _)?
+                    }
+                }
+            }
+            None => IoUring::new(capacity as _)?,
+        };
+
+        // some environments let us create the ring but then block
+        // everything after that, so make sure it's actually usable
+        let submitter = uring.submitter();
+        let mut probe = Probe::new();
+        submitter.register_probe(&mut probe)?;
+        for code in [opcode::Read::CODE, opcode::Write::CODE] {
+            if !probe.is_supported(code) {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "io_uring does not support the required operations",
+                ));
+            }
+        }
+        submitter.submit()?;
+
+        Self::with_ring(uring, Some(probe), builder)
+    }
+
+    /// Wrap a ring that was set up elsewhere.
+    ///
+    /// # Safety
+    ///
+    /// See `CompletionBuilder::build_from_uring`.
+    #[cfg(feature = "unstable-uring")]
+    pub(crate) unsafe fn adopt(uring: IoUring, builder: &CompletionBuilder) -> Result<Self> {
+        if uring.params().is_setup_iopoll() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "rings set up with IORING_SETUP_IOPOLL can't be used",
+            ));
+        }
+
+        // a ring with restrictions may not allow probing, in which case
+        // the kernel decides what it runs
+        let mut probe = Probe::new();
+        let probe = match uring.submitter().register_probe(&mut probe) {
+            Ok(()) => Some(probe),
+            Err(e) => {
+                tracing::debug!("Failed to probe the adopted ring: {:?}", e);
+                None
+            }
+        };
+
+        let completion = Self::with_ring(uring, probe, builder)?;
+
+        // `sq_tail` starts at zero, which is only where the tail is if
+        // nothing was ever submitted to the ring
+        if !completion.sqes.as_ref().is_none_or(Sqes::untouched) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "entries were already submitted to the ring",
+            ));
+        }
+
+        Ok(completion)
+    }
+
+    /// Set up everything around the ring.
+    fn with_ring(
+        uring: IoUring,
+        probe: Option<Probe>,
+        builder: &CompletionBuilder,
+    ) -> Result<Self> {
+        // waiting with a timeout passes it as an extended argument
+        if !uring.params().is_feature_ext_arg() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "io_uring does not support extended arguments to io_uring_enter",
+            ));
+        }
+
+        let capacity = builder.capacity;
+        let supported = |code| probe.as_ref().is_none_or(|probe| probe.is_supported(code));
+
+        let buffer_slots = match builder.fixed_buffers {
+            0 => Vec::new(),
+            slots => match register_empty_buffers(&uring, slots) {
+                Ok(()) => vec![false; slots as usize],
+                Err(e) => {
+                    tracing::debug!("Failed to register fixed buffer slots: {:?}", e);
+                    Vec::new()
+                }
+            },
+        };
+        let sparse_buffers = !buffer_slots.is_empty()
+            || (builder.fixed_buffers == 0 && supports_sparse_buffers(&uring));
+        let file_slots = match builder.fixed_files {
+            0 => FileSlots::default(),
+            slots => match uring.submitter().register_files(&vec![-1; slots as usize]) {
+                Ok(()) => FileSlots {
+                    // hand out the lowest slots first
+                    free: (0..slots).rev().collect(),
+                    files: HashMap::new(),
+                },
+                Err(e) => {
+                    tracing::debug!("Failed to register fixed file slots: {:?}", e);
+                    FileSlots::default()
+                }
+            },
+        };
+        let capabilities = Capabilities {
+            uring_cmd: probe
+                .as_ref()
+                .is_some_and(|probe| probe.is_supported(IORING_OP_URING_CMD)),
+            fixed_buffers: !buffer_slots.is_empty(),
+            sparse_buffers,
+            fixed_files: !file_slots.free.is_empty(),
+        };
+
+        let sqes = match Sqes::map(&uring) {
+            Ok(sqes) => Some(sqes),
+            Err(e) => {
+                tracing::debug!("Failed to map the submission queue entries: {:?}", e);
+                None
+            }
+        };
+
         Ok(Self {
-            uring: IoUring::new(capacity as _)?,
+            uring,
             submit_lock: Mutex::new(()),
-            complete_buffer: Mutex::new({
-                let mut v = Vec::with_capacity(capacity);
-                unsafe {
-                    v.set_len(capacity);
-                }
-                v.into_boxed_slice()
-            }),
+            staging: (0..STAGING_SHARDS)
+                .map(|_| Mutex::new(Vec::new()))
+                .collect(),
+            urgent: Mutex::new(Vec::new()),
+            staged: AtomicUsize::new(0),
+            waiting: AtomicUsize::new(0),
+            sq_tail: AtomicU32::new(0),
+            sqes,
+            rejected: Mutex::new(Vec::new()),
+            #[cfg(feature = "fault-injection")]
+            refused_key: builder.refused_key,
             wakeup_fd: syscall!(eventfd(0, libc::EFD_CLOEXEC))?,
             wakeup_buffer: [0u8; 8].into(),
             notified: AtomicBool::new(false),
+            chains: Mutex::new(HashMap::new()),
+            resubmits: Mutex::new(HashMap::new()),
+            reaped: Mutex::new(Vec::with_capacity(capacity)),
+            pool: BlockingPool::new(&builder.blocking, builder.poison),
+            blocking: Arc::new(BlockingOps {
+                fd: {
+                    let fd = syscall!(eventfd(0, libc::EFD_CLOEXEC | libc::EFD_SEMAPHORE))?;
+                    unsafe { OwnedFd::from_raw_fd(fd) }
+                },
+                finished: Mutex::new(Vec::new()),
+            }),
+            blocking_buffer: [0u8; 8].into(),
+            poison: builder.poison,
+            retry_interrupted: builder.retry_interrupted,
+            capabilities,
+            opcodes: (0..=u8::MAX).map(supported).collect(),
+            buffer_slots: Mutex::new(buffer_slots),
+            file_slots: Mutex::new(file_slots),
+            fixed_files: AtomicUsize::new(0),
         })
     }
 
+    pub(crate) fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Fill in an empty fixed buffer slot with `len` bytes at `ptr`, and
+    /// return its index.
+    ///
+    /// # Safety
+    ///
+    /// The memory must stay valid until it's unregistered, or the ring is
+    /// dropped.
+    pub(crate) unsafe fn register_buffer(&self, ptr: *mut u8, len: usize) -> Result<u16> {
+        let mut slots = lock!(self.buffer_slots, self.poison);
+        if slots.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "no fixed buffer slots were set aside",
+            ));
+        }
+
+        let index = slots.iter().position(|&used| !used).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::OutOfMemory,
+                "every fixed buffer slot is in use",
+            )
+        })?;
+
+        let iov = libc::iovec {
+            iov_base: ptr.cast(),
+            iov_len: len,
+        };
+        self.update_buffer(index as u32, &iov)?;
+
+        slots[index] = true;
+        Ok(index as u16)
+    }
+
+    /// Empty the fixed buffer slot at `index`.
+    ///
+    /// Operations in flight that use the buffer keep it until they
+    /// complete.
+    pub(crate) fn unregister_buffer(&self, index: u16) -> Result<()> {
+        let mut slots = lock!(self.buffer_slots, self.poison);
+        match slots.get(index as usize) {
+            Some(true) => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no buffer is registered at this index",
+                ))
+            }
+        }
+
+        let iov = libc::iovec {
+            iov_base: ptr::null_mut(),
+            iov_len: 0,
+        };
+        self.update_buffer(index as u32, &iov)?;
+
+        slots[index as usize] = false;
+        Ok(())
+    }
+
+    /// Put `iov` into the fixed buffer slot at `index`.
+    fn update_buffer(&self, index: u32, iov: &libc::iovec) -> Result<()> {
+        let update = RsrcUpdate {
+            offset: index,
+            resv: 0,
+            data: iov as *const libc::iovec as u64,
+            tags: 0,
+            nr: 1,
+            resv2: 0,
+        };
+
+        syscall!(syscall(
+            libc::SYS_io_uring_register,
+            self.uring.as_raw_fd(),
+            REGISTER_BUFFERS_UPDATE,
+            &update as *const RsrcUpdate,
+            mem::size_of::<RsrcUpdate>()
+        ))?;
+        Ok(())
+    }
+
+    /// Put a file into an empty fixed file slot, so that the reads, writes
+    /// and syncs on it use the slot instead of looking up its descriptor.
+    ///
+    /// This does nothing if no slots were set aside, or the file is already
+    /// in one, and only logs if they're all taken, since the file still
+    /// works without one.
+    pub(crate) fn register_file(&self, fd: Raw) -> Result<()> {
+        let mut slots = lock!(self.file_slots, self.poison);
+        if slots.files.contains_key(&fd) {
+            return Ok(());
+        }
+
+        let index = match slots.free.pop() {
+            Some(index) => index,
+            None => {
+                if !slots.files.is_empty() {
+                    tracing::debug!(fd, "Every fixed file slot is in use");
+                }
+                return Ok(());
+            }
+        };
+
+        if let Err(e) = self.submitter().register_files_update(index, &[fd]) {
+            slots.free.push(index);
+            return Err(e);
+        }
+
+        slots.files.insert(fd, index);
+        self.fixed_files.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Take a file out of its fixed file slot, if it's in one.
+    pub(crate) fn deregister_file(&self, fd: Raw) -> Result<()> {
+        if self.fixed_files.load(Ordering::SeqCst) == 0 {
+            return Ok(());
+        }
+
+        // nothing can be pushed for the slot while it's emptied
+        let _guard = lock!(self.submit_lock, self.poison);
+        let mut slots = lock!(self.file_slots, self.poison);
+        let index = match slots.files.get(&fd) {
+            Some(&index) => index,
+            None => return Ok(()),
+        };
+
+        // the entries that use the slot have to reach the kernel first, or
+        // they'd find whatever file goes into it next
+        self.submitter().submit()?;
+        self.submitter().register_files_update(index, &[-1])?;
+
+        slots.files.remove(&fd);
+        slots.free.push(index);
+        self.fixed_files.fetch_sub(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Point `entry` at the fixed file slot of its file, if it's in one
+    /// and the entry can use it.
+    fn with_fixed_file(&self, entry: &SEntry) -> Option<SEntry> {
+        if self.fixed_files.load(Ordering::SeqCst) == 0 || !uses_fd(entry) {
+            return None;
+        }
+
+        let mut entry = entry.clone();
+        // SAFETY: see `entry_opcode`
+        let sqe = unsafe { &mut *(&mut entry as *mut SEntry).cast::<[u8; 64]>() };
+        if sqe[FLAGS_OFFSET] & Flags::FIXED_FILE.bits() != 0 {
+            return None;
+        }
+
+        let mut fd = [0u8; 4];
+        fd.copy_from_slice(&sqe[FD_OFFSET..FD_OFFSET + 4]);
+        let index = *lock!(self.file_slots, self.poison, infallible)
+            .files
+            .get(&Raw::from_ne_bytes(fd))?;
+
+        sqe[FD_OFFSET..FD_OFFSET + 4].copy_from_slice(&index.to_ne_bytes());
+        sqe[FLAGS_OFFSET] |= Flags::FIXED_FILE.bits();
+        Some(entry)
+    }
+
+    /// Get the submitter, for registering things with the ring.
+    pub(crate) fn submitter(&self) -> io_uring::Submitter<'_> {
+        self.uring.submitter()
+    }
+
+    /// The parameters the ring was set up with.
+    pub(crate) fn params(&self) -> &io_uring::Parameters {
+        self.uring.params()
+    }
+
     pub(crate) fn register(&self, _source: &impl Source) -> Result<()> {
         // no op
         Ok(())
@@ -88,13 +706,66 @@ impl Completion {
         Ok(())
     }
 
-    pub(crate) fn submit(&self, op: &mut impl Op, key: u64) -> Result<SubmissionStatus> {
+    pub(crate) fn register_group(&self, _group: &SourceGroup) -> Result<()> {
+        // no op
+        Ok(())
+    }
+
+    pub(crate) fn deregister_group(&self, _group: &SourceGroup) -> Result<()> {
+        // no op
+        Ok(())
+    }
+
+    pub(crate) fn cancel_group(&self, _group: &SourceGroup) -> Result<()> {
+        // we don't keep track of which operations use which source
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "cancelling by source is not supported with io_uring",
+        ))
+    }
+
+    /// Ask the kernel to cancel the operation with `key`.
+    ///
+    /// The operation completes with `ECANCELED` if it was cancelled, or
+    /// as usual if it was too late.
+    pub(crate) fn cancel(&self, key: u64) -> Result<()> {
+        // this would cancel our own entries
+        check_key(key)?;
+
+        if let Some(repeating) = lock!(self.resubmits, self.poison).get_mut(&key) {
+            // its entry may be on its way back to the kernel
+            repeating.cancelled = true;
+        }
+
+        // a cancellation is worth getting in quickly
+        self.stage(
+            opcode::AsyncCancel::new(key).build().user_data(CANCEL_KEY),
+            Priority::High,
+        )?;
+        self.submit_staged()
+    }
+
+    pub(crate) fn submit(
+        &self,
+        op: &mut impl Op,
+        key: u64,
+        priority: Priority,
+    ) -> Result<SubmissionStatus> {
+        // its completion would be taken for one of ours
+        check_key(key)?;
+
         // feed it an OpData and see if it produces an SEvent
-        let mut opdata = super::OpData::Entry(None);
+        let mut opdata = super::OpData::Entry(Vec::new());
         op.run(&mut opdata)?;
 
-        let entry = match opdata {
-            super::OpData::Entry(Some(entry)) => entry.user_data(key),
+        let mut entries = match opdata {
+            super::OpData::Entry(entries) if !entries.is_empty() => entries,
+            super::OpData::Resubmit(resubmit) => {
+                return self.submit_resubmit(resubmit, key, priority)
+            }
+            super::OpData::Blocking(blocking) => {
+                return self.submit_blocking(blocking.0, key, priority)
+            }
             _ => {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidInput,
@@ -103,79 +774,766 @@ impl Completion {
             }
         };
 
-        // lock the submission queue and write to it
-        let _guard = lock!(self.submit_lock);
+        if entries.len() > 1 {
+            return self.submit_chain(entries, key);
+        }
+
+        // an older kernel can't run it, so fall back to the blocking pool
+        if !self.opcodes[entry_opcode(&entries[0]) as usize] {
+            let mut opdata = super::OpData::Polling(polling::OpData::new());
+            op.run(&mut opdata)?;
+
+            if let super::OpData::Polling(polling::OpData {
+                blocking: Some(blocking),
+                ..
+            }) = opdata
+            {
+                return self.submit_blocking(blocking, key, priority);
+            }
+        }
+
+        // stage the entry, then move it to the submission queue unless
+        // another thread is already doing that
+        self.stage(entries.remove(0).user_data(key), priority)?;
+        self.submit_staged()?;
+
+        Ok(SubmissionStatus::Submitted)
+    }
+
+    /// Submit several entries that run one after another, and complete
+    /// as a single event.
+    ///
+    /// The chain goes straight into the submission queue, so it doesn't
+    /// need a lane.
+    fn submit_chain(&self, entries: Vec<SEntry>, key: u64) -> Result<SubmissionStatus> {
+        let guard = lock!(self.submit_lock, self.poison);
+
+        if entries.len() > self.uring.params().sq_entries() as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "operation has more entries than the submission queue can hold",
+            ));
+        }
+
+        match lock!(self.chains, self.poison).entry(key) {
+            hash_map::Entry::Occupied(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "a linked operation with this key is already in flight",
+                ))
+            }
+            hash_map::Entry::Vacant(slot) => {
+                slot.insert(Chain {
+                    remaining: entries.len(),
+                    result: Ok(0),
+                });
+            }
+        }
+
+        // the chain is cut at the end of each submission, so it has to fit
+        // into the queue all at once
+        self.drain_staging(&guard)?;
         // SAFETY: with the guard held, we can write to the submission queue
         let mut queue = unsafe { self.uring.submission_shared() };
+        if queue.capacity() - queue.len() < entries.len() {
+            self.make_room(&mut queue)?;
+        }
 
-        // SAFETY: contract of Op guarantees "entry" is a valid entry
-        unsafe {
-            queue
-                .push(&entry)
+        let last = entries.len() - 1;
+        for (i, entry) in entries.into_iter().enumerate() {
+            let mut entry = entry.user_data(key);
+            if i < last {
+                entry = entry.flags(Flags::IO_LINK);
+            }
+
+            // SAFETY: contract of Op guarantees "entry" is a valid entry
+            unsafe { self.push(&mut queue, &entry) }
                 .map_err(|err| io::Error::new(io::ErrorKind::OutOfMemory, err))?;
         }
 
+        drop(queue);
+        drop(guard);
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            self.flush()?;
+        }
+
         Ok(SubmissionStatus::Submitted)
     }
 
-    pub(crate) fn wait(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<usize> {
-        // determine the timeout args
-        let mut sargs = SubmitArgs::new();
-        let timespec = timeout.map(|timeout| {
-            let target_time = SystemTime::now() + timeout;
-            let duration_since_epoch = target_time.duration_since(SystemTime::UNIX_EPOCH).unwrap();
-
-            // create a linux timespec
-            Timespec::new()
-                .sec(duration_since_epoch.as_secs())
-                .nsec(duration_since_epoch.subsec_nanos())
+    /// Submit an entry that's submitted again until the operation is done.
+    fn submit_resubmit(
+        &self,
+        resubmit: Resubmit,
+        key: u64,
+        priority: Priority,
+    ) -> Result<SubmissionStatus> {
+        let entry = resubmit.entry.clone().user_data(key);
+
+        match lock!(self.resubmits, self.poison).entry(key) {
+            hash_map::Entry::Occupied(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "a repeating operation with this key is already in flight",
+                ))
+            }
+            hash_map::Entry::Vacant(slot) => {
+                slot.insert(Repeating {
+                    resubmit,
+                    cancelled: false,
+                    priority,
+                });
+            }
+        }
+
+        self.stage(entry, priority)?;
+        self.submit_staged()?;
+
+        Ok(SubmissionStatus::Submitted)
+    }
+
+    /// Run an operation on the blocking pool.
+    fn submit_blocking(
+        &self,
+        mut blocking: PollingFn,
+        key: u64,
+        priority: Priority,
+    ) -> Result<SubmissionStatus> {
+        let ops = self.blocking.clone();
+        let poison = self.poison;
+
+        self.pool.spawn(move || {
+            let result = blocking.call();
+            lock!(ops.finished, poison, infallible).push(Event::new(key, result));
+
+            // completes one of the reads
+            let one = 1u64.to_ne_bytes();
+            if let Err(e) = syscall!(write(ops.fd.as_raw_fd(), one.as_ptr().cast(), 8)) {
+                tracing::error!("Failed to write to blocking event FD: {:?}", e);
+            }
+        })?;
+
+        let entry = opcode::Read::new(
+            Fd(self.blocking.fd.as_raw_fd()),
+            self.blocking_buffer.get() as *mut _,
+            8,
+        )
+        .build()
+        .user_data(BLOCKING_KEY);
+        self.stage(entry, priority)?;
+        self.submit_staged()?;
+
+        Ok(SubmissionStatus::Submitted)
+    }
+
+    /// Add an entry to this thread's staging buffer, or to the high
+    /// priority one.
+    fn stage(&self, entry: SEntry, priority: Priority) -> Result<()> {
+        if priority.is_high() {
+            lock!(self.urgent, self.poison).push(entry);
+        } else {
+            let shard = STAGING_SHARD.with(|shard| *shard);
+            lock!(self.staging[shard], self.poison).push(entry);
+        }
+        self.staged.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Move staged entries into the submission queue, unless another
+    /// thread holds the submission lock.
+    ///
+    /// The thread holding the lock checks for new entries after it
+    /// releases it, so entries are never left behind.
+    fn try_drain_staging(&self) -> Result<()> {
+        while self.staged.load(Ordering::SeqCst) > 0 {
+            let guard = match self.submit_lock.try_lock() {
+                Ok(guard) => guard,
+                Err(TryLockError::WouldBlock) => break,
+                Err(TryLockError::Poisoned(err)) => self.poison.handle(Err(err))?,
+            };
+
+            self.drain_staging(&guard)?;
+        }
+
+        Ok(())
+    }
+
+    /// Move staged entries into the submission queue, and hand them to
+    /// the kernel if a thread is blocked in `wait`.
+    ///
+    /// That thread counts itself as waiting before it drains the staged
+    /// entries, so either it takes them in, or we see it and flush.
+    fn submit_staged(&self) -> Result<()> {
+        self.try_drain_staging()?;
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Move all staged entries into the submission queue, high priority
+    /// ones first.
+    ///
+    /// If an entry can't be pushed, it and the rest of its shard are put
+    /// back, since their threads were already told they're submitted, and
+    /// the next drain tries them again.
+    fn drain_staging(&self, _guard: &MutexGuard<'_, ()>) -> Result<()> {
+        // SAFETY: with the guard held, we can write to the submission queue
+        let mut queue = unsafe { self.uring.submission_shared() };
+
+        // making room may stage entries that are submitted again
+        while self.staged.load(Ordering::SeqCst) > 0 {
+            for shard in iter::once(&self.urgent).chain(self.staging.iter()) {
+                let entries = mem::take(&mut *lock!(shard, self.poison));
+                self.staged.fetch_sub(entries.len(), Ordering::SeqCst);
+
+                let mut entries = entries.into_iter();
+                while let Some(entry) = entries.next() {
+                    if let Err(e) = self.push_or_make_room(&mut queue, &entry) {
+                        self.restage(shard, iter::once(entry).chain(entries));
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Push a staged entry into the submission queue, making room if it's
+    /// full.
+    fn push_or_make_room(&self, queue: &mut SubmissionQueue<'_>, entry: &SEntry) -> Result<()> {
+        // SAFETY: contract of Op guarantees "entry" is a valid entry
+        if unsafe { self.push(queue, entry) }.is_err() {
+            // the queue is full, so make room and try again
+            self.make_room(queue)?;
+
+            unsafe {
+                self.push(queue, entry)
+                    .map_err(|err| io::Error::new(io::ErrorKind::OutOfMemory, err))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Put entries that couldn't be pushed back at the front of their
+    /// staging buffer.
+    ///
+    /// This is already on an error path, and dropping the entries would
+    /// lose their events, so a poisoned lock aborts.
+    fn restage(&self, shard: &Mutex<Vec<SEntry>>, entries: impl Iterator<Item = SEntry>) {
+        let mut shard = lock!(shard, self.poison, infallible);
+        let staged_since = mem::take(&mut *shard);
+        shard.extend(entries);
+        self.staged.fetch_add(shard.len(), Ordering::SeqCst);
+        shard.extend(staged_since);
+    }
+
+    /// Push an entry into the submission queue, keeping track of its tail.
+    ///
+    /// # Safety
+    ///
+    /// Same as `SubmissionQueue::push`, and the submission lock must be
+    /// held.
+    unsafe fn push(
+        &self,
+        queue: &mut SubmissionQueue<'_>,
+        entry: &SEntry,
+    ) -> std::result::Result<(), io_uring::squeue::PushError> {
+        match self.with_fixed_file(entry) {
+            Some(entry) => queue.push(&entry)?,
+            None => queue.push(entry)?,
+        }
+        self.sq_tail.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// The entry at the head of the submission queue, with its key and
+    /// flags, if it can be looked at.
+    ///
+    /// The submission lock must be held.
+    fn head(&self, queue: &mut SubmissionQueue<'_>) -> Option<(*mut SEntry, u64, u8)> {
+        // the polling thread takes entries on its own, and would race us
+        let sqes = match &self.sqes {
+            Some(sqes) if !self.uring.params().is_setup_sqpoll() => sqes,
+            _ => return None,
+        };
+
+        queue.sync();
+        if queue.is_empty() {
+            return None;
+        }
+
+        let head = self
+            .sq_tail
+            .load(Ordering::Relaxed)
+            .wrapping_sub(queue.len() as u32);
+        let sqe = sqes.get(head);
+
+        // SAFETY: the kernel hasn't taken the entry, and we hold the lock
+        let (key, flags) = unsafe {
+            let bytes = sqe.cast::<u8>();
+            (
+                ptr::read_volatile(bytes.add(USER_DATA_OFFSET).cast::<u64>()),
+                ptr::read_volatile(bytes.add(FLAGS_OFFSET)),
+            )
+        };
+        Some((sqe, key, flags))
+    }
+
+    /// Fail the way the kernel does when it refuses the entry at the head
+    /// of the submission queue, if `CompletionBuilder::refuse_io_uring_key`
+    /// asked for that.
+    ///
+    /// The submission lock must be held.
+    fn check_refusal(&self, queue: &mut SubmissionQueue<'_>) -> Result<()> {
+        #[cfg(feature = "fault-injection")]
+        if let Some(refused) = self.refused_key {
+            if matches!(self.head(queue), Some((_, key, _)) if key == refused) {
+                return Err(io::Error::from_raw_os_error(libc::EINVAL));
+            }
+        }
+
+        let _ = queue;
+        Ok(())
+    }
+
+    /// Lock the submission queue, then see `check_refusal`.
+    fn check_refusal_locked(&self) -> Result<()> {
+        #[cfg(feature = "fault-injection")]
+        if self.refused_key.is_some() {
+            let _guard = lock!(self.submit_lock, self.poison);
+            // SAFETY: with the guard held, we can use the submission queue
+            let mut queue = unsafe { self.uring.submission_shared() };
+            return self.check_refusal(&mut queue);
+        }
+
+        Ok(())
+    }
+
+    /// Take the entry at the head of the submission queue out of the way
+    /// if the kernel refused to take it with `err`, so that the ones
+    /// behind it still go in.
+    ///
+    /// The kernel gives the same errors for a bad ring or bad arguments
+    /// to `io_uring_enter`, so the entry is only blamed if entering without
+    /// any entries works, and submitting it on its own fails the same way.
+    /// If it goes in after all, it's left there. Either way, the caller
+    /// should try again.
+    ///
+    /// A refused entry is replaced by a `NOP` whose completion is
+    /// discarded, and its operation fails with `err` on the next harvest.
+    /// Returns `false` if there's nothing to do about `err`. The
+    /// submission lock must be held.
+    fn reject_head(&self, queue: &mut SubmissionQueue<'_>, err: &io::Error) -> bool {
+        let (sqe, key, flags) = match self.head(queue) {
+            Some(head) => head,
+            None => return false,
+        };
+        if key == REJECTED_KEY {
+            // it isn't about the entries at all
+            return false;
+        }
+
+        let submitter = self.uring.submitter();
+        // SAFETY: there are no arguments, and the entries are valid
+        let entered = unsafe { submitter.enter::<libc::sigset_t>(0, 0, 0, None) }.and_then(|_| {
+            self.check_refusal(queue)?;
+            unsafe { submitter.enter::<libc::sigset_t>(1, 0, 0, None) }
         });
+        match entered {
+            Err(e) if e.raw_os_error() == err.raw_os_error() => {}
+            // it went in, so whatever went wrong, it wasn't the entry
+            Ok(1) => {
+                queue.sync();
+                return true;
+            }
+            _ => return false,
+        }
+
+        // keep the link, so that the rest of a chain still completes
+        let nop = opcode::Nop::new()
+            .build()
+            .flags(Flags::from_bits_truncate(flags))
+            .user_data(REJECTED_KEY);
+        unsafe { ptr::write_volatile(sqe, nop) };
+
+        tracing::debug!(key, "The kernel rejected an entry: {:?}", err);
+        let errno = err.raw_os_error().unwrap_or(libc::EINVAL);
+        lock!(self.rejected, self.poison, infallible).push((key, -errno));
+        true
+    }
+
+    /// Lock the submission queue and take the entry at its head out of the
+    /// way, see `reject_head`.
+    fn reject_head_locked(&self, err: &io::Error) -> Result<bool> {
+        let _guard = lock!(self.submit_lock, self.poison);
+        // SAFETY: with the guard held, we can write to the submission queue
+        let mut queue = unsafe { self.uring.submission_shared() };
+        Ok(self.reject_head(&mut queue, err))
+    }
+
+    /// Hand the entries in the full submission queue to the kernel.
+    ///
+    /// The kernel may refuse them while the completion queue is backed
+    /// up, which would leave a caller that submits in bursts stuck, so
+    /// the events in it are moved aside first, and handed out by the next
+    /// `wait`. The submission lock must be held.
+    fn make_room(&self, queue: &mut SubmissionQueue<'_>) -> Result<()> {
+        queue.sync();
+        let mut reaped = lock!(self.reaped, self.poison);
+        self.take_completions(&mut reaped);
+
+        loop {
+            let submitted = self
+                .check_refusal(queue)
+                .and_then(|()| self.uring.submitter().submit());
+            match submitted {
+                Ok(_) => break,
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => {
+                    // the kernel still has events we haven't seen
+                    self.take_completions(&mut reaped);
+                    self.uring.submitter().submit()?;
+                    break;
+                }
+                // try again without the entry it refused
+                Err(e) if is_rejection(&e) && self.reject_head(queue, &e) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        queue.sync();
+
+        // the polling thread takes the entries in its own time
+        if queue.is_full() && self.uring.params().is_setup_sqpoll() {
+            self.uring.submitter().squeue_wait()?;
+            queue.sync();
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn wait(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<usize> {
+        let _waiting = Waiting::new(&self.waiting);
+
+        // make sure everything staged reaches the kernel
+        self.drain_staging(&lock!(self.submit_lock, self.poison))?;
 
-        if let Some(ref timespec) = timespec {
-            sargs = sargs.timespec(timespec);
+        // don't block if submitting already turned up some events
+        if !lock!(self.reaped, self.poison).is_empty()
+            || !lock!(self.rejected, self.poison).is_empty()
+        {
+            return self.harvest(out);
         }
 
         // use the submitter to wait for completion events
         let submitter = self.uring.submitter();
-        submitter.submit_with_args(1, &sargs)?;
+        let entered = retry_interrupted(timeout, self.retry_interrupted, |timeout| {
+            self.check_refusal_locked()?;
+
+            // determine the timeout args
+            let mut sargs = SubmitArgs::new();
+            let timespec = timeout.map(|timeout| {
+                // the timeout is relative to now
+                Timespec::new()
+                    .sec(timeout.as_secs())
+                    .nsec(timeout.subsec_nanos())
+            });
+
+            if let Some(ref timespec) = timespec {
+                sargs = sargs.timespec(timespec);
+            }
+
+            match submitter.submit_with_args(1, &sargs) {
+                Ok(_) => Ok(()),
+                Err(e) if e.raw_os_error() == Some(libc::ETIME) => {
+                    // timed out, there may not be any events
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            }
+        });
+
+        match entered {
+            // if the kernel refused the first entry, its operation fails
+            // and the ones behind it go in without it
+            Err(e) if is_rejection(&e) => {
+                if !self.reject_head_locked(&e)? {
+                    return Err(e);
+                }
+                self.flush()?;
+            }
+            result => result?,
+        }
 
         // we now have at least one event, try reading all of them
-        let mut complete_buffer = lock!(self.complete_buffer);
-        // SAFETY: we own the mutex, we can access the buffer
+        self.harvest(out)
+    }
+
+    /// Get the underlying ring.
+    #[cfg(feature = "unstable-uring")]
+    pub(crate) fn raw(&self) -> &IoUring {
+        &self.uring
+    }
+
+    /// Push entries directly into the submission queue, then submit them
+    /// to the kernel.
+    ///
+    /// # Safety
+    ///
+    /// The entries must be valid until they complete, and must not use
+    /// a reserved key as their user data, see `FIRST_RESERVED_KEY`.
+    #[cfg(feature = "unstable-uring")]
+    pub(crate) unsafe fn with_submission<R>(
+        &self,
+        f: impl FnOnce(&mut io_uring::SubmissionQueue<'_>) -> R,
+    ) -> Result<R> {
+        let guard = lock!(self.submit_lock, self.poison);
+
+        // keep the order of entries that were staged before
+        self.drain_staging(&guard)?;
+
+        let result = {
+            let mut queue = self.uring.submission_shared();
+            let before = queue.len();
+            let result = f(&mut queue);
+
+            // keep track of the tail, for entries the kernel rejects
+            let pushed = queue.len().saturating_sub(before);
+            self.sq_tail.fetch_add(pushed as u32, Ordering::Relaxed);
+            result
+        };
+
+        drop(guard);
+        self.flush()?;
+        Ok(result)
+    }
+
+    /// Submit pending entries to the kernel without waiting.
+    pub(crate) fn flush(&self) -> Result<()> {
+        self.drain_staging(&lock!(self.submit_lock, self.poison))?;
+
+        loop {
+            let submitted = self
+                .check_refusal_locked()
+                .and_then(|()| self.uring.submitter().submit());
+            match submitted {
+                // try again without the entry it refused
+                Err(e) if is_rejection(&e) && self.reject_head_locked(&e)? => {}
+                result => return result.map(drop),
+            }
+        }
+    }
+
+    /// The handles that become readable when `wait` has something to do.
+    ///
+    /// Besides the ring itself, the event FDs may be written before their
+    /// reads reach the kernel.
+    pub(crate) fn notifiers(&self) -> Result<Vec<Raw>> {
+        Ok(vec![
+            self.uring.as_raw_fd(),
+            self.wakeup_fd,
+            self.blocking.fd.as_raw_fd(),
+        ])
+    }
+
+    /// Read all of the events currently in the completion queue, along
+    /// with any that were taken out of it while submitting.
+    ///
+    /// No locks are held while the events are processed, so operations
+    /// that are submitted again can run code that submits more of them.
+    pub(crate) fn harvest(&self, out: &mut Vec<Event>) -> Result<usize> {
+        let rejected = mem::take(&mut *lock!(self.rejected, self.poison));
+        let mut completions = {
+            let mut reaped = lock!(self.reaped, self.poison);
+            self.take_completions(&mut reaped);
+            mem::take(&mut *reaped)
+        };
+
+        let total = rejected.len() + completions.len();
+        let restaged = self.process(
+            rejected.into_iter().chain(
+                completions
+                    .drain(..)
+                    .map(|event| (event.user_data(), event.result())),
+            ),
+            out,
+        )?;
+
+        // hand the buffer back, unless it's already been replaced
+        let mut reaped = lock!(self.reaped, self.poison);
+        if reaped.is_empty() {
+            mem::swap(&mut *reaped, &mut completions);
+        }
+        drop(reaped);
+
+        if restaged {
+            self.flush()?;
+        }
+
+        Ok(total)
+    }
+
+    /// Move all of the events in the completion queue to `reaped`.
+    ///
+    /// `reaped` must be the contents of `self.reaped`, which grants
+    /// exclusive access to the completion queue.
+    fn take_completions(&self, reaped: &mut Vec<CEvent>) {
+        // SAFETY: we own the mutex, we can access the queue
         let mut queue = unsafe { self.uring.completion_shared() };
 
-        // read to the buffer
-        let completed_events = queue.fill(&mut complete_buffer).len();
-
-        // process the events
-        out.extend(
-            complete_buffer
-                .iter()
-                .take(completed_events)
-                .map(|event| {
-                    // SAFETY: we know the event is initialized
-                    unsafe { ptr::read(event.as_ptr()) }
-                })
-                .filter(|event| {
-                    // if the event is our filtered-out key,
-                    // unset the notified switch and discard it
-                    if event.user_data() == ENTRY_KEY {
-                        self.notified.store(false, Ordering::SeqCst);
-                        false
-                    } else {
-                        true
+        loop {
+            reaped.extend(&mut queue);
+
+            // more events may have arrived in the meantime
+            queue.sync();
+            if queue.is_empty() {
+                break;
+            }
+        }
+    }
+
+    /// Turn the keys and results of completion queue events into events
+    /// for operations.
+    ///
+    /// Entries that are submitted again are staged, and the return value
+    /// tells whether there were any; they still have to be flushed.
+    fn process(
+        &self,
+        completions: impl Iterator<Item = (u64, i32)>,
+        out: &mut Vec<Event>,
+    ) -> Result<bool> {
+        let mut restaged = false;
+
+        for (key, result) in completions {
+            match key {
+                // if the event is our filtered-out key, unset the notified
+                // switch and discard it
+                ENTRY_KEY => {
+                    self.notified.store(false, Ordering::SeqCst);
+                    continue;
+                }
+                // the cancelled operation completes by itself, and the
+                // rejected one already failed
+                CANCEL_KEY | REJECTED_KEY => continue,
+                BLOCKING_KEY => {
+                    if result < 0 {
+                        tracing::error!(
+                            "Failed to read blocking event FD: {:?}",
+                            cqe_result(result)
+                        );
+                    }
+                    out.extend(lock!(self.blocking.finished, self.poison).pop());
+                    continue;
+                }
+                _ => {}
+            }
+
+            if self.resubmit_done(key, result, out, &mut restaged)? {
+                continue;
+            }
+
+            // linked entries only produce an event once the last one
+            // completes
+            {
+                let mut chains = lock!(self.chains, self.poison);
+                if let Some(chain) = chains.get_mut(&key) {
+                    chain.complete(result);
+                    if chain.remaining == 0 {
+                        let chain = chains.remove(&key).unwrap();
+                        out.push(Event::new(key, chain.result));
                     }
-                })
-                .map(|event| Event {
-                    key: event.user_data(),
-                    result: match event.result() {
-                        -1 => Err(io::Error::last_os_error()),
-                        n => Ok(n as _),
-                    },
-                }),
-        );
+                    continue;
+                }
+            }
+
+            out.push(Event::new(key, cqe_result(result)));
+        }
+
+        Ok(restaged)
+    }
+
+    /// Tell a repeating operation that its entry completed, pushing its
+    /// event if it's done.
+    ///
+    /// Returns `false` if `key` isn't a repeating operation. Its function
+    /// runs without the lock held, while it stays in the map so it can be
+    /// cancelled.
+    fn resubmit_done(
+        &self,
+        key: u64,
+        result: i32,
+        out: &mut Vec<Event>,
+        restaged: &mut bool,
+    ) -> Result<bool> {
+        let (mut done, mut entry) = {
+            let mut resubmits = lock!(self.resubmits, self.poison);
+            let resubmit = match resubmits.get_mut(&key) {
+                Some(repeating) => &mut repeating.resubmit,
+                None => return Ok(false),
+            };
+            (
+                mem::replace(&mut resubmit.done, Box::new(|_, _| None)),
+                resubmit.entry.clone(),
+            )
+        };
+
+        let finished = done(result, &mut entry);
+
+        let mut resubmits = lock!(self.resubmits, self.poison);
+        let repeating = resubmits.get_mut(&key).unwrap();
+        let result = match finished {
+            Some(result) => result,
+            None if repeating.cancelled => cqe_result(-libc::ECANCELED),
+            None => {
+                repeating.resubmit.done = done;
+                repeating.resubmit.entry = entry.clone();
+                let priority = repeating.priority;
+                drop(resubmits);
+
+                self.stage(entry.user_data(key), priority)?;
+                *restaged = true;
+                return Ok(true);
+            }
+        };
+
+        resubmits.remove(&key);
+        out.push(Event::new(key, result));
+        Ok(true)
+    }
+
+    /// The number of entries in the submission queue.
+    pub(crate) fn capacity(&self) -> usize {
+        self.params().sq_entries() as usize
+    }
+
+    pub(crate) fn reserve(&self, _additional: usize) -> Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the io_uring queues can't be resized",
+        ))
+    }
+
+    /// Give back the memory that isn't needed for the operations there
+    /// are now.
+    ///
+    /// The rings themselves keep their size.
+    pub(crate) fn shrink_to_fit(&self) -> Result<()> {
+        for shard in iter::once(&self.urgent).chain(self.staging.iter()) {
+            lock!(shard, self.poison).shrink_to_fit();
+        }
+        lock!(self.rejected, self.poison).shrink_to_fit();
+        lock!(self.chains, self.poison).shrink_to_fit();
+        lock!(self.resubmits, self.poison).shrink_to_fit();
+        lock!(self.blocking.finished, self.poison).shrink_to_fit();
 
-        Ok(completed_events)
+        // `wait` holds on to this one while it reaps
+        if let Ok(mut reaped) = self.reaped.try_lock() {
+            let capacity = self.params().cq_entries() as usize;
+            reaped.shrink_to(capacity);
+        }
+
+        Ok(())
     }
 
     pub(crate) fn notify(&self) -> Result<()> {
@@ -185,25 +1543,122 @@ impl Completion {
             syscall!(write(self.wakeup_fd, notification.as_ptr().cast(), 8))?;
 
             // wait for an event to be read
-            let entry = io_uring::opcode::Read::new(
-                Fd(self.wakeup_fd),
-                self.wakeup_buffer.get() as *mut _,
-                8,
-            )
-            .build();
+            let entry =
+                opcode::Read::new(Fd(self.wakeup_fd), self.wakeup_buffer.get() as *mut _, 8)
+                    .build()
+                    .user_data(ENTRY_KEY);
 
-            // submit the entry
-            let _guard = lock!(self.submit_lock);
-            let mut queue = unsafe { self.uring.submission_shared() };
+            // hand the entry to the kernel, so a waiter blocked on it
+            // wakes up
+            self.stage(entry, Priority::Normal)?;
+            self.flush()?;
+        }
 
-            unsafe {
-                queue
-                    .push(&entry)
-                    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        Ok(())
+    }
+}
+
+/// Register `slots` empty fixed buffers, to be filled in later.
+fn register_empty_buffers(uring: &IoUring, slots: u16) -> Result<()> {
+    // empty entries leave the slot empty
+    let iovecs = vec![
+        libc::iovec {
+            iov_base: ptr::null_mut(),
+            iov_len: 0,
+        };
+        slots as usize
+    ];
+    let register = RsrcRegister {
+        nr: slots as u32,
+        flags: 0,
+        resv2: 0,
+        data: iovecs.as_ptr() as u64,
+        tags: 0,
+    };
+
+    syscall!(syscall(
+        libc::SYS_io_uring_register,
+        uring.as_raw_fd(),
+        REGISTER_BUFFERS2,
+        &register as *const RsrcRegister,
+        mem::size_of::<RsrcRegister>()
+    ))?;
+    Ok(())
+}
+
+/// Whether the kernel can register empty fixed buffer slots, checked by
+/// registering one and taking it back.
+fn supports_sparse_buffers(uring: &IoUring) -> bool {
+    match register_empty_buffers(uring, 1) {
+        Ok(()) => {
+            if let Err(e) = uring.submitter().unregister_buffers() {
+                tracing::debug!("Failed to unregister fixed buffer slot: {:?}", e);
             }
+            true
         }
+        Err(_) => false,
+    }
+}
 
-        Ok(())
+/// The opcode of a submission queue entry.
+fn entry_opcode(entry: &SEntry) -> u8 {
+    // SAFETY: an `Entry` is a transparent wrapper around the 64-byte
+    // `io_uring_sqe`, which is plain data
+    let sqe = unsafe { &*(entry as *const SEntry).cast::<[u8; 64]>() };
+    sqe[OPCODE_OFFSET]
+}
+
+/// Whether the only file of a submission queue entry is in its descriptor
+/// field, so that a fixed file slot can take its place.
+fn uses_fd(entry: &SEntry) -> bool {
+    matches!(
+        entry_opcode(entry),
+        opcode::Read::CODE
+            | opcode::Readv::CODE
+            | opcode::ReadFixed::CODE
+            | opcode::Write::CODE
+            | opcode::Writev::CODE
+            | opcode::WriteFixed::CODE
+            | opcode::Fsync::CODE
+    )
+}
+
+/// Fail for keys that are reserved for our own entries.
+fn check_key(key: u64) -> Result<()> {
+    if key >= FIRST_RESERVED_KEY {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "key {:#x} is reserved by the io_uring backend, use a key below {:#x}",
+                key, FIRST_RESERVED_KEY
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Is this an error the kernel gives for an entry it refuses to take?
+fn is_rejection(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EINVAL) | Some(libc::EBADF) | Some(libc::EFAULT) | Some(libc::EOPNOTSUPP)
+    )
+}
+
+/// Convert the result of a completion queue entry, which holds the
+/// negated error code if the operation failed.
+fn cqe_result(result: i32) -> Result<usize> {
+    if result < 0 {
+        Err(io::Error::from_raw_os_error(-result))
+    } else {
+        Ok(result as usize)
+    }
+}
+
+impl AsRawFd for Completion {
+    fn as_raw_fd(&self) -> Raw {
+        self.uring.as_raw_fd()
     }
 }
 
@@ -0,0 +1,107 @@
+// GNU GPL v3 License
+
+use crate::Event;
+use std::{
+    collections::HashMap,
+    io::{self, Result},
+};
+
+/// The memory pinned by operations in flight, and how much may be.
+#[derive(Debug)]
+pub(crate) struct Memory {
+    /// The most bytes that may be pinned at once.
+    limit: usize,
+    /// The bytes pinned right now.
+    in_use: usize,
+    /// The bytes pinned by the operations in flight with each key.
+    keys: HashMap<u64, Vec<usize>>,
+    /// The bytes pinned by rearmed operations, which keep them from their
+    /// first submission until their last event.
+    rearmed: HashMap<u64, usize>,
+}
+
+impl Memory {
+    pub(crate) fn new(limit: usize) -> Self {
+        Memory {
+            limit,
+            in_use: 0,
+            keys: HashMap::new(),
+            rearmed: HashMap::new(),
+        }
+    }
+
+    /// The bytes pinned right now.
+    pub(crate) fn in_use(&self) -> usize {
+        self.in_use
+    }
+
+    /// Record an operation that is about to be submitted, unless it would
+    /// pin more than the limit allows.
+    ///
+    /// An operation is always let through if nothing else is pinned, so
+    /// that one larger than the limit can still be submitted on its own.
+    pub(crate) fn submitted(&mut self, key: u64, bytes: usize) -> Result<()> {
+        if bytes == 0 {
+            return Ok(());
+        }
+
+        self.pin(bytes)?;
+        self.keys.entry(key).or_default().push(bytes);
+        Ok(())
+    }
+
+    /// Record a rearmed operation that is about to be submitted for the
+    /// first time.
+    ///
+    /// Its buffer stays in use between submissions, so the memory is only
+    /// released by `rearm_done`, not by its events.
+    pub(crate) fn rearmed(&mut self, key: u64, bytes: usize) -> Result<()> {
+        if bytes == 0 {
+            return Ok(());
+        }
+
+        self.pin(bytes)?;
+        self.rearmed.insert(key, bytes);
+        Ok(())
+    }
+
+    /// Release the memory of a rearmed operation that won't be submitted
+    /// again.
+    pub(crate) fn rearm_done(&mut self, key: u64) {
+        if let Some(bytes) = self.rearmed.remove(&key) {
+            self.in_use -= bytes;
+        }
+    }
+
+    /// Count `bytes` as pinned, unless it would go over the limit.
+    fn pin(&mut self, bytes: usize) -> Result<()> {
+        if self.in_use > 0 && self.in_use.saturating_add(bytes) > self.limit {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "memory limit for operations in flight reached",
+            ));
+        }
+
+        self.in_use += bytes;
+        Ok(())
+    }
+
+    /// Forget an operation that won't deliver an event.
+    pub(crate) fn cancelled(&mut self, key: u64) {
+        if let Some(sizes) = self.keys.get_mut(&key) {
+            if let Some(bytes) = sizes.pop() {
+                self.in_use -= bytes;
+            }
+            if sizes.is_empty() {
+                self.keys.remove(&key);
+            }
+        }
+    }
+
+    /// Release the memory of the operations that delivered these events.
+    pub(crate) fn completed(&mut self, events: &[Event]) {
+        for event in events {
+            self.cancelled(event.key);
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// GNU GPL v3 License
+
+use crate::{Completion, Event, Raw};
+use std::{
+    fmt,
+    io::{self, Result},
+    time::{Duration, Instant},
+};
+
+/// Wait on several `Completion`s at once.
+///
+/// This is for applications that ended up with more than one completion,
+/// for instance one per kind of device, but still want to block in a single
+/// place. It works by waiting on the handles that each completion uses to
+/// wake itself up.
+///
+/// With `io_uring`, operations submitted from another thread while a wait
+/// is in progress may only reach the kernel during the next wait; call
+/// `Completion::notify` after submitting to make sure they start.
+pub struct CompletionSet {
+    _private: (),
+}
+
+impl fmt::Debug for CompletionSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompletionSet").finish_non_exhaustive()
+    }
+}
+
+impl CompletionSet {
+    /// Wait until any of the completions has events, or is notified.
+    ///
+    /// Events are pushed to `out` along with the index of the completion
+    /// they came from. Returns the number of events received, which is
+    /// zero if the timeout expired or a completion was notified first.
+    pub fn wait_any(
+        completions: &[&Completion],
+        timeout: Option<Duration>,
+        out: &mut Vec<(usize, Event)>,
+    ) -> Result<usize> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut notifiers = Vec::new();
+        for completion in completions {
+            notifiers.extend(completion.inner.notifiers()?);
+        }
+
+        let count = Self::take_ready(completions, out)?;
+        if count > 0 {
+            return Ok(count);
+        }
+
+        // don't sleep past any idle deadline
+        let mut wake = deadline;
+        for completion in completions {
+            if let Some(next) = completion.next_idle_deadline()? {
+                wake = Some(wake.map_or(next, |wake| wake.min(next)));
+            }
+        }
+        let timeout = wake.map(|wake| wake.saturating_duration_since(Instant::now()));
+        wait_notifiers(&notifiers, timeout)?;
+
+        Self::take_ready(completions, out)
+    }
+
+    /// Take the events that are ready, without blocking.
+    fn take_ready(completions: &[&Completion], out: &mut Vec<(usize, Event)>) -> Result<usize> {
+        let mut events = Vec::new();
+        let mut count = 0;
+
+        for (i, completion) in completions.iter().enumerate() {
+            completion.wait(Some(Duration::ZERO), &mut events)?;
+            count += events.len();
+            out.extend(events.drain(..).map(|event| (i, event)));
+        }
+
+        Ok(count)
+    }
+}
+
+/// Block until one of the handles is signalled, or the timeout expires.
+#[cfg(unix)]
+fn wait_notifiers(notifiers: &[Raw], timeout: Option<Duration>) -> Result<()> {
+    let mut fds: Vec<_> = notifiers
+        .iter()
+        .map(|&fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+
+    match syscall!(poll(
+        fds.as_mut_ptr(),
+        fds.len() as _,
+        timeout_to_ms(timeout)
+    )) {
+        // the caller checks again either way
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => Ok(()),
+        result => result.map(drop),
+    }
+}
+
+/// Block until one of the handles is signalled, or the timeout expires.
+#[cfg(windows)]
+fn wait_notifiers(notifiers: &[Raw], timeout: Option<Duration>) -> Result<()> {
+    use windows_sys::Win32::{Foundation::WAIT_FAILED, System::Threading::WaitForMultipleObjects};
+
+    if notifiers.len() > 64 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "can't wait on more than 64 completions at once",
+        ));
+    }
+
+    let handles: Vec<_> = notifiers.iter().map(|&handle| handle as _).collect();
+    match unsafe {
+        WaitForMultipleObjects(
+            handles.len() as _,
+            handles.as_ptr(),
+            0,
+            timeout_to_ms(timeout) as _,
+        )
+    } {
+        WAIT_FAILED => Err(io::Error::last_os_error()),
+        // the caller checks again either way
+        _ => Ok(()),
+    }
+}
+
+/// Convert a timeout to milliseconds, rounding up so that it doesn't
+/// spin.
+fn timeout_to_ms(timeout: Option<Duration>) -> i32 {
+    match timeout {
+        None => -1,
+        Some(timeout) => {
+            let ms = timeout.as_micros().saturating_add(999) / 1000;
+            ms.min(i32::MAX as u128) as i32
+        }
+    }
+}
@@ -0,0 +1,643 @@
+// GNU GPL v3 License
+
+use super::{split_nonnull, TsPtr};
+use crate::{BufMut, PollingFn, Raw, Source, SourceType};
+use std::{
+    io::Result,
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    ptr::NonNull,
+};
+
+#[cfg(unix)]
+use std::os::unix::io::FromRawFd;
+#[cfg(windows)]
+use std::os::windows::io::FromRawSocket;
+#[cfg(windows)]
+use windows_sys::Win32::Networking::WinSock::{SOCKADDR_STORAGE, SOCKET};
+
+/// A socket as the OS hands it out.
+#[cfg(unix)]
+type Socket = libc::c_int;
+#[cfg(windows)]
+type Socket = SOCKET;
+
+#[cfg(unix)]
+const NO_SOCKET: Socket = -1;
+#[cfg(windows)]
+const NO_SOCKET: Socket = windows_sys::Win32::Networking::WinSock::INVALID_SOCKET;
+
+/// The space `AcceptEx` needs for each address.
+#[cfg(windows)]
+const ADDR_LEN: usize = std::mem::size_of::<SOCKADDR_STORAGE>() + 16;
+
+/// The accepted socket, boxed so that its address stays stable while the
+/// operation is in flight.
+#[doc(hidden)]
+pub struct State {
+    socket: Socket,
+    /// Put the accepted socket in non-blocking mode.
+    nonblocking: bool,
+    /// The peer's address, as `accept` writes it.
+    #[cfg(unix)]
+    addr: libc::sockaddr_storage,
+    #[cfg(unix)]
+    addr_len: libc::socklen_t,
+    /// The listener, for updating the accepted socket's context.
+    #[cfg(windows)]
+    listener: Socket,
+    /// `AcceptEx` writes the data and both addresses into one buffer.
+    #[cfg(windows)]
+    output: Vec<u8>,
+}
+
+impl State {
+    /// Get ready to accept a connection on `listener`.
+    fn new(listener: Raw) -> Box<Self> {
+        let _ = listener;
+        Box::new(State {
+            socket: NO_SOCKET,
+            nonblocking: true,
+            #[cfg(unix)]
+            addr: unsafe { std::mem::zeroed() },
+            #[cfg(unix)]
+            addr_len: std::mem::size_of::<libc::sockaddr_storage>() as _,
+            #[cfg(windows)]
+            listener: listener as usize as _,
+            #[cfg(windows)]
+            output: Vec::new(),
+        })
+    }
+
+    /// Take the accepted connection.
+    fn take(&mut self) -> TcpStream {
+        let socket = std::mem::replace(&mut self.socket, NO_SOCKET);
+
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] {
+                unsafe { TcpStream::from_raw_fd(socket) }
+            } else if #[cfg(windows)] {
+                use windows_sys::Win32::Networking::WinSock::{
+                    setsockopt, SOL_SOCKET, SO_UPDATE_ACCEPT_CONTEXT,
+                };
+
+                // let functions like getpeername work on the socket
+                let res = unsafe {
+                    setsockopt(
+                        socket,
+                        SOL_SOCKET as _,
+                        SO_UPDATE_ACCEPT_CONTEXT as _,
+                        &self.listener as *const _ as _,
+                        std::mem::size_of::<Socket>() as _,
+                    )
+                };
+                if res != 0 {
+                    tracing::debug!("Failed to update accept context: {:?}", std::io::Error::last_os_error());
+                }
+
+                let stream = unsafe { TcpStream::from_raw_socket(socket as _) };
+                if self.nonblocking {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        tracing::debug!("Failed to make the accepted socket non-blocking: {:?}", e);
+                    }
+                }
+                stream
+            }
+        }
+    }
+
+    /// The address of the peer that connected.
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] {
+                super::addr::from_raw(&self.addr, self.addr_len)
+            } else if #[cfg(windows)] {
+                use windows_sys::Win32::Networking::WinSock::{GetAcceptExSockaddrs, SOCKADDR};
+
+                // the operation never started
+                if self.output.is_empty() {
+                    return None;
+                }
+
+                let len = self.output.len() - 2 * ADDR_LEN;
+                let mut local: *mut SOCKADDR = std::ptr::null_mut();
+                let mut local_len = 0;
+                let mut remote: *mut SOCKADDR = std::ptr::null_mut();
+                let mut remote_len = 0;
+                unsafe {
+                    GetAcceptExSockaddrs(
+                        self.output.as_ptr().cast(),
+                        len as _,
+                        ADDR_LEN as _,
+                        ADDR_LEN as _,
+                        &mut local,
+                        &mut local_len,
+                        &mut remote,
+                        &mut remote_len,
+                    )
+                };
+
+                if remote.is_null() {
+                    None
+                } else {
+                    unsafe { from_win32_addr(remote, remote_len) }
+                }
+            }
+        }
+    }
+}
+
+/// Convert an address from `AcceptEx`'s buffer into a `SocketAddr`.
+#[cfg(windows)]
+unsafe fn from_win32_addr(
+    addr: *const windows_sys::Win32::Networking::WinSock::SOCKADDR,
+    len: i32,
+) -> Option<SocketAddr> {
+    use std::net::{Ipv6Addr, SocketAddrV4, SocketAddrV6};
+    use windows_sys::Win32::Networking::WinSock::{AF_INET, AF_INET6, SOCKADDR_IN, SOCKADDR_IN6};
+
+    let len = len as usize;
+    match (*addr).sa_family as u32 {
+        AF_INET if len >= std::mem::size_of::<SOCKADDR_IN>() => {
+            let sin = &*addr.cast::<SOCKADDR_IN>();
+            let ip = Ipv4Addr::from(sin.sin_addr.S_un.S_addr.to_ne_bytes());
+            Some(SocketAddrV4::new(ip, u16::from_be(sin.sin_port)).into())
+        }
+        AF_INET6 if len >= std::mem::size_of::<SOCKADDR_IN6>() => {
+            let sin6 = &*addr.cast::<SOCKADDR_IN6>();
+            let ip = Ipv6Addr::from(sin6.sin6_addr.u.Byte);
+            Some(
+                SocketAddrV6::new(
+                    ip,
+                    u16::from_be(sin6.sin6_port),
+                    sin6.sin6_flowinfo,
+                    sin6.Anonymous.sin6_scope_id,
+                )
+                .into(),
+            )
+        }
+        _ => None,
+    }
+}
+
+#[cfg(windows)]
+impl State {
+    /// Move the data `AcceptEx` received into the buffer.
+    fn copy_output<B: BufMut>(&self, mut buf: B, received: usize) -> B {
+        let (ptr, len) = split_nonnull(buf.pointer());
+        let received = received.min(len).min(self.output.len());
+        unsafe { std::ptr::copy_nonoverlapping(self.output.as_ptr(), ptr.as_ptr(), received) };
+        buf
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        // the operation failed after accepting, or was never completed
+        if self.socket != NO_SOCKET {
+            cfg_if::cfg_if! {
+                if #[cfg(unix)] {
+                    unsafe { libc::close(self.socket) };
+                } else if #[cfg(windows)] {
+                    unsafe { windows_sys::Win32::Networking::WinSock::closesocket(self.socket) };
+                }
+            }
+        }
+    }
+}
+
+/// Accept a connection and receive the first data sent over it.
+///
+/// This saves a round trip for servers where the client speaks first. The
+/// output is the accepted connection, the peer's address, the number of
+/// bytes received and the buffer. The address comes from the accept
+/// itself, so there's no need to call `peer_addr` on the connection.
+///
+/// With `io_uring`, the receive is submitted as soon as the accept
+/// completes, without going through `wait`. On Windows, this uses the
+/// receive built into `AcceptEx`. When polling for readiness, the receive
+/// waits on the blocking pool if there's no data yet.
+///
+/// The connection is close-on-exec and, unless `nonblocking` says
+/// otherwise, in non-blocking mode.
+pub struct AcceptAndRecv<B> {
+    source: Raw,
+    variant: SourceType,
+    buf: B,
+    state: Box<State>,
+}
+
+impl<B: BufMut + Send> AcceptAndRecv<B> {
+    /// Create a new `AcceptAndRecv` from a listening socket and a buffer to
+    /// receive into.
+    pub fn new<S: Source>(source: &S, buf: B) -> Self {
+        AcceptAndRecv {
+            source: source.as_raw(),
+            variant: S::SOURCE_TYPE,
+            buf,
+            state: State::new(source.as_raw()),
+        }
+    }
+
+    /// Set whether the accepted connection is in non-blocking mode.
+    ///
+    /// This is on by default, so that reading from the connection outside
+    /// of an operation can't stall the thread that waits for events. Turn
+    /// it off to use the connection with blocking I/O.
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut Self {
+        self.state.nonblocking = nonblocking;
+        self
+    }
+
+    /// Retrieve the inner buffer and the accepted socket.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffer is retrieved.
+    unsafe fn into_buf(self) -> (B, Box<State>) {
+        (self.buf, self.state)
+    }
+
+    /// The size of the buffer.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.buf)
+    }
+
+    #[cfg(unix)]
+    fn polling_function(&mut self) -> PollingFn {
+        let (ptr, len) = split_nonnull(self.buf.pointer());
+        let ptr = TsPtr(ptr);
+        let state = TsPtr(NonNull::from(&mut *self.state));
+        let listener = self.source;
+
+        PollingFn::new(move || {
+            let state = unsafe { &mut *state.0.as_ptr() };
+            if state.socket == NO_SOCKET {
+                state.socket = accept(
+                    listener,
+                    &mut state.addr,
+                    &mut state.addr_len,
+                    state.nonblocking,
+                )?;
+            }
+
+            // don't wait for data on the listener's readiness
+            match syscall!(recv(
+                state.socket,
+                ptr.0.as_ptr().cast(),
+                len,
+                libc::MSG_DONTWAIT
+            )) {
+                Ok(n) => Ok(n as _),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    Err(crate::polling::hand_off())
+                }
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    #[cfg(unix)]
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        let (ptr, len) = split_nonnull(self.buf.pointer());
+        let ptr = TsPtr(ptr);
+        let state = TsPtr(NonNull::from(&mut *self.state));
+
+        Some(PollingFn::new(move || {
+            let socket = unsafe { state.0.as_ref() }.socket;
+            let mut pollfd = libc::pollfd {
+                fd: socket,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+
+            loop {
+                match syscall!(poll(&mut pollfd, 1, -1)) {
+                    Ok(_) => break,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+
+            let n = syscall!(recv(socket, ptr.0.as_ptr().cast(), len, libc::MSG_DONTWAIT))?;
+            Ok(n as _)
+        }))
+    }
+
+    #[cfg(unix)]
+    const READ: bool = true;
+    #[cfg(unix)]
+    const WRITE: bool = false;
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> crate::linux::Resubmit {
+        use io_uring::{opcode, types::Fd};
+
+        let (ptr, len) = split_nonnull(self.buf.pointer());
+        let ptr = TsPtr(ptr);
+        let state = TsPtr(NonNull::from(&mut *self.state));
+
+        let addr: *mut libc::sockaddr_storage = &mut self.state.addr;
+        let addr_len: *mut libc::socklen_t = &mut self.state.addr_len;
+
+        crate::linux::Resubmit {
+            entry: opcode::Accept::new(Fd(self.source), addr.cast(), addr_len)
+                .flags(accept_flags(self.state.nonblocking))
+                .build(),
+            done: Box::new(move |result, entry| {
+                if result < 0 {
+                    return Some(Err(std::io::Error::from_raw_os_error(-result)));
+                }
+
+                // the accept finished, receive from the new socket
+                let state = unsafe { &mut *state.0.as_ptr() };
+                if state.socket == NO_SOCKET {
+                    state.socket = result;
+                    *entry = opcode::Recv::new(Fd(result), ptr.0.as_ptr(), len as _).build();
+                    return None;
+                }
+
+                Some(Ok(result as usize))
+            }),
+        }
+    }
+
+    #[cfg(windows)]
+    fn win32_start(&mut self, op_data: &mut crate::OpData<'_>) -> Result<Option<usize>> {
+        let len = split_nonnull(self.buf.pointer()).1;
+        accept_ex(&mut self.state, len, op_data.overlapped)
+    }
+}
+
+/// Accept a connection.
+///
+/// The output is the accepted connection and the peer's address, which
+/// comes from the accept itself.
+///
+/// The connection is close-on-exec and, unless `nonblocking` says
+/// otherwise, in non-blocking mode.
+pub struct Accept {
+    source: Raw,
+    variant: SourceType,
+    state: Box<State>,
+}
+
+impl Accept {
+    /// Create a new `Accept` from a listening socket.
+    pub fn new<S: Source>(source: &S) -> Self {
+        Accept {
+            source: source.as_raw(),
+            variant: S::SOURCE_TYPE,
+            state: State::new(source.as_raw()),
+        }
+    }
+
+    /// Set whether the accepted connection is in non-blocking mode.
+    ///
+    /// See `AcceptAndRecv::nonblocking`.
+    pub fn nonblocking(&mut self, nonblocking: bool) -> &mut Self {
+        self.state.nonblocking = nonblocking;
+        self
+    }
+
+    /// Retrieve the accepted socket.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the socket is retrieved.
+    unsafe fn into_buf(self) -> Box<State> {
+        self.state
+    }
+
+    #[cfg(unix)]
+    fn polling_function(&mut self) -> PollingFn {
+        let state = TsPtr(NonNull::from(&mut *self.state));
+        let listener = self.source;
+
+        PollingFn::new(move || {
+            let state = unsafe { &mut *state.0.as_ptr() };
+            state.socket = accept(
+                listener,
+                &mut state.addr,
+                &mut state.addr_len,
+                state.nonblocking,
+            )?;
+            Ok(0)
+        })
+    }
+
+    #[cfg(unix)]
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        None
+    }
+
+    #[cfg(unix)]
+    const READ: bool = true;
+    #[cfg(unix)]
+    const WRITE: bool = false;
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> crate::linux::Resubmit {
+        use io_uring::{opcode, types::Fd};
+
+        let state = TsPtr(NonNull::from(&mut *self.state));
+        let addr: *mut libc::sockaddr_storage = &mut self.state.addr;
+        let addr_len: *mut libc::socklen_t = &mut self.state.addr_len;
+
+        crate::linux::Resubmit {
+            entry: opcode::Accept::new(Fd(self.source), addr.cast(), addr_len)
+                .flags(accept_flags(self.state.nonblocking))
+                .build(),
+            done: Box::new(move |result, _| {
+                if result < 0 {
+                    return Some(Err(std::io::Error::from_raw_os_error(-result)));
+                }
+
+                // the state owns the socket from here on, even if the
+                // output is never taken
+                unsafe { (*state.0.as_ptr()).socket = result };
+                Some(Ok(0))
+            }),
+        }
+    }
+
+    #[cfg(windows)]
+    fn win32_start(&mut self, op_data: &mut crate::OpData<'_>) -> Result<Option<usize>> {
+        accept_ex(&mut self.state, 0, op_data.overlapped)
+    }
+}
+
+/// Start accepting a connection with `AcceptEx`, receiving up to `len`
+/// bytes along with it.
+#[cfg(windows)]
+fn accept_ex(
+    state: &mut State,
+    len: usize,
+    overlapped: *mut windows_sys::Win32::System::IO::OVERLAPPED,
+) -> Result<Option<usize>> {
+    use windows_sys::Win32::{
+        Foundation::ERROR_IO_PENDING,
+        Networking::WinSock::{
+            getsockname, AcceptEx, WSAGetLastError, WSASocketW, IPPROTO_TCP, SOCKADDR, SOCK_STREAM,
+            WSA_FLAG_OVERLAPPED,
+        },
+    };
+
+    // the accepted socket has to be created up front, in the same
+    // family as the listener
+    let mut addr: SOCKADDR_STORAGE = unsafe { std::mem::zeroed() };
+    let mut addr_len = std::mem::size_of::<SOCKADDR_STORAGE>() as i32;
+    if unsafe {
+        getsockname(
+            state.listener,
+            &mut addr as *mut _ as *mut SOCKADDR,
+            &mut addr_len,
+        )
+    } != 0
+    {
+        return Err(std::io::Error::from_raw_os_error(unsafe {
+            WSAGetLastError()
+        }));
+    }
+
+    let socket = unsafe {
+        WSASocketW(
+            addr.ss_family as _,
+            SOCK_STREAM as _,
+            IPPROTO_TCP,
+            std::ptr::null(),
+            0,
+            WSA_FLAG_OVERLAPPED,
+        )
+    };
+    if socket == NO_SOCKET {
+        return Err(std::io::Error::from_raw_os_error(unsafe {
+            WSAGetLastError()
+        }));
+    }
+    state.socket = socket;
+
+    state.output = vec![0; len + 2 * ADDR_LEN];
+    let mut received = 0;
+    let res = unsafe {
+        AcceptEx(
+            state.listener,
+            socket,
+            state.output.as_mut_ptr().cast(),
+            len as _,
+            ADDR_LEN as _,
+            ADDR_LEN as _,
+            &mut received,
+            overlapped,
+        )
+    };
+
+    if res == 0 {
+        let err = unsafe { WSAGetLastError() };
+        if err == ERROR_IO_PENDING as _ {
+            Ok(None)
+        } else {
+            Err(std::io::Error::from_raw_os_error(err))
+        }
+    } else {
+        Ok(Some(received as usize))
+    }
+}
+
+/// The flags to create accepted sockets with.
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "netbsd",
+    target_os = "openbsd",
+    target_os = "dragonfly",
+    target_os = "illumos",
+))]
+fn accept_flags(nonblocking: bool) -> libc::c_int {
+    if nonblocking {
+        libc::SOCK_CLOEXEC | libc::SOCK_NONBLOCK
+    } else {
+        libc::SOCK_CLOEXEC
+    }
+}
+
+/// Accept a connection without blocking, writing the peer's address.
+#[cfg(unix)]
+fn accept(
+    listener: Raw,
+    addr: &mut libc::sockaddr_storage,
+    addr_len: &mut libc::socklen_t,
+    nonblocking: bool,
+) -> Result<Socket> {
+    let addr: *mut libc::sockaddr = (addr as *mut libc::sockaddr_storage).cast();
+
+    cfg_if::cfg_if! {
+        if #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "netbsd",
+            target_os = "openbsd",
+            target_os = "dragonfly",
+            target_os = "illumos",
+        ))] {
+            syscall!(accept4(listener, addr, addr_len, accept_flags(nonblocking)))
+        } else {
+            let socket = syscall!(accept(listener, addr, addr_len))?;
+            let result = syscall!(fcntl(socket, libc::F_SETFD, libc::FD_CLOEXEC)).and_then(|_| {
+                if nonblocking {
+                    let flags = syscall!(fcntl(socket, libc::F_GETFL))?;
+                    syscall!(fcntl(socket, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+                }
+                Ok(())
+            });
+            if let Err(e) = result {
+                unsafe { libc::close(socket) };
+                return Err(e);
+            }
+            Ok(socket)
+        }
+    }
+}
+
+/// Hand out the connection, its peer's address and the buffer.
+fn finish<B: BufMut>(
+    received: usize,
+    (buf, mut state): (B, Box<State>),
+) -> (TcpStream, SocketAddr, usize, B) {
+    #[cfg(windows)]
+    let buf = state.copy_output(buf, received);
+
+    let (stream, peer) = connection(&mut state);
+    (stream, peer, received, buf)
+}
+
+/// Hand out the connection and its peer's address.
+fn connection(state: &mut State) -> (TcpStream, SocketAddr) {
+    let peer = state.peer_addr();
+    let stream = state.take();
+
+    // only a kernel that hands out odd addresses gets us here
+    let peer = match peer {
+        Some(peer) => peer,
+        None => stream
+            .peer_addr()
+            .unwrap_or_else(|_| (Ipv4Addr::UNSPECIFIED, 0).into()),
+    };
+
+    (stream, peer)
+}
+
+impl_op! {
+    <B: BufMut + Send> AcceptAndRecv: (B, Box<State>) => (TcpStream, SocketAddr, usize, B),
+    |result, captured| finish(result, captured),
+    pinned = pinned
+}
+
+impl_op! {
+    <> Accept: Box<State> => (TcpStream, SocketAddr),
+    |_result, state| {
+        let mut state = state;
+        connection(&mut state)
+    }
+}
@@ -0,0 +1,301 @@
+// GNU GPL v3 License
+
+#![cfg(unix)]
+
+use super::TsPtr;
+use crate::{BufPool, PollingFn, PooledBuf, Raw, Source, SourceType};
+use std::{io::Result, mem, ptr::NonNull};
+
+/// The buffer being read into, boxed so that its address stays stable
+/// while the operation is in flight.
+#[doc(hidden)]
+pub struct State {
+    pool: BufPool,
+    buf: Vec<u8>,
+    /// The size class of `buf`.
+    class: usize,
+    /// The number of bytes read so far.
+    filled: usize,
+    /// Does the source deliver whole messages?
+    datagram: bool,
+}
+
+impl State {
+    /// Is there a larger class to move to?
+    fn can_grow(&self) -> bool {
+        self.class + 1 < self.pool.classes()
+    }
+
+    /// Move the data read so far into a buffer of a larger class.
+    fn grow(&mut self, class: usize) {
+        let mut buf = self.pool.take(class);
+        buf[..self.filled].copy_from_slice(&self.buf[..self.filled]);
+        self.pool.put(mem::replace(&mut self.buf, buf));
+        self.class = class;
+    }
+
+    /// The part of the buffer that hasn't been read into yet.
+    fn spare(&mut self) -> (*mut u8, usize) {
+        let spare = &mut self.buf[self.filled..];
+        (spare.as_mut_ptr(), spare.len())
+    }
+
+    /// Account for `n` more bytes, growing the buffer if they filled it.
+    ///
+    /// Returns `true` once there's no point in reading any more.
+    fn advance(&mut self, n: usize) -> bool {
+        self.filled += n;
+        if n == 0 || self.filled < self.buf.len() || !self.can_grow() {
+            return true;
+        }
+
+        self.grow(self.class + 1);
+        false
+    }
+
+    /// Hand out the buffer with the data that was read.
+    fn finish(mut self, len: usize) -> PooledBuf {
+        PooledBuf::new(mem::take(&mut self.buf), len, self.pool.clone())
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        // the operation was never completed
+        if !self.buf.is_empty() {
+            self.pool.put(mem::take(&mut self.buf));
+        }
+    }
+}
+
+/// Read into a buffer from a pool, moving to a larger one if it fills up.
+///
+/// The read starts with the smallest buffer in the pool. For streams, if
+/// the data fills it entirely, it's moved into the next larger one and the
+/// read continues with whatever else is available right away. For
+/// datagrams, the datagram is peeked at first, and received into a buffer
+/// that fits it; datagrams larger than the largest buffer are truncated.
+/// This keeps memory use low when most messages are small. This is meant
+/// for sockets and pipes; files are read from their current position.
+///
+/// This isn't available on Windows, where the completion port has no way
+/// to read again into the grown buffer.
+pub struct ReadAdaptive {
+    source: Raw,
+    variant: SourceType,
+    state: Box<State>,
+}
+
+impl ReadAdaptive {
+    /// Create a new `ReadAdaptive` from the source and the pool to take
+    /// buffers from.
+    pub fn new<S: Source>(source: &S, pool: &BufPool) -> Self {
+        let source = source.as_raw();
+
+        ReadAdaptive {
+            source,
+            variant: S::SOURCE_TYPE,
+            state: Box::new(State {
+                buf: pool.take(0),
+                pool: pool.clone(),
+                class: 0,
+                filled: 0,
+                datagram: S::SOURCE_TYPE == SourceType::Socket && is_datagram(source),
+            }),
+        }
+    }
+
+    /// Retrieve the buffer.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffer is retrieved.
+    unsafe fn into_buf(self) -> Box<State> {
+        self.state
+    }
+
+    /// The size of the buffer being read into.
+    fn pinned(&self) -> usize {
+        self.state.buf.len()
+    }
+
+    /// Read from the start of the buffer again, keeping its size.
+    fn restart(&mut self) {
+        self.state.filled = 0;
+    }
+
+    /// Read from the source, growing the buffer as needed.
+    fn read_function(&mut self) -> PollingFn {
+        let state = TsPtr(NonNull::from(&mut *self.state));
+        let source = self.source;
+
+        if self.state.datagram {
+            return PollingFn::new(move || {
+                let state = unsafe { &mut *state.0.as_ptr() };
+                recv_datagram(source, state)
+            });
+        }
+
+        PollingFn::new(move || {
+            let state = unsafe { &mut *state.0.as_ptr() };
+
+            loop {
+                let (ptr, len) = state.spare();
+                match syscall!(read(source, ptr.cast(), len)) {
+                    Ok(n) => {
+                        if state.advance(n as usize) {
+                            return Ok(state.filled);
+                        }
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    // hand out what we have, the error comes up again
+                    Err(_) if state.filled > 0 => return Ok(state.filled),
+                    Err(e) => return Err(e),
+                }
+            }
+        })
+    }
+
+    fn polling_function(&mut self) -> PollingFn {
+        if self.variant == SourceType::File {
+            // always use the blocking pool
+            return PollingFn::new(|| Err(std::io::ErrorKind::WouldBlock.into()));
+        }
+
+        self.read_function()
+    }
+
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        Some(self.read_function())
+    }
+
+    const READ: bool = true;
+    const WRITE: bool = false;
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> crate::linux::Resubmit {
+        use io_uring::{opcode, types::Fd};
+
+        let socket = self.variant == SourceType::Socket;
+        let source = self.source;
+        let (ptr, len) = self.state.spare();
+        let state = TsPtr(NonNull::from(&mut *self.state));
+
+        if self.state.datagram {
+            // peek first, the result is the length of the whole datagram
+            let mut peeked = false;
+            return crate::linux::Resubmit {
+                entry: opcode::Recv::new(Fd(source), ptr, len as _)
+                    .flags(libc::MSG_PEEK | libc::MSG_TRUNC)
+                    .build(),
+                done: Box::new(move |result, entry| {
+                    if result < 0 {
+                        return Some(Err(std::io::Error::from_raw_os_error(-result)));
+                    }
+
+                    let state = unsafe { &mut *state.0.as_ptr() };
+                    if peeked {
+                        state.filled = result as usize;
+                        return Some(Ok(state.filled));
+                    }
+
+                    let class = state.pool.class_for(result as usize);
+                    if class > state.class {
+                        state.grow(class);
+                    }
+
+                    peeked = true;
+                    let (ptr, len) = state.spare();
+                    *entry = opcode::Recv::new(Fd(source), ptr, len as _).build();
+                    None
+                }),
+            };
+        }
+
+        // the follow-up reads only take what's already there
+        let read = move |ptr, len: usize, first: bool| {
+            if socket {
+                let flags = if first { 0 } else { libc::MSG_DONTWAIT };
+                opcode::Recv::new(Fd(source), ptr, len as _)
+                    .flags(flags)
+                    .build()
+            } else {
+                let flags = if first { 0 } else { libc::RWF_NOWAIT };
+                opcode::Read::new(Fd(source), ptr, len as _)
+                    .offset(-1)
+                    .rw_flags(flags)
+                    .build()
+            }
+        };
+
+        crate::linux::Resubmit {
+            entry: read(ptr, len, true),
+            done: Box::new(move |result, entry| {
+                let state = unsafe { &mut *state.0.as_ptr() };
+
+                if result < 0 {
+                    // hand out what we have, the error comes up again
+                    if state.filled > 0 {
+                        return Some(Ok(state.filled));
+                    }
+                    return Some(Err(std::io::Error::from_raw_os_error(-result)));
+                }
+
+                if state.advance(result as usize) {
+                    return Some(Ok(state.filled));
+                }
+
+                let (ptr, len) = state.spare();
+                *entry = read(ptr, len, false);
+                None
+            }),
+        }
+    }
+}
+
+/// Tell whether the socket delivers whole messages.
+pub(super) fn is_datagram(socket: Raw) -> bool {
+    let mut ty: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    match syscall!(getsockopt(
+        socket,
+        libc::SOL_SOCKET,
+        libc::SO_TYPE,
+        &mut ty as *mut _ as *mut _,
+        &mut len
+    )) {
+        Ok(_) => ty == libc::SOCK_DGRAM || ty == libc::SOCK_SEQPACKET,
+        Err(_) => false,
+    }
+}
+
+/// Receive a datagram into a buffer that fits it, peeking at it until one
+/// does.
+fn recv_datagram(source: Raw, state: &mut State) -> Result<usize> {
+    loop {
+        let mut iov = libc::iovec {
+            iov_base: state.buf.as_mut_ptr().cast(),
+            iov_len: state.buf.len(),
+        };
+        let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+        hdr.msg_iov = &mut iov;
+        hdr.msg_iovlen = 1;
+
+        syscall!(recvmsg(source, &mut hdr, libc::MSG_PEEK))?;
+        if hdr.msg_flags & libc::MSG_TRUNC != 0 && state.can_grow() {
+            state.grow(state.class + 1);
+            continue;
+        }
+
+        let n = syscall!(recv(source, iov.iov_base, iov.iov_len, 0))?;
+        state.filled = n as usize;
+        return Ok(state.filled);
+    }
+}
+
+impl_op! {
+    <> ReadAdaptive: Box<State> => PooledBuf, |result, state| state.finish(result),
+    pinned = pinned,
+    reset = restart
+}
@@ -0,0 +1,152 @@
+// GNU GPL v3 License
+
+use super::{Op, OpBase};
+use crate::{OpData, Raw, SourceType};
+use std::{any::Any, io::Result, ptr::NonNull};
+
+/// An object-safe version of `Op`.
+///
+/// Every `Op` with a `'static` captured value implements this trait, so
+/// operations of different types can be stored together as
+/// `Box<dyn AnyOp>`. A `Box<dyn AnyOp>` is itself an `Op` that can be
+/// submitted like any other; its output is the raw result along with the
+/// type-erased captured value, which can be downcast back into the
+/// original operation's `Captured` type.
+///
+/// # Safety
+///
+/// This is a sealed trait, only implemented on crate-specific types.
+pub unsafe trait AnyOp: OpBase {
+    /// The raw file descriptor that this operation is associated with.
+    #[doc(hidden)]
+    fn erased_source(&self) -> Raw;
+
+    /// The variant of the source.
+    #[doc(hidden)]
+    fn erased_variant(&self) -> SourceType;
+
+    /// The number of bytes the operation keeps in use.
+    #[doc(hidden)]
+    fn erased_pinned_bytes(&self) -> usize;
+
+    /// Get the operation ready to be submitted again.
+    fn erased_reset(&mut self);
+
+    /// Whether the operation is submitted again every time it completes.
+    #[doc(hidden)]
+    fn erased_is_rearmed(&self) -> bool;
+
+    /// Whether the operation is a `Barrier`.
+    #[doc(hidden)]
+    fn erased_is_barrier(&self) -> bool;
+
+    /// The bytes that the operation writes, if it can be merged.
+    #[doc(hidden)]
+    fn erased_coalescable(&mut self) -> Option<NonNull<[u8]>>;
+
+    /// The span that the operation runs in, if it's traced.
+    #[cfg(feature = "tracing-spans")]
+    #[doc(hidden)]
+    fn erased_span(&self) -> Option<&tracing::Span>;
+
+    /// Get the captured variables.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete at this point.
+    unsafe fn into_any_captured(self: Box<Self>) -> Box<dyn Any>;
+}
+
+unsafe impl<O: Op> AnyOp for O
+where
+    O::Captured: 'static,
+{
+    fn erased_source(&self) -> Raw {
+        Op::source(self)
+    }
+
+    fn erased_variant(&self) -> SourceType {
+        Op::variant(self)
+    }
+
+    fn erased_pinned_bytes(&self) -> usize {
+        Op::pinned_bytes(self)
+    }
+
+    fn erased_reset(&mut self) {
+        Op::reset(self)
+    }
+
+    fn erased_is_rearmed(&self) -> bool {
+        Op::is_rearmed(self)
+    }
+
+    fn erased_is_barrier(&self) -> bool {
+        Op::is_barrier(self)
+    }
+
+    fn erased_coalescable(&mut self) -> Option<NonNull<[u8]>> {
+        Op::coalescable(self)
+    }
+
+    #[cfg(feature = "tracing-spans")]
+    fn erased_span(&self) -> Option<&tracing::Span> {
+        Op::op_span(self)
+    }
+
+    unsafe fn into_any_captured(self: Box<Self>) -> Box<dyn Any> {
+        Box::new((*self).into_captured())
+    }
+}
+
+unsafe impl OpBase for Box<dyn AnyOp> {
+    fn run(&mut self, op_data: &mut OpData<'_>) -> Result<()> {
+        (**self).run(op_data)
+    }
+}
+
+unsafe impl Op for Box<dyn AnyOp> {
+    type Captured = Box<dyn Any>;
+    type Output = (usize, Box<dyn Any>);
+
+    fn source(&self) -> Raw {
+        (**self).erased_source()
+    }
+
+    fn variant(&self) -> SourceType {
+        (**self).erased_variant()
+    }
+
+    fn pinned_bytes(&self) -> usize {
+        (**self).erased_pinned_bytes()
+    }
+
+    fn reset(&mut self) {
+        (**self).erased_reset()
+    }
+
+    fn is_rearmed(&self) -> bool {
+        (**self).erased_is_rearmed()
+    }
+
+    fn is_barrier(&self) -> bool {
+        (**self).erased_is_barrier()
+    }
+
+    fn coalescable(&mut self) -> Option<NonNull<[u8]>> {
+        (**self).erased_coalescable()
+    }
+
+    #[cfg(feature = "tracing-spans")]
+    fn op_span(&self) -> Option<&tracing::Span> {
+        (**self).erased_span()
+    }
+
+    unsafe fn into_captured(self) -> Box<dyn Any> {
+        self.into_any_captured()
+    }
+
+    fn decode(result: usize, captured: Box<dyn Any>) -> Self::Output {
+        (result, captured)
+    }
+}
@@ -0,0 +1,157 @@
+// GNU GPL v3 License
+
+use crate::{PollingFn, Raw, Source, SourceType};
+use std::io::Result;
+
+/// Wait for every operation submitted before it on a source, and hold
+/// back the ones submitted after it.
+///
+/// The barrier starts once all of the operations on its source that were
+/// submitted before it have completed, and the operations submitted on
+/// the source after it only start once it has completed. With `sync_all`
+/// or `sync_data`, it also flushes the file to disk in between, which is
+/// what a write-ahead log needs between its records and its commit mark.
+///
+/// This works the same on every backend, and needs
+/// `CompletionBuilder::barriers`; otherwise, submitting it fails with
+/// `InvalidInput`. Only operations on the barrier's own source are
+/// ordered, so unlike `IOSQE_IO_DRAIN`, a read that waits on a socket
+/// doesn't hold it up. Cancelling an operation that's held back completes
+/// it with `Interrupted`.
+pub struct Barrier {
+    source: Raw,
+    variant: SourceType,
+    sync: SyncMode,
+}
+
+/// What the barrier flushes to disk.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SyncMode {
+    Nothing,
+    Data,
+    All,
+}
+
+impl Barrier {
+    /// Create a new `Barrier` for the source.
+    pub fn new<S: Source>(source: &S) -> Self {
+        Barrier {
+            source: source.as_raw(),
+            variant: S::SOURCE_TYPE,
+            sync: SyncMode::Nothing,
+        }
+    }
+
+    /// Flush the file's data and metadata to disk, like `fsync`.
+    ///
+    /// This has no effect for sources other than files.
+    pub fn sync_all(&mut self) -> &mut Self {
+        self.sync = SyncMode::All;
+        self
+    }
+
+    /// Flush the file's data to disk, like `fdatasync`.
+    ///
+    /// This has no effect for sources other than files. On Windows, the
+    /// metadata is flushed as well.
+    pub fn sync_data(&mut self) -> &mut Self {
+        self.sync = SyncMode::Data;
+        self
+    }
+
+    /// Does the barrier have to flush anything?
+    fn syncs(&self) -> bool {
+        self.sync != SyncMode::Nothing && self.variant == SourceType::File
+    }
+
+    /// There is nothing to retrieve.
+    ///
+    /// # Safety
+    ///
+    /// Always safe, only unsafe for consistency with other operations.
+    unsafe fn into_buf(self) {}
+
+    #[cfg(unix)]
+    fn polling_function(&mut self) -> PollingFn {
+        // flushing blocks, so do it on the blocking pool
+        if self.syncs() {
+            PollingFn::new(|| Err(std::io::ErrorKind::WouldBlock.into()))
+        } else {
+            PollingFn::new(|| Ok(0))
+        }
+    }
+
+    #[cfg(unix)]
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        let source = self.source;
+        let sync = self.sync;
+
+        Some(PollingFn::new(move || {
+            match sync {
+                SyncMode::Nothing => {}
+                #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+                SyncMode::Data => {
+                    syscall!(fdatasync(source))?;
+                }
+                _ => {
+                    syscall!(fsync(source))?;
+                }
+            }
+
+            Ok(0)
+        }))
+    }
+
+    // run the polling function, rather than completing right away
+    #[cfg(unix)]
+    const READ: bool = true;
+    #[cfg(unix)]
+    const WRITE: bool = false;
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> io_uring::squeue::Entry {
+        use io_uring::{opcode, types};
+
+        if !self.syncs() {
+            return opcode::Nop::new().build();
+        }
+
+        let flags = match self.sync {
+            SyncMode::Data => types::FsyncFlags::DATASYNC,
+            _ => types::FsyncFlags::empty(),
+        };
+        opcode::Fsync::new(types::Fd(self.source))
+            .flags(flags)
+            .build()
+    }
+
+    #[cfg(windows)]
+    fn win32_start(&mut self, op_data: &mut crate::OpData<'_>) -> Result<Option<usize>> {
+        use windows_sys::Win32::{
+            Storage::FileSystem::FlushFileBuffers, System::IO::PostQueuedCompletionStatus,
+        };
+
+        if !self.syncs() {
+            let res = unsafe { PostQueuedCompletionStatus(op_data.port, 0, 0, op_data.overlapped) };
+            return if res == 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(None)
+            };
+        }
+
+        // flushing can't be overlapped
+        let handle = self.source as usize;
+        crate::iocp::complete_on_thread(op_data, move || {
+            if unsafe { FlushFileBuffers(handle as _) } == 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(0)
+            }
+        })
+    }
+}
+
+impl_op! {
+    <> Barrier: () => (), |_result, _captured| (), barrier = true
+}
@@ -0,0 +1,192 @@
+// GNU GPL v3 License
+
+use crate::{PollingFn, Raw, Source, SourceType};
+use std::io::Result;
+
+/// How much is copied at once when the data goes through a buffer.
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// Copy a range of one file into another, without handing the data to
+/// userspace where the OS allows it.
+///
+/// On Linux, this is `copy_file_range`, which lets the file system share
+/// or copy the data on its own. Elsewhere, or across file systems that
+/// don't support it, the data is read and written a chunk at a time. It
+/// always runs on a separate thread, since neither `io_uring` nor IOCP
+/// can copy files. The result is the number of bytes copied, which is
+/// less than requested only if the end of the source file was reached.
+pub struct CopyFileRange {
+    source: Raw,
+    variant: SourceType,
+    to: Raw,
+    from_offset: i64,
+    to_offset: i64,
+    len: usize,
+}
+
+impl CopyFileRange {
+    /// Create a new `CopyFileRange` that copies `len` bytes from the start
+    /// of `from` to the start of `to`.
+    pub fn new<S: Source, T: Source>(from: &S, to: &T, len: usize) -> Self {
+        CopyFileRange {
+            source: from.as_raw(),
+            variant: SourceType::File,
+            to: to.as_raw(),
+            from_offset: 0,
+            to_offset: 0,
+            len,
+        }
+    }
+
+    /// Set the offset to copy from.
+    ///
+    /// Fails with `InvalidInput` if `offset` is larger than `i64::MAX`.
+    pub fn from_offset(&mut self, offset: u64) -> Result<&mut Self> {
+        self.from_offset = super::check_offset(offset)?;
+        Ok(self)
+    }
+
+    /// Set the offset to copy to.
+    ///
+    /// Fails with `InvalidInput` if `offset` is larger than `i64::MAX`.
+    pub fn to_offset(&mut self, offset: u64) -> Result<&mut Self> {
+        self.to_offset = super::check_offset(offset)?;
+        Ok(self)
+    }
+
+    /// There is nothing to retrieve.
+    ///
+    /// # Safety
+    ///
+    /// Always safe, only unsafe for consistency with other operations.
+    unsafe fn into_buf(self) {}
+
+    /// Copy the range on whatever thread this is called on.
+    fn copy_function(&mut self) -> PollingFn {
+        let (from, to, len) = (self.source as usize, self.to as usize, self.len);
+        let (from_offset, to_offset) = (self.from_offset, self.to_offset);
+
+        PollingFn::new(move || copy(from as _, to as _, from_offset, to_offset, len))
+    }
+
+    #[cfg(unix)]
+    fn polling_function(&mut self) -> PollingFn {
+        // always use the blocking pool
+        PollingFn::new(|| Err(std::io::ErrorKind::WouldBlock.into()))
+    }
+
+    #[cfg(unix)]
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        Some(self.copy_function())
+    }
+
+    #[cfg(unix)]
+    const READ: bool = true;
+    #[cfg(unix)]
+    const WRITE: bool = false;
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> PollingFn {
+        self.copy_function()
+    }
+
+    #[cfg(windows)]
+    fn win32_start(&mut self, op_data: &mut crate::OpData<'_>) -> Result<Option<usize>> {
+        let mut copy = self.copy_function();
+        crate::iocp::complete_on_thread(op_data, move || copy.call())
+    }
+}
+
+/// Copy `len` bytes, stopping early at the end of `from`.
+#[cfg(unix)]
+fn copy(from: Raw, to: Raw, mut from_offset: i64, mut to_offset: i64, len: usize) -> Result<usize> {
+    let mut done = 0;
+
+    #[cfg(target_os = "linux")]
+    while done < len {
+        let n = match syscall!(copy_file_range(
+            from,
+            &mut from_offset,
+            to,
+            &mut to_offset,
+            len - done,
+            0
+        )) {
+            Ok(0) => return Ok(done),
+            Ok(n) => n as usize,
+            // copy the rest through a buffer
+            Err(e)
+                if matches!(
+                    e.raw_os_error(),
+                    Some(libc::ENOSYS) | Some(libc::EXDEV) | Some(libc::EOPNOTSUPP)
+                ) =>
+            {
+                break
+            }
+            Err(e) => return Err(e),
+        };
+        done += n;
+    }
+
+    let mut buf = vec![0u8; CHUNK_LEN.min(len - done)];
+    while done < len {
+        let chunk = buf.len().min(len - done);
+        let read = syscall!(pread(from, buf.as_mut_ptr().cast(), chunk, from_offset))? as usize;
+        if read == 0 {
+            break;
+        }
+
+        let mut written = 0;
+        while written < read {
+            let n = syscall!(pwrite(
+                to,
+                buf[written..].as_ptr().cast(),
+                read - written,
+                to_offset + written as i64
+            ))?;
+            written += n as usize;
+        }
+
+        from_offset += read as i64;
+        to_offset += read as i64;
+        done += read;
+    }
+
+    Ok(done)
+}
+
+/// Copy `len` bytes, stopping early at the end of `from`.
+#[cfg(windows)]
+fn copy(from: Raw, to: Raw, from_offset: i64, to_offset: i64, len: usize) -> Result<usize> {
+    // the handles may be overlapped, so every transfer waits on an event
+    super::with_event(|event| {
+        let mut buf = vec![0u8; CHUNK_LEN.min(len)];
+        let mut done = 0;
+
+        while done < len {
+            let chunk = buf.len().min(len - done);
+            let offset = (from_offset + done as i64) as u64;
+            let ptr = buf.as_mut_ptr();
+            let read = super::overlapped_transfer(from, event, offset, ptr, chunk, false)?;
+            if read == 0 {
+                break;
+            }
+
+            let mut written = 0;
+            while written < read {
+                let offset = (to_offset + (done + written) as i64) as u64;
+                let ptr = buf[written..].as_mut_ptr();
+                let len = read - written;
+                written += super::overlapped_transfer(to, event, offset, ptr, len, true)?;
+            }
+
+            done += read;
+        }
+
+        Ok(done)
+    })
+}
+
+impl_op! {
+    <> CopyFileRange: () => usize, |result, _captured| result
+}
@@ -0,0 +1,253 @@
+// GNU GPL v3 License
+
+use super::{Op, OpBase};
+use crate::{OpData, PollingFn, Raw, SourceType};
+use std::io::Result;
+
+/// A function that performs a custom operation.
+pub type CustomFn = Box<dyn FnMut() -> Result<usize> + Send + Sync>;
+
+/// An operation defined outside of this crate.
+///
+/// This lets other crates add operations without patching this one. The
+/// operation is described by a function that performs it without
+/// blocking, which is called whenever the source is ready, along with an
+/// `io_uring` entry or a Windows start function where one can do better.
+/// Wrap it in a `Custom` to submit it.
+///
+/// # Safety
+///
+/// The functions and entries may run until the operation's event is
+/// received, on other threads, and the operation may move in the
+/// meantime. Anything they point to must stay valid and in place until
+/// then, such as a boxed buffer that the operation owns. `source` and
+/// `variant` must accurately describe a source that stays open until then.
+pub unsafe trait CustomOp: Send {
+    /// What the operation hands out once it's complete.
+    type Output;
+
+    /// Wait for the source to be readable before polling.
+    const READABLE: bool = false;
+    /// Wait for the source to be writable before polling.
+    const WRITABLE: bool = false;
+
+    /// The source that the operation uses.
+    fn source(&self) -> Raw;
+
+    /// The variant of the source.
+    fn variant(&self) -> SourceType;
+
+    /// The number of bytes the operation keeps in use while it's in
+    /// flight.
+    ///
+    /// See `Op::pinned_bytes`.
+    fn pinned_bytes(&self) -> usize {
+        0
+    }
+
+    /// Get the operation ready to be submitted again, once it's complete.
+    ///
+    /// See `Op::reset`.
+    fn reset(&mut self) {}
+
+    /// Create the function that performs the operation without blocking.
+    ///
+    /// It returns `WouldBlock` to be called again once the source is
+    /// ready, and the result of the operation otherwise. If neither
+    /// `READABLE` nor `WRITABLE` is set, it's only called once.
+    fn polling_function(&mut self) -> CustomFn;
+
+    /// Create a function that performs the operation, blocking if needed.
+    ///
+    /// This is used for files, which can't be waited on, and runs on the
+    /// blocking pool. By default, another polling function is used.
+    fn blocking_function(&mut self) -> Option<CustomFn> {
+        None
+    }
+
+    /// Create an `io_uring` entry that performs the operation.
+    ///
+    /// Its user data is overwritten. By default, there's none, and
+    /// `io_uring` waits for the source to be ready and then calls the
+    /// polling function instead.
+    ///
+    /// This is semver-exempt, and only available with the
+    /// `unstable-uring` feature.
+    #[cfg(all(target_os = "linux", feature = "unstable-uring"))]
+    fn uring_entry(&mut self) -> Option<io_uring::squeue::Entry> {
+        None
+    }
+
+    /// Start the operation on Windows.
+    ///
+    /// `overlapped` points to the `OVERLAPPED` to start it with. Returns
+    /// `None` if it's pending, and the result if it completed right away.
+    ///
+    /// If this fails with `Unsupported`, which it does by default, the
+    /// polling function is used instead. It's called whenever a socket is
+    /// ready, which is found out through the AFD driver, so the socket
+    /// doesn't need to be overlapped. Other sources, and operations that
+    /// don't wait for readiness, run on the blocking pool.
+    #[cfg(windows)]
+    fn win32_start(&mut self, overlapped: *mut std::ffi::c_void) -> Result<Option<usize>> {
+        let _ = overlapped;
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+
+    /// Build the output from the result of the operation.
+    fn finish(self, result: usize) -> Self::Output;
+}
+
+/// Submit a `CustomOp`.
+#[derive(Debug)]
+pub struct Custom<T> {
+    inner: T,
+}
+
+impl<T: CustomOp> Custom<T> {
+    /// Wrap a custom operation so it can be submitted.
+    pub fn new(op: T) -> Self {
+        Custom { inner: op }
+    }
+
+    /// Create the functions that perform the operation.
+    fn functions(&mut self) -> (PollingFn, PollingFn) {
+        let poll = PollingFn::new(self.inner.polling_function());
+        let blocking = match self.inner.blocking_function() {
+            Some(blocking) => blocking,
+            None => self.inner.polling_function(),
+        };
+
+        (poll, PollingFn::new(blocking))
+    }
+
+    /// Wait for readiness with `io_uring`, then poll.
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> crate::linux::Resubmit {
+        use io_uring::{opcode, types::Fd};
+
+        let (mut poll, _) = self.functions();
+        let mut mask = 0;
+        if T::READABLE {
+            mask |= libc::POLLIN;
+        }
+        if T::WRITABLE {
+            mask |= libc::POLLOUT;
+        }
+
+        crate::linux::Resubmit {
+            entry: opcode::PollAdd::new(Fd(self.inner.source()), mask as _).build(),
+            done: Box::new(move |result, _| {
+                if result < 0 {
+                    return Some(Err(std::io::Error::from_raw_os_error(-result)));
+                }
+
+                match poll.call() {
+                    // wait for readiness again
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => None,
+                    result => Some(result),
+                }
+            }),
+        }
+    }
+
+    /// Call the polling function whenever the socket is ready, or run the
+    /// blocking function on the blocking pool.
+    #[cfg(windows)]
+    fn win32_poll(&mut self, op_data: &mut OpData<'_>) -> Result<Option<usize>> {
+        let (poll, mut blocking) = self.functions();
+        let waits = T::READABLE || T::WRITABLE;
+        if self.inner.variant() != SourceType::Socket || !waits {
+            return crate::iocp::complete_on_thread(op_data, move || blocking.call());
+        }
+
+        let mut events = 0;
+        if T::READABLE {
+            events |= crate::afd::READABLE;
+        }
+        if T::WRITABLE {
+            events |= crate::afd::WRITABLE;
+        }
+
+        op_data.poll_ready(self.inner.source(), events, poll)
+    }
+}
+
+unsafe impl<T: CustomOp> OpBase for Custom<T> {
+    fn run(&mut self, op_data: &mut OpData<'_>) -> Result<()> {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                use super::UringEntries;
+
+                match op_data {
+                    OpData::Polling(poll) => {
+                        let (slot, blocking) = self.functions();
+                        poll.slot = Some(slot);
+                        poll.blocking = Some(blocking);
+                        poll.read = T::READABLE;
+                        poll.write = T::WRITABLE;
+                    }
+                    op_data => {
+                        #[cfg(feature = "unstable-uring")]
+                        if let Some(entry) = self.inner.uring_entry() {
+                            entry.install(op_data);
+                            return Ok(());
+                        }
+
+                        let waits = T::READABLE || T::WRITABLE;
+                        if self.inner.variant() == SourceType::File || !waits {
+                            self.functions().1.install(op_data);
+                        } else {
+                            self.uring_entry().install(op_data);
+                        }
+                    }
+                }
+            } else if #[cfg(unix)] {
+                let (slot, blocking) = self.functions();
+                op_data.slot = Some(slot);
+                op_data.blocking = Some(blocking);
+                op_data.read = T::READABLE;
+                op_data.write = T::WRITABLE;
+            } else if #[cfg(windows)] {
+                let res = match self.inner.win32_start(op_data.overlapped.cast()) {
+                    Err(e) if e.kind() == std::io::ErrorKind::Unsupported => {
+                        self.win32_poll(op_data)
+                    }
+                    res => res,
+                };
+                op_data.immediate_result = res.transpose();
+            }
+        }
+
+        Ok(())
+    }
+}
+
+unsafe impl<T: CustomOp> Op for Custom<T> {
+    type Captured = T;
+    type Output = T::Output;
+
+    fn source(&self) -> Raw {
+        self.inner.source()
+    }
+
+    fn variant(&self) -> SourceType {
+        self.inner.variant()
+    }
+
+    fn pinned_bytes(&self) -> usize {
+        self.inner.pinned_bytes()
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    unsafe fn into_captured(self) -> T {
+        self.inner
+    }
+
+    fn decode(result: usize, captured: T) -> T::Output {
+        captured.finish(result)
+    }
+}
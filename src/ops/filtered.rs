@@ -0,0 +1,200 @@
+// GNU GPL v3 License
+
+#![cfg(unix)]
+
+use super::{addr, split_nonnull, TsPtr};
+use crate::{BufMut, PollingFn, Raw, Source, SourceType};
+use std::{
+    io::{self, Result},
+    mem,
+    net::SocketAddr,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    ptr::NonNull,
+};
+
+/// The peer to accept datagrams from.
+enum Peer {
+    Inet(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl Peer {
+    /// Tell whether the sender of a datagram is this peer.
+    fn matches(&self, storage: &libc::sockaddr_storage, len: libc::socklen_t) -> bool {
+        match self {
+            Peer::Inet(peer) => matches!(
+                addr::from_raw(storage, len),
+                Some(from) if from.ip() == peer.ip() && from.port() == peer.port()
+            ),
+            Peer::Unix(peer) => {
+                if storage.ss_family as libc::c_int != libc::AF_UNIX {
+                    return false;
+                }
+
+                // SAFETY: the family tells us this is a sockaddr_un
+                let sun = unsafe { &*(storage as *const _ as *const libc::sockaddr_un) };
+                let start = sun.sun_path.as_ptr() as usize - sun as *const _ as usize;
+                let len = (len as usize).saturating_sub(start).min(sun.sun_path.len());
+                let path =
+                    unsafe { std::slice::from_raw_parts(sun.sun_path.as_ptr().cast::<u8>(), len) };
+                let path = path.split(|&b| b == 0).next().unwrap_or(&[]);
+
+                path == peer.as_os_str().as_bytes()
+            }
+        }
+    }
+}
+
+/// A message header, boxed so that its address stays stable while the
+/// operation is in flight.
+struct Msg {
+    hdr: libc::msghdr,
+    iov: libc::iovec,
+    addr: libc::sockaddr_storage,
+}
+
+// SAFETY: the pointers in `Msg` only point into the `Msg` itself and into
+// the buffer owned by the operation
+unsafe impl Send for Msg {}
+unsafe impl Sync for Msg {}
+
+impl Msg {
+    /// Reset the address length before receiving another datagram.
+    fn reset(&mut self) {
+        self.hdr.msg_name = &mut self.addr as *mut _ as *mut _;
+        self.hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+    }
+}
+
+/// Receive a datagram, but only from a given peer.
+///
+/// Datagrams from any other sender are received and dropped, and the
+/// operation keeps waiting until one from the peer arrives. This is only
+/// supported on Unix.
+pub struct RecvFromFiltered<B> {
+    source: Raw,
+    variant: SourceType,
+    buf: B,
+    peer: Peer,
+    msg: Box<Msg>,
+}
+
+impl<B: BufMut + Send> RecvFromFiltered<B> {
+    /// Create a new `RecvFromFiltered` from the source, a buffer to read
+    /// into and the address of the peer.
+    ///
+    /// Only the IP address and port of the sender are compared.
+    pub fn new<S: Source>(source: &S, buf: B, peer: SocketAddr) -> Self {
+        Self::with_peer(source, buf, Peer::Inet(peer))
+    }
+
+    /// Create a new `RecvFromFiltered` that only accepts datagrams from the
+    /// Unix socket bound to `path`.
+    pub fn new_unix<S: Source>(source: &S, buf: B, path: impl AsRef<Path>) -> Self {
+        Self::with_peer(source, buf, Peer::Unix(path.as_ref().to_path_buf()))
+    }
+
+    fn with_peer<S: Source>(source: &S, buf: B, peer: Peer) -> Self {
+        RecvFromFiltered {
+            source: source.as_raw(),
+            variant: S::SOURCE_TYPE,
+            buf,
+            peer,
+            // SAFETY: all of these are C types that are valid when zeroed
+            msg: Box::new(unsafe { mem::zeroed() }),
+        }
+    }
+
+    /// Retrieve the inner buffer.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffer is retrieved.
+    unsafe fn into_buf(self) -> B {
+        self.buf
+    }
+
+    /// The size of the buffer.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.buf)
+    }
+
+    /// Fill out the message header.
+    fn prepare(&mut self) -> NonNull<Msg> {
+        let (ptr, len) = split_nonnull(self.buf.pointer());
+        let msg = &mut *self.msg;
+
+        msg.iov = libc::iovec {
+            iov_base: ptr.as_ptr().cast(),
+            iov_len: len,
+        };
+        msg.hdr.msg_iov = &mut msg.iov;
+        msg.hdr.msg_iovlen = 1;
+        msg.reset();
+
+        NonNull::from(msg)
+    }
+
+    /// A shareable copy of the peer.
+    fn peer(&self) -> Peer {
+        match &self.peer {
+            Peer::Inet(addr) => Peer::Inet(*addr),
+            Peer::Unix(path) => Peer::Unix(path.clone()),
+        }
+    }
+
+    fn polling_function(&mut self) -> PollingFn {
+        let msg = TsPtr(self.prepare());
+        let source = self.source;
+        let peer = self.peer();
+
+        PollingFn::new(move || loop {
+            let msg = unsafe { &mut *msg.0.as_ptr() };
+            msg.reset();
+
+            // runs until the socket would block
+            let n = syscall!(recvmsg(source, &mut msg.hdr, 0))?;
+            if peer.matches(&msg.addr, msg.hdr.msg_namelen) {
+                return Ok(n as _);
+            }
+        })
+    }
+
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        None
+    }
+
+    const READ: bool = true;
+    const WRITE: bool = false;
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> crate::linux::Resubmit {
+        use io_uring::types::Fd;
+
+        let msg = TsPtr(self.prepare());
+        let peer = self.peer();
+        let hdr = unsafe { &mut (*msg.0.as_ptr()).hdr as *mut libc::msghdr };
+
+        crate::linux::Resubmit {
+            entry: io_uring::opcode::RecvMsg::new(Fd(self.source), hdr).build(),
+            done: Box::new(move |result, _| {
+                if result < 0 {
+                    return Some(Err(io::Error::from_raw_os_error(-result)));
+                }
+
+                let msg = unsafe { &mut *msg.0.as_ptr() };
+                if peer.matches(&msg.addr, msg.hdr.msg_namelen) {
+                    Some(Ok(result as usize))
+                } else {
+                    msg.reset();
+                    None
+                }
+            }),
+        }
+    }
+}
+
+impl_op! {
+    <B: BufMut + Send> RecvFromFiltered: B, pinned = pinned
+}
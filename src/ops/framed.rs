@@ -0,0 +1,435 @@
+// GNU GPL v3 License
+
+#![cfg(unix)]
+
+use super::{split_nonnull, Op, OpBase, TsPtr};
+use crate::{Buf, BufMut, OpData, PollingFn, Raw, Source, SourceType};
+use std::{
+    io::{self, Result},
+    ptr::NonNull,
+    slice,
+};
+
+/// The size of the length prefix of `ReadFrame`.
+const PREFIX_LEN: usize = 4;
+
+/// How frames are told apart.
+#[derive(Debug, Clone, Copy)]
+enum Framing {
+    /// A frame ends with this byte.
+    Delimiter(u8),
+    /// A frame starts with its length, as a big-endian `u32`.
+    LengthPrefix,
+}
+
+impl Framing {
+    /// Find the start and end of the first frame in `data`, which may be
+    /// up to `capacity` bytes long, starting to look at `from`.
+    fn find(self, data: &[u8], from: usize, capacity: usize) -> Result<Option<(usize, usize)>> {
+        match self {
+            Framing::Delimiter(delimiter) => Ok(data[from..]
+                .iter()
+                .position(|&b| b == delimiter)
+                .map(|i| (0, from + i + 1))),
+            Framing::LengthPrefix => {
+                if data.len() < PREFIX_LEN {
+                    return Ok(None);
+                }
+
+                let mut prefix = [0; PREFIX_LEN];
+                prefix.copy_from_slice(&data[..PREFIX_LEN]);
+                let end = PREFIX_LEN.saturating_add(u32::from_be_bytes(prefix) as usize);
+                if end > capacity {
+                    return Err(too_large());
+                }
+
+                Ok(Some((PREFIX_LEN, end)).filter(|_| data.len() >= end))
+            }
+        }
+    }
+}
+
+/// The progress of the read, boxed so that its address stays stable
+/// while the operation is in flight.
+#[doc(hidden)]
+pub struct State {
+    framing: Framing,
+    /// The number of bytes in the buffer.
+    filled: usize,
+    /// Was the data that was already in the buffer looked at?
+    checked: bool,
+    /// The start and end of the frame, once it's complete.
+    frame: (usize, usize),
+}
+
+impl State {
+    /// Look for a frame in the data that was already in the buffer.
+    fn resume(&mut self, buf: &[u8]) -> Option<Result<usize>> {
+        if self.checked {
+            return None;
+        }
+
+        self.checked = true;
+        match self.filled {
+            0 => None,
+            _ => self.check(buf, 0),
+        }
+    }
+
+    /// Account for `n` more bytes in the buffer.
+    ///
+    /// Returns the result of the operation once it's done.
+    fn advance(&mut self, buf: &[u8], n: usize) -> Option<Result<usize>> {
+        if n == 0 {
+            return Some(Err(io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        let from = self.filled;
+        self.filled += n;
+        self.check(buf, from)
+    }
+
+    /// Look for a complete frame, from `from` onwards for delimiters.
+    fn check(&mut self, buf: &[u8], from: usize) -> Option<Result<usize>> {
+        match self.framing.find(&buf[..self.filled], from, buf.len()) {
+            Ok(Some(frame)) => {
+                self.frame = frame;
+                Some(Ok(self.filled))
+            }
+            Ok(None) if self.filled == buf.len() => Some(Err(too_large())),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// The error for a frame that doesn't fit into the buffer.
+fn too_large() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "frame is larger than the buffer",
+    )
+}
+
+/// A complete frame, along with the data read after it.
+#[derive(Debug)]
+pub struct Frame<B> {
+    buf: B,
+    start: usize,
+    end: usize,
+    filled: usize,
+}
+
+impl<B: Buf> Frame<B> {
+    /// The contents of the frame.
+    ///
+    /// For `ReadUntil`, this includes the delimiter. For `ReadFrame`, this
+    /// doesn't include the length prefix.
+    pub fn frame(&self) -> &[u8] {
+        &self.data()[self.start..self.end]
+    }
+
+    /// The data read after the end of the frame.
+    ///
+    /// This is the start of the next frame; pass it on to the next read
+    /// with `filled`.
+    pub fn remainder(&self) -> &[u8] {
+        &self.data()[self.end..]
+    }
+
+    /// Where the frame ends in the buffer, which is where the remainder
+    /// starts.
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    /// Retrieve the buffer.
+    pub fn into_inner(self) -> B {
+        self.buf
+    }
+
+    /// The part of the buffer that was filled.
+    fn data(&self) -> &[u8] {
+        let (ptr, _) = split_nonnull(self.buf.pointer());
+        // SAFETY: the operation filled this part of the buffer
+        unsafe { slice::from_raw_parts(ptr.as_ptr(), self.filled) }
+    }
+}
+
+/// The operation behind `ReadUntil` and `ReadFrame`.
+struct Framed<B> {
+    source: Raw,
+    variant: SourceType,
+    buf: B,
+    state: Box<State>,
+}
+
+impl<B: BufMut + Send> Framed<B> {
+    fn new<S: Source>(source: &S, buf: B, framing: Framing) -> Self {
+        Framed {
+            source: source.as_raw(),
+            variant: S::SOURCE_TYPE,
+            buf,
+            state: Box::new(State {
+                framing,
+                filled: 0,
+                checked: false,
+                frame: (0, 0),
+            }),
+        }
+    }
+
+    #[track_caller]
+    fn filled(&mut self, filled: usize) {
+        let len = split_nonnull(self.buf.pointer()).1;
+        assert!(
+            filled <= len,
+            "filled length ({}) must be less than or equal to length ({})",
+            filled,
+            len,
+        );
+
+        self.state.filled = filled;
+    }
+
+    /// Retrieve the buffer and the progress.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffer is retrieved.
+    unsafe fn into_buf(self) -> (B, Box<State>) {
+        (self.buf, self.state)
+    }
+
+    /// The size of the buffer.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.buf)
+    }
+
+    /// Look for a frame in a fresh buffer.
+    fn restart(&mut self) {
+        self.state.filled = 0;
+        self.state.checked = false;
+        self.state.frame = (0, 0);
+    }
+
+    /// Read until there's a complete frame.
+    fn read_function(&mut self) -> PollingFn {
+        let (ptr, len) = split_nonnull(self.buf.pointer());
+        let ptr = TsPtr(ptr);
+        let state = TsPtr(NonNull::from(&mut *self.state));
+        let source = self.source;
+
+        PollingFn::new(move || {
+            let state = unsafe { &mut *state.0.as_ptr() };
+            let buf = || unsafe { slice::from_raw_parts(ptr.0.as_ptr(), len) };
+            if let Some(result) = state.resume(buf()) {
+                return result;
+            }
+
+            loop {
+                let spare = unsafe { ptr.0.as_ptr().add(state.filled) };
+                match syscall!(read(source, spare.cast(), len - state.filled)) {
+                    Ok(n) => {
+                        if let Some(result) = state.advance(buf(), n as usize) {
+                            return result;
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    // the progress is kept until we're polled again
+                    Err(e) => return Err(e),
+                }
+            }
+        })
+    }
+
+    fn polling_function(&mut self) -> PollingFn {
+        if self.variant == SourceType::File {
+            // always use the blocking pool
+            return PollingFn::new(|| Err(io::ErrorKind::WouldBlock.into()));
+        }
+
+        self.read_function()
+    }
+
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        Some(self.read_function())
+    }
+
+    const READ: bool = true;
+    const WRITE: bool = false;
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> crate::linux::Resubmit {
+        use io_uring::{opcode, types::Fd};
+
+        let (ptr, len) = split_nonnull(self.buf.pointer());
+        let read_ptr = TsPtr(ptr);
+        let buf_ptr = TsPtr(ptr);
+        let state = TsPtr(NonNull::from(&mut *self.state));
+        let socket = self.variant == SourceType::Socket;
+        let source = self.source;
+
+        let read = move |filled: usize| {
+            let spare = unsafe { read_ptr.0.as_ptr().add(filled) };
+            if socket {
+                opcode::Recv::new(Fd(source), spare, (len - filled) as _).build()
+            } else {
+                opcode::Read::new(Fd(source), spare, (len - filled) as _)
+                    .offset(-1)
+                    .build()
+            }
+        };
+
+        // the data that's already there may hold a frame
+        let buf = unsafe { slice::from_raw_parts(ptr.as_ptr(), len) };
+        let mut early = unsafe { &mut *state.0.as_ptr() }.resume(buf);
+        let entry = match early {
+            Some(_) => opcode::Nop::new().build(),
+            None => read(self.state.filled),
+        };
+
+        crate::linux::Resubmit {
+            entry,
+            done: Box::new(move |result, entry| {
+                if let Some(result) = early.take() {
+                    return Some(result);
+                }
+                if result < 0 {
+                    return Some(Err(io::Error::from_raw_os_error(-result)));
+                }
+
+                let state = unsafe { &mut *state.0.as_ptr() };
+                let buf = unsafe { slice::from_raw_parts(buf_ptr.0.as_ptr(), len) };
+                let done = state.advance(buf, result as usize);
+                if done.is_none() {
+                    *entry = read(state.filled);
+                }
+                done
+            }),
+        }
+    }
+}
+
+/// Hand out the frame.
+fn finish<B>(result: usize, (buf, state): (B, Box<State>)) -> Frame<B> {
+    let (start, end) = state.frame;
+
+    Frame {
+        buf,
+        start,
+        end,
+        filled: result,
+    }
+}
+
+impl_op! {
+    <B: BufMut + Send> Framed: (B, Box<State>) => Frame<B>,
+    |result, captured| finish(result, captured),
+    pinned = pinned,
+    reset = restart
+}
+
+macro_rules! framed_op {
+    ($(#[$meta: meta])* $name: ident) => {
+        $(#[$meta])*
+        pub struct $name<B> {
+            inner: Framed<B>,
+        }
+
+        impl<B: BufMut + Send> $name<B> {
+            /// Treat the first `filled` bytes of the buffer as already read.
+            ///
+            /// This is for the remainder of the previous frame, which is
+            /// looked at before anything else is read.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `filled` is larger than the buffer.
+            #[track_caller]
+            pub fn filled(&mut self, filled: usize) -> &mut Self {
+                self.inner.filled(filled);
+                self
+            }
+        }
+
+        unsafe impl<B: BufMut + Send> OpBase for $name<B> {
+            fn run(&mut self, op_data: &mut OpData<'_>) -> Result<()> {
+                self.inner.run(op_data)
+            }
+        }
+
+        unsafe impl<B: BufMut + Send> Op for $name<B> {
+            type Captured = (B, Box<State>);
+            type Output = Frame<B>;
+
+            fn source(&self) -> Raw {
+                self.inner.source()
+            }
+
+            fn variant(&self) -> SourceType {
+                self.inner.variant()
+            }
+
+            fn pinned_bytes(&self) -> usize {
+                self.inner.pinned_bytes()
+            }
+
+            fn reset(&mut self) {
+                self.inner.reset()
+            }
+
+            unsafe fn into_captured(self) -> (B, Box<State>) {
+                self.inner.into_captured()
+            }
+
+            fn decode(result: usize, captured: (B, Box<State>)) -> Frame<B> {
+                Framed::decode(result, captured)
+            }
+        }
+    };
+}
+
+framed_op! {
+    /// Read until a delimiter, such as a newline, comes up.
+    ///
+    /// The read is repeated until the delimiter is in the buffer, and the
+    /// operation only completes then. The data read after the delimiter is
+    /// kept in the output. If the buffer fills up first, the operation
+    /// fails with `InvalidData`; if the stream ends first, it fails with
+    /// `UnexpectedEof`. This is meant for streams, such as sockets and
+    /// pipes, and is only supported on Unix.
+    ReadUntil
+}
+
+impl<B: BufMut + Send> ReadUntil<B> {
+    /// Create a new `ReadUntil` from the source, a buffer to read into and
+    /// the byte that ends a frame.
+    pub fn new<S: Source>(source: &S, buf: B, delimiter: u8) -> Self {
+        ReadUntil {
+            inner: Framed::new(source, buf, Framing::Delimiter(delimiter)),
+        }
+    }
+}
+
+framed_op! {
+    /// Read a frame that starts with its length.
+    ///
+    /// The length is a big-endian `u32` that doesn't count itself. The
+    /// read is repeated until the whole frame is in the buffer, and the
+    /// operation only completes then. The data read after the frame is
+    /// kept in the output. If the frame doesn't fit into the buffer, the
+    /// operation fails with `InvalidData`; if the stream ends first, it
+    /// fails with `UnexpectedEof`. This is meant for streams, such as
+    /// sockets and pipes, and is only supported on Unix.
+    ReadFrame
+}
+
+impl<B: BufMut + Send> ReadFrame<B> {
+    /// Create a new `ReadFrame` from the source and a buffer to read into.
+    pub fn new<S: Source>(source: &S, buf: B) -> Self {
+        ReadFrame {
+            inner: Framed::new(source, buf, Framing::LengthPrefix),
+        }
+    }
+}
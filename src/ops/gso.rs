@@ -0,0 +1,292 @@
+// GNU GPL v3 License
+
+#![cfg(target_os = "linux")]
+
+use super::{addr, split_nonnull, OpSpec, Opcode, TsPtr};
+use crate::{Buf, BufMut, PollingFn, Raw, Source, SourceType};
+use std::{
+    io::{self, Result},
+    mem,
+    net::SocketAddr,
+    ptr::{self, NonNull},
+};
+
+/// Space for a single control message, suitably aligned.
+#[repr(C, align(8))]
+pub(super) struct Control(pub(super) [u8; 32]);
+
+/// A message header, boxed so that its address stays stable while the
+/// operation is in flight.
+pub(super) struct Msg {
+    pub(super) hdr: libc::msghdr,
+    iov: libc::iovec,
+    addr: libc::sockaddr_storage,
+    pub(super) control: Control,
+}
+
+// SAFETY: the pointers in `Msg` only point into the `Msg` itself and into
+// the buffer owned by the operation
+unsafe impl Send for Msg {}
+unsafe impl Sync for Msg {}
+
+impl Msg {
+    pub(super) fn new() -> Box<Self> {
+        // SAFETY: all of these are C types that are valid when zeroed
+        Box::new(unsafe { mem::zeroed() })
+    }
+
+    /// Point the header at `buf` and at our own fields.
+    pub(super) fn prepare(&mut self, (ptr, len): (NonNull<u8>, usize)) -> NonNull<libc::msghdr> {
+        self.iov = libc::iovec {
+            iov_base: ptr.as_ptr().cast(),
+            iov_len: len,
+        };
+        self.hdr.msg_iov = &mut self.iov;
+        self.hdr.msg_iovlen = 1;
+        NonNull::from(&mut self.hdr)
+    }
+}
+
+/// Send a buffer as several UDP datagrams with a single operation.
+///
+/// This uses UDP generic segmentation offload (`UDP_SEGMENT`): the buffer
+/// is split into datagrams of `segment_size` bytes, with the last one
+/// possibly being shorter. If the kernel doesn't support it, the polling
+/// backend falls back to sending each datagram separately; check
+/// `is_supported` before relying on it with `io_uring`.
+pub struct SendMsgGso<B> {
+    source: Raw,
+    variant: SourceType,
+    buf: B,
+    segment_size: u16,
+    dest: Option<SocketAddr>,
+    msg: Box<Msg>,
+}
+
+impl<B: Buf + Send> SendMsgGso<B> {
+    /// Create a new `SendMsgGso` from the source, a buffer to send and
+    /// the size of each datagram.
+    pub fn new<S: Source>(source: &S, buf: B, segment_size: u16) -> Self {
+        SendMsgGso {
+            source: source.as_raw(),
+            variant: S::SOURCE_TYPE,
+            buf,
+            segment_size,
+            dest: None,
+            msg: Msg::new(),
+        }
+    }
+
+    /// Set the address to send the datagrams to.
+    ///
+    /// This is required for unconnected sockets.
+    pub fn to(&mut self, addr: SocketAddr) -> &mut Self {
+        self.dest = Some(addr);
+        self
+    }
+
+    /// Tell whether the socket supports UDP segmentation offload.
+    pub fn is_supported(source: &impl Source) -> bool {
+        let mut value: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+        syscall!(getsockopt(
+            source.as_raw(),
+            libc::SOL_UDP,
+            libc::UDP_SEGMENT,
+            &mut value as *mut _ as *mut _,
+            &mut len
+        ))
+        .is_ok()
+    }
+
+    /// Retrieve the inner buffer.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffer is retrieved.
+    unsafe fn into_buf(self) -> B {
+        self.buf
+    }
+
+    /// The size of the buffer.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.buf)
+    }
+
+    /// Fill out the message header.
+    fn prepare(&mut self) -> NonNull<libc::msghdr> {
+        let msg = &mut *self.msg;
+
+        if let Some(dest) = self.dest {
+            let (storage, len) = addr::to_raw(&dest);
+            msg.addr = storage;
+            msg.hdr.msg_name = &mut msg.addr as *mut _ as *mut _;
+            msg.hdr.msg_namelen = len;
+        }
+
+        // install the segment size as a control message
+        msg.hdr.msg_control = msg.control.0.as_mut_ptr().cast();
+        msg.hdr.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<u16>() as _) } as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg.hdr);
+            (*cmsg).cmsg_level = libc::SOL_UDP;
+            (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as _) as _;
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<u16>(), self.segment_size);
+        }
+
+        msg.prepare(split_nonnull(self.buf.pointer()))
+    }
+
+    fn polling_function(&mut self) -> PollingFn {
+        let hdr = TsPtr(self.prepare());
+        let source = self.source;
+        let segment_size = self.segment_size as usize;
+
+        PollingFn::new(move || {
+            let hdr = unsafe { hdr.0.as_ref() };
+
+            match syscall!(sendmsg(source, hdr, 0)) {
+                Ok(n) => Ok(n as _),
+                Err(e)
+                    if matches!(
+                        e.raw_os_error(),
+                        Some(libc::EINVAL) | Some(libc::ENOPROTOOPT) | Some(libc::EOPNOTSUPP)
+                    ) =>
+                {
+                    // segmentation isn't supported, send them one by one
+                    send_segments(source, hdr, segment_size)
+                }
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        None
+    }
+
+    const READ: bool = false;
+    const WRITE: bool = true;
+
+    fn uring_entry(&mut self) -> io_uring::squeue::Entry {
+        use io_uring::types::Fd;
+
+        io_uring::opcode::SendMsg::new(Fd(self.source), self.prepare().as_ptr()).build()
+    }
+}
+
+/// Send each segment of the message as its own datagram.
+fn send_segments(source: Raw, hdr: &libc::msghdr, segment_size: usize) -> Result<usize> {
+    let iov = unsafe { &*hdr.msg_iov };
+    let mut sent = 0;
+
+    while sent < iov.iov_len {
+        let len = segment_size.min(iov.iov_len - sent);
+        let ptr = unsafe { iov.iov_base.cast::<u8>().add(sent) };
+        let result = syscall!(sendto(
+            source,
+            ptr.cast(),
+            len,
+            0,
+            hdr.msg_name as *const libc::sockaddr,
+            hdr.msg_namelen
+        ));
+
+        match result {
+            Ok(n) => sent += n as usize,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && sent > 0 => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(sent)
+}
+
+/// Receive several coalesced UDP datagrams with a single operation.
+///
+/// This uses UDP generic receive offload, which must first be enabled on
+/// the socket with `enable`. The output contains the number of bytes
+/// received and, if several datagrams were coalesced, the size of each
+/// datagram (with the last one possibly being shorter).
+pub struct RecvMsgGro<B> {
+    source: Raw,
+    variant: SourceType,
+    buf: B,
+    msg: Box<Msg>,
+}
+
+impl<B: BufMut + Send> RecvMsgGro<B> {
+    /// Create a new `RecvMsgGro` from the source and a buffer to read into.
+    pub fn new<S: Source>(source: &S, buf: B) -> Self {
+        RecvMsgGro {
+            source: source.as_raw(),
+            variant: S::SOURCE_TYPE,
+            buf,
+            msg: Msg::new(),
+        }
+    }
+
+    /// Enable UDP generic receive offload on the socket.
+    pub fn enable(source: &impl Source) -> Result<()> {
+        let value: libc::c_int = 1;
+        syscall!(setsockopt(
+            source.as_raw(),
+            libc::SOL_UDP,
+            libc::UDP_GRO,
+            &value as *const _ as *const _,
+            mem::size_of::<libc::c_int>() as _
+        ))?;
+        Ok(())
+    }
+
+    /// Retrieve the inner buffer and the segment size, if any.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffer is retrieved.
+    unsafe fn into_buf(self) -> (B, Option<u16>) {
+        let hdr = &self.msg.hdr;
+        let mut segment_size = None;
+
+        let mut cmsg = libc::CMSG_FIRSTHDR(hdr);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+                let size = ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::c_int>());
+                segment_size = Some(size as u16);
+            }
+            cmsg = libc::CMSG_NXTHDR(hdr, cmsg);
+        }
+
+        (self.buf, segment_size)
+    }
+
+    /// The size of the buffer.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.buf)
+    }
+
+    /// Fill out the message header.
+    fn prepare(&mut self) -> NonNull<libc::msghdr> {
+        let msg = &mut *self.msg;
+        msg.hdr.msg_control = msg.control.0.as_mut_ptr().cast();
+        msg.hdr.msg_controllen = mem::size_of::<Control>() as _;
+        msg.prepare(split_nonnull(self.buf.pointer()))
+    }
+
+    /// Describe the operation.
+    fn spec(&mut self) -> OpSpec {
+        OpSpec::new(Opcode::RecvMsg(self.prepare()), self.source, self.variant)
+    }
+}
+
+impl_op! {
+    <B: Buf + Send> SendMsgGso: B, pinned = pinned
+}
+
+impl_op! {
+    <B: BufMut + Send> RecvMsgGro: (B, Option<u16>) => (usize, Option<u16>, B),
+    |result, captured| (result, captured.1, captured.0),
+    pinned = pinned, spec = spec
+}
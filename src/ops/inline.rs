@@ -0,0 +1,145 @@
+// GNU GPL v3 License
+
+use super::{Op, OpBase, Read};
+use crate::{Buf, BufMut, OpData, Raw, Source, SourceType};
+use std::{fmt, io::Result, ops::Deref, ptr::NonNull};
+
+/// The most data that a `ReadInline` operation reads.
+const INLINE_LEN: usize = 64;
+
+/// The data read by a `ReadInline` operation, stored inline.
+#[derive(Clone, Copy)]
+pub struct InlineBuf {
+    data: [u8; INLINE_LEN],
+    len: u8,
+}
+
+impl InlineBuf {
+    /// The most data that this buffer can hold.
+    pub const CAPACITY: usize = INLINE_LEN;
+
+    fn new(data: &[u8]) -> Self {
+        let mut buf = InlineBuf {
+            data: [0; INLINE_LEN],
+            len: data.len() as u8,
+        };
+        buf.data[..data.len()].copy_from_slice(data);
+        buf
+    }
+}
+
+impl Deref for InlineBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+}
+
+impl AsRef<[u8]> for InlineBuf {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl fmt::Debug for InlineBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// The buffer that a `ReadInline` operation reads into while in flight.
+#[doc(hidden)]
+pub struct Storage(Box<[u8; INLINE_LEN]>);
+
+unsafe impl Buf for Storage {
+    fn pointer(&self) -> NonNull<[u8]> {
+        NonNull::from(&self.0[..])
+    }
+}
+
+unsafe impl BufMut for Storage {}
+
+/// Read a small amount of data from a source, without providing a buffer.
+///
+/// At most `InlineBuf::CAPACITY` bytes are read, and the output holds a
+/// copy of them. This is meant for tiny messages, such as the ones used by
+/// control protocols, where managing a buffer isn't worth it.
+pub struct ReadInline {
+    inner: Read<Storage>,
+}
+
+impl ReadInline {
+    /// Create a new `ReadInline` from the source.
+    pub fn new<S: Source>(source: &S) -> Self {
+        ReadInline {
+            inner: Read::new(source, Storage(Box::new([0; INLINE_LEN]))),
+        }
+    }
+
+    /// Set the offset to read from.
+    ///
+    /// This has no effect for sockets. Fails with `InvalidInput` if
+    /// `offset` is larger than `i64::MAX`.
+    pub fn offset(&mut self, offset: u64) -> Result<&mut Self> {
+        self.inner.offset(offset)?;
+        Ok(self)
+    }
+
+    /// The position in the file right after the data that was read.
+    ///
+    /// See `Read::end_position`.
+    pub fn end_position(&self, result: usize) -> Option<u64> {
+        self.inner.end_position(result)
+    }
+
+    /// Read at most `max_len` bytes.
+    pub fn max_len(&mut self, max_len: usize) -> &mut Self {
+        self.inner.max_len(max_len);
+        self
+    }
+
+    /// Keep reading until `max_len` bytes, or `InlineBuf::CAPACITY` bytes
+    /// if it isn't set, are read.
+    ///
+    /// See `Read::exact`.
+    pub fn exact(&mut self) -> &mut Self {
+        self.inner.exact();
+        self
+    }
+}
+
+unsafe impl OpBase for ReadInline {
+    fn run(&mut self, op_data: &mut OpData<'_>) -> Result<()> {
+        self.inner.run(op_data)
+    }
+}
+
+unsafe impl Op for ReadInline {
+    type Captured = Storage;
+    type Output = InlineBuf;
+
+    fn source(&self) -> Raw {
+        self.inner.source()
+    }
+
+    fn variant(&self) -> SourceType {
+        self.inner.variant()
+    }
+
+    fn pinned_bytes(&self) -> usize {
+        self.inner.pinned_bytes()
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    unsafe fn into_captured(self) -> Storage {
+        self.inner.into_captured()
+    }
+
+    fn decode(result: usize, captured: Storage) -> InlineBuf {
+        InlineBuf::new(&captured.0[..result.min(INLINE_LEN)])
+    }
+}
@@ -0,0 +1,411 @@
+// GNU GPL v3 License
+
+#![cfg(unix)]
+
+use super::{addr, split_nonnull, TsPtr};
+use crate::{Buf, BufMut, PollingFn, Raw, Source, SourceType};
+use std::{io::Result, mem, net::SocketAddr, ptr::NonNull};
+
+/// The length and address of each datagram.
+type Datagrams = Vec<(usize, Option<SocketAddr>)>;
+
+/// The header for a single datagram, laid out like `mmsghdr`.
+#[repr(C)]
+struct MMsgHdr {
+    hdr: libc::msghdr,
+    len: libc::c_uint,
+}
+
+/// The buffer and address for a single datagram.
+struct Slot {
+    iov: libc::iovec,
+    addr: libc::sockaddr_storage,
+}
+
+/// The headers for a batch of datagrams, boxed so that their addresses stay
+/// stable while the operation is in flight.
+struct Batch {
+    hdrs: Box<[MMsgHdr]>,
+    slots: Box<[Slot]>,
+}
+
+// SAFETY: the pointers in `Batch` only point into the `Batch` itself and
+// into the buffers owned by the operation
+unsafe impl Send for Batch {}
+unsafe impl Sync for Batch {}
+
+impl Batch {
+    fn new() -> Self {
+        Batch {
+            hdrs: Box::new([]),
+            slots: Box::new([]),
+        }
+    }
+
+    /// Point the headers at the buffers and at our own fields.
+    ///
+    /// If `dests` is set, the datagrams are sent to those addresses.
+    /// Otherwise, the headers are set up to receive the source addresses.
+    /// This is only done once, since several functions may point to the
+    /// headers.
+    fn prepare(
+        &mut self,
+        bufs: impl Iterator<Item = (NonNull<u8>, usize)>,
+        dests: Option<&[Option<SocketAddr>]>,
+    ) -> NonNull<[MMsgHdr]> {
+        if self.hdrs.is_empty() {
+            self.slots = bufs
+                .map(|(ptr, len)| Slot {
+                    iov: libc::iovec {
+                        iov_base: ptr.as_ptr().cast(),
+                        iov_len: len,
+                    },
+                    // SAFETY: all zeroes is a valid sockaddr_storage
+                    addr: unsafe { mem::zeroed() },
+                })
+                .collect();
+
+            self.hdrs = self
+                .slots
+                .iter_mut()
+                .enumerate()
+                .map(|(i, slot)| {
+                    // SAFETY: all zeroes is a valid msghdr
+                    let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+
+                    match dests {
+                        None => {
+                            hdr.msg_name = &mut slot.addr as *mut _ as *mut _;
+                            hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+                        }
+                        Some(dests) => {
+                            if let Some(Some(dest)) = dests.get(i) {
+                                let (storage, len) = addr::to_raw(dest);
+                                slot.addr = storage;
+                                hdr.msg_name = &mut slot.addr as *mut _ as *mut _;
+                                hdr.msg_namelen = len;
+                            }
+                        }
+                    }
+
+                    hdr.msg_iov = &mut slot.iov;
+                    hdr.msg_iovlen = 1;
+                    MMsgHdr { hdr, len: 0 }
+                })
+                .collect();
+        }
+
+        NonNull::from(&mut *self.hdrs)
+    }
+
+    /// The length and source address of every datagram.
+    fn datagrams(&self) -> Datagrams {
+        self.hdrs
+            .iter()
+            .zip(self.slots.iter())
+            .map(|(hdr, slot)| {
+                (
+                    hdr.len as usize,
+                    addr::from_raw(&slot.addr, hdr.hdr.msg_namelen),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Receive several datagrams with a single operation.
+///
+/// Each buffer receives one datagram. The output contains the length and
+/// source address of every datagram that was received, followed by the
+/// buffers. On Linux, this uses `recvmmsg`; elsewhere, datagrams are
+/// received until the socket has no more. `io_uring` has no way to receive
+/// a batch of datagrams, so a single datagram is received there.
+pub struct RecvMMsg<B> {
+    source: Raw,
+    variant: SourceType,
+    bufs: Vec<B>,
+    batch: Batch,
+    /// Was this submitted as an operation that receives one datagram?
+    single: bool,
+}
+
+impl<B: BufMut + Send> RecvMMsg<B> {
+    /// Create a new `RecvMMsg` from the source and the buffers to receive
+    /// datagrams into.
+    pub fn new<S: Source>(source: &S, bufs: Vec<B>) -> Self {
+        RecvMMsg {
+            source: source.as_raw(),
+            variant: S::SOURCE_TYPE,
+            bufs,
+            batch: Batch::new(),
+            single: false,
+        }
+    }
+
+    /// Retrieve the buffers and the datagrams.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffers are retrieved.
+    unsafe fn into_buf(self) -> (Vec<B>, Datagrams, bool) {
+        let datagrams = self.batch.datagrams();
+        (self.bufs, datagrams, self.single)
+    }
+
+    /// The total size of the buffers.
+    fn pinned(&self) -> usize {
+        self.bufs.iter().map(super::buf_len).sum()
+    }
+
+    fn prepare(&mut self) -> NonNull<[MMsgHdr]> {
+        let bufs = self.bufs.iter().map(|buf| split_nonnull(buf.pointer()));
+        self.batch.prepare(bufs, None)
+    }
+
+    fn polling_function(&mut self) -> PollingFn {
+        let hdrs = TsPtr(self.prepare());
+        let source = self.source;
+
+        PollingFn::new(move || {
+            let hdrs = unsafe { &mut *hdrs.0.as_ptr() };
+
+            cfg_if::cfg_if! {
+                if #[cfg(target_os = "linux")] {
+                    let n = syscall!(recvmmsg(
+                        source,
+                        hdrs.as_mut_ptr().cast(),
+                        hdrs.len() as _,
+                        0,
+                        std::ptr::null_mut()
+                    ))?;
+                    Ok(n as _)
+                } else {
+                    // receive until the socket runs dry
+                    let mut count = 0;
+                    for hdr in hdrs.iter_mut() {
+                        match syscall!(recvmsg(source, &mut hdr.hdr, 0)) {
+                            Ok(n) => hdr.len = n as _,
+                            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock && count > 0 => {
+                                break
+                            }
+                            Err(e) => return Err(e),
+                        }
+                        count += 1;
+                    }
+                    Ok(count)
+                }
+            }
+        })
+    }
+
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        None
+    }
+
+    const READ: bool = true;
+    const WRITE: bool = false;
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> io_uring::squeue::Entry {
+        use io_uring::types::Fd;
+
+        self.single = true;
+        let hdrs = unsafe { &mut *self.prepare().as_ptr() };
+        match hdrs.first_mut() {
+            Some(hdr) => io_uring::opcode::RecvMsg::new(Fd(self.source), &mut hdr.hdr).build(),
+            None => io_uring::opcode::Nop::new().build(),
+        }
+    }
+}
+
+/// Decode the number of datagrams received into their lengths and
+/// addresses.
+fn received<B>(
+    result: usize,
+    (bufs, mut datagrams, single): (Vec<B>, Datagrams, bool),
+) -> (Datagrams, Vec<B>) {
+    if single && !datagrams.is_empty() {
+        // the result is the length of the only datagram
+        datagrams.truncate(1);
+        datagrams[0].0 = result;
+    } else {
+        datagrams.truncate(result);
+    }
+
+    (datagrams, bufs)
+}
+
+impl_op! {
+    <B: BufMut + Send> RecvMMsg: (Vec<B>, Datagrams, bool)
+        => (Datagrams, Vec<B>),
+    |result, captured| received(result, captured),
+    pinned = pinned
+}
+
+/// Send several datagrams with a single operation.
+///
+/// Each buffer is sent as one datagram. The output contains the number of
+/// bytes sent for every datagram that was sent, followed by the buffers.
+/// On Linux, this uses `sendmmsg`, and is repeated if the kernel sends
+/// fewer datagrams than requested; elsewhere, datagrams are sent one by
+/// one. With `io_uring`, each datagram is sent by its own linked entry, so
+/// the key must not be shared with other operations that are in flight.
+pub struct SendMMsg<B> {
+    source: Raw,
+    variant: SourceType,
+    bufs: Vec<B>,
+    dests: Vec<Option<SocketAddr>>,
+    batch: Batch,
+    /// Was this submitted as entries that only report the bytes sent?
+    chained: bool,
+}
+
+impl<B: Buf + Send> SendMMsg<B> {
+    /// Create a new `SendMMsg` from the source and the datagrams to send.
+    pub fn new<S: Source>(source: &S, bufs: Vec<B>) -> Self {
+        SendMMsg {
+            source: source.as_raw(),
+            variant: S::SOURCE_TYPE,
+            bufs,
+            dests: Vec::new(),
+            batch: Batch::new(),
+            chained: false,
+        }
+    }
+
+    /// Send every datagram to `addr`.
+    ///
+    /// This is required for unconnected sockets.
+    pub fn to(&mut self, addr: SocketAddr) -> &mut Self {
+        self.dests = vec![Some(addr); self.bufs.len()];
+        self
+    }
+
+    /// Send each datagram to the address at the same position.
+    ///
+    /// Datagrams past the end of `addrs` are sent without an address.
+    pub fn to_each(&mut self, addrs: impl IntoIterator<Item = SocketAddr>) -> &mut Self {
+        self.dests = addrs.into_iter().map(Some).collect();
+        self
+    }
+
+    /// Retrieve the buffers and the number of bytes sent for each datagram.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffers are retrieved.
+    unsafe fn into_buf(self) -> (Vec<B>, Vec<usize>, bool) {
+        let sent = if self.chained {
+            // only the total is known, so start from the full lengths
+            self.batch
+                .slots
+                .iter()
+                .map(|slot| slot.iov.iov_len)
+                .collect()
+        } else {
+            self.batch.hdrs.iter().map(|hdr| hdr.len as usize).collect()
+        };
+
+        (self.bufs, sent, self.chained)
+    }
+
+    /// The total size of the buffers.
+    fn pinned(&self) -> usize {
+        self.bufs.iter().map(super::buf_len).sum()
+    }
+
+    fn prepare(&mut self) -> NonNull<[MMsgHdr]> {
+        let bufs = self.bufs.iter().map(|buf| split_nonnull(buf.pointer()));
+        self.batch.prepare(bufs, Some(&self.dests))
+    }
+
+    fn polling_function(&mut self) -> PollingFn {
+        let hdrs = TsPtr(self.prepare());
+        let source = self.source;
+        let mut sent = 0;
+
+        PollingFn::new(move || {
+            let hdrs = unsafe { &mut *hdrs.0.as_ptr() };
+
+            while sent < hdrs.len() {
+                let rest = &mut hdrs[sent..];
+
+                cfg_if::cfg_if! {
+                    if #[cfg(target_os = "linux")] {
+                        let result = syscall!(sendmmsg(
+                            source,
+                            rest.as_mut_ptr().cast(),
+                            rest.len() as _,
+                            0
+                        ));
+                    } else {
+                        let result = syscall!(sendmsg(source, &rest[0].hdr, 0)).map(|n| {
+                            rest[0].len = n as _;
+                            1
+                        });
+                    }
+                }
+
+                match result {
+                    Ok(n) => sent += n as usize,
+                    // report the datagrams that were sent
+                    Err(_) if sent > 0 => break,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            Ok(sent)
+        })
+    }
+
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        None
+    }
+
+    const READ: bool = false;
+    const WRITE: bool = true;
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> Vec<io_uring::squeue::Entry> {
+        use io_uring::types::Fd;
+
+        self.chained = true;
+        let hdrs = unsafe { &*self.prepare().as_ptr() };
+        if hdrs.is_empty() {
+            return vec![io_uring::opcode::Nop::new().build()];
+        }
+
+        hdrs.iter()
+            .map(|hdr| io_uring::opcode::SendMsg::new(Fd(self.source), &hdr.hdr).build())
+            .collect()
+    }
+}
+
+/// Decode the result into the number of bytes sent for each datagram.
+fn sent<B>(
+    result: usize,
+    (bufs, mut sent, chained): (Vec<B>, Vec<usize>, bool),
+) -> (Vec<usize>, Vec<B>) {
+    if chained {
+        // datagrams are sent whole, so count the ones the total covers
+        let mut total = 0;
+        let count = sent
+            .iter()
+            .take_while(|&&len| {
+                total += len;
+                total <= result
+            })
+            .count();
+        sent.truncate(count);
+    } else {
+        sent.truncate(result);
+    }
+
+    (sent, bufs)
+}
+
+impl_op! {
+    <B: Buf + Send> SendMMsg: (Vec<B>, Vec<usize>, bool) => (Vec<usize>, Vec<B>),
+    |result, captured| sent(result, captured),
+    pinned = pinned
+}
@@ -28,16 +28,97 @@ pub unsafe trait Op: OpBase {
     /// very end.
     type Captured;
 
+    /// The typed result of this operation, built from the raw result
+    /// and the captured variables.
+    type Output;
+
     /// The raw file descriptor that this operation is associated with.
     fn source(&self) -> Raw;
     /// The variant of the source.
     fn variant(&self) -> SourceType;
+    /// The number of bytes the operation keeps in use while it's in
+    /// flight.
+    ///
+    /// This is the size of its buffers, and what
+    /// `CompletionBuilder::memory_limit` counts. Operations without
+    /// buffers pin nothing.
+    fn pinned_bytes(&self) -> usize {
+        0
+    }
+    /// Get the operation ready to be submitted again, once it's complete.
+    ///
+    /// Operations that keep track of their progress start over. This is
+    /// called by `Completion::resubmit`.
+    fn reset(&mut self) {}
+    /// Whether the operation is submitted again every time it completes.
+    ///
+    /// See `Read::rearm`.
+    fn is_rearmed(&self) -> bool {
+        false
+    }
+    /// Whether the operation is a `Barrier` for its source.
+    #[doc(hidden)]
+    fn is_barrier(&self) -> bool {
+        false
+    }
+    /// The bytes that the operation writes, if it's a plain write that
+    /// can be merged with others.
+    ///
+    /// See `CompletionBuilder::coalesce_writes`.
+    #[doc(hidden)]
+    fn coalescable(&mut self) -> Option<NonNull<[u8]>> {
+        None
+    }
+    /// The span that the operation runs in, if it's traced.
+    #[cfg(feature = "tracing-spans")]
+    #[doc(hidden)]
+    fn op_span(&self) -> Option<&tracing::Span> {
+        None
+    }
+    /// Run the operation in a `tracing` span, from submission to
+    /// completion.
+    ///
+    /// See `Traced`. This is only available with the `tracing-spans`
+    /// feature.
+    #[cfg(feature = "tracing-spans")]
+    fn with_span(self, span: tracing::Span) -> Traced<Self>
+    where
+        Self: Sized,
+    {
+        Traced::new(self, span)
+    }
     /// Get the captured variables.
     /// 
+    /// This also works for operations that failed or were cancelled.
+    /// 
     /// # Safety
     /// 
-    /// The operation must be complete at this point.
+    /// The operation must be complete at this point, meaning that its
+    /// event was received.
     unsafe fn into_captured(self) -> Self::Captured;
+
+    /// Convert the raw result of this operation and its captured
+    /// variables into the typed output.
+    fn decode(result: usize, captured: Self::Captured) -> Self::Output;
+
+    /// Get the typed output of this operation, given its raw result.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete at this point, and `result`
+    /// must be the result of this operation.
+    unsafe fn complete(self, result: usize) -> Self::Output
+    where
+        Self: Sized,
+    {
+        let captured = self.into_captured();
+        Self::decode(result, captured)
+    }
+}
+
+/// The length of a buffer.
+fn buf_len(buf: &impl crate::Buf) -> usize {
+    split_nonnull(buf.pointer()).1
 }
 
 // split a NonNull<[u8]> into ptr and len
@@ -48,7 +129,42 @@ fn split_nonnull(ptr: NonNull<[u8]>) -> (NonNull<u8>, usize) {
     (unsafe { NonNull::new_unchecked(ptr) }, len)
 }
 
+/// Create a polling function out of a single transfer.
+///
+/// `transfer` is called with the number of bytes transferred so far. If
+/// `exact` is set, it's repeated until `len` bytes are transferred, and a
+/// transfer of zero bytes fails with `zero`. Progress is kept when the
+/// transfer would block, so the function can be polled again later.
+#[cfg(unix)]
+fn transfer_function(
+    exact: bool,
+    len: usize,
+    zero: std::io::ErrorKind,
+    mut transfer: impl FnMut(usize) -> Result<usize> + Send + Sync + 'static,
+) -> crate::PollingFn {
+    if !exact {
+        return crate::PollingFn::new(move || transfer(0));
+    }
+
+    let mut done = 0;
+    crate::PollingFn::new(move || {
+        while done < len {
+            match transfer(done) {
+                Ok(0) => return Err(zero.into()),
+                Ok(n) => done += n,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(done)
+    })
+}
+
 /// Thread-safe container for `NonNull<T>`
+///
+/// The pointers are sent to the blocking pool, so the operations that use
+/// this require their buffers to be `Send`.
 struct TsPtr<T: ?Sized>(NonNull<T>);
 
 unsafe impl<T: ?Sized> Send for TsPtr<T> {}
@@ -70,7 +186,7 @@ macro_rules! check_socket_error {
             if err == ERROR_IO_PENDING as _ {
                 Ok(None)
             } else {
-                Err(std::io::Error::last_os_error())
+                Err(std::io::Error::from_raw_os_error(err as _))
             }
         } else {
             Ok(Some(res as usize))
@@ -81,7 +197,7 @@ macro_rules! check_socket_error {
 #[cfg(windows)]
 macro_rules! check_win32_error {
     ($res: expr) => {{
-        use windows_sys::Win32::Foundation::{GetLastError, ERROR_IO_PENDING};
+        use windows_sys::Win32::Foundation::{GetLastError, ERROR_HANDLE_EOF, ERROR_IO_PENDING};
 
         let res = ($res);
 
@@ -89,8 +205,11 @@ macro_rules! check_win32_error {
             let err = unsafe { windows_sys::Win32::Foundation::GetLastError() };
             if err == ERROR_IO_PENDING {
                 Ok(None)
+            } else if err == ERROR_HANDLE_EOF {
+                // other platforms report end-of-file as reading nothing
+                Ok(Some(0))
             } else {
-                Err(std::io::Error::last_os_error())
+                Err(std::io::Error::from_raw_os_error(err as _))
             }
         } else {
             Ok(Some(res as usize))
@@ -109,10 +228,141 @@ macro_rules! install_offset {
     }};
 }
 
+/// Run `f` with a manual-reset event to wait on, and close it afterwards.
+#[cfg(windows)]
+fn with_event<T>(f: impl FnOnce(windows_sys::Win32::Foundation::HANDLE) -> Result<T>) -> Result<T> {
+    use std::ptr::null;
+    use windows_sys::Win32::{
+        Foundation::{CloseHandle, FALSE, TRUE},
+        System::Threading::CreateEventW,
+    };
+
+    let event = unsafe { CreateEventW(null(), TRUE, FALSE, null()) };
+    if event == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let result = f(event);
+    unsafe { CloseHandle(event) };
+    result
+}
+
+/// Read or write at `offset`, and wait for it to finish.
+#[cfg(windows)]
+fn overlapped_transfer(
+    handle: Raw,
+    event: windows_sys::Win32::Foundation::HANDLE,
+    offset: u64,
+    ptr: *mut u8,
+    len: usize,
+    write: bool,
+) -> Result<usize> {
+    use windows_sys::Win32::{
+        Foundation::{GetLastError, ERROR_HANDLE_EOF, ERROR_IO_PENDING, TRUE},
+        Storage::FileSystem::{ReadFile, WriteFile},
+        System::IO::{GetOverlappedResult, OVERLAPPED},
+    };
+
+    let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+    install_offset!(&mut overlapped as *mut OVERLAPPED, offset);
+    // the low bit keeps the completion off of the completion port
+    overlapped.hEvent = event | 1;
+
+    let res = unsafe {
+        if write {
+            WriteFile(
+                handle as _,
+                ptr as _,
+                len as _,
+                std::ptr::null_mut(),
+                &mut overlapped,
+            )
+        } else {
+            ReadFile(
+                handle as _,
+                ptr as _,
+                len as _,
+                std::ptr::null_mut(),
+                &mut overlapped,
+            )
+        }
+    };
+    if res == 0 {
+        match unsafe { GetLastError() } {
+            ERROR_IO_PENDING => {}
+            ERROR_HANDLE_EOF => return Ok(0),
+            _ => return Err(std::io::Error::last_os_error()),
+        }
+    }
+
+    let mut transferred = 0;
+    if unsafe { GetOverlappedResult(handle as _, &overlapped, &mut transferred, TRUE) } == 0 {
+        return match unsafe { GetLastError() } {
+            ERROR_HANDLE_EOF => Ok(0),
+            _ => Err(std::io::Error::last_os_error()),
+        };
+    }
+
+    Ok(transferred as usize)
+}
+
+/// Repeat a blocking transfer until `len` bytes are transferred.
+///
+/// `transfer` is called with the number of bytes transferred so far. A
+/// transfer of zero bytes fails with `zero`.
+#[cfg(windows)]
+fn transfer_exact(
+    len: usize,
+    zero: std::io::ErrorKind,
+    mut transfer: impl FnMut(usize) -> Result<usize>,
+) -> Result<usize> {
+    let mut done = 0;
+    while done < len {
+        match transfer(done)? {
+            0 => return Err(zero.into()),
+            n => done += n,
+        }
+    }
+
+    Ok(done)
+}
+
 macro_rules! impl_op {
-    (< $($gname: ident: $gbound: ident),* > $name: ident: $cap: ty) => {
-        unsafe impl<$($gname: $gbound),*> $crate::ops::Op for $name<$($gname),*> {
+    (
+        < $($gname: ident: $gbound: ident $(+ $extra: ident)*),* > $name: ident: $cap: ty
+        $(, pinned = $pinned: ident)?
+        $(, reset = $reset: ident)?
+        $(, rearm = $rearm: ident)?
+        $(, barrier = $barrier: literal)?
+        $(, coalesce = $coalesce: ident)?
+        $(, aio = $aio: ident)?
+        $(, spec = $spec: ident)?
+    ) => {
+        impl_op! {
+            <$($gname: $gbound $(+ $extra)*),*> $name: $cap => (usize, $cap), |result, captured| (result, captured)
+            $(, pinned = $pinned)?
+            $(, reset = $reset)?
+            $(, rearm = $rearm)?
+            $(, barrier = $barrier)?
+            $(, coalesce = $coalesce)?
+            $(, aio = $aio)?
+            $(, spec = $spec)?
+        }
+    };
+    (
+        < $($gname: ident: $gbound: ident $(+ $extra: ident)*),* > $name: ident: $cap: ty => $out: ty,
+        |$res: ident, $captured: ident| $decode: expr
+        $(, pinned = $pinned: ident)?
+        $(, reset = $reset: ident)?
+        $(, rearm = $rearm: ident)?
+        $(, barrier = $barrier: literal)?
+        $(, coalesce = $coalesce: ident)?
+        $(, aio = $aio: ident)?
+        $(, spec = $spec: ident)?
+    ) => {
+        unsafe impl<$($gname: $gbound $(+ $extra)*),*> $crate::ops::Op for $name<$($gname),*> {
             type Captured = $cap;
+            type Output = $out;
 
             fn source(&self) -> $crate::Raw {
                 self.source
@@ -122,33 +372,91 @@ macro_rules! impl_op {
                 self.variant
             }
 
+            $(
+                fn pinned_bytes(&self) -> usize {
+                    self.$pinned()
+                }
+            )?
+
+            $(
+                fn reset(&mut self) {
+                    self.$reset()
+                }
+            )?
+
+            $(
+                fn is_rearmed(&self) -> bool {
+                    self.$rearm
+                }
+            )?
+
+            $(
+                fn is_barrier(&self) -> bool {
+                    $barrier
+                }
+            )?
+
+            $(
+                fn coalescable(&mut self) -> Option<std::ptr::NonNull<[u8]>> {
+                    self.$coalesce()
+                }
+            )?
+
             unsafe fn into_captured(self) -> $cap {
                 self.into_buf()
             }
+
+            fn decode($res: usize, $captured: $cap) -> $out {
+                $decode
+            }
         }
 
-        unsafe impl<$($gname: $gbound),*> $crate::ops::OpBase for $name<$($gname),*> {
+        impl_op! {
+            @base <$($gname: $gbound $(+ $extra)*),*> $name $(, aio = $aio)? $(, spec = $spec)?
+        }
+    };
+    // the operation describes itself with an `OpSpec`, which is lowered
+    (
+        @base < $($gname: ident: $gbound: ident $(+ $extra: ident)*),* > $name: ident, spec = $spec: ident
+    ) => {
+        unsafe impl<$($gname: $gbound $(+ $extra)*),*> $crate::ops::OpBase for $name<$($gname),*> {
+            fn run(&mut self, op_data: &mut $crate::OpData<'_>) -> Result<()> {
+                self.$spec().lower(op_data)
+            }
+        }
+    };
+    // the operation lowers itself, for operations that `OpSpec` can't
+    // describe yet
+    (
+        @base < $($gname: ident: $gbound: ident $(+ $extra: ident)*),* > $name: ident
+        $(, aio = $aio: ident)?
+    ) => {
+        unsafe impl<$($gname: $gbound $(+ $extra)*),*> $crate::ops::OpBase for $name<$($gname),*> {
             fn run(&mut self, op_data: &mut $crate::OpData<'_>) -> Result<()> {
                 cfg_if::cfg_if! {
                     if #[cfg(target_os = "linux")] {
-                        use $crate::OpData::{Polling, Entry};
-
                         match op_data {
-                            Entry(ref mut entry) => {
-                                *entry = Some(self.uring_entry());
-                            },
-                            Polling(ref mut poll) => {
+                            $crate::OpData::Polling(ref mut poll) => {
                                 poll.slot = Some(self.polling_function());
+                                poll.blocking = self.blocking_function();
                                 poll.read = Self::READ;
                                 poll.write = Self::WRITE;
                             }
+                            op_data => $crate::ops::UringEntries::install(self.uring_entry(), op_data),
                         }
                     } else if #[cfg(unix)] {
-                        op_data.slot.insert(self.polling_function());
+                        op_data.slot = Some(self.polling_function());
+                        op_data.blocking = self.blocking_function();
                         op_data.read = Self::READ;
                         op_data.write = Self::WRITE;
+                        $(
+                            #[cfg(target_os = "freebsd")]
+                            {
+                                op_data.aio = self.$aio();
+                            }
+                        )?
                     } else if #[cfg(windows)] {
-                        let res = self.win32_start(op_data.overlapped);
+                        let res = self.win32_start(op_data);
                         op_data.immediate_result = res.transpose();
                     }
                 }
@@ -156,21 +464,225 @@ macro_rules! impl_op {
                 Ok(())
             }
         }
+    };
+}
+
+/// The `io_uring` entries produced by an operation.
+#[cfg(target_os = "linux")]
+trait UringEntries {
+    fn install(self, op_data: &mut crate::OpData<'_>);
+}
+
+#[cfg(target_os = "linux")]
+impl UringEntries for io_uring::squeue::Entry {
+    fn install(self, op_data: &mut crate::OpData<'_>) {
+        if let crate::OpData::Entry(entries) = op_data {
+            entries.push(self);
+        }
+    }
+}
+
+/// Several entries are linked, and run one after another.
+#[cfg(target_os = "linux")]
+impl UringEntries for Vec<io_uring::squeue::Entry> {
+    fn install(self, op_data: &mut crate::OpData<'_>) {
+        if let crate::OpData::Entry(entries) = op_data {
+            entries.extend(self);
+        }
+    }
+}
+
+/// The entry is submitted again until the operation is done.
+#[cfg(target_os = "linux")]
+impl UringEntries for crate::linux::Resubmit {
+    fn install(self, op_data: &mut crate::OpData<'_>) {
+        *op_data = crate::OpData::Resubmit(self);
+    }
+}
+
+/// The operation runs on the blocking pool.
+#[cfg(target_os = "linux")]
+impl UringEntries for crate::PollingFn {
+    fn install(self, op_data: &mut crate::OpData<'_>) {
+        *op_data = crate::OpData::Blocking(crate::linux::Blocking(self));
+    }
+}
+
+/// An entry that's either submitted once, or resubmitted until the transfer is done.
+#[cfg(target_os = "linux")]
+enum UringTransfer {
+    Once(io_uring::squeue::Entry),
+    Repeat(crate::linux::Resubmit),
+}
+
+#[cfg(target_os = "linux")]
+impl UringEntries for UringTransfer {
+    fn install(self, op_data: &mut crate::OpData<'_>) {
+        match self {
+            UringTransfer::Once(entry) => entry.install(op_data),
+            UringTransfer::Repeat(resubmit) => resubmit.install(op_data),
+        }
+    }
+}
+
+/// Create an `io_uring` entry out of a single transfer.
+///
+/// `entry` is called with the number of bytes transferred so far, and
+/// builds the entry for the rest. If `exact` is set, the entry is
+/// resubmitted until `len` bytes are transferred, and a transfer of zero
+/// bytes fails with `zero`.
+#[cfg(target_os = "linux")]
+fn transfer_entry(
+    exact: bool,
+    len: usize,
+    zero: std::io::ErrorKind,
+    mut entry: impl FnMut(usize) -> io_uring::squeue::Entry + Send + 'static,
+) -> UringTransfer {
+    if !exact || len == 0 {
+        return UringTransfer::Once(entry(0));
+    }
+
+    let mut done = 0;
+    UringTransfer::Repeat(crate::linux::Resubmit {
+        entry: entry(0),
+        done: Box::new(move |result, next| {
+            if result == -libc::EINTR {
+                *next = entry(done);
+                return None;
+            } else if result < 0 {
+                return Some(Err(std::io::Error::from_raw_os_error(-result)));
+            } else if result == 0 {
+                return Some(Err(zero.into()));
+            }
+
+            done += result as usize;
+            if done >= len {
+                return Some(Ok(done));
+            }
+
+            *next = entry(done);
+            None
+        }),
+    })
+}
+
+/// Check that an offset fits into the signed offsets used by the OS.
+fn check_offset(offset: u64) -> Result<i64> {
+    std::convert::TryFrom::try_from(offset).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "offset is larger than i64::MAX",
+        )
+    })
+}
+
+/// The position in the file right after `result` bytes were transferred
+/// at `offset`, or `None` if the source isn't a file.
+fn end_position(variant: SourceType, offset: i64, result: usize) -> Option<u64> {
+    match variant {
+        SourceType::File => Some(offset as u64 + result as u64),
+        _ => None,
     }
 }
 
 /// Split into Offset and OffsetHigh
 #[cfg(windows)]
 #[inline]
-fn split_into_offsets(offset: isize) -> (u32, u32) {
-    let offset = offset as u64;
+fn split_into_offsets(offset: u64) -> (u32, u32) {
     let offset_high = (offset >> 32) as u32;
     let offset_low = (offset & 0xffffffff) as u32;
     (offset_low, offset_high)
 }
 
+mod accept;
+pub use accept::{Accept, AcceptAndRecv};
+
+mod adaptive;
+#[cfg(unix)]
+pub use adaptive::ReadAdaptive;
+
+mod addr;
+
+mod any;
+pub use any::AnyOp;
+
+mod barrier;
+pub use barrier::Barrier;
+
+mod copy;
+pub use copy::CopyFileRange;
+
+mod custom;
+pub use custom::{Custom, CustomFn, CustomOp};
+
+mod filtered;
+#[cfg(unix)]
+pub use filtered::RecvFromFiltered;
+
+mod framed;
+#[cfg(unix)]
+pub use framed::{Frame, ReadFrame, ReadUntil};
+
+mod gso;
+#[cfg(target_os = "linux")]
+pub use gso::{RecvMsgGro, SendMsgGso};
+
+mod inline;
+pub use inline::{InlineBuf, ReadInline};
+
+mod mmsg;
+#[cfg(unix)]
+pub use mmsg::{RecvMMsg, SendMMsg};
+
+mod nop;
+pub use nop::Nop;
+
+mod open;
+pub use open::OpenAt;
+
+mod poll;
+pub use poll::{PollReadable, PollWritable};
+
 mod read;
 pub use read::Read;
 
+mod recv;
+#[cfg(unix)]
+pub use recv::{RecvFrom, RecvMeta};
+
+mod resolve;
+pub use resolve::Resolve;
+
+mod spec;
+use spec::{OpSpec, Opcode};
+
+mod splice;
+#[cfg(target_os = "linux")]
+pub use splice::Splice;
+
+mod stream;
+pub use stream::{CompletionKind, ReadStream};
+
+mod tls;
+#[cfg(target_os = "linux")]
+pub use tls::{is_ktls, RecvTlsRecord, SendTlsRecord, TlsRecordType};
+
+mod traced;
+#[cfg(feature = "tracing-spans")]
+pub use traced::Traced;
+
+mod uring_cmd;
+#[cfg(target_os = "linux")]
+pub(crate) use uring_cmd::IORING_OP_URING_CMD;
+#[cfg(target_os = "linux")]
+pub use uring_cmd::{UringCmd, URING_CMD_LEN};
+
+mod vectored;
+pub use vectored::{ReadVectored, WriteVectored};
+
 mod write;
 pub use write::Write;
+
+mod xattr;
+#[cfg(target_os = "linux")]
+pub use xattr::{GetXattr, SetXattr};
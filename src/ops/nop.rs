@@ -0,0 +1,53 @@
+// GNU GPL v3 License
+
+use super::{OpSpec, Opcode};
+use crate::{Raw, SourceType};
+use std::io::Result;
+
+/// An operation that does nothing.
+///
+/// This completes through the queue like any other operation, which makes
+/// it useful for measuring the round-trip latency of the queue and for
+/// checking that the wait loop is still alive.
+pub struct Nop {
+    source: Raw,
+    variant: SourceType,
+}
+
+impl Nop {
+    /// Create a new `Nop`.
+    pub fn new() -> Self {
+        Nop {
+            source: NO_SOURCE,
+            variant: SourceType::Socket,
+        }
+    }
+
+    /// There is nothing to retrieve.
+    ///
+    /// # Safety
+    ///
+    /// Always safe, only unsafe for consistency with other operations.
+    unsafe fn into_buf(self) {}
+
+    /// Describe the operation.
+    fn spec(&mut self) -> OpSpec {
+        OpSpec::new(Opcode::Nop, self.source, self.variant)
+    }
+}
+
+impl Default for Nop {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The `Raw` used for operations without a source.
+#[cfg(unix)]
+pub(super) const NO_SOURCE: Raw = -1;
+#[cfg(windows)]
+pub(super) const NO_SOURCE: Raw = windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE as Raw;
+
+impl_op! {
+    <> Nop: () => (), |_result, _captured| (), spec = spec
+}
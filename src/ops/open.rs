@@ -0,0 +1,154 @@
+// GNU GPL v3 License
+
+use crate::{PollingFn, Raw, SourceType};
+use std::{fs::File, io::Result};
+
+#[cfg(windows)]
+use std::os::windows::io::FromRawHandle;
+#[cfg(unix)]
+use std::{ffi::CString, os::unix::io::FromRawFd};
+
+/// Open a file.
+///
+/// This is the operation behind `fs::Open`, which is created through
+/// `fs::OpenOptions`. The output of this operation is the opened `File`,
+/// before it's registered into the `Completion`.
+pub struct OpenAt {
+    source: Raw,
+    variant: SourceType,
+    path: Path,
+    #[cfg(unix)]
+    flags: libc::c_int,
+    #[cfg(unix)]
+    mode: libc::mode_t,
+    #[cfg(windows)]
+    access: u32,
+    #[cfg(windows)]
+    disposition: u32,
+    #[cfg(windows)]
+    attributes: u32,
+}
+
+/// The path, in the form the OS expects it.
+#[cfg(unix)]
+type Path = CString;
+#[cfg(windows)]
+type Path = Vec<u16>;
+
+impl OpenAt {
+    /// Create a new `OpenAt` for a path relative to the current directory.
+    #[cfg(unix)]
+    pub(crate) fn new(path: CString, flags: libc::c_int, mode: libc::mode_t) -> Self {
+        OpenAt {
+            source: libc::AT_FDCWD,
+            variant: SourceType::File,
+            path,
+            flags,
+            mode,
+        }
+    }
+
+    /// Create a new `OpenAt` for a null-terminated wide path.
+    #[cfg(windows)]
+    pub(crate) fn new(path: Vec<u16>, access: u32, disposition: u32, attributes: u32) -> Self {
+        OpenAt {
+            source: super::nop::NO_SOURCE,
+            variant: SourceType::File,
+            path,
+            access,
+            disposition,
+            attributes,
+        }
+    }
+
+    /// Retrieve the path.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the path is retrieved.
+    unsafe fn into_buf(self) -> Path {
+        self.path
+    }
+
+    #[cfg(unix)]
+    fn polling_function(&mut self) -> PollingFn {
+        // opening a file may block, so always use the blocking pool
+        PollingFn::new(|| Err(std::io::ErrorKind::WouldBlock.into()))
+    }
+
+    #[cfg(unix)]
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        let path = super::TsPtr(std::ptr::NonNull::from(self.path.as_c_str()));
+        let (flags, mode) = (self.flags, self.mode);
+
+        Some(PollingFn::new(move || {
+            let path = unsafe { path.0.as_ref() };
+            let fd = syscall!(open(path.as_ptr(), flags, mode as libc::c_uint))?;
+            Ok(fd as _)
+        }))
+    }
+
+    #[cfg(unix)]
+    const READ: bool = true;
+    #[cfg(unix)]
+    const WRITE: bool = false;
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> io_uring::squeue::Entry {
+        use io_uring::types::Fd;
+
+        io_uring::opcode::OpenAt::new(Fd(self.source), self.path.as_ptr())
+            .flags(self.flags)
+            .mode(self.mode)
+            .build()
+    }
+
+    #[cfg(windows)]
+    fn win32_start(&mut self, op_data: &mut crate::OpData<'_>) -> Result<Option<usize>> {
+        use windows_sys::Win32::{
+            Foundation::INVALID_HANDLE_VALUE,
+            Storage::FileSystem::{
+                CreateFileW, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE,
+            },
+        };
+
+        // CreateFileW can't be overlapped
+        let path = super::TsPtr(std::ptr::NonNull::from(self.path.as_slice()));
+        let (access, disposition, attributes) = (self.access, self.disposition, self.attributes);
+
+        crate::iocp::complete_on_thread(op_data, move || {
+            let handle = unsafe {
+                CreateFileW(
+                    path.0.as_ptr() as *const u16,
+                    access,
+                    FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                    std::ptr::null(),
+                    disposition,
+                    attributes,
+                    0,
+                )
+            };
+
+            if handle == INVALID_HANDLE_VALUE {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(handle as usize)
+            }
+        })
+    }
+}
+
+/// Convert the raw result into the opened file.
+fn into_file(result: usize) -> File {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            unsafe { File::from_raw_fd(result as _) }
+        } else if #[cfg(windows)] {
+            unsafe { File::from_raw_handle(result as _) }
+        }
+    }
+}
+
+impl_op! {
+    <> OpenAt: Path => File, |result, _path| into_file(result)
+}
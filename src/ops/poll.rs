@@ -0,0 +1,64 @@
+// GNU GPL v3 License
+
+use super::{OpSpec, Opcode};
+use crate::{Raw, Source, SourceType};
+use std::io::Result;
+
+macro_rules! poll_op {
+    (
+        $(#[$meta: meta])*
+        $name: ident
+    ) => {
+        $(#[$meta])*
+        pub struct $name {
+            source: Raw,
+            variant: SourceType,
+        }
+
+        impl $name {
+            /// Create a new operation that waits for the source.
+            pub fn new<S: Source>(source: &S) -> Self {
+                $name {
+                    source: source.as_raw(),
+                    variant: S::SOURCE_TYPE,
+                }
+            }
+
+            /// There is nothing to retrieve.
+            ///
+            /// # Safety
+            ///
+            /// Always safe, only unsafe for consistency with other operations.
+            unsafe fn into_buf(self) {}
+
+            /// Describe the operation.
+            fn spec(&mut self) -> OpSpec {
+                OpSpec::new(Opcode::$name, self.source, self.variant)
+            }
+        }
+
+        impl_op! {
+            <> $name: () => (), |_result, _captured| (), spec = spec
+        }
+    };
+}
+
+poll_op! {
+    /// Wait for a source to become readable.
+    ///
+    /// This doesn't transfer any data, so it can be used to integrate
+    /// code that does its own non-blocking reads. It also completes when
+    /// the source hangs up or has an error. On Windows, this only works on
+    /// sockets, including ones that weren't opened in overlapped mode.
+    PollReadable
+}
+
+poll_op! {
+    /// Wait for a source to become writable.
+    ///
+    /// This doesn't transfer any data, so it can be used to integrate
+    /// code that does its own non-blocking writes. It also completes when
+    /// the source hangs up or has an error. On Windows, this only works on
+    /// sockets, including ones that weren't opened in overlapped mode.
+    PollWritable
+}
@@ -1,15 +1,8 @@
 // GNU GPL v3 License
 
-use super::split_nonnull;
-use crate::{BufMut, PollingFn, Raw, Source, SourceType};
-use std::io::Result;
-
-#[cfg(windows)]
-use windows_sys::Win32::{
-    Foundation::ERROR_IO_PENDING,
-    Networking::WinSock::{WSAGetLastError, SOCKET_ERROR, WSABUF},
-    System::IO::OVERLAPPED,
-};
+use super::{split_nonnull, OpSpec, Opcode};
+use crate::{BufMut, Raw, Source, SourceType};
+use std::{io::Result, ptr::NonNull};
 
 /// Read in data from a source to a buffer.
 pub struct Read<B> {
@@ -17,9 +10,14 @@ pub struct Read<B> {
     variant: SourceType,
     buf: B,
     offset: i64,
+    buf_offset: usize,
+    max_len: Option<usize>,
+    exact: bool,
+    rearm: bool,
+    fixed_buffer: Option<u16>,
 }
 
-impl<B: BufMut> Read<B> {
+impl<B: BufMut + Send> Read<B> {
     /// Create a new `Read` from the source and a buffer to read into.
     pub fn new<S: Source>(source: &S, buf: B) -> Self {
         Read {
@@ -27,6 +25,11 @@ impl<B: BufMut> Read<B> {
             variant: S::SOURCE_TYPE,
             buf,
             offset: 0,
+            buf_offset: 0,
+            max_len: None,
+            exact: false,
+            rearm: false,
+            fixed_buffer: None,
         }
     }
 
@@ -34,109 +37,162 @@ impl<B: BufMut> Read<B> {
     ///
     /// This has no effect for sockets. For files, this indicates the
     /// offset to start reading at.
-    pub fn offset(&mut self, offset: i64) -> &mut Self {
-        self.offset = offset;
+    ///
+    /// Fails with `InvalidInput` if `offset` is larger than `i64::MAX`.
+    pub fn offset(&mut self, offset: u64) -> Result<&mut Self> {
+        self.offset = super::check_offset(offset)?;
+        Ok(self)
+    }
+
+    /// Start reading into the buffer at `buf_offset`.
+    ///
+    /// The bytes before it are left alone, and the output still holds the
+    /// entire buffer. This is useful for resubmitting after a partial
+    /// transfer without re-slicing the buffer. `max_len` counts from this
+    /// offset.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf_offset` is larger than the buffer.
+    #[track_caller]
+    pub fn buf_offset(&mut self, buf_offset: usize) -> &mut Self {
+        let len = split_nonnull(self.buf.pointer()).1;
+        assert!(
+            buf_offset <= len,
+            "buffer offset ({}) must be less than or equal to length ({})",
+            buf_offset,
+            len,
+        );
+
+        self.buf_offset = buf_offset;
         self
     }
 
-    /// Retrieve the inner buffer.
+    /// Read at most `max_len` bytes, even if the buffer is larger.
+    pub fn max_len(&mut self, max_len: usize) -> &mut Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Keep reading until the buffer is full.
     ///
-    /// # Safety
+    /// If the source reaches end-of-file first, the operation fails with
+    /// `UnexpectedEof`. With `io_uring` and IOCP, reads from sockets use
+    /// `MSG_WAITALL` to keep the kernel from coming up short, and with
+    /// IOCP, reads from files run on the blocking pool.
+    pub fn exact(&mut self) -> &mut Self {
+        self.exact = true;
+        self
+    }
+
+    /// Allow reading less than the entire buffer.
     ///
-    /// The operation must be complete before the buffer is retrieved.
-    unsafe fn into_buf(self) -> B {
-        self.buf
+    /// This is the default.
+    pub fn allow_short(&mut self) -> &mut Self {
+        self.exact = false;
+        self
     }
 
-    #[cfg(unix)]
-    fn polling_function(&mut self) -> PollingFn {
-        let (ptr, len) = split_nonnull(self.buf.pointer());
-        let source = self.source;
-        let offset = self.offset;
-        let mut seeked = false;
-        let ptr = super::TsPtr(ptr);
-
-        // if we're a file, use seeking
-        match self.variant {
-            SourceType::File => Box::new(move || {
-                if !seeked {
-                    syscall!(lseek(source, offset, libc::SEEK_SET))?;
-                    seeked = true;
-                }
-
-                let n = syscall!(read(source, ptr.0.as_ptr().cast(), len))?;
-                Ok(n as _)
-            }),
-            SourceType::Socket => Box::new(move || {
-                let n = syscall!(read(source, ptr.0.as_ptr().cast(), len))?;
-                Ok(n as _)
-            }),
-        }
+    /// Use the fixed buffer at `index` for the read.
+    ///
+    /// The part of the buffer that's read into must lie within the buffer
+    /// registered at `index` with `Completion::register_buffer`, or the
+    /// operation fails with `EFAULT`. This only has an effect with
+    /// `io_uring`, and not for reads from sockets with `exact`.
+    pub fn fixed_buffer(&mut self, index: u16) -> &mut Self {
+        self.fixed_buffer = Some(index);
+        self
     }
 
-    #[cfg(unix)]
-    const READ: bool = true;
-    #[cfg(unix)]
-    const WRITE: bool = false;
+    /// Submit the read again every time it completes with data.
+    ///
+    /// Each time, the event is delivered with the same key, and the read is
+    /// submitted again by the next call to `Completion::wait` or any of its
+    /// variants. Until then, the data can be looked at with `buf`. Reading
+    /// zero bytes, failing or being cancelled is the last event. This works
+    /// the same on every backend, and is meant for sockets that are always
+    /// being read from.
+    ///
+    /// If the read is cancelled while it's waiting to be submitted again,
+    /// its last event fails with `Interrupted`. Otherwise, the next event
+    /// after `Completion::cancel` is the last one.
+    ///
+    /// # Safety
+    ///
+    /// Once submitted, the operation must stay in place and must not be
+    /// used, other than looking at its buffer between events, until its
+    /// last event is received.
+    pub unsafe fn rearm(&mut self, rearm: bool) -> &mut Self {
+        self.rearm = rearm;
+        self
+    }
 
-    #[cfg(target_os = "linux")]
-    fn uring_entry(&mut self) -> io_uring::squeue::Entry {
-        use io_uring::types::Fd;
+    /// The position in the file right after the data that was read, given
+    /// the result of the operation's event.
+    ///
+    /// This is the offset to continue reading from. Returns `None` if the
+    /// source isn't a file.
+    pub fn end_position(&self, result: usize) -> Option<u64> {
+        super::end_position(self.variant, self.offset, result)
+    }
 
+    /// The part of the buffer to read into.
+    fn target(&mut self) -> (NonNull<u8>, usize) {
         let (ptr, len) = split_nonnull(self.buf.pointer());
-        let mut read = io_uring::opcode::Read::new(Fd(self.source), ptr.as_ptr().cast(), len as _);
+        let start = self.buf_offset.min(len);
+        // SAFETY: `start` is within the buffer
+        let ptr = unsafe { NonNull::new_unchecked(ptr.as_ptr().add(start)) };
+        let len = len - start;
+        (ptr, self.max_len.map_or(len, |max_len| len.min(max_len)))
+    }
 
-        if matches!(self.variant, SourceType::File) {
-            read = read.offset(self.offset);
-        }
+    /// The most bytes this reads.
+    pub(super) fn requested_len(&mut self) -> usize {
+        self.target().1
+    }
 
-        read.build()
+    /// Get the buffer, to look at the data before resubmitting.
+    ///
+    /// # Safety
+    ///
+    /// The operation must not be in flight.
+    pub unsafe fn buf(&self) -> &B {
+        &self.buf
     }
 
-    #[cfg(windows)]
-    fn win32_start(&mut self, overlapped: *mut OVERLAPPED) -> Result<Option<usize>> {
-        use std::mem::MaybeUninit;
+    /// Get the buffer mutably.
+    ///
+    /// # Safety
+    ///
+    /// The operation must not be in flight.
+    pub unsafe fn buf_mut(&mut self) -> &mut B {
+        &mut self.buf
+    }
 
-        let (ptr, len) = split_nonnull(self.buf.pointer());
-        match self.variant {
-            SourceType::Socket => {
-                let buf = WSABUF {
-                    len: len as _,
-                    buf: ptr.as_ptr() as _,
-                };
-                let mut recv_bytes = 0;
-                let mut flags = 0;
-
-                check_socket_error!(unsafe {
-                    windows_sys::Win32::Networking::WinSock::WSARecv(
-                        self.source as _,
-                        &buf,
-                        1,
-                        &mut recv_bytes,
-                        &mut flags,
-                        overlapped,
-                        None,
-                    )
-                })
-            }
-            SourceType::File => {
-                let mut recv_bytes = 0;
-
-                install_offset!(overlapped, self.offset);
-                check_win32_error!(unsafe {
-                    windows_sys::Win32::Storage::FileSystem::ReadFile(
-                        self.source as _,
-                        ptr.as_ptr() as _,
-                        len as _,
-                        &mut recv_bytes,
-                        overlapped,
-                    )
-                })
-            }
-        }
+    /// Retrieve the inner buffer.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffer is retrieved.
+    unsafe fn into_buf(self) -> B {
+        self.buf
+    }
+
+    /// The size of the buffer.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.buf)
+    }
+
+    /// Describe the operation.
+    fn spec(&mut self) -> OpSpec {
+        OpSpec::new(Opcode::Read, self.source, self.variant)
+            .buf(self.target())
+            .offset(self.offset)
+            .exact(self.exact)
+            .fixed_buffer(self.fixed_buffer)
     }
 }
 
 impl_op! {
-    <B: BufMut> Read: B
+    <B: BufMut + Send> Read: B, pinned = pinned, rearm = rearm, spec = spec
 }
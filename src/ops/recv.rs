@@ -0,0 +1,146 @@
+// GNU GPL v3 License
+
+#![cfg(unix)]
+
+use super::{addr, split_nonnull, OpSpec, Opcode};
+use crate::{BufMut, Raw, Source, SourceType};
+use std::{io::Result, mem, net::SocketAddr, ptr::NonNull};
+
+/// What `RecvFrom` found out about a datagram.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvMeta {
+    /// The sender, if it has an IP address.
+    pub from: Option<SocketAddr>,
+    /// Whether the datagram was larger than the buffer, and was cut short.
+    pub truncated: bool,
+    /// The size of the whole datagram.
+    ///
+    /// Only Linux reports this for truncated datagrams; elsewhere, it's
+    /// the number of bytes received.
+    pub datagram_len: usize,
+}
+
+/// A message header, boxed so that its address stays stable while the
+/// operation is in flight.
+struct Msg {
+    hdr: libc::msghdr,
+    iov: libc::iovec,
+    addr: libc::sockaddr_storage,
+}
+
+// SAFETY: the pointers in `Msg` only point into the `Msg` itself and into
+// the buffer owned by the operation
+unsafe impl Send for Msg {}
+unsafe impl Sync for Msg {}
+
+impl Msg {
+    /// Reset the address length before receiving another datagram.
+    fn reset(&mut self) {
+        self.hdr.msg_name = &mut self.addr as *mut _ as *mut _;
+        self.hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+        self.hdr.msg_flags = 0;
+    }
+}
+
+/// Receive a datagram, along with its sender, telling whether it was
+/// truncated.
+///
+/// A datagram larger than the buffer is cut short, and the rest of it is
+/// lost. Plain reads don't say when that happens; this reports it in the
+/// `RecvMeta` of the output, along with the size of the whole datagram on
+/// Linux, so that the buffer can be grown. The output contains the number
+/// of bytes received, the `RecvMeta` and the buffer. This is only
+/// supported on Unix.
+pub struct RecvFrom<B> {
+    source: Raw,
+    variant: SourceType,
+    buf: B,
+    flags: libc::c_int,
+    msg: Box<Msg>,
+}
+
+impl<B: BufMut + Send> RecvFrom<B> {
+    /// Create a new `RecvFrom` from the source and a buffer to read into.
+    pub fn new<S: Source>(source: &S, buf: B) -> Self {
+        let source = source.as_raw();
+
+        // ask Linux for the size of the whole datagram, which would discard
+        // data on stream sockets
+        let flags = if cfg!(target_os = "linux")
+            && S::SOURCE_TYPE == SourceType::Socket
+            && super::adaptive::is_datagram(source)
+        {
+            libc::MSG_TRUNC
+        } else {
+            0
+        };
+
+        RecvFrom {
+            source,
+            variant: S::SOURCE_TYPE,
+            buf,
+            flags,
+            // SAFETY: all of these are C types that are valid when zeroed
+            msg: Box::new(unsafe { mem::zeroed() }),
+        }
+    }
+
+    /// Retrieve the inner buffer, its size, whether the datagram was
+    /// truncated and the sender.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffer is retrieved.
+    unsafe fn into_buf(self) -> (B, usize, bool, Option<SocketAddr>) {
+        let capacity = super::buf_len(&self.buf);
+        let truncated = self.msg.hdr.msg_flags & libc::MSG_TRUNC != 0;
+        let from = addr::from_raw(&self.msg.addr, self.msg.hdr.msg_namelen);
+        (self.buf, capacity, truncated, from)
+    }
+
+    /// The size of the buffer.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.buf)
+    }
+
+    /// Fill out the message header.
+    fn prepare(&mut self) -> NonNull<Msg> {
+        let (ptr, len) = split_nonnull(self.buf.pointer());
+        let msg = &mut *self.msg;
+
+        msg.iov = libc::iovec {
+            iov_base: ptr.as_ptr().cast(),
+            iov_len: len,
+        };
+        msg.hdr.msg_iov = &mut msg.iov;
+        msg.hdr.msg_iovlen = 1;
+        msg.reset();
+
+        NonNull::from(msg)
+    }
+
+    /// Describe the operation.
+    ///
+    /// The header is reset here, once per submission: the kernel only
+    /// writes it back once a datagram is received.
+    fn spec(&mut self) -> OpSpec {
+        let msg = self.prepare();
+        let hdr = unsafe { NonNull::from(&mut (*msg.as_ptr()).hdr) };
+
+        OpSpec::new(Opcode::RecvMsg(hdr), self.source, self.variant).flags(self.flags)
+    }
+}
+
+impl_op! {
+    <B: BufMut + Send> RecvFrom: (B, usize, bool, Option<SocketAddr>) => (usize, RecvMeta, B),
+    |result, captured| {
+        let (buf, capacity, truncated, from) = captured;
+        let meta = RecvMeta {
+            from,
+            truncated: truncated || result > capacity,
+            datagram_len: result,
+        };
+        (result.min(capacity), meta, buf)
+    },
+    pinned = pinned, spec = spec
+}
@@ -0,0 +1,133 @@
+// GNU GPL v3 License
+
+use super::nop::NO_SOURCE;
+use crate::{PollingFn, Raw, SourceType};
+use std::{
+    fmt,
+    io::Result,
+    net::{SocketAddr, ToSocketAddrs},
+    ptr::NonNull,
+};
+
+/// A function that resolves a host name and port into addresses.
+type Resolver = dyn Fn(&str, u16) -> Result<Vec<SocketAddr>> + Send + Sync;
+
+/// The request and its answer, boxed so that their address stays stable
+/// while the operation is in flight.
+struct State {
+    host: String,
+    port: u16,
+    resolver: Option<Box<Resolver>>,
+    addrs: Vec<SocketAddr>,
+}
+
+impl State {
+    /// Resolve the host, returning the number of addresses.
+    fn resolve(&mut self) -> Result<usize> {
+        self.addrs = match &self.resolver {
+            Some(resolver) => resolver(&self.host, self.port)?,
+            None => (self.host.as_str(), self.port).to_socket_addrs()?.collect(),
+        };
+
+        Ok(self.addrs.len())
+    }
+}
+
+/// Resolve a host name into socket addresses.
+///
+/// The lookup blocks, so it always runs on a separate thread; by default,
+/// it uses the system resolver (`getaddrinfo` on Unix). The output is the
+/// list of addresses, ready to connect to.
+pub struct Resolve {
+    source: Raw,
+    variant: SourceType,
+    state: Box<State>,
+}
+
+impl fmt::Debug for Resolve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resolve")
+            .field("host", &self.state.host)
+            .field("port", &self.state.port)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Resolve {
+    /// Create a new `Resolve` for a host name and the port to put into
+    /// the addresses.
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Resolve {
+            source: NO_SOURCE,
+            // lookups can't be polled for readiness
+            variant: SourceType::File,
+            state: Box::new(State {
+                host: host.into(),
+                port,
+                resolver: None,
+                addrs: Vec::new(),
+            }),
+        }
+    }
+
+    /// Resolve the host with `resolver` instead of the system resolver.
+    ///
+    /// It's still called on a separate thread.
+    pub fn resolver(
+        &mut self,
+        resolver: impl Fn(&str, u16) -> Result<Vec<SocketAddr>> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.state.resolver = Some(Box::new(resolver));
+        self
+    }
+
+    /// Retrieve the addresses.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the addresses are retrieved.
+    unsafe fn into_buf(self) -> Vec<SocketAddr> {
+        self.state.addrs
+    }
+
+    /// Resolve the host on whatever thread this is called on.
+    fn resolve_function(&mut self) -> PollingFn {
+        let state = super::TsPtr(NonNull::from(&mut *self.state));
+
+        PollingFn::new(move || {
+            let state = unsafe { &mut *state.0.as_ptr() };
+            state.resolve()
+        })
+    }
+
+    #[cfg(unix)]
+    fn polling_function(&mut self) -> PollingFn {
+        // always use the blocking pool
+        PollingFn::new(|| Err(std::io::ErrorKind::WouldBlock.into()))
+    }
+
+    #[cfg(unix)]
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        Some(self.resolve_function())
+    }
+
+    #[cfg(unix)]
+    const READ: bool = true;
+    #[cfg(unix)]
+    const WRITE: bool = false;
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&mut self) -> PollingFn {
+        self.resolve_function()
+    }
+
+    #[cfg(windows)]
+    fn win32_start(&mut self, op_data: &mut crate::OpData<'_>) -> Result<Option<usize>> {
+        let mut resolve = self.resolve_function();
+        crate::iocp::complete_on_thread(op_data, move || resolve.call())
+    }
+}
+
+impl_op! {
+    <> Resolve: Vec<SocketAddr> => Vec<SocketAddr>, |_result, addrs| addrs
+}
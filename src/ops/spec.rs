@@ -0,0 +1,609 @@
+// GNU GPL v3 License
+
+use super::TsPtr;
+use crate::{OpData, PollingFn, Raw, SourceType};
+use std::{
+    io::{self, Result},
+    ptr::NonNull,
+};
+
+/// What an operation asks the OS to do.
+///
+/// Opcodes are added here as operations are moved over to `OpSpec`.
+#[derive(Clone, Copy)]
+pub(crate) enum Opcode {
+    /// Do nothing, and complete right away.
+    Nop,
+    /// Wait for the source to become readable.
+    PollReadable,
+    /// Wait for the source to become writable.
+    PollWritable,
+    /// Read into the buffer.
+    Read,
+    /// Write from the buffer.
+    Write,
+    /// `recvmsg` into the message header.
+    #[cfg(unix)]
+    RecvMsg(NonNull<libc::msghdr>),
+    /// `sendmsg` from the message header.
+    #[cfg(unix)]
+    SendMsg(NonNull<libc::msghdr>),
+}
+
+/// A description of an operation that every backend knows how to perform.
+///
+/// An operation builds one with its `spec` method and hands it to
+/// `impl_op!`, instead of writing a polling function, an `io_uring` entry
+/// and a Windows start function of its own. Each backend lowers the spec
+/// in one place below, so a new operation that fits an existing opcode
+/// only touches its own file, and a new backend only touches this one.
+///
+/// The buffer and whatever the opcode points to must stay in place until
+/// the operation is complete, as with the buffers of any other operation.
+#[derive(Clone, Copy)]
+pub(crate) struct OpSpec {
+    opcode: Opcode,
+    fd: Raw,
+    variant: SourceType,
+    buf: NonNull<u8>,
+    len: usize,
+    offset: i64,
+    append: bool,
+    exact: bool,
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    fixed_buffer: Option<u16>,
+    #[cfg_attr(windows, allow(dead_code))]
+    flags: i32,
+}
+
+impl OpSpec {
+    /// Describe an operation on `fd`.
+    pub(crate) fn new(opcode: Opcode, fd: Raw, variant: SourceType) -> Self {
+        OpSpec {
+            opcode,
+            fd,
+            variant,
+            buf: NonNull::dangling(),
+            len: 0,
+            offset: 0,
+            append: false,
+            exact: false,
+            fixed_buffer: None,
+            flags: 0,
+        }
+    }
+
+    /// Set the buffer that `Read` and `Write` transfer.
+    pub(crate) fn buf(mut self, (buf, len): (NonNull<u8>, usize)) -> Self {
+        self.buf = buf;
+        self.len = len;
+        self
+    }
+
+    /// Set the offset into the file.
+    pub(crate) fn offset(mut self, offset: i64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Write to the end of the file instead of at the offset.
+    pub(crate) fn append(mut self, append: bool) -> Self {
+        self.append = append;
+        self
+    }
+
+    /// Keep transferring until the entire buffer is done.
+    pub(crate) fn exact(mut self, exact: bool) -> Self {
+        self.exact = exact;
+        self
+    }
+
+    /// Use a registered buffer, with `io_uring`.
+    pub(crate) fn fixed_buffer(mut self, index: Option<u16>) -> Self {
+        self.fixed_buffer = index;
+        self
+    }
+
+    /// Set the flags passed to the system call.
+    #[cfg(unix)]
+    pub(crate) fn flags(mut self, flags: i32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Lower the spec into whatever the backend behind `op_data` runs.
+    pub(crate) fn lower(self, op_data: &mut OpData<'_>) -> Result<()> {
+        cfg_if::cfg_if! {
+            if #[cfg(target_os = "linux")] {
+                match op_data {
+                    OpData::Polling(poll) => {
+                        poll.slot = Some(self.polling_function());
+                        poll.blocking = self.blocking_function();
+                        poll.read = self.readable();
+                        poll.write = self.writable();
+                    }
+                    op_data => super::UringEntries::install(self.uring_entry(), op_data),
+                }
+            } else if #[cfg(unix)] {
+                op_data.slot = Some(self.polling_function());
+                op_data.blocking = self.blocking_function();
+                op_data.read = self.readable();
+                op_data.write = self.writable();
+                #[cfg(target_os = "freebsd")]
+                {
+                    op_data.aio = self.aio_request();
+                }
+            } else if #[cfg(windows)] {
+                let res = self.win32_start(op_data);
+                op_data.immediate_result = res.transpose();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The error for a transfer of zero bytes, when it's `exact`.
+    fn zero(&self) -> io::ErrorKind {
+        match self.opcode {
+            Opcode::Write => io::ErrorKind::WriteZero,
+            _ => io::ErrorKind::UnexpectedEof,
+        }
+    }
+
+    /// Whether the polling function waits for the source to be readable.
+    #[cfg(unix)]
+    fn readable(&self) -> bool {
+        matches!(
+            self.opcode,
+            Opcode::PollReadable | Opcode::Read | Opcode::RecvMsg(_)
+        )
+    }
+
+    /// Whether the polling function waits for the source to be writable.
+    #[cfg(unix)]
+    fn writable(&self) -> bool {
+        matches!(
+            self.opcode,
+            Opcode::PollWritable | Opcode::Write | Opcode::SendMsg(_)
+        )
+    }
+
+    #[cfg(unix)]
+    fn polling_function(&self) -> PollingFn {
+        let OpSpec {
+            fd,
+            offset,
+            append,
+            exact,
+            len,
+            flags,
+            ..
+        } = *self;
+        let ptr = TsPtr(self.buf);
+        let zero = self.zero();
+        let mut seeked = false;
+
+        match (self.opcode, self.variant) {
+            (Opcode::Nop, _) => PollingFn::new(|| Ok(0)),
+            (Opcode::PollReadable, _) => PollingFn::new(move || ready(fd, libc::POLLIN)),
+            (Opcode::PollWritable, _) => PollingFn::new(move || ready(fd, libc::POLLOUT)),
+            (Opcode::RecvMsg(hdr), _) => {
+                let hdr = TsPtr(hdr);
+                PollingFn::new(move || {
+                    let n = syscall!(recvmsg(fd, hdr.0.as_ptr(), flags))?;
+                    Ok(n as _)
+                })
+            }
+            (Opcode::SendMsg(hdr), _) => {
+                let hdr = TsPtr(hdr);
+                PollingFn::new(move || {
+                    let n = syscall!(sendmsg(fd, hdr.0.as_ptr(), flags))?;
+                    Ok(n as _)
+                })
+            }
+            // only read if the data is already in the page cache, the
+            // blocking pool takes care of it otherwise
+            #[cfg(target_os = "linux")]
+            (Opcode::Read, SourceType::File) => {
+                super::transfer_function(exact, len, zero, move |done| {
+                    let iov = libc::iovec {
+                        iov_base: unsafe { ptr.0.as_ptr().add(done) }.cast(),
+                        iov_len: len - done,
+                    };
+                    let offset = offset + done as i64;
+
+                    match syscall!(preadv2(fd, &iov, 1, offset, libc::RWF_NOWAIT)) {
+                        Ok(n) => Ok(n as _),
+                        Err(e)
+                            if matches!(
+                                e.raw_os_error(),
+                                Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS)
+                            ) =>
+                        {
+                            // RWF_NOWAIT isn't supported here
+                            Err(io::ErrorKind::WouldBlock.into())
+                        }
+                        Err(e) => Err(e),
+                    }
+                })
+            }
+            // if we're a file, use seeking
+            (opcode, SourceType::File) => {
+                let write = matches!(opcode, Opcode::Write);
+
+                super::transfer_function(exact, len, zero, move |done| {
+                    if !seeked {
+                        if append {
+                            syscall!(lseek(fd, 0, libc::SEEK_END))?;
+                        } else {
+                            syscall!(lseek(fd, offset, libc::SEEK_SET))?;
+                        }
+                        seeked = true;
+                    }
+
+                    let ptr = unsafe { ptr.0.as_ptr().add(done) };
+                    let n = if write {
+                        syscall!(write(fd, ptr.cast(), len - done))?
+                    } else {
+                        syscall!(read(fd, ptr.cast(), len - done))?
+                    };
+                    Ok(n as _)
+                })
+            }
+            (Opcode::Read, _) => super::transfer_function(exact, len, zero, move |done| {
+                let ptr = unsafe { ptr.0.as_ptr().add(done) };
+                let n = syscall!(read(fd, ptr.cast(), len - done))?;
+                Ok(n as _)
+            }),
+            (Opcode::Write, _) => super::transfer_function(exact, len, zero, move |done| {
+                let ptr = unsafe { ptr.0.as_ptr().add(done) };
+                let n = syscall!(write(fd, ptr.cast(), len - done))?;
+                Ok(n as _)
+            }),
+        }
+    }
+
+    /// The function run on the blocking pool when the source can't be
+    /// polled for readiness, such as a regular file.
+    #[cfg(unix)]
+    fn blocking_function(&self) -> Option<PollingFn> {
+        let OpSpec {
+            fd,
+            offset,
+            append,
+            exact,
+            len,
+            ..
+        } = *self;
+        let ptr = TsPtr(self.buf);
+        let zero = self.zero();
+
+        match self.opcode {
+            Opcode::Read => Some(super::transfer_function(exact, len, zero, move |done| {
+                let ptr = unsafe { ptr.0.as_ptr().add(done) };
+                let offset = offset + done as i64;
+                let n = syscall!(pread(fd, ptr.cast(), len - done, offset))?;
+                Ok(n as _)
+            })),
+            Opcode::Write => Some(super::transfer_function(exact, len, zero, move |done| {
+                let ptr = unsafe { ptr.0.as_ptr().add(done) };
+                let n = if append {
+                    append_write(fd, ptr, len - done)?
+                } else {
+                    let offset = offset + done as i64;
+                    syscall!(pwrite(fd, ptr.cast(), len - done, offset))?
+                };
+                Ok(n as _)
+            })),
+            _ => None,
+        }
+    }
+
+    #[cfg(target_os = "freebsd")]
+    fn aio_request(&self) -> Option<crate::freebsd::aio::Request> {
+        use crate::freebsd::aio::Request;
+
+        // AIO doesn't retry short transfers, and writes files at an offset
+        let supported = match self.variant {
+            SourceType::File => !self.append,
+            SourceType::Socket => true,
+            SourceType::Tty => false,
+        };
+        if self.exact || !supported {
+            return None;
+        }
+
+        match self.opcode {
+            Opcode::Read => Some(Request::read(self.fd, self.buf, self.len, self.offset)),
+            Opcode::Write => Some(Request::write(self.fd, self.buf, self.len, self.offset)),
+            _ => None,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn uring_entry(&self) -> super::UringTransfer {
+        use io_uring::{opcode, types::Fd};
+
+        let OpSpec {
+            opcode,
+            variant,
+            offset,
+            append,
+            exact,
+            len,
+            fixed_buffer,
+            flags,
+            ..
+        } = *self;
+        let fd = Fd(self.fd);
+        let ptr = TsPtr(self.buf);
+
+        let write = match opcode {
+            Opcode::Read => false,
+            Opcode::Write => true,
+            Opcode::Nop => return super::UringTransfer::Once(opcode::Nop::new().build()),
+            Opcode::PollReadable => {
+                let entry = opcode::PollAdd::new(fd, libc::POLLIN as _).build();
+                return super::UringTransfer::Once(entry);
+            }
+            Opcode::PollWritable => {
+                let entry = opcode::PollAdd::new(fd, libc::POLLOUT as _).build();
+                return super::UringTransfer::Once(entry);
+            }
+            Opcode::RecvMsg(hdr) => {
+                let entry = opcode::RecvMsg::new(fd, hdr.as_ptr())
+                    .flags(flags as _)
+                    .build();
+                return super::UringTransfer::Once(entry);
+            }
+            Opcode::SendMsg(hdr) => {
+                let entry = opcode::SendMsg::new(fd, hdr.as_ptr())
+                    .flags(flags as _)
+                    .build();
+                return super::UringTransfer::Once(entry);
+            }
+        };
+
+        // short transfers are submitted again for the rest of the buffer
+        super::transfer_entry(exact, len, self.zero(), move |done| {
+            let ptr = unsafe { ptr.0.as_ptr().add(done) };
+            let len = (len - done) as _;
+
+            let (offset, rw_flags) = match variant {
+                // let the kernel retry short transfers on sockets
+                SourceType::Socket if exact && write => {
+                    return opcode::Send::new(fd, ptr, len)
+                        .flags(libc::MSG_WAITALL)
+                        .build();
+                }
+                SourceType::Socket if exact => {
+                    return opcode::Recv::new(fd, ptr, len)
+                        .flags(libc::MSG_WAITALL)
+                        .build();
+                }
+                SourceType::File if append => (0, libc::RWF_APPEND),
+                SourceType::File => (offset + done as i64, 0),
+                _ => (0, 0),
+            };
+
+            match (write, fixed_buffer) {
+                (true, Some(index)) => opcode::WriteFixed::new(fd, ptr, len, index)
+                    .offset(offset)
+                    .rw_flags(rw_flags)
+                    .build(),
+                (true, None) => opcode::Write::new(fd, ptr, len)
+                    .offset(offset)
+                    .rw_flags(rw_flags)
+                    .build(),
+                (false, Some(index)) => opcode::ReadFixed::new(fd, ptr, len, index)
+                    .offset(offset)
+                    .build(),
+                (false, None) => opcode::Read::new(fd, ptr, len).offset(offset).build(),
+            }
+        })
+    }
+
+    #[cfg(windows)]
+    fn win32_start(&self, op_data: &mut OpData<'_>) -> Result<Option<usize>> {
+        use windows_sys::Win32::{
+            Networking::WinSock::{WSARecv, WSASend, MSG_WAITALL, WSABUF},
+            Storage::FileSystem::{ReadFile, WriteFile},
+            System::IO::PostQueuedCompletionStatus,
+        };
+
+        let write = match self.opcode {
+            Opcode::Nop => {
+                let res =
+                    unsafe { PostQueuedCompletionStatus(op_data.port, 0, 0, op_data.overlapped) };
+
+                return if res == 0 {
+                    Err(std::io::Error::last_os_error())
+                } else {
+                    Ok(None)
+                };
+            }
+            Opcode::PollReadable | Opcode::PollWritable => {
+                if self.variant != SourceType::Socket {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "only sockets can be polled for readiness",
+                    ));
+                }
+
+                let events = match self.opcode {
+                    Opcode::PollReadable => crate::afd::READABLE,
+                    _ => crate::afd::WRITABLE,
+                };
+
+                // there's nothing left to do once the socket is ready
+                return op_data.poll_ready(self.fd, events, PollingFn::new(|| Ok(0)));
+            }
+            Opcode::Read => false,
+            Opcode::Write => true,
+        };
+
+        let overlapped = op_data.overlapped;
+        let (ptr, len) = (self.buf, self.len);
+        let zero = self.zero();
+        // an offset of all ones writes to the end of the file
+        let offset = if self.append {
+            u64::MAX
+        } else {
+            self.offset as u64
+        };
+
+        match self.variant {
+            SourceType::Socket => {
+                let buf = WSABUF {
+                    len: len as _,
+                    buf: ptr.as_ptr() as _,
+                };
+                let mut transferred = 0;
+
+                if write {
+                    check_socket_error!(unsafe {
+                        WSASend(self.fd as _, &buf, 1, &mut transferred, 0, overlapped, None)
+                    })
+                } else {
+                    let mut flags = if self.exact { MSG_WAITALL as _ } else { 0 };
+
+                    check_socket_error!(unsafe {
+                        WSARecv(
+                            self.fd as _,
+                            &buf,
+                            1,
+                            &mut transferred,
+                            &mut flags,
+                            overlapped,
+                            None,
+                        )
+                    })
+                }
+            }
+            SourceType::File if self.exact => {
+                // IOCP doesn't retry short transfers, so do it on another thread
+                let handle = self.fd;
+                let append = self.append;
+                let ptr = TsPtr(ptr);
+
+                crate::iocp::complete_on_thread(op_data, move || {
+                    super::with_event(|event| {
+                        super::transfer_exact(len, zero, |done| {
+                            let ptr = unsafe { ptr.0.as_ptr().add(done) };
+                            let offset = if append { offset } else { offset + done as u64 };
+                            super::overlapped_transfer(
+                                handle,
+                                event,
+                                offset,
+                                ptr,
+                                len - done,
+                                write,
+                            )
+                        })
+                    })
+                })
+            }
+            SourceType::File => {
+                let mut transferred = 0;
+
+                install_offset!(overlapped, offset);
+                check_win32_error!(unsafe {
+                    if write {
+                        WriteFile(
+                            self.fd as _,
+                            ptr.as_ptr() as _,
+                            len as _,
+                            &mut transferred,
+                            overlapped,
+                        )
+                    } else {
+                        ReadFile(
+                            self.fd as _,
+                            ptr.as_ptr() as _,
+                            len as _,
+                            &mut transferred,
+                            overlapped,
+                        )
+                    }
+                })
+            }
+            SourceType::Tty => {
+                // console handles don't support overlapped I/O, so
+                // do a blocking operation on another thread
+                let handle = self.fd as usize;
+                let ptr = TsPtr(ptr);
+                let exact = self.exact;
+
+                crate::iocp::complete_on_thread(op_data, move || {
+                    let transfer = |done: usize| {
+                        let mut transferred = 0;
+                        let (ptr, len) = (unsafe { ptr.0.as_ptr().add(done) }, len - done);
+                        let res = unsafe {
+                            if write {
+                                WriteFile(
+                                    handle as _,
+                                    ptr as _,
+                                    len as _,
+                                    &mut transferred,
+                                    std::ptr::null_mut(),
+                                )
+                            } else {
+                                ReadFile(
+                                    handle as _,
+                                    ptr as _,
+                                    len as _,
+                                    &mut transferred,
+                                    std::ptr::null_mut(),
+                                )
+                            }
+                        };
+
+                        if res == 0 {
+                            Err(std::io::Error::last_os_error())
+                        } else {
+                            Ok(transferred as usize)
+                        }
+                    };
+
+                    if exact {
+                        super::transfer_exact(len, zero, transfer)
+                    } else {
+                        transfer(0)
+                    }
+                })
+            }
+        }
+    }
+}
+
+/// Check whether the file descriptor is ready for `events`.
+#[cfg(unix)]
+fn ready(fd: Raw, events: libc::c_short) -> Result<usize> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    };
+
+    match syscall!(poll(&mut pollfd, 1, 0))? {
+        0 => Err(io::ErrorKind::WouldBlock.into()),
+        _ => Ok(0),
+    }
+}
+
+/// Write to the end of a file.
+#[cfg(unix)]
+fn append_write(fd: Raw, ptr: *mut u8, len: usize) -> Result<libc::ssize_t> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            let iov = libc::iovec {
+                iov_base: ptr.cast(),
+                iov_len: len,
+            };
+            syscall!(pwritev2(fd, &iov, 1, -1, libc::RWF_APPEND))
+        } else {
+            syscall!(lseek(fd, 0, libc::SEEK_END))?;
+            syscall!(write(fd, ptr.cast(), len))
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// GNU GPL v3 License
+
+#![cfg(target_os = "linux")]
+
+use crate::{PollingFn, Raw, Source, SourceType};
+use io_uring::{opcode, types::Fd};
+use std::{io::Result, ptr};
+
+/// Move data between a pipe and another source, without copying it
+/// through userspace.
+///
+/// This is `splice`, so `from` or `to` has to be a pipe. The result is
+/// the number of bytes moved, which may be less than requested, and is
+/// zero at the end of `from`.
+///
+/// `io_uring` waits for either end on its own. The readiness backends
+/// only wait for `to` to become writable, so `from` should already have
+/// the data, like a file or a pipe that was just spliced into. A file as
+/// `to` is always written on a separate thread.
+pub struct Splice {
+    source: Raw,
+    variant: SourceType,
+    from: Raw,
+    from_offset: i64,
+    to_offset: i64,
+    len: usize,
+}
+
+impl Splice {
+    /// Create a new `Splice` that moves up to `len` bytes from `from` to
+    /// `to`.
+    ///
+    /// Both ends start at their current position, which pipes and
+    /// sockets must.
+    pub fn new<S: Source, T: Source>(from: &S, to: &T, len: usize) -> Self {
+        Splice {
+            source: to.as_raw(),
+            variant: T::SOURCE_TYPE,
+            from: from.as_raw(),
+            from_offset: -1,
+            to_offset: -1,
+            len,
+        }
+    }
+
+    /// Set the offset to move from, if `from` isn't a pipe.
+    ///
+    /// Fails with `InvalidInput` if `offset` is larger than `i64::MAX`.
+    pub fn from_offset(&mut self, offset: u64) -> Result<&mut Self> {
+        self.from_offset = super::check_offset(offset)?;
+        Ok(self)
+    }
+
+    /// Set the offset to move to, if `to` isn't a pipe.
+    ///
+    /// Fails with `InvalidInput` if `offset` is larger than `i64::MAX`.
+    pub fn to_offset(&mut self, offset: u64) -> Result<&mut Self> {
+        self.to_offset = super::check_offset(offset)?;
+        Ok(self)
+    }
+
+    /// There is nothing to retrieve.
+    ///
+    /// # Safety
+    ///
+    /// Always safe, only unsafe for consistency with other operations.
+    unsafe fn into_buf(self) {}
+
+    /// Splice with the given flags on whatever thread this is called on.
+    fn splice_function(&mut self, flags: libc::c_uint) -> PollingFn {
+        let (from, to, len) = (self.from, self.source, self.len);
+        let (mut from_offset, mut to_offset) = (self.from_offset, self.to_offset);
+
+        PollingFn::new(move || {
+            let n = syscall!(splice(
+                from,
+                offset_ptr(&mut from_offset),
+                to,
+                offset_ptr(&mut to_offset),
+                len,
+                flags
+            ))?;
+            Ok(n as usize)
+        })
+    }
+
+    fn polling_function(&mut self) -> PollingFn {
+        // a pipe that isn't ready fails instead of blocking the poller
+        self.splice_function(libc::SPLICE_F_NONBLOCK)
+    }
+
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        Some(self.splice_function(0))
+    }
+
+    const READ: bool = false;
+    const WRITE: bool = true;
+
+    fn uring_entry(&mut self) -> io_uring::squeue::Entry {
+        // a longer splice is cut short anyway
+        let len = self.len.min(u32::MAX as usize) as u32;
+        opcode::Splice::new(
+            Fd(self.from),
+            self.from_offset,
+            Fd(self.source),
+            self.to_offset,
+            len,
+        )
+        .build()
+    }
+}
+
+/// The offset to hand to `splice`, where a negative one means the current
+/// position.
+fn offset_ptr(offset: &mut i64) -> *mut i64 {
+    if *offset < 0 {
+        ptr::null_mut()
+    } else {
+        offset
+    }
+}
+
+impl_op! {
+    <> Splice: () => usize, |result, _captured| result
+}
@@ -0,0 +1,124 @@
+// GNU GPL v3 License
+
+use super::{Op, OpBase, Read};
+use crate::{BufMut, OpData, Raw, Source, SourceType};
+use std::io::Result;
+
+/// How a read from a stream completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompletionKind {
+    /// This many bytes were read.
+    Data(usize),
+    /// The stream reached its end, or the peer closed the connection.
+    Eof,
+}
+
+/// Read from a stream, telling end-of-file apart from data.
+///
+/// A plain `Read` reports end-of-file as reading zero bytes, which a
+/// datagram socket also reports for an empty datagram. This reports it as
+/// `CompletionKind::Eof` instead, the same way on every backend. Only use
+/// this for streams, such as files, pipes and TCP sockets. A connection
+/// that the peer resets still fails with `ConnectionReset`.
+pub struct ReadStream<B> {
+    inner: Read<B>,
+}
+
+impl<B: BufMut + Send> ReadStream<B> {
+    /// Create a new `ReadStream` from the source and a buffer to read into.
+    pub fn new<S: Source>(source: &S, buf: B) -> Self {
+        ReadStream {
+            inner: Read::new(source, buf),
+        }
+    }
+
+    /// Set the offset to read from.
+    ///
+    /// See `Read::offset`.
+    pub fn offset(&mut self, offset: u64) -> Result<&mut Self> {
+        self.inner.offset(offset)?;
+        Ok(self)
+    }
+
+    /// The position in the file right after the data that was read.
+    ///
+    /// See `Read::end_position`.
+    pub fn end_position(&self, result: usize) -> Option<u64> {
+        self.inner.end_position(result)
+    }
+
+    /// Start reading into the buffer at `buf_offset`.
+    ///
+    /// See `Read::buf_offset`.
+    #[track_caller]
+    pub fn buf_offset(&mut self, buf_offset: usize) -> &mut Self {
+        self.inner.buf_offset(buf_offset);
+        self
+    }
+
+    /// Read at most `max_len` bytes, even if the buffer is larger.
+    pub fn max_len(&mut self, max_len: usize) -> &mut Self {
+        self.inner.max_len(max_len);
+        self
+    }
+
+    /// Submit the read again every time it completes with data.
+    ///
+    /// See `Read::rearm`.
+    ///
+    /// # Safety
+    ///
+    /// See `Read::rearm`.
+    pub unsafe fn rearm(&mut self, rearm: bool) -> &mut Self {
+        self.inner.rearm(rearm);
+        self
+    }
+}
+
+unsafe impl<B: BufMut + Send> OpBase for ReadStream<B> {
+    fn run(&mut self, op_data: &mut OpData<'_>) -> Result<()> {
+        self.inner.run(op_data)
+    }
+}
+
+unsafe impl<B: BufMut + Send> Op for ReadStream<B> {
+    /// The buffer, and whether any bytes were asked for.
+    type Captured = (B, bool);
+    type Output = (CompletionKind, B);
+
+    fn source(&self) -> Raw {
+        self.inner.source()
+    }
+
+    fn variant(&self) -> SourceType {
+        self.inner.variant()
+    }
+
+    fn pinned_bytes(&self) -> usize {
+        self.inner.pinned_bytes()
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    fn is_rearmed(&self) -> bool {
+        self.inner.is_rearmed()
+    }
+
+    unsafe fn into_captured(self) -> (B, bool) {
+        let mut inner = self.inner;
+        let requested = inner.requested_len() > 0;
+        (inner.into_captured(), requested)
+    }
+
+    fn decode(result: usize, (buf, requested): (B, bool)) -> (CompletionKind, B) {
+        // reading nothing into an empty buffer isn't the end
+        let kind = match result {
+            0 if requested => CompletionKind::Eof,
+            n => CompletionKind::Data(n),
+        };
+
+        (kind, buf)
+    }
+}
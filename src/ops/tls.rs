@@ -0,0 +1,193 @@
+// GNU GPL v3 License
+
+#![cfg(target_os = "linux")]
+
+use super::{
+    gso::{Control, Msg},
+    split_nonnull, OpSpec, Opcode,
+};
+use crate::{Buf, BufMut, Raw, Source, SourceType};
+use std::{
+    io::Result,
+    mem,
+    ptr::{self, NonNull},
+};
+
+/// The control message that carries the type of a TLS record.
+const TLS_SET_RECORD_TYPE: libc::c_int = 1;
+const TLS_GET_RECORD_TYPE: libc::c_int = 2;
+
+/// The content type of a TLS record.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TlsRecordType(pub u8);
+
+impl TlsRecordType {
+    /// `change_cipher_spec`.
+    pub const CHANGE_CIPHER_SPEC: Self = TlsRecordType(20);
+    /// `alert`.
+    pub const ALERT: Self = TlsRecordType(21);
+    /// `handshake`.
+    pub const HANDSHAKE: Self = TlsRecordType(22);
+    /// `application_data`, which is what plain reads and writes use.
+    pub const APPLICATION_DATA: Self = TlsRecordType(23);
+}
+
+/// Tell whether kernel TLS is enabled on the socket.
+///
+/// This is the case once the `tls` upper layer protocol is installed with
+/// `TCP_ULP`. The keys still have to be set with `TLS_TX` and `TLS_RX`
+/// before the kernel encrypts or decrypts anything.
+pub fn is_ktls(source: &impl Source) -> bool {
+    let mut name = [0u8; 16];
+    let mut len = name.len() as libc::socklen_t;
+
+    let result = syscall!(getsockopt(
+        source.as_raw(),
+        libc::SOL_TCP,
+        libc::TCP_ULP,
+        name.as_mut_ptr().cast(),
+        &mut len
+    ));
+
+    result.is_ok() && name.starts_with(b"tls\0")
+}
+
+/// Send a buffer as a TLS record of a given type, over a kernel TLS socket.
+///
+/// Plain writes are always sent as application data; this is needed for
+/// alerts and handshake messages, such as key updates. The record type is
+/// passed as a `TLS_SET_RECORD_TYPE` control message. Files are best sent
+/// with plain writes once `TLS_TX` is set up, which the kernel encrypts
+/// without copying them to user space first.
+pub struct SendTlsRecord<B> {
+    source: Raw,
+    variant: SourceType,
+    buf: B,
+    record_type: TlsRecordType,
+    msg: Box<Msg>,
+}
+
+impl<B: Buf + Send> SendTlsRecord<B> {
+    /// Create a new `SendTlsRecord` from the source, a buffer to send and
+    /// the type of the record.
+    pub fn new<S: Source>(source: &S, buf: B, record_type: TlsRecordType) -> Self {
+        SendTlsRecord {
+            source: source.as_raw(),
+            variant: S::SOURCE_TYPE,
+            buf,
+            record_type,
+            msg: Msg::new(),
+        }
+    }
+
+    /// Retrieve the inner buffer.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffer is retrieved.
+    unsafe fn into_buf(self) -> B {
+        self.buf
+    }
+
+    /// The size of the buffer.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.buf)
+    }
+
+    /// Fill out the message header.
+    fn prepare(&mut self) -> NonNull<libc::msghdr> {
+        let msg = &mut *self.msg;
+
+        // install the record type as a control message
+        msg.hdr.msg_control = msg.control.0.as_mut_ptr().cast();
+        msg.hdr.msg_controllen = unsafe { libc::CMSG_SPACE(mem::size_of::<u8>() as _) } as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg.hdr);
+            (*cmsg).cmsg_level = libc::SOL_TLS;
+            (*cmsg).cmsg_type = TLS_SET_RECORD_TYPE;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u8>() as _) as _;
+            ptr::write(libc::CMSG_DATA(cmsg), self.record_type.0);
+        }
+
+        msg.prepare(split_nonnull(self.buf.pointer()))
+    }
+
+    /// Describe the operation.
+    fn spec(&mut self) -> OpSpec {
+        OpSpec::new(Opcode::SendMsg(self.prepare()), self.source, self.variant)
+    }
+}
+
+/// Receive a TLS record, along with its type, over a kernel TLS socket.
+///
+/// Plain reads fail with `EIO` when the next record isn't application data,
+/// since they have nowhere to put its type; this reads any record. The
+/// output contains the number of bytes received, the type of the record
+/// and the buffer.
+pub struct RecvTlsRecord<B> {
+    source: Raw,
+    variant: SourceType,
+    buf: B,
+    msg: Box<Msg>,
+}
+
+impl<B: BufMut + Send> RecvTlsRecord<B> {
+    /// Create a new `RecvTlsRecord` from the source and a buffer to read
+    /// into.
+    pub fn new<S: Source>(source: &S, buf: B) -> Self {
+        RecvTlsRecord {
+            source: source.as_raw(),
+            variant: S::SOURCE_TYPE,
+            buf,
+            msg: Msg::new(),
+        }
+    }
+
+    /// Retrieve the inner buffer and the record type.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffer is retrieved.
+    unsafe fn into_buf(self) -> (B, TlsRecordType) {
+        let hdr = &self.msg.hdr;
+        let mut record_type = TlsRecordType::APPLICATION_DATA;
+
+        let mut cmsg = libc::CMSG_FIRSTHDR(hdr);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_TLS && (*cmsg).cmsg_type == TLS_GET_RECORD_TYPE {
+                record_type = TlsRecordType(ptr::read(libc::CMSG_DATA(cmsg)));
+            }
+            cmsg = libc::CMSG_NXTHDR(hdr, cmsg);
+        }
+
+        (self.buf, record_type)
+    }
+
+    /// The size of the buffer.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.buf)
+    }
+
+    /// Fill out the message header.
+    fn prepare(&mut self) -> NonNull<libc::msghdr> {
+        let msg = &mut *self.msg;
+        msg.hdr.msg_control = msg.control.0.as_mut_ptr().cast();
+        msg.hdr.msg_controllen = mem::size_of::<Control>() as _;
+        msg.prepare(split_nonnull(self.buf.pointer()))
+    }
+
+    /// Describe the operation.
+    fn spec(&mut self) -> OpSpec {
+        OpSpec::new(Opcode::RecvMsg(self.prepare()), self.source, self.variant)
+    }
+}
+
+impl_op! {
+    <B: Buf + Send> SendTlsRecord: B, pinned = pinned, spec = spec
+}
+
+impl_op! {
+    <B: BufMut + Send> RecvTlsRecord: (B, TlsRecordType) => (usize, TlsRecordType, B),
+    |result, captured| (result, captured.1, captured.0),
+    pinned = pinned, spec = spec
+}
@@ -0,0 +1,82 @@
+// GNU GPL v3 License
+
+#![cfg(feature = "tracing-spans")]
+
+use super::{Op, OpBase};
+use crate::{OpData, Raw, SourceType};
+use std::{io::Result, ptr::NonNull};
+use tracing::Span;
+
+/// An operation that carries a `tracing` span from submission to
+/// completion.
+///
+/// The span is entered while the operation is submitted, while its event
+/// is received by `wait` and while its output is built, so that everything
+/// logged along the way belongs to it. Create one with `Op::with_span`.
+pub struct Traced<O> {
+    inner: O,
+    span: Span,
+}
+
+impl<O: Op> Traced<O> {
+    pub(super) fn new(inner: O, span: Span) -> Self {
+        Traced { inner, span }
+    }
+
+    /// The span of the operation.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+}
+
+unsafe impl<O: Op> OpBase for Traced<O> {
+    fn run(&mut self, op_data: &mut OpData<'_>) -> Result<()> {
+        let _enter = self.span.enter();
+        self.inner.run(op_data)
+    }
+}
+
+unsafe impl<O: Op> Op for Traced<O> {
+    type Captured = (O::Captured, Span);
+    type Output = O::Output;
+
+    fn source(&self) -> Raw {
+        self.inner.source()
+    }
+
+    fn variant(&self) -> SourceType {
+        self.inner.variant()
+    }
+
+    fn pinned_bytes(&self) -> usize {
+        self.inner.pinned_bytes()
+    }
+
+    fn reset(&mut self) {
+        self.inner.reset()
+    }
+
+    fn is_rearmed(&self) -> bool {
+        self.inner.is_rearmed()
+    }
+
+    fn is_barrier(&self) -> bool {
+        self.inner.is_barrier()
+    }
+
+    fn coalescable(&mut self) -> Option<NonNull<[u8]>> {
+        self.inner.coalescable()
+    }
+
+    fn op_span(&self) -> Option<&Span> {
+        Some(&self.span)
+    }
+
+    unsafe fn into_captured(self) -> Self::Captured {
+        (self.inner.into_captured(), self.span)
+    }
+
+    fn decode(result: usize, (captured, span): Self::Captured) -> O::Output {
+        span.in_scope(|| O::decode(result, captured))
+    }
+}
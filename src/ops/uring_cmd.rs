@@ -0,0 +1,96 @@
+// GNU GPL v3 License
+
+#![cfg(target_os = "linux")]
+
+use crate::{PollingFn, Raw, Source, SourceType};
+use std::io::{self, Result};
+
+/// The opcode of `IORING_OP_URING_CMD`, which `io-uring` doesn't wrap.
+pub(crate) const IORING_OP_URING_CMD: u8 = 46;
+
+/// Where the fields of `IORING_OP_URING_CMD` live in a submission entry.
+const CMD_OP_OFFSET: usize = 8;
+const CMD_OFFSET: usize = 48;
+
+/// The size of the command that fits in a submission entry.
+pub const URING_CMD_LEN: usize = 16;
+
+/// Pass a command through to the driver behind a device.
+///
+/// This is `IORING_OP_URING_CMD`, used by `ublk` and NVMe character
+/// devices. The command is `URING_CMD_LEN` bytes, which are handed to the
+/// driver as they are; commands that need a 128-byte entry, such as NVMe
+/// passthrough, aren't supported yet. The result is the driver's.
+///
+/// This only runs on `io_uring`, and fails with `Unsupported` anywhere
+/// else. Check `Capabilities::uring_cmd` before relying on it. It always
+/// goes through `io_uring` with the hybrid backend, as if the device were
+/// a file.
+pub struct UringCmd {
+    source: Raw,
+    variant: SourceType,
+    cmd_op: u32,
+    cmd: [u8; URING_CMD_LEN],
+}
+
+impl UringCmd {
+    /// Create a new `UringCmd` that sends `cmd` to the driver, as the
+    /// operation `cmd_op`.
+    ///
+    /// # Safety
+    ///
+    /// The driver does whatever the command tells it to. Any memory that
+    /// the command points to must stay valid, and not be used otherwise,
+    /// until the operation's event is received.
+    pub unsafe fn new(source: &impl Source, cmd_op: u32, cmd: [u8; URING_CMD_LEN]) -> Self {
+        UringCmd {
+            source: source.as_raw(),
+            variant: SourceType::File,
+            cmd_op,
+            cmd,
+        }
+    }
+
+    /// Get the command back.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete at this point.
+    unsafe fn into_buf(self) -> [u8; URING_CMD_LEN] {
+        self.cmd
+    }
+
+    fn polling_function(&mut self) -> PollingFn {
+        PollingFn::new(|| {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "uring_cmd is only supported by io_uring",
+            ))
+        })
+    }
+
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        None
+    }
+
+    const READ: bool = false;
+    const WRITE: bool = false;
+
+    fn uring_entry(&mut self) -> io_uring::squeue::Entry {
+        let mut entry = io_uring::opcode::Nop::new().build();
+
+        // SAFETY: an `Entry` is a transparent wrapper around the 64-byte
+        // `io_uring_sqe`, which is plain data
+        let sqe = unsafe { &mut *(&mut entry as *mut io_uring::squeue::Entry).cast::<[u8; 64]>() };
+        sqe[0] = IORING_OP_URING_CMD;
+        sqe[4..8].copy_from_slice(&self.source.to_ne_bytes());
+        sqe[CMD_OP_OFFSET..CMD_OP_OFFSET + 4].copy_from_slice(&self.cmd_op.to_ne_bytes());
+        sqe[CMD_OFFSET..CMD_OFFSET + URING_CMD_LEN].copy_from_slice(&self.cmd);
+
+        entry
+    }
+}
+
+impl_op! {
+    <> UringCmd: [u8; URING_CMD_LEN]
+}
@@ -0,0 +1,401 @@
+// GNU GPL v3 License
+
+use super::TsPtr;
+use crate::{IoBuf, PollingFn, Raw, Source, SourceType, VectoredBuf, VectoredBufMut};
+use std::{io::Result, ptr::NonNull};
+
+#[cfg(unix)]
+use std::io;
+
+#[cfg(unix)]
+type Sys = libc::iovec;
+#[cfg(windows)]
+type Sys = windows_sys::Win32::Networking::WinSock::WSABUF;
+
+/// The number of buffers used by a single Windows operation.
+#[cfg(windows)]
+const WSABUF_MAX: usize = 64;
+
+/// The maximum number of buffers in a single vectored system call.
+#[cfg(unix)]
+fn iov_max() -> usize {
+    match unsafe { libc::sysconf(libc::_SC_IOV_MAX) } {
+        max if max > 0 => max as usize,
+        // the smallest value POSIX allows
+        _ => 16,
+    }
+}
+
+/// Create a system buffer for a slice.
+fn sys(ptr: *const u8, len: usize) -> Sys {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            libc::iovec {
+                iov_base: ptr as *mut _,
+                iov_len: len,
+            }
+        } else {
+            Sys {
+                len: len as _,
+                buf: ptr as *mut _,
+            }
+        }
+    }
+}
+
+/// Build the list of system buffers for a vectored buffer.
+///
+/// # Safety
+///
+/// `bufs` must be valid to read from.
+unsafe fn to_sys<T: IoBuf>(bufs: NonNull<[T]>) -> Box<[Sys]> {
+    (*bufs.as_ptr())
+        .iter()
+        .map(|buf| {
+            let slice = buf.as_io_slice();
+            sys(slice.as_ptr(), slice.len())
+        })
+        .collect()
+}
+
+/// Build the list of system buffers for a mutable vectored buffer.
+///
+/// # Safety
+///
+/// `bufs` must be valid to write to, and not be used by anything else.
+unsafe fn to_sys_mut<T: IoBuf>(bufs: NonNull<[T]>) -> Box<[Sys]> {
+    // go through the raw pointers, `VectoredBufMut` allows writing to them
+    (*bufs.as_ptr())
+        .iter()
+        .map(|buf| {
+            let (ptr, len) = super::split_nonnull(buf.pointer());
+            sys(ptr.as_ptr(), len)
+        })
+        .collect()
+}
+
+/// Run `transfer` over `iovecs` in chunks of at most `iov_max()` buffers.
+///
+/// `transfer` is called with the chunk and the number of bytes transferred
+/// so far. This stops at the first short transfer, and errors after some
+/// data was transferred are reported as a short transfer.
+#[cfg(unix)]
+fn chunked(
+    iovecs: &[libc::iovec],
+    mut transfer: impl FnMut(&[libc::iovec], usize) -> Result<usize>,
+) -> Result<usize> {
+    let mut total = 0;
+
+    for chunk in iovecs.chunks(iov_max()) {
+        let expected: usize = chunk.iter().map(|iov| iov.iov_len).sum();
+
+        match transfer(chunk, total) {
+            Ok(n) => {
+                total += n;
+                if n < expected {
+                    break;
+                }
+            }
+            Err(_) if total > 0 => break,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(total)
+}
+
+/// Build one `io_uring` entry for each chunk of `iovecs`.
+#[cfg(target_os = "linux")]
+fn uring_chunks(
+    iovecs: &[libc::iovec],
+    offset: Option<i64>,
+    mut entry: impl FnMut(&[libc::iovec], i64) -> io_uring::squeue::Entry,
+) -> Vec<io_uring::squeue::Entry> {
+    let mut position = offset.unwrap_or(0);
+
+    iovecs
+        .chunks(iov_max())
+        .map(|chunk| {
+            let e = entry(chunk, position);
+            if offset.is_some() {
+                position += chunk.iter().map(|iov| iov.iov_len as i64).sum::<i64>();
+            }
+            e
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn nowait(result: Result<libc::ssize_t>) -> Result<usize> {
+    match result {
+        Ok(n) => Ok(n as _),
+        Err(e)
+            if matches!(
+                e.raw_os_error(),
+                Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS)
+            ) =>
+        {
+            // RWF_NOWAIT isn't supported here
+            Err(io::ErrorKind::WouldBlock.into())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+macro_rules! vectored_op {
+    (
+        $(#[$meta: meta])*
+        $name: ident: $bound: ident, $to_sys: ident,
+        readv = $readv: ident,
+        preadv = $preadv: ident,
+        preadv2 = $preadv2: ident,
+        opcode = $opcode: ident,
+        win32 = $win32: ident($($flags: tt)*),
+        read = $read: expr
+    ) => {
+        $(#[$meta])*
+        pub struct $name<B> {
+            source: Raw,
+            variant: SourceType,
+            buf: B,
+            offset: i64,
+            iovecs: Box<[Sys]>,
+        }
+
+        impl<B: $bound + Send> $name<B> {
+            /// Create a new operation from the source and the buffers.
+            pub fn new<S: Source>(source: &S, buf: B) -> Self {
+                $name {
+                    source: source.as_raw(),
+                    variant: S::SOURCE_TYPE,
+                    buf,
+                    offset: 0,
+                    iovecs: Box::new([]),
+                }
+            }
+
+            /// Set the offset to start at.
+            ///
+            /// This has no effect for sockets.
+            ///
+            /// Fails with `InvalidInput` if `offset` is larger than `i64::MAX`.
+            pub fn offset(&mut self, offset: u64) -> Result<&mut Self> {
+                self.offset = super::check_offset(offset)?;
+                Ok(self)
+            }
+
+            /// The position in the file right after the data that was
+            /// transferred, given the result of the operation's event.
+            ///
+            /// Returns `None` if the source isn't a file.
+            pub fn end_position(&self, result: usize) -> Option<u64> {
+                super::end_position(self.variant, self.offset, result)
+            }
+
+            /// Retrieve the inner buffer.
+            ///
+            /// # Safety
+            ///
+            /// The operation must be complete before the buffer is retrieved.
+            unsafe fn into_buf(self) -> B {
+                self.buf
+            }
+
+            /// The total size of the buffers.
+            fn pinned(&self) -> usize {
+                // SAFETY: we own the buffers
+                let bufs = unsafe { &*self.buf.pointer().as_ptr() };
+                bufs.iter().map(super::buf_len).sum()
+            }
+
+            /// Build the system buffers, returning a pointer to them.
+            ///
+            /// They're only built once, since both the polling and the
+            /// blocking function point to them.
+            fn prepare(&mut self) -> NonNull<[Sys]> {
+                if self.iovecs.is_empty() {
+                    // SAFETY: the buffers are valid for as long as we own
+                    // them, and nothing else uses them while the operation
+                    // is in flight
+                    self.iovecs = unsafe { $to_sys(self.buf.pointer()) };
+                }
+
+                NonNull::from(&mut *self.iovecs)
+            }
+
+            #[cfg(unix)]
+            fn polling_function(&mut self) -> PollingFn {
+                let iovecs = TsPtr(self.prepare());
+                let source = self.source;
+                let offset = self.offset;
+
+                match self.variant {
+                    // only transfer if the data is in the page cache, the
+                    // blocking pool takes care of it otherwise
+                    #[cfg(target_os = "linux")]
+                    SourceType::File => PollingFn::new(move || {
+                        let iovecs = unsafe { &*iovecs.0.as_ptr() };
+                        chunked(iovecs, |chunk, done| {
+                            nowait(syscall!($preadv2(
+                                source,
+                                chunk.as_ptr(),
+                                chunk.len() as _,
+                                offset + done as i64,
+                                libc::RWF_NOWAIT
+                            )))
+                        })
+                    }),
+                    #[cfg(not(target_os = "linux"))]
+                    SourceType::File => {
+                        let mut seeked = false;
+
+                        PollingFn::new(move || {
+                            if !seeked {
+                                syscall!(lseek(source, offset, libc::SEEK_SET))?;
+                                seeked = true;
+                            }
+
+                            let iovecs = unsafe { &*iovecs.0.as_ptr() };
+                            chunked(iovecs, |chunk, _| {
+                                let n = syscall!($readv(source, chunk.as_ptr(), chunk.len() as _))?;
+                                Ok(n as _)
+                            })
+                        })
+                    }
+                    SourceType::Socket | SourceType::Tty => PollingFn::new(move || {
+                        let iovecs = unsafe { &*iovecs.0.as_ptr() };
+                        chunked(iovecs, |chunk, _| {
+                            let n = syscall!($readv(source, chunk.as_ptr(), chunk.len() as _))?;
+                            Ok(n as _)
+                        })
+                    }),
+                }
+            }
+
+            #[cfg(unix)]
+            fn blocking_function(&mut self) -> Option<PollingFn> {
+                let iovecs = TsPtr(self.prepare());
+                let source = self.source;
+                let offset = self.offset;
+
+                Some(PollingFn::new(move || {
+                    let iovecs = unsafe { &*iovecs.0.as_ptr() };
+                    chunked(iovecs, |chunk, done| {
+                        let n = syscall!($preadv(
+                            source,
+                            chunk.as_ptr(),
+                            chunk.len() as _,
+                            (offset + done as i64) as _
+                        ))?;
+                        Ok(n as _)
+                    })
+                }))
+            }
+
+            #[cfg(unix)]
+            const READ: bool = $read;
+            #[cfg(unix)]
+            const WRITE: bool = !$read;
+
+            #[cfg(target_os = "linux")]
+            fn uring_entry(&mut self) -> Vec<io_uring::squeue::Entry> {
+                use io_uring::{opcode::$opcode, types::Fd};
+
+                let iovecs = unsafe { &*self.prepare().as_ptr() };
+                let source = self.source;
+                let offset = match self.variant {
+                    SourceType::File => Some(self.offset),
+                    _ => None,
+                };
+
+                uring_chunks(iovecs, offset, |chunk, position| {
+                    let mut entry = $opcode::new(Fd(source), chunk.as_ptr(), chunk.len() as _);
+                    if offset.is_some() {
+                        entry = entry.offset(position);
+                    }
+                    entry.build()
+                })
+            }
+
+            #[cfg(windows)]
+            fn win32_start(&mut self, op_data: &mut crate::OpData<'_>) -> Result<Option<usize>> {
+                if !matches!(self.variant, SourceType::Socket) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "vectored operations are only supported on sockets",
+                    ));
+                }
+
+                let iovecs = unsafe { &*self.prepare().as_ptr() };
+                let count = iovecs.len().min(WSABUF_MAX);
+                let mut transferred = 0;
+                let mut flags = 0;
+
+                check_socket_error!(unsafe {
+                    windows_sys::Win32::Networking::WinSock::$win32(
+                        self.source as _,
+                        iovecs.as_ptr(),
+                        count as _,
+                        &mut transferred,
+                        $($flags)* flags,
+                        op_data.overlapped,
+                        None,
+                    )
+                })
+            }
+        }
+
+        impl_op! {
+            <B: $bound + Send> $name: B, pinned = pinned
+        }
+    };
+}
+
+vectored_op! {
+    /// Read in data from a source to several buffers.
+    ///
+    /// Buffers beyond the system's limit on the number of buffers in a single
+    /// call (`IOV_MAX`) are read in further calls, and the output holds the
+    /// combined number of bytes read. With `io_uring`, each further call is
+    /// a linked entry, so the key must not be shared with other operations
+    /// that are in flight. On Windows, only sockets are supported, and a read
+    /// fills at most 64 buffers.
+    ReadVectored: VectoredBufMut, to_sys_mut,
+    readv = readv,
+    preadv = preadv,
+    preadv2 = preadv2,
+    opcode = Readv,
+    win32 = WSARecv(&mut),
+    read = true
+}
+
+vectored_op! {
+    /// Write data from several buffers to a source.
+    ///
+    /// Buffers beyond the system's limit on the number of buffers in a single
+    /// call (`IOV_MAX`) are written in further calls, and the output holds the
+    /// combined number of bytes written. With `io_uring`, each further call
+    /// is a linked entry, so the key must not be shared with other operations
+    /// that are in flight. On Windows, only sockets are supported, and a write
+    /// sends at most 64 buffers.
+    WriteVectored: VectoredBuf, to_sys,
+    readv = writev,
+    preadv = pwritev,
+    preadv2 = pwritev2,
+    opcode = Writev,
+    win32 = WSASend(),
+    read = false
+}
+
+impl<B: VectoredBuf + Send> WriteVectored<B> {
+    /// Create a new `WriteVectored` from a raw source.
+    pub(crate) fn from_raw(source: Raw, variant: SourceType, buf: B) -> Self {
+        WriteVectored {
+            source,
+            variant,
+            buf,
+            offset: 0,
+            iovecs: Box::new([]),
+        }
+    }
+}
@@ -1,15 +1,8 @@
 // GNU GPL v3 License
 
-use super::split_nonnull;
-use crate::{Buf, PollingFn, Raw, Source, SourceType};
-use std::io::Result;
-
-#[cfg(windows)]
-use windows_sys::Win32::{
-    Foundation::ERROR_IO_PENDING,
-    Networking::WinSock::{WSAGetLastError, SOCKET_ERROR, WSABUF},
-    System::IO::OVERLAPPED,
-};
+use super::{split_nonnull, OpSpec, Opcode};
+use crate::{Buf, Raw, Source, SourceType};
+use std::{io::Result, ptr::NonNull};
 
 /// Write data from a buffer to a source.
 pub struct Write<B> {
@@ -17,9 +10,14 @@ pub struct Write<B> {
     variant: SourceType,
     buf: B,
     offset: i64,
+    append: bool,
+    buf_offset: usize,
+    max_len: Option<usize>,
+    exact: bool,
+    fixed_buffer: Option<u16>,
 }
 
-impl<B: Buf> Write<B> {
+impl<B: Buf + Send> Write<B> {
     /// Create a new `Read` from the source and a buffer to read into.
     pub fn new<S: Source>(source: &S, buf: B) -> Self {
         Write {
@@ -27,18 +25,135 @@ impl<B: Buf> Write<B> {
             variant: S::SOURCE_TYPE,
             buf,
             offset: 0,
+            buf_offset: 0,
+            append: false,
+            max_len: None,
+            exact: false,
+            fixed_buffer: None,
         }
     }
 
-    /// Set the offset to read from.
+    /// Set the offset to write to.
     ///
     /// This has no effect for sockets. For files, this indicates the
-    /// offset to start reading at.
-    pub fn offset(&mut self, offset: i64) -> &mut Self {
-        self.offset = offset;
+    /// offset to start writing at.
+    ///
+    /// Fails with `InvalidInput` if `offset` is larger than `i64::MAX`.
+    pub fn offset(&mut self, offset: u64) -> Result<&mut Self> {
+        self.offset = super::check_offset(offset)?;
+        self.append = false;
+        Ok(self)
+    }
+
+    /// Write to the end of the file instead of at an offset.
+    ///
+    /// This has no effect for sockets.
+    pub fn append(&mut self) -> &mut Self {
+        self.append = true;
+        self
+    }
+
+    /// The position in the file right after the data that was written,
+    /// given the result of the operation's event.
+    ///
+    /// This is the offset to continue writing at. Returns `None` if the
+    /// source isn't a file, or if the data was appended.
+    pub fn end_position(&self, result: usize) -> Option<u64> {
+        if self.append {
+            return None;
+        }
+
+        super::end_position(self.variant, self.offset, result)
+    }
+
+    /// Start writing from the buffer at `buf_offset`.
+    ///
+    /// The bytes before it are left alone, and the output still holds the
+    /// entire buffer. This is useful for resubmitting after a partial
+    /// transfer without re-slicing the buffer. `max_len` counts from this
+    /// offset.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `buf_offset` is larger than the buffer.
+    #[track_caller]
+    pub fn buf_offset(&mut self, buf_offset: usize) -> &mut Self {
+        let len = split_nonnull(self.buf.pointer()).1;
+        assert!(
+            buf_offset <= len,
+            "buffer offset ({}) must be less than or equal to length ({})",
+            buf_offset,
+            len,
+        );
+
+        self.buf_offset = buf_offset;
+        self
+    }
+
+    /// Write at most `max_len` bytes, even if the buffer is larger.
+    pub fn max_len(&mut self, max_len: usize) -> &mut Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Keep writing until the entire buffer is written.
+    ///
+    /// If the source stops accepting data first, the operation fails with
+    /// `WriteZero`. With `io_uring`, writes to sockets use `MSG_WAITALL` to
+    /// keep the kernel from coming up short, and with IOCP, writes to files
+    /// run on the blocking pool.
+    pub fn exact(&mut self) -> &mut Self {
+        self.exact = true;
+        self
+    }
+
+    /// Allow writing less than the entire buffer.
+    ///
+    /// This is the default.
+    pub fn allow_short(&mut self) -> &mut Self {
+        self.exact = false;
         self
     }
 
+    /// Use the fixed buffer at `index` for the write.
+    ///
+    /// The part of the buffer that's written must lie within the buffer
+    /// registered at `index` with `Completion::register_buffer`, or the
+    /// operation fails with `EFAULT`. This only has an effect with
+    /// `io_uring`, and not for writes to sockets with `exact`.
+    pub fn fixed_buffer(&mut self, index: u16) -> &mut Self {
+        self.fixed_buffer = Some(index);
+        self
+    }
+
+    /// The part of the buffer to write from.
+    fn target(&mut self) -> (NonNull<u8>, usize) {
+        let (ptr, len) = split_nonnull(self.buf.pointer());
+        let start = self.buf_offset.min(len);
+        // SAFETY: `start` is within the buffer
+        let ptr = unsafe { NonNull::new_unchecked(ptr.as_ptr().add(start)) };
+        let len = len - start;
+        (ptr, self.max_len.map_or(len, |max_len| len.min(max_len)))
+    }
+
+    /// Get the buffer, to fill it before resubmitting.
+    ///
+    /// # Safety
+    ///
+    /// The operation must not be in flight.
+    pub unsafe fn buf(&self) -> &B {
+        &self.buf
+    }
+
+    /// Get the buffer mutably.
+    ///
+    /// # Safety
+    ///
+    /// The operation must not be in flight.
+    pub unsafe fn buf_mut(&mut self) -> &mut B {
+        &mut self.buf
+    }
+
     /// Retrieve the inner buffer.
     ///
     /// # Safety
@@ -48,93 +163,39 @@ impl<B: Buf> Write<B> {
         self.buf
     }
 
-    #[cfg(unix)]
-    fn polling_function(&mut self) -> PollingFn {
-        let (ptr, len) = split_nonnull(self.buf.pointer());
-        let source = self.source;
-        let offset = self.offset;
-        let mut seeked = false;
-        let ptr = super::TsPtr(ptr);
-
-        // if we're a file, use seeking
-        match self.variant {
-            SourceType::File => Box::new(move || {
-                if !seeked {
-                    syscall!(lseek(source, offset, libc::SEEK_SET))?;
-                    seeked = true;
-                }
-
-                let n = syscall!(write(source, ptr.0.as_ptr().cast(), len))?;
-                Ok(n as _)
-            }),
-            SourceType::Socket => Box::new(move || {
-                let n = syscall!(write(source, ptr.0.as_ptr().cast(), len))?;
-                Ok(n as _)
-            }),
-        }
+    /// The size of the buffer.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.buf)
     }
 
-    #[cfg(unix)]
-    const READ: bool = true;
-    #[cfg(unix)]
-    const WRITE: bool = false;
-
-    #[cfg(target_os = "linux")]
-    fn uring_entry(&mut self) -> io_uring::squeue::Entry {
-        use io_uring::types::Fd;
-
-        let (ptr, len) = split_nonnull(self.buf.pointer());
-        let mut read = io_uring::opcode::Write::new(Fd(self.source), ptr.as_ptr().cast(), len as _);
-
-        if matches!(self.variant, SourceType::File) {
-            read = read.offset(self.offset);
+    /// The part of the buffer to write, if this can be merged with other
+    /// writes to the same stream.
+    fn coalescable(&mut self) -> Option<NonNull<[u8]>> {
+        let mergeable = match self.variant {
+            SourceType::Socket => true,
+            // vectored writes only support sockets on Windows
+            SourceType::Tty => cfg!(unix),
+            SourceType::File => false,
+        };
+        if self.exact || !mergeable {
+            return None;
         }
 
-        read.build()
+        let (ptr, len) = self.target();
+        Some(NonNull::slice_from_raw_parts(ptr, len))
     }
 
-    #[cfg(windows)]
-    fn win32_start(&mut self, overlapped: *mut OVERLAPPED) -> Result<Option<usize>> {
-        let (ptr, len) = split_nonnull(self.buf.pointer());
-        match self.variant {
-            SourceType::Socket => {
-                let mut buf = WSABUF {
-                    len: len as _,
-                    buf: ptr.as_ptr() as _,
-                };
-                let mut recv_bytes = 0;
-                let mut flags = 0;
-
-                check_socket_error!(unsafe {
-                    windows_sys::Win32::Networking::WinSock::WSASend(
-                        self.source as _,
-                        &buf,
-                        1,
-                        &mut recv_bytes,
-                        &mut flags,
-                        overlapped,
-                        None,
-                    )
-                })
-            }
-            SourceType::File => {
-                let mut recv_bytes = 0;
-
-                install_offset!(overlapped, self.offset);
-                check_win32_error!(unsafe {
-                    windows_sys::Win32::Storage::FileSystem::WriteFile(
-                        self.source as _,
-                        ptr.as_ptr() as _,
-                        len as _,
-                        &mut recv_bytes,
-                        overlapped,
-                    )
-                })
-            }
-        }
+    /// Describe the operation.
+    fn spec(&mut self) -> OpSpec {
+        OpSpec::new(Opcode::Write, self.source, self.variant)
+            .buf(self.target())
+            .offset(self.offset)
+            .append(self.append)
+            .exact(self.exact)
+            .fixed_buffer(self.fixed_buffer)
     }
 }
 
 impl_op! {
-    <B: Buf> Write: B
+    <B: Buf + Send> Write: B, pinned = pinned, coalesce = coalescable, spec = spec
 }
@@ -0,0 +1,223 @@
+// GNU GPL v3 License
+
+#![cfg(target_os = "linux")]
+
+use super::split_nonnull;
+use crate::{Buf, BufMut, PollingFn, Raw, Source, SourceType};
+use std::{ffi::CString, io::Result};
+
+/// The opcodes of `IORING_OP_FSETXATTR` and `IORING_OP_FGETXATTR`, which
+/// `io-uring` doesn't wrap.
+const IORING_OP_FSETXATTR: u8 = 41;
+const IORING_OP_FGETXATTR: u8 = 43;
+
+/// Where the fields of the xattr operations live in a submission entry.
+const VALUE_OFFSET: usize = 8;
+const NAME_OFFSET: usize = 16;
+const LEN_OFFSET: usize = 24;
+const XATTR_FLAGS_OFFSET: usize = 28;
+
+/// Read an extended attribute of a file.
+///
+/// The value is read into the buffer, and the result is its length. If
+/// the buffer is empty, the result is the length of the value instead,
+/// and nothing is read. A value that doesn't fit fails with `ERANGE`,
+/// and a missing attribute with `ENODATA`.
+///
+/// With `io_uring`, this needs Linux 5.19; older kernels run it on a
+/// separate thread, as do the other backends.
+pub struct GetXattr<B> {
+    source: Raw,
+    variant: SourceType,
+    name: CString,
+    buf: B,
+}
+
+/// Set an extended attribute of a file.
+///
+/// With `io_uring`, this needs Linux 5.19; older kernels run it on a
+/// separate thread, as do the other backends. The output is the value.
+pub struct SetXattr<B> {
+    source: Raw,
+    variant: SourceType,
+    name: CString,
+    value: B,
+    flags: libc::c_int,
+}
+
+/// Turn an attribute name into the form the kernel expects.
+#[track_caller]
+fn attr_name(name: &str) -> CString {
+    match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => panic!("attribute name {:?} contains a null byte", name),
+    }
+}
+
+/// Build a submission entry for an xattr operation.
+fn xattr_entry(
+    opcode: u8,
+    source: Raw,
+    name: &CString,
+    value: (*mut u8, usize),
+    flags: libc::c_int,
+) -> io_uring::squeue::Entry {
+    let mut entry = io_uring::opcode::Nop::new().build();
+
+    // SAFETY: an `Entry` is a transparent wrapper around the 64-byte
+    // `io_uring_sqe`, which is plain data
+    let sqe = unsafe { &mut *(&mut entry as *mut io_uring::squeue::Entry).cast::<[u8; 64]>() };
+    sqe[0] = opcode;
+    sqe[4..8].copy_from_slice(&source.to_ne_bytes());
+    sqe[VALUE_OFFSET..VALUE_OFFSET + 8].copy_from_slice(&(value.0 as u64).to_ne_bytes());
+    sqe[NAME_OFFSET..NAME_OFFSET + 8].copy_from_slice(&(name.as_ptr() as u64).to_ne_bytes());
+    sqe[LEN_OFFSET..LEN_OFFSET + 4].copy_from_slice(&(value.1 as u32).to_ne_bytes());
+    sqe[XATTR_FLAGS_OFFSET..XATTR_FLAGS_OFFSET + 4].copy_from_slice(&flags.to_ne_bytes());
+
+    entry
+}
+
+impl<B: BufMut + Send> GetXattr<B> {
+    /// Create a new `GetXattr` that reads the attribute `name`, such as
+    /// `user.etag`, into `buf`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` contains a null byte.
+    #[track_caller]
+    pub fn new<S: Source>(source: &S, name: &str, buf: B) -> Self {
+        GetXattr {
+            source: source.as_raw(),
+            variant: SourceType::File,
+            name: attr_name(name),
+            buf,
+        }
+    }
+
+    /// Retrieve the inner buffer.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the buffer is retrieved.
+    unsafe fn into_buf(self) -> B {
+        self.buf
+    }
+
+    /// The size of the buffer.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.buf)
+    }
+
+    fn polling_function(&mut self) -> PollingFn {
+        // the file system may block, so always use the blocking pool
+        PollingFn::new(|| Err(std::io::ErrorKind::WouldBlock.into()))
+    }
+
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        let (ptr, len) = split_nonnull(self.buf.pointer());
+        let (source, ptr) = (self.source, ptr.as_ptr() as usize);
+        let name = self.name.as_ptr() as usize;
+
+        Some(PollingFn::new(move || {
+            let n = syscall!(fgetxattr(source, name as _, ptr as _, len))?;
+            Ok(n as usize)
+        }))
+    }
+
+    const READ: bool = true;
+    const WRITE: bool = false;
+
+    fn uring_entry(&mut self) -> io_uring::squeue::Entry {
+        let (ptr, len) = split_nonnull(self.buf.pointer());
+        xattr_entry(
+            IORING_OP_FGETXATTR,
+            self.source,
+            &self.name,
+            (ptr.as_ptr(), len),
+            0,
+        )
+    }
+}
+
+impl<B: Buf + Send> SetXattr<B> {
+    /// Create a new `SetXattr` that sets the attribute `name`, such as
+    /// `user.etag`, to `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `name` contains a null byte.
+    #[track_caller]
+    pub fn new<S: Source>(source: &S, name: &str, value: B) -> Self {
+        SetXattr {
+            source: source.as_raw(),
+            variant: SourceType::File,
+            name: attr_name(name),
+            value,
+            flags: 0,
+        }
+    }
+
+    /// Fail with `EEXIST` if the attribute is already there.
+    pub fn create(&mut self) -> &mut Self {
+        self.flags = libc::XATTR_CREATE;
+        self
+    }
+
+    /// Fail with `ENODATA` if the attribute isn't there yet.
+    pub fn replace(&mut self) -> &mut Self {
+        self.flags = libc::XATTR_REPLACE;
+        self
+    }
+
+    /// Retrieve the value.
+    ///
+    /// # Safety
+    ///
+    /// The operation must be complete before the value is retrieved.
+    unsafe fn into_buf(self) -> B {
+        self.value
+    }
+
+    /// The size of the value.
+    fn pinned(&self) -> usize {
+        super::buf_len(&self.value)
+    }
+
+    fn polling_function(&mut self) -> PollingFn {
+        // the file system may block, so always use the blocking pool
+        PollingFn::new(|| Err(std::io::ErrorKind::WouldBlock.into()))
+    }
+
+    fn blocking_function(&mut self) -> Option<PollingFn> {
+        let (ptr, len) = split_nonnull(self.value.pointer());
+        let (source, ptr, flags) = (self.source, ptr.as_ptr() as usize, self.flags);
+        let name = self.name.as_ptr() as usize;
+
+        Some(PollingFn::new(move || {
+            syscall!(fsetxattr(source, name as _, ptr as _, len, flags))?;
+            Ok(0)
+        }))
+    }
+
+    const READ: bool = true;
+    const WRITE: bool = false;
+
+    fn uring_entry(&mut self) -> io_uring::squeue::Entry {
+        let (ptr, len) = split_nonnull(self.value.pointer());
+        xattr_entry(
+            IORING_OP_FSETXATTR,
+            self.source,
+            &self.name,
+            (ptr.as_ptr(), len),
+            self.flags,
+        )
+    }
+}
+
+impl_op! {
+    <B: BufMut + Send> GetXattr: B, pinned = pinned
+}
+
+impl_op! {
+    <B: Buf + Send> SetXattr: B => B, |_result, value| value, pinned = pinned
+}
@@ -0,0 +1,169 @@
+// GNU GPL v3 License
+
+use crate::{Event, Raw};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Result,
+    mem,
+};
+
+/// The order in which events are delivered.
+///
+/// This is set with `CompletionBuilder::ordering`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum OrderingMode {
+    /// Events are delivered in whatever order the backend completes the
+    /// operations.
+    ///
+    /// This is the default. Backends differ here: readiness polling
+    /// completes operations on a source in the order they were submitted,
+    /// while `io_uring` and IOCP may complete them in any order.
+    #[default]
+    Unordered,
+    /// Events for operations on the same source are delivered in the order
+    /// the operations were submitted.
+    ///
+    /// Operations that complete early are held back until every operation
+    /// submitted before them on the same source has completed. An operation
+    /// that completes during submission while earlier ones are in flight is
+    /// reported as `Submitted`, and its event is delivered through `wait`.
+    /// There's no ordering between different sources.
+    SubmissionOrderPerSource,
+}
+
+/// The operations in flight on a source, in submission order, along with
+/// their results if they completed early.
+type InFlight = VecDeque<(u64, Option<Result<usize>>)>;
+
+/// Holds back events so that they're delivered in submission order.
+#[derive(Debug, Default)]
+pub(crate) struct Sequencer {
+    /// The operations in flight on each source.
+    sources: HashMap<Raw, InFlight>,
+    /// The sources of the operations in flight, by key.
+    ///
+    /// Keys may be reused, so each one has a queue.
+    keys: HashMap<u64, VecDeque<Raw>>,
+    /// Reused for events that are being reordered.
+    scratch: Vec<Event>,
+}
+
+impl Sequencer {
+    /// Give back the memory that isn't needed for the operations in
+    /// flight.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.sources.shrink_to_fit();
+        self.keys.shrink_to_fit();
+        self.scratch.shrink_to_fit();
+    }
+
+    /// Record an operation that is about to be submitted.
+    pub(crate) fn submitted(&mut self, key: u64, source: Raw) {
+        self.sources
+            .entry(source)
+            .or_default()
+            .push_back((key, None));
+        self.keys.entry(key).or_default().push_back(source);
+    }
+
+    /// Forget an operation that failed to submit.
+    pub(crate) fn cancelled(&mut self, key: u64, source: Raw) {
+        if let Some(ops) = self.sources.get_mut(&source) {
+            if let Some(i) = ops.iter().rposition(|(k, r)| *k == key && r.is_none()) {
+                ops.remove(i);
+            }
+            if ops.is_empty() {
+                self.sources.remove(&source);
+            }
+        }
+
+        self.forget_key(key, source);
+    }
+
+    /// Record an operation that completed during submission.
+    ///
+    /// Returns the result back if no operation submitted before it on the
+    /// same source is in flight, so it can be reported right away.
+    pub(crate) fn completed_early(
+        &mut self,
+        key: u64,
+        source: Raw,
+        result: Result<usize>,
+    ) -> Option<Result<usize>> {
+        self.forget_key(key, source);
+
+        let ops = self.sources.get_mut(&source)?;
+        let i = ops.iter().rposition(|(k, r)| *k == key && r.is_none())?;
+
+        if i == 0 {
+            ops.remove(0);
+            if ops.is_empty() {
+                self.sources.remove(&source);
+            }
+            return Some(result);
+        }
+
+        ops[i].1 = Some(result);
+        None
+    }
+
+    /// Put the events in `events[start..]` into submission order, holding
+    /// back the ones that completed early.
+    pub(crate) fn reorder(&mut self, events: &mut Vec<Event>, start: usize) {
+        let mut completed = mem::take(&mut self.scratch);
+        completed.extend(events.drain(start..));
+
+        for event in completed.drain(..) {
+            // events for unknown operations are passed through
+            let source = match self.keys.get(&event.key).and_then(|s| s.front()) {
+                Some(&source) => source,
+                None => {
+                    events.push(event);
+                    continue;
+                }
+            };
+            self.forget_key(event.key, source);
+
+            let ops = match self.sources.get_mut(&source) {
+                Some(ops) => ops,
+                None => {
+                    events.push(event);
+                    continue;
+                }
+            };
+
+            match ops.iter_mut().find(|(k, r)| *k == event.key && r.is_none()) {
+                Some(slot) => slot.1 = Some(event.result),
+                None => {
+                    events.push(event);
+                    continue;
+                }
+            }
+
+            // release everything at the front that has completed
+            while let Some((_, Some(_))) = ops.front() {
+                let (key, result) = ops.pop_front().unwrap();
+                events.push(Event::new(key, result.unwrap()));
+            }
+
+            if ops.is_empty() {
+                self.sources.remove(&source);
+            }
+        }
+
+        self.scratch = completed;
+    }
+
+    /// Remove the oldest record of `key` being in flight on `source`.
+    fn forget_key(&mut self, key: u64, source: Raw) {
+        if let Some(sources) = self.keys.get_mut(&key) {
+            if let Some(i) = sources.iter().position(|s| *s == source) {
+                sources.remove(i);
+            }
+            if sources.is_empty() {
+                self.keys.remove(&key);
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// GNU GPL v3 License
+
+//! Extensions for Linux.
+
+use crate::Completion;
+use std::{
+    io::{self, Result},
+    os::unix::io::RawFd,
+};
+
+/// The features of the `io_uring` instance in use.
+///
+/// This is returned by `CompletionExt::uring_features`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct UringFeatures {
+    /// Completions are never dropped when the completion queue overflows.
+    pub nodrop: bool,
+    /// Data for a submission doesn't need to stay valid once it's submitted.
+    pub submit_stable: bool,
+    /// Sockets are polled internally instead of being handed to a thread.
+    pub fast_poll: bool,
+    /// Submissions can use a personality registered with
+    /// `register_personality`.
+    pub cur_personality: bool,
+    /// Work is offloaded to threads that belong to the process.
+    pub native_workers: bool,
+    /// A kernel thread polls the submission queue.
+    pub sqpoll: bool,
+}
+
+/// Linux-specific methods for `Completion`.
+///
+/// These only apply when `io_uring` is in use, and fail with `Unsupported`
+/// otherwise. This is a sealed trait, only implemented on `Completion`.
+pub trait CompletionExt {
+    /// Register an `eventfd` that's signalled whenever an operation
+    /// completes through `io_uring`.
+    ///
+    /// If `async_only` is set, it's only signalled for operations that
+    /// couldn't complete inline. Only one `eventfd` can be registered at
+    /// once.
+    fn register_eventfd(&self, fd: RawFd, async_only: bool) -> Result<()>;
+
+    /// Unregister the `eventfd` registered with `register_eventfd`.
+    fn unregister_eventfd(&self) -> Result<()>;
+
+    /// Register the credentials of the current thread with the ring,
+    /// returning an ID that submissions can use to run as them.
+    fn register_personality(&self) -> Result<u16>;
+
+    /// Unregister a personality returned by `register_personality`.
+    fn unregister_personality(&self, id: u16) -> Result<()>;
+
+    /// The features the ring supports.
+    fn uring_features(&self) -> Result<UringFeatures>;
+}
+
+impl CompletionExt for Completion {
+    fn register_eventfd(&self, fd: RawFd, async_only: bool) -> Result<()> {
+        let submitter = uring(self)?.submitter();
+        if async_only {
+            submitter.register_eventfd_async(fd)
+        } else {
+            submitter.register_eventfd(fd)
+        }
+    }
+
+    fn unregister_eventfd(&self) -> Result<()> {
+        uring(self)?.submitter().unregister_eventfd()
+    }
+
+    fn register_personality(&self) -> Result<u16> {
+        uring(self)?.submitter().register_personality()
+    }
+
+    fn unregister_personality(&self, id: u16) -> Result<()> {
+        uring(self)?.submitter().unregister_personality(id)
+    }
+
+    fn uring_features(&self) -> Result<UringFeatures> {
+        let params = uring(self)?.params();
+        Ok(UringFeatures {
+            nodrop: params.is_feature_nodrop(),
+            submit_stable: params.is_feature_submit_stable(),
+            fast_poll: params.is_feature_fast_poll(),
+            cur_personality: params.is_feature_cur_personality(),
+            native_workers: params.is_feature_native_workers(),
+            sqpoll: params.is_setup_sqpoll(),
+        })
+    }
+}
+
+/// Get the `io_uring` part of the completion.
+fn uring(completion: &Completion) -> Result<&crate::linux::uring::Completion> {
+    completion
+        .inner
+        .uring()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "io_uring is not being used"))
+}
@@ -0,0 +1,12 @@
+// GNU GPL v3 License
+
+//! Platform-specific extensions.
+//!
+//! These give access to tuning that only makes sense on one platform,
+//! without adding it to the portable API.
+
+#[cfg(target_os = "linux")]
+pub mod linux;
+
+#[cfg(windows)]
+pub mod windows;
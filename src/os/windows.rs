@@ -0,0 +1,27 @@
+// GNU GPL v3 License
+
+//! Extensions for Windows.
+
+use crate::{Completion, Source};
+use std::io::Result;
+
+/// Windows-specific methods for `Completion`.
+///
+/// This is a sealed trait, only implemented on `Completion`.
+pub trait CompletionExt {
+    /// Stop operations on `source` that complete right away from also
+    /// queueing a completion.
+    ///
+    /// This calls `SetFileCompletionNotificationModes` with
+    /// `FILE_SKIP_COMPLETION_PORT_ON_SUCCESS`, and can't be undone. These
+    /// operations are then reported as `AlreadyComplete`. The source should
+    /// be registered first. `CompletionBuilder::skip_completion_on_success`
+    /// does this for every source.
+    fn skip_completion_on_success(&self, source: &impl Source) -> Result<()>;
+}
+
+impl CompletionExt for Completion {
+    fn skip_completion_on_success(&self, source: &impl Source) -> Result<()> {
+        self.inner.skip_completion_on_success(source.as_raw())
+    }
+}
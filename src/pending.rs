@@ -0,0 +1,394 @@
+// GNU GPL v3 License
+
+use crate::{Event, PoisonPolicy, Raw, Source};
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Keeps track of the operations in flight.
+#[derive(Debug)]
+pub(crate) struct Pending {
+    /// The number of operations in flight.
+    count: AtomicUsize,
+    /// The number of operations in flight with each key.
+    ///
+    /// Only events for these are counted as completions, since events
+    /// also come from entries pushed through the raw ring, watchdogs and
+    /// notifications.
+    keys: Mutex<HashMap<u64, usize>>,
+    /// What we know about every operation, by key, if we keep track of
+    /// them.
+    ops: Option<Mutex<Tracked>>,
+    /// What to do when `ops` is poisoned.
+    poison: PoisonPolicy,
+    /// Flags operations that have been in flight for too long, if enabled.
+    watchdog: Option<Watchdog>,
+}
+
+/// Flags operations that have been in flight for too long.
+#[derive(Debug)]
+struct Watchdog {
+    /// How long an operation may be in flight before it's flagged.
+    threshold: Duration,
+    /// The key of the event delivered for every flagged operation, if any.
+    key: Option<u64>,
+    /// When the next operation goes over the threshold, if any.
+    next_check: Mutex<Option<Instant>>,
+}
+
+/// What we know about operations, by key.
+///
+/// Keys may be reused, so each one has a queue.
+type Tracked = HashMap<u64, VecDeque<Entry>>;
+
+/// What we know about an operation in flight.
+#[derive(Debug)]
+struct Entry {
+    source: Raw,
+    submitted: Instant,
+    kind: &'static str,
+    backend: Backend,
+    /// Whether the watchdog flagged it already.
+    flagged: bool,
+}
+
+/// The backend that performs an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Backend {
+    /// Readiness polling, along with the blocking pool for files.
+    Polling,
+    /// Linux's `io_uring`.
+    IoUring,
+    /// Windows' I/O completion ports.
+    Iocp,
+    /// FreeBSD's POSIX AIO, for files.
+    #[cfg(target_os = "freebsd")]
+    Aio,
+    /// A thread for every operation.
+    #[cfg(feature = "fallback-threads")]
+    Threads,
+}
+
+/// A description of an operation in flight, for debugging.
+///
+/// This is returned by `Completion::debug_lookup`.
+#[derive(Debug, Clone)]
+pub struct OpDebugInfo {
+    /// The key the operation was submitted with.
+    pub key: u64,
+    /// The name of the operation's type, such as `Read`.
+    pub kind: &'static str,
+    /// The source the operation was submitted on.
+    pub source: Raw,
+    /// The backend performing the operation.
+    pub backend: Backend,
+    /// How long ago the operation was submitted.
+    pub age: Duration,
+}
+
+/// The name of an operation's type, without its path or parameters.
+pub(crate) fn op_kind<O: ?Sized>(_op: &O) -> &'static str {
+    let name = std::any::type_name::<O>();
+    let name = match name.find('<') {
+        Some(i) => &name[..i],
+        None => name,
+    };
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// A snapshot of the operations in flight.
+///
+/// This is returned by `Completion::pending_snapshot`.
+#[derive(Debug, Clone)]
+pub struct PendingSnapshot {
+    ops: Vec<PendingOp>,
+}
+
+/// An operation in flight.
+#[derive(Debug, Clone)]
+pub struct PendingOp {
+    /// The key the operation was submitted with.
+    pub key: u64,
+    /// The source the operation was submitted on.
+    pub source: Raw,
+    /// How long ago the operation was submitted.
+    pub age: Duration,
+}
+
+impl Pending {
+    pub(crate) fn new(track: bool, poison: PoisonPolicy) -> Self {
+        Pending {
+            count: AtomicUsize::new(0),
+            keys: Mutex::new(HashMap::new()),
+            ops: if track {
+                Some(Mutex::new(HashMap::new()))
+            } else {
+                None
+            },
+            poison,
+            watchdog: None,
+        }
+    }
+
+    /// Flag operations that are in flight for longer than `threshold`.
+    ///
+    /// Operations have to be tracked for this to have an effect.
+    pub(crate) fn set_watchdog(&mut self, threshold: Duration, key: Option<u64>) {
+        self.watchdog = Some(Watchdog {
+            threshold,
+            key,
+            next_check: Mutex::new(None),
+        });
+    }
+
+    /// The number of operations in flight.
+    pub(crate) fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Note that an operation was submitted.
+    pub(crate) fn submitted(
+        &self,
+        key: u64,
+        source: Raw,
+        kind: &'static str,
+        backend: Backend,
+    ) -> io::Result<()> {
+        *lock!(self.keys, self.poison).entry(key).or_default() += 1;
+        self.count.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(ops) = &self.ops {
+            let submitted = Instant::now();
+            lock!(ops, self.poison)
+                .entry(key)
+                .or_default()
+                .push_back(Entry {
+                    source,
+                    submitted,
+                    kind,
+                    backend,
+                    flagged: false,
+                });
+
+            if let Some(watchdog) = &self.watchdog {
+                let due = submitted + watchdog.threshold;
+                let mut next_check = lock!(watchdog.next_check, self.poison);
+                *next_check = Some(next_check.map_or(due, |next| next.min(due)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take back `submitted` for an operation that never went in flight,
+    /// because it couldn't be submitted or completed right away.
+    pub(crate) fn cancelled(&self, key: u64) -> io::Result<()> {
+        let mut keys = lock!(self.keys, self.poison);
+        if let Some(count) = keys.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                keys.remove(&key);
+            }
+            self.count.fetch_sub(1, Ordering::Relaxed);
+        }
+        drop(keys);
+
+        if let Some(ops) = &self.ops {
+            let mut ops = lock!(ops, self.poison);
+            if let Some(queue) = ops.get_mut(&key) {
+                queue.pop_back();
+                if queue.is_empty() {
+                    ops.remove(&key);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// When the watchdog has to check the operations in flight next, if
+    /// ever.
+    pub(crate) fn next_watchdog_check(&self) -> io::Result<Option<Instant>> {
+        match &self.watchdog {
+            Some(watchdog) => Ok(*lock!(watchdog.next_check, self.poison)),
+            None => Ok(None),
+        }
+    }
+
+    /// Flag the operations that went over the watchdog's threshold.
+    ///
+    /// Each one is logged, and an event is pushed into `out` for it if the
+    /// watchdog has a key. Returns the number of events pushed.
+    pub(crate) fn watch(&self, out: &mut Vec<Event>) -> io::Result<usize> {
+        let (watchdog, ops) = match (&self.watchdog, &self.ops) {
+            (Some(watchdog), Some(ops)) => (watchdog, ops),
+            _ => return Ok(0),
+        };
+
+        let now = Instant::now();
+        let mut next_check = lock!(watchdog.next_check, self.poison);
+        match *next_check {
+            Some(next) if next <= now => {}
+            _ => return Ok(0),
+        }
+
+        let mut ops = lock!(ops, self.poison);
+        let mut count = 0;
+        *next_check = None;
+
+        for (&key, queue) in ops.iter_mut() {
+            for entry in queue.iter_mut().filter(|entry| !entry.flagged) {
+                let due = entry.submitted + watchdog.threshold;
+                if due > now {
+                    *next_check = Some(next_check.map_or(due, |next| next.min(due)));
+                    continue;
+                }
+
+                entry.flagged = true;
+                let age = now.saturating_duration_since(entry.submitted);
+                tracing::warn!(
+                    key,
+                    kind = entry.kind,
+                    source = ?entry.source,
+                    backend = ?entry.backend,
+                    ?age,
+                    "operation has been in flight for too long"
+                );
+
+                if let Some(watchdog_key) = watchdog.key {
+                    out.push(Event::new(
+                        watchdog_key,
+                        Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!(
+                                "{} operation with key {} has been in flight for {:?}",
+                                entry.kind, key, age
+                            ),
+                        )),
+                    ));
+                    count += 1;
+                }
+            }
+        }
+
+        Ok(count)
+    }
+
+    /// Note that these operations completed.
+    pub(crate) fn completed(&self, events: &[Event]) -> io::Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let mut keys = lock!(self.keys, self.poison);
+        for event in events {
+            if let Some(count) = keys.get_mut(&event.key) {
+                *count -= 1;
+                if *count == 0 {
+                    keys.remove(&event.key);
+                }
+                self.count.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+        drop(keys);
+
+        if let Some(ops) = &self.ops {
+            let mut ops = lock!(ops, self.poison);
+            for event in events {
+                if let Some(queue) = ops.get_mut(&event.key) {
+                    queue.pop_front();
+                    if queue.is_empty() {
+                        ops.remove(&event.key);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Give back the memory that isn't needed for the operations in
+    /// flight.
+    pub(crate) fn shrink_to_fit(&self) -> io::Result<()> {
+        lock!(self.keys, self.poison).shrink_to_fit();
+        if let Some(ops) = &self.ops {
+            lock!(ops, self.poison).shrink_to_fit();
+        }
+        Ok(())
+    }
+
+    /// Take a snapshot, if we keep track of operations.
+    ///
+    /// This has no way to report a poisoned lock, so it aborts if the
+    /// policy wants an error returned.
+    pub(crate) fn snapshot(&self) -> Option<PendingSnapshot> {
+        let ops = lock!(self.ops.as_ref()?, self.poison, infallible);
+        let now = Instant::now();
+
+        let mut ops: Vec<_> = ops
+            .iter()
+            .flat_map(|(&key, queue)| {
+                queue.iter().map(move |entry| PendingOp {
+                    key,
+                    source: entry.source,
+                    age: now.saturating_duration_since(entry.submitted),
+                })
+            })
+            .collect();
+        ops.sort_by_key(|op| std::cmp::Reverse(op.age));
+
+        Some(PendingSnapshot { ops })
+    }
+
+    /// Describe the oldest operation in flight with this key, if we keep
+    /// track of operations.
+    ///
+    /// This aborts like `snapshot` does.
+    pub(crate) fn lookup(&self, key: u64) -> Option<OpDebugInfo> {
+        let ops = lock!(self.ops.as_ref()?, self.poison, infallible);
+        let entry = ops.get(&key)?.front()?;
+
+        Some(OpDebugInfo {
+            key,
+            kind: entry.kind,
+            source: entry.source,
+            backend: entry.backend,
+            age: entry.submitted.elapsed(),
+        })
+    }
+}
+
+impl PendingSnapshot {
+    /// The number of operations in flight.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Whether there are no operations in flight.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// The operations in flight, oldest first.
+    pub fn ops(&self) -> &[PendingOp] {
+        &self.ops
+    }
+
+    /// The oldest operation in flight.
+    pub fn oldest(&self) -> Option<&PendingOp> {
+        self.ops.first()
+    }
+
+    /// The number of operations in flight on `source`.
+    pub fn on_source(&self, source: &impl Source) -> usize {
+        let raw = source.as_raw();
+        self.ops.iter().filter(|op| op.source == raw).count()
+    }
+}
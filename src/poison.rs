@@ -0,0 +1,51 @@
+// GNU GPL v3 License
+
+use std::{
+    io::{self, Result},
+    sync::LockResult,
+};
+
+/// What to do when an internal mutex is found poisoned.
+///
+/// A mutex is poisoned when a thread panics while holding it, which may
+/// leave the data it protects in an inconsistent state. This is set with
+/// `CompletionBuilder::on_poison`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum PoisonPolicy {
+    /// Log the poisoning and keep using the data.
+    ///
+    /// This is the default.
+    #[default]
+    Recover,
+    /// Log the poisoning and abort the process.
+    Abort,
+    /// Return an error from the operation that found the mutex poisoned.
+    ///
+    /// Where no error can be returned, such as in `Completion::len_in_flight`
+    /// and the other getters, in `Drop` implementations and on background
+    /// threads, the process is aborted instead.
+    PropagateError,
+}
+
+impl PoisonPolicy {
+    /// Handle the result of locking a mutex.
+    ///
+    /// This only returns an error for `PropagateError`.
+    pub(crate) fn handle<G>(self, result: LockResult<G>) -> Result<G> {
+        match result {
+            Ok(guard) => Ok(guard),
+            Err(e) => {
+                tracing::error!("Mutex was poisoned: {:?}", &e);
+
+                match self {
+                    PoisonPolicy::Recover => Ok(e.into_inner()),
+                    PoisonPolicy::Abort => std::process::abort(),
+                    PoisonPolicy::PropagateError => {
+                        Err(io::Error::other("an internal mutex was poisoned"))
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// GNU GPL v3 License
+
+use std::{
+    fmt,
+    io::Result,
+    mem::{self, MaybeUninit},
+    ptr,
+};
+
+/// The number of words a polling function can capture before it's boxed.
+const INLINE_WORDS: usize = 8;
+
+type Storage = MaybeUninit<[usize; INLINE_WORDS]>;
+
+/// A function that polls an operation, called until it stops returning
+/// `WouldBlock`.
+///
+/// Polling functions are created for every submitted operation, so they
+/// are stored inline instead of being boxed, as long as they're small
+/// enough.
+pub(crate) struct PollingFn {
+    /// The closure itself, or a box containing it.
+    storage: Storage,
+    /// Calls the closure in `storage`.
+    call: unsafe fn(*mut u8) -> Result<usize>,
+    /// Drops the closure in `storage`.
+    drop: unsafe fn(*mut u8),
+}
+
+// SAFETY: the closure is required to be Send and Sync
+unsafe impl Send for PollingFn {}
+unsafe impl Sync for PollingFn {}
+
+impl PollingFn {
+    /// Create a new polling function.
+    pub(crate) fn new<F>(f: F) -> Self
+    where
+        F: FnMut() -> Result<usize> + Send + Sync + 'static,
+    {
+        if fits::<F>() {
+            Self::inline(f)
+        } else {
+            Self::inline(Box::new(f))
+        }
+    }
+
+    /// Store `f` inline, which must fit.
+    fn inline<F>(f: F) -> Self
+    where
+        F: FnMut() -> Result<usize> + Send + Sync + 'static,
+    {
+        unsafe fn call<F: FnMut() -> Result<usize>>(f: *mut u8) -> Result<usize> {
+            (*f.cast::<F>())()
+        }
+
+        unsafe fn drop<F>(f: *mut u8) {
+            ptr::drop_in_place(f.cast::<F>());
+        }
+
+        assert!(fits::<F>());
+
+        let mut storage = Storage::uninit();
+        // SAFETY: the storage is big enough and aligned for `F`
+        unsafe {
+            ptr::write(storage.as_mut_ptr().cast::<F>(), f);
+        }
+
+        PollingFn {
+            storage,
+            call: call::<F>,
+            drop: drop::<F>,
+        }
+    }
+
+    /// Poll the operation.
+    pub(crate) fn call(&mut self) -> Result<usize> {
+        // SAFETY: `storage` contains the closure that `call` expects
+        unsafe { (self.call)(self.storage.as_mut_ptr().cast()) }
+    }
+}
+
+impl Drop for PollingFn {
+    fn drop(&mut self) {
+        // SAFETY: `storage` contains the closure that `drop` expects
+        unsafe { (self.drop)(self.storage.as_mut_ptr().cast()) }
+    }
+}
+
+impl fmt::Debug for PollingFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PollingFn").finish_non_exhaustive()
+    }
+}
+
+/// Can `F` be stored inline?
+const fn fits<F>() -> bool {
+    mem::size_of::<F>() <= mem::size_of::<Storage>()
+        && mem::align_of::<F>() <= mem::align_of::<Storage>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// Counts calls, and keeps `N` words alongside so the closure has a
+    /// chosen size.
+    fn counting<const N: usize>(
+        calls: &Arc<AtomicUsize>,
+    ) -> impl FnMut() -> Result<usize> + Send + Sync + 'static {
+        let (calls, padding) = (calls.clone(), [7usize; N]);
+        move || Ok(calls.fetch_add(1, Ordering::SeqCst) + padding[N - 1])
+    }
+
+    #[test]
+    fn inline() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let f = counting::<1>(&calls);
+        assert!(fits_val(&f));
+
+        let mut f = PollingFn::new(f);
+        assert_eq!(f.call().unwrap(), 7);
+        assert_eq!(f.call().unwrap(), 8);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        drop(f);
+        assert_eq!(Arc::strong_count(&calls), 1);
+    }
+
+    #[test]
+    fn boxed() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let f = counting::<{ INLINE_WORDS * 2 }>(&calls);
+        assert!(!fits_val(&f));
+
+        let mut f = PollingFn::new(f);
+        assert_eq!(f.call().unwrap(), 7);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        drop(f);
+        assert_eq!(Arc::strong_count(&calls), 1);
+    }
+
+    #[test]
+    fn dropped_without_call() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        drop(PollingFn::new(counting::<1>(&calls)));
+        drop(PollingFn::new(counting::<{ INLINE_WORDS * 2 }>(&calls)));
+
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert_eq!(Arc::strong_count(&calls), 1);
+    }
+
+    fn fits_val<F>(_: &F) -> bool {
+        fits::<F>()
+    }
+}
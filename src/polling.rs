@@ -2,36 +2,101 @@
 
 #![cfg(unix)]
 
-use crate::{ops::Op, Event, PollingFn, Raw, Source, SourceType, SubmissionStatus};
-use polling::{Event as PollEvent, Poller};
+use crate::{
+    ops::Op, pool::BlockingPool, retry::retry_interrupted, CompletionBuilder, Event, PoisonPolicy,
+    PollingFn, Priority, Raw, Source, SourceGroup, SourceType, SubmissionStatus,
+};
+use polling::{Event as PollEvent, PollMode, Poller};
 use slab::Slab;
 use std::{
+    cell::Cell,
     collections::HashMap,
     fmt,
     io::{self, Result},
     marker::PhantomData,
-    sync::Mutex,
+    mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, TryLockError,
+    },
     time::Duration,
 };
 
+/// The poller key used for the foreign source, see `watch`.
+///
+/// `usize::MAX` is reserved by `polling` itself.
+const FOREIGN_KEY: usize = usize::MAX - 1;
+
+/// The number of operations polled per source in a single `wait` in
+/// edge-triggered mode.
+const EDGE_BUDGET: usize = 32;
+
+thread_local! {
+    /// The address of the `Completion` whose operations this thread is
+    /// polling in `wait`, if any.
+    static POLLING: Cell<usize> = const { Cell::new(0) };
+}
+
 /// This `OpData` is a carrier for a function that polls for
 /// readiness on a source.
 #[doc(hidden)]
 pub struct OpData<'a> {
     pub(crate) slot: Option<PollingFn>,
+    /// A blocking version of the operation, run on the blocking pool
+    /// when the source can't be polled for readiness.
+    pub(crate) blocking: Option<PollingFn>,
     pub(crate) read: bool,
     pub(crate) write: bool,
+    /// The operation as an AIO request, if it can be one.
+    #[cfg(target_os = "freebsd")]
+    pub(crate) aio: Option<crate::freebsd::aio::Request>,
     _marker: PhantomData<&'a ()>,
 }
 
+impl OpData<'_> {
+    /// Create an empty `OpData`, for the operation to fill in.
+    pub(crate) fn new() -> Self {
+        OpData {
+            slot: None,
+            blocking: None,
+            read: false,
+            write: false,
+            #[cfg(target_os = "freebsd")]
+            aio: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Completion {
     /// The inner interface to the polling runtime.
-    poller: Poller,
+    poller: Arc<Poller>,
     /// A buffer for holding events.
     event_buffer: Mutex<Vec<PollEvent>>,
+    /// The number of events that `event_buffer` has room for.
+    capacity: AtomicUsize,
     /// The list of sources we have to mind.
     sources: Mutex<Sources>,
+    /// Do we put registered sources into non-blocking mode?
+    nonblocking: bool,
+    /// Does that include the standard I/O we inherited?
+    nonblocking_stdio: bool,
+    /// Is interest installed once in edge-triggered mode?
+    edge: bool,
+    /// Threads used to run operations on sources that can't be polled.
+    pool: BlockingPool,
+    /// Events for operations that completed on the blocking pool.
+    finished: Arc<Mutex<Vec<Event>>>,
+    /// Operations submitted by a polling function while `wait` held the
+    /// sources, to be added once it's done.
+    deferred: Mutex<Vec<(Raw, OpEntry)>>,
+    /// A source that wakes us up when readable, but isn't registered.
+    foreign: Option<Raw>,
+    /// What to do when one of our mutexes is poisoned.
+    poison: PoisonPolicy,
+    /// Do we retry waits interrupted by a signal?
+    retry_interrupted: bool,
 }
 
 #[derive(Debug)]
@@ -40,29 +105,140 @@ struct Sources {
     sources: Slab<SourceEntry>,
     /// Reverses the `sources` slab, mapping raw FDs to their keys.
     fd_to_key: HashMap<Raw, usize>,
+    /// Sources that ran out of budget in edge-triggered mode, and need
+    /// to be polled again without waiting for another edge.
+    backlog: Vec<usize>,
 }
 
 #[derive(Debug)]
 struct SourceEntry {
     /// The ongoing list of operations.
+    ///
+    /// This is only changed through the methods below, which keep the
+    /// interest counts up to date.
     operations: Vec<OpEntry>,
-    /// Is this source currently registered as readable?
+    /// The number of operations waiting for the source to be readable.
+    readers: usize,
+    /// The number of operations waiting for the source to be writable.
+    writers: usize,
+    /// The number of high priority operations.
+    urgent: usize,
+    /// Is interest in readability currently installed in the poller?
     readable: bool,
-    /// Is this source currently registered as writable?
+    /// Is interest in writability currently installed in the poller?
     writable: bool,
     /// The raw source for this entry.
     source: Raw,
+    /// The file status flags this source had before we made it
+    /// non-blocking, if we manage them.
+    original_flags: Option<libc::c_int>,
+    /// Was this source added to the poller?
+    polled: bool,
+    /// Is this source a socket?
+    socket: bool,
 }
 
 struct OpEntry {
     /// The function used to poll for readiness.
     poll: PollingFn,
+    /// The function that finishes the operation on the blocking pool, see
+    /// `hand_off`.
+    blocking: Option<PollingFn>,
     /// The key for the source.
     key: u64,
     /// Do we poll for read readiness?
     read: bool,
     /// Do we poll for write readiness?
     write: bool,
+    /// Is this a high priority operation?
+    urgent: bool,
+}
+
+impl SourceEntry {
+    /// Add an operation to the list.
+    fn push(&mut self, op: OpEntry) {
+        self.readers += op.read as usize;
+        self.writers += op.write as usize;
+        self.urgent += op.urgent as usize;
+        self.operations.push(op);
+    }
+
+    /// Remove the operation at `index` from the list.
+    fn swap_remove(&mut self, index: usize) -> OpEntry {
+        let op = self.operations.swap_remove(index);
+        self.readers -= op.read as usize;
+        self.writers -= op.write as usize;
+        self.urgent -= op.urgent as usize;
+        op
+    }
+
+    /// Remove every operation from the list.
+    fn take_operations(&mut self) -> Vec<OpEntry> {
+        self.readers = 0;
+        self.writers = 0;
+        self.urgent = 0;
+        mem::take(&mut self.operations)
+    }
+
+    /// Is interest missing from the poller that the operations need?
+    fn needs_arming(&self) -> bool {
+        (self.readers > 0 && !self.readable) || (self.writers > 0 && !self.writable)
+    }
+
+    /// Install the interest the operations need in the poller.
+    fn arm(&mut self, poller: &Poller, key: usize) -> Result<()> {
+        self.readable = self.readers > 0;
+        self.writable = self.writers > 0;
+        poller.modify(
+            self.source,
+            PollEvent {
+                key,
+                readable: self.readable,
+                writable: self.writable,
+            },
+        )
+    }
+
+    /// Take interest that no operation needs anymore out of the poller,
+    /// so that it doesn't wake us up for nothing.
+    fn trim(&mut self, poller: &Poller, key: usize) -> Result<()> {
+        let readable = self.readable && self.readers > 0;
+        let writable = self.writable && self.writers > 0;
+        if !self.polled || (readable, writable) == (self.readable, self.writable) {
+            return Ok(());
+        }
+
+        self.readable = readable;
+        self.writable = writable;
+        poller.modify(
+            self.source,
+            PollEvent {
+                key,
+                readable,
+                writable,
+            },
+        )
+    }
+
+    /// Move the source to another key in the poller, keeping its interest.
+    fn rekey(&self, poller: &Poller, edge: bool, key: usize) -> Result<()> {
+        if !self.polled {
+            return Ok(());
+        }
+
+        if edge {
+            poller.modify_with_mode(self.source, PollEvent::all(key), PollMode::Edge)
+        } else {
+            poller.modify(
+                self.source,
+                PollEvent {
+                    key,
+                    readable: self.readable,
+                    writable: self.writable,
+                },
+            )
+        }
+    }
 }
 
 impl fmt::Debug for OpEntry {
@@ -76,205 +252,872 @@ impl fmt::Debug for OpEntry {
 }
 
 impl Completion {
-    pub(crate) fn new(capacity: usize) -> Result<Self> {
+    pub(crate) fn new(builder: &CompletionBuilder) -> Result<Self> {
+        let poller = Poller::new()?;
+        let edge = builder.edge_triggered && poller.supports_edge();
+
         Ok(Self {
-            poller: Poller::new()?,
-            event_buffer: Mutex::new(Vec::with_capacity(capacity)),
+            poller: Arc::new(poller),
+            event_buffer: Mutex::new(Vec::with_capacity(builder.capacity)),
+            capacity: AtomicUsize::new(builder.capacity),
             sources: Mutex::new(Sources {
                 sources: Slab::new(),
                 fd_to_key: HashMap::new(),
+                backlog: Vec::new(),
             }),
+            nonblocking: builder.nonblocking,
+            nonblocking_stdio: builder.nonblocking_stdio,
+            edge,
+            pool: BlockingPool::new(&builder.blocking, builder.poison),
+            finished: Arc::new(Mutex::new(Vec::new())),
+            deferred: Mutex::new(Vec::new()),
+            foreign: None,
+            poison: builder.poison,
+            retry_interrupted: builder.retry_interrupted,
         })
     }
 
+    /// Wake up the wait loop whenever `fd` becomes readable.
+    ///
+    /// This is used by the hybrid backend to wait on another completion
+    /// mechanism alongside this one.
+    #[cfg(any(target_os = "linux", target_os = "freebsd"))]
+    pub(crate) fn watch(&mut self, fd: Raw) -> Result<()> {
+        self.poller.add(fd, PollEvent::readable(FOREIGN_KEY))?;
+        self.foreign = Some(fd);
+        Ok(())
+    }
+
     pub(crate) fn register<S: Source>(&self, source: &S) -> Result<()> {
-        assert!(
-            S::SOURCE_TYPE != SourceType::File,
-            "File sources are not supported on this platform"
+        let mut sources = lock!(self.sources, self.poison);
+        self.add_source(&mut sources, source.as_raw(), S::SOURCE_TYPE)
+    }
+
+    pub(crate) fn deregister(&self, source: &impl Source) -> Result<()> {
+        let mut sources = lock!(self.sources, self.poison);
+        self.remove_source(&mut sources, source.as_raw())
+    }
+
+    /// Register every source in the group, taking the lock only once.
+    ///
+    /// If one of them fails, the ones registered before it are
+    /// deregistered again.
+    pub(crate) fn register_group(&self, group: &SourceGroup) -> Result<()> {
+        let mut sources = lock!(self.sources, self.poison);
+
+        for (i, &(raw, source_type)) in group.sources.iter().enumerate() {
+            if let Err(e) = self.add_source(&mut sources, raw, source_type) {
+                for &(raw, _) in &group.sources[..i] {
+                    if let Err(e) = self.remove_source(&mut sources, raw) {
+                        tracing::error!("Failed to roll back registration: {:?}", e);
+                    }
+                }
+
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deregister every source in the group, taking the lock only once.
+    pub(crate) fn deregister_group(&self, group: &SourceGroup) -> Result<()> {
+        let mut sources = lock!(self.sources, self.poison);
+        let mut result = Ok(());
+
+        // keep going, so one bad source doesn't leak the others
+        for &(raw, _) in &group.sources {
+            if let Err(e) = self.remove_source(&mut sources, raw) {
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+
+    /// Complete every pending operation on the group's sources with an
+    /// `Interrupted` error.
+    pub(crate) fn cancel_group(&self, group: &SourceGroup) -> Result<()> {
+        let mut sources = lock!(self.sources, self.poison);
+        let sources = &mut *sources;
+        let mut cancelled = Vec::new();
+        let mut result = Ok(());
+
+        for &(raw, _) in &group.sources {
+            if let Some(&key) = sources.fd_to_key.get(&raw) {
+                let entry = &mut sources.sources[key];
+                cancelled.append(&mut entry.take_operations());
+
+                // keep going, the operations are cancelled either way
+                if let Err(e) = entry.trim(&self.poller, key) {
+                    result = Err(e);
+                }
+            }
+        }
+
+        self.interrupt(cancelled).and(result)
+    }
+
+    /// Complete the operation with `key` with an `Interrupted` error.
+    pub(crate) fn cancel(&self, key: u64) -> Result<()> {
+        self.try_cancel(key).map(drop)
+    }
+
+    /// Complete the operation with `key` with an `Interrupted` error.
+    ///
+    /// Returns `false` if it isn't waiting for readiness, which includes
+    /// operations running on the blocking pool.
+    pub(crate) fn try_cancel(&self, key: u64) -> Result<bool> {
+        let mut sources = lock!(self.sources, self.poison);
+
+        for (poll_key, entry) in sources.sources.iter_mut() {
+            if let Some(index) = entry.operations.iter().position(|op| op.key == key) {
+                let op = entry.swap_remove(index);
+                let trimmed = entry.trim(&self.poller, poll_key);
+                drop(sources);
+                self.interrupt(vec![op])?;
+                return trimmed.map(|()| true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Complete the operations with an `Interrupted` error.
+    fn interrupt(&self, ops: Vec<OpEntry>) -> Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+
+        lock!(self.finished, self.poison).extend(
+            ops.into_iter()
+                .map(|op| Event::new(op.key, Err(io::ErrorKind::Interrupted.into()))),
         );
-        let raw = source.as_raw();
+        self.poller.notify()
+    }
+
+    /// Finish an operation on the blocking pool.
+    fn spawn_blocking(&self, op: OpEntry) -> Result<()> {
+        let blocking = op.blocking.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No blocking function provided")
+        })?;
+        self.spawn_function(op.key, blocking)
+    }
+
+    /// Finish the operation with `key` by calling `blocking` on the
+    /// blocking pool.
+    fn spawn_function(&self, key: u64, mut blocking: PollingFn) -> Result<()> {
+        let finished = self.finished.clone();
+        let poller = self.poller.clone();
+        let poison = self.poison;
+
+        self.pool.spawn(move || {
+            let result = blocking.call();
+            lock!(finished, poison, infallible).push(Event::new(key, result));
+
+            // wake up the waiter so it can collect the event
+            if let Err(e) = poller.notify() {
+                tracing::error!("Failed to notify poller: {:?}", e);
+            }
+        })
+    }
+
+    /// Add a source to the list and to the poller.
+    fn add_source(&self, sources: &mut Sources, raw: Raw, source_type: SourceType) -> Result<()> {
+        if sources.fd_to_key.contains_key(&raw) {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+        }
+
+        // standard input and the like may be redirected from a file
+        let source_type = match source_type {
+            SourceType::Tty if is_regular_file(raw)? => SourceType::File,
+            source_type => source_type,
+        };
+
+        // a blocking read or write would stall the entire wait loop, but
+        // the flags of inherited standard I/O are shared with other
+        // processes, so it's left alone unless we're told otherwise
+        let shared = self.nonblocking && !self.nonblocking_stdio && is_inherited_stdio(raw);
+        let original_flags = if self.nonblocking && !shared {
+            Some(set_nonblocking(raw)?)
+        } else {
+            None
+        };
+        let polled = source_type != SourceType::File && (!shared || is_nonblocking(raw)?);
 
         // get the key for the source as we create an entry
-        let mut sources = lock!(self.sources);
-        let key = sources.sources.insert(SourceEntry {
+        let entry = sources.sources.vacant_entry();
+        let key = entry.key();
+
+        // files and blocking sources are never polled for readiness
+        if polled {
+            let result = if self.edge {
+                self.poller
+                    .add_with_mode(raw, PollEvent::all(key), PollMode::Edge)
+            } else {
+                // interest is installed once operations block
+                self.poller.add(raw, PollEvent::none(key))
+            };
+
+            if let Err(e) = result {
+                if let Some(flags) = original_flags {
+                    syscall!(fcntl(raw, libc::F_SETFL, flags))?;
+                }
+                return Err(e);
+            }
+        }
+
+        entry.insert(SourceEntry {
             operations: Vec::new(),
+            readers: 0,
+            writers: 0,
+            urgent: 0,
             readable: false,
             writable: false,
             source: raw,
+            original_flags,
+            polled,
+            socket: source_type == SourceType::Socket,
         });
 
         // also allow reversing the source
-        if sources.fd_to_key.insert(raw, key).is_some() {
-            Err(io::Error::from(io::ErrorKind::AlreadyExists))
-        } else {
-            Ok(())
-        }
+        sources.fd_to_key.insert(raw, key);
+        Ok(())
     }
 
-    pub(crate) fn deregister(&self, source: &impl Source) -> Result<()> {
-        let mut sources = lock!(self.sources);
-        let key = match sources.fd_to_key.remove(&source.as_raw()) {
+    /// Remove a source from the list and from the poller.
+    fn remove_source(&self, sources: &mut Sources, raw: Raw) -> Result<()> {
+        let key = match sources.fd_to_key.remove(&raw) {
             Some(key) => key,
             None => return Ok(()),
         };
 
-        sources.sources.remove(key);
-        Ok(())
+        let mut entry = sources.sources.remove(key);
+        sources.backlog.retain(|&k| k != key);
+
+        // the source is forgotten either way, so everything below happens
+        // even if a step fails, and the first failure is reported
+        let deleted = if entry.polled {
+            self.poller.delete(entry.source)
+        } else {
+            Ok(())
+        };
+
+        // restore the source to how we found it
+        let restored = match entry.original_flags {
+            Some(flags) => syscall!(fcntl(entry.source, libc::F_SETFL, flags)).map(drop),
+            None => Ok(()),
+        };
+
+        // the operations can never complete now, so hand their keys back
+        let interrupted = self.interrupt(entry.take_operations());
+        deleted.and(restored).and(interrupted)
     }
 
-    pub(crate) fn submit(&self, op: &mut impl Op, key: u64) -> Result<SubmissionStatus> {
+    pub(crate) fn submit(
+        &self,
+        op: &mut impl Op,
+        key: u64,
+        priority: Priority,
+    ) -> Result<SubmissionStatus> {
         // populate an OpData structure
         #[allow(unused_mut)]
-        let mut op_data = OpData {
-            slot: None,
-            read: false,
-            write: false,
-            _marker: PhantomData,
-        };
+        let mut op_data = OpData::new();
 
         #[cfg(target_os = "linux")]
         let mut op_data = crate::OpData::Polling(op_data);
 
         op.run(&mut op_data)?;
 
+        #[cfg(target_os = "linux")]
+        let op_data = match op_data {
+            crate::OpData::Polling(op_data) => op_data,
+            _ => return Err(no_polling_function()),
+        };
+
+        self.submit_data(op_data, op.source(), op.variant(), key, priority)
+    }
+
+    /// Submit an operation that was already run into its `OpData`.
+    pub(crate) fn submit_data(
+        &self,
+        op_data: OpData<'_>,
+        raw: Raw,
+        variant: SourceType,
+        key: u64,
+        priority: Priority,
+    ) -> Result<SubmissionStatus> {
         let mut new_op = match op_data {
-            #[cfg(target_os = "linux")]
-            crate::OpData::Polling(OpData {
-                slot: Some(poll),
-                read,
-                write,
-                ..
-            }) => OpEntry {
-                poll,
-                key,
-                read,
-                write,
-            },
-            #[cfg(not(target_os = "linux"))]
             OpData {
                 slot: Some(poll),
+                blocking,
                 read,
                 write,
                 ..
             } => OpEntry {
                 poll,
+                blocking,
                 key,
                 read,
                 write,
+                urgent: priority.is_high(),
             },
-            _ => {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "No polling function provided",
-                ))
-            }
+            _ => return Err(no_polling_function()),
         };
 
-        // poll the operation once to see if we even need to register
-        // the source for polling
-        match (new_op.poll)() {
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
-            result => {
-                // we're already complete
-                return Ok(SubmissionStatus::AlreadyComplete(result));
+        // operations that don't wait for readiness still complete
+        // through the queue
+        if !new_op.read && !new_op.write {
+            let result = new_op.poll.call();
+            lock!(self.finished, self.poison).push(Event::new(key, result));
+            self.poller.notify()?;
+            return Ok(SubmissionStatus::Submitted);
+        }
+
+        // files can't be polled for readiness, so run the operation
+        // on the blocking pool instead
+        if variant == SourceType::File {
+            // unless it can complete right away
+            match new_op.poll.call() {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return Ok(SubmissionStatus::AlreadyComplete(result)),
+            }
+
+            self.spawn_blocking(new_op)?;
+            return Ok(SubmissionStatus::Submitted);
+        }
+
+        // a polling function is submitting this while `wait` holds the
+        // sources, so leave it for `wait` to add
+        if POLLING.with(Cell::get) == self as *const Self as usize {
+            match new_op.poll.call() {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) if is_hand_off(&e) => {
+                    self.spawn_blocking(new_op)?;
+                    return Ok(SubmissionStatus::Submitted);
+                }
+                result => return Ok(SubmissionStatus::AlreadyComplete(result)),
             }
+
+            lock!(self.deferred, self.poison).push((raw, new_op));
+            return Ok(SubmissionStatus::Submitted);
         }
 
-        let mut sources = lock!(self.sources);
+        let mut sources = lock!(self.sources, self.poison);
 
         // get the source entry for the raw FD
-        let raw = op.source();
         let poll_key = *sources
             .fd_to_key
             .get(&raw)
             .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
         let entry = sources.sources.get_mut(poll_key).unwrap();
 
-        // add the operation to the source entry
-        let mut register = false;
-        if !entry.readable && new_op.read {
-            register = true;
-            entry.readable = true;
+        // the source is left blocking, so its polling function runs on the
+        // blocking pool, where it may block
+        if !entry.polled {
+            drop(sources);
+            self.spawn_function(key, new_op.poll)?;
+            return Ok(SubmissionStatus::Submitted);
         }
-        if !entry.writable && new_op.write {
-            register = true;
-            entry.writable = true;
+
+        // poll the operation once to see if we even need to register
+        // the source for polling
+        //
+        // this happens under the lock so that, in edge-triggered mode, an
+        // edge arriving after this can't be processed before the operation
+        // is in the list
+        match new_op.poll.call() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) if is_hand_off(&e) => {
+                drop(sources);
+                self.spawn_blocking(new_op)?;
+                return Ok(SubmissionStatus::Submitted);
+            }
+            result => {
+                // we're already complete
+                return Ok(SubmissionStatus::AlreadyComplete(result));
+            }
         }
 
-        if register {
-            // we need to re-register this source into the poller
-            self.poller.add(
-                raw,
-                PollEvent {
-                    key: poll_key,
-                    readable: entry.readable,
-                    writable: entry.writable,
-                },
-            )?;
+        // interest stays installed in edge-triggered mode
+        if self.edge {
+            entry.push(new_op);
+            return Ok(SubmissionStatus::Submitted);
         }
 
-        entry.operations.push(new_op);
+        // add the operation to the source entry, and re-arm the source if
+        // it needs interest that isn't installed yet
+        entry.push(new_op);
+        if entry.needs_arming() {
+            if let Err(e) = entry.arm(&self.poller, poll_key) {
+                entry.swap_remove(entry.operations.len() - 1);
+                return Err(e);
+            }
+        }
 
         Ok(SubmissionStatus::Submitted)
     }
 
     pub(crate) fn wait(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<usize> {
         // begin waiting for events
-        let mut poll_events = lock!(self.event_buffer);
-        self.poller.wait(&mut poll_events, timeout)?;
+        let mut poll_events = lock!(self.event_buffer, self.poison);
+
+        // grow the buffer now if `reserve` was called while we were waiting
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if poll_events.capacity() < capacity {
+            let additional = capacity - poll_events.len();
+            poll_events.reserve(additional);
+        }
+
+        // don't block if some sources still have ready operations
+        let backlog = mem::take(&mut lock!(self.sources, self.poison).backlog);
+        let timeout = if backlog.is_empty() {
+            timeout
+        } else {
+            Some(Duration::ZERO)
+        };
+        retry_interrupted(timeout, self.retry_interrupted, |timeout| {
+            self.poller.wait(&mut poll_events, timeout)
+        })?;
+        poll_events.extend(backlog.into_iter().map(PollEvent::none));
+
+        // sources with high priority operations go first
+        let mut sources = lock!(self.sources, self.poison);
+        let sources = &mut *sources;
+        let _polling = PollingGuard::new(self);
+        poll_events.sort_by_key(|event| match sources.sources.get(event.key) {
+            Some(entry) => entry.urgent == 0,
+            None => true,
+        });
+
+        // collect operations that finished on the blocking pool
+        let mut num_events = {
+            let mut finished = lock!(self.finished, self.poison);
+            let len = finished.len();
+            out.append(&mut finished);
+            len
+        };
 
         // process the events
-        let mut sources = lock!(self.sources);
-        let mut num_events = 0;
         for event in poll_events.drain(..) {
+            if event.key == FOREIGN_KEY {
+                // just a wakeup, re-arm it for next time
+                if let Some(fd) = self.foreign {
+                    self.poller.modify(fd, PollEvent::readable(FOREIGN_KEY))?;
+                }
+                continue;
+            }
+
             // match the event to a source entry
             let poll_key = event.key;
-            let entry = sources.sources.get_mut(poll_key).unwrap();
+            let entry = match sources.sources.get_mut(poll_key) {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            // the poller only reports readiness that wasn't asked for when
+            // the source hung up or failed, which epoll reports both ways.
+            // In edge-triggered mode, interest is always installed both
+            // ways, so go by what the operations are waiting for instead
+            let (readable, writable) = if self.edge {
+                (entry.readers > 0, entry.writers > 0)
+            } else {
+                (entry.readable, entry.writable)
+            };
+            let unasked = (event.readable && !readable) || (event.writable && !writable);
 
-            // clear the flags
+            // the poller disarms the source when it reports an event
             entry.readable = false;
             entry.writable = false;
-            let mut register = false;
-
-            // poll the operations to see which ones are ready
-            for i in (0..entry.operations.len()).rev() {
-                let op = &mut entry.operations[i];
-                match (op.poll)() {
-                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                        // blocked, re-run the operation
-                        entry.readable |= op.read;
-                        entry.writable |= op.write;
-                        register = true;
+            let mut blocked = false;
+
+            // in edge-triggered mode, only poll as many operations as the
+            // budget allows, and come back for the rest later
+            let len = entry.operations.len();
+            let start = if self.edge {
+                let start = len.saturating_sub(EDGE_BUDGET);
+                if start > 0 {
+                    sources.backlog.push(poll_key);
+                }
+                start
+            } else {
+                0
+            };
+
+            // poll the operations to see which ones are ready, high
+            // priority ones first
+            let passes: &[bool] = if entry.urgent > 0 {
+                &[true, false]
+            } else {
+                &[false]
+            };
+            for &urgent in passes {
+                let len = entry.operations.len().min(len);
+                for i in (start..len).rev() {
+                    let op = &mut entry.operations[i];
+                    if op.urgent != urgent {
+                        continue;
+                    }
+
+                    match op.poll.call() {
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                            // blocked, it keeps its interest
+                            blocked = true;
+                        }
+                        Err(e) if is_hand_off(&e) => {
+                            let op = entry.swap_remove(i);
+                            let key = op.key;
+                            if let Err(e) = self.spawn_blocking(op) {
+                                out.push(Event::new(key, Err(e)));
+                                num_events += 1;
+                            }
+                        }
+                        result => {
+                            // resolved to a final result, return it
+                            let op = entry.swap_remove(i);
+                            out.push(Event::new(op.key, result));
+                            num_events += 1;
+                        }
                     }
-                    result => {
-                        // resolved to a final result, return it
-                        let op = entry.operations.swap_remove(i);
-                        out.push(Event {
-                            key: op.key,
-                            result,
-                        });
+                }
+            }
+
+            // if the socket hung up or failed, the remaining operations
+            // would never become ready, so fail them instead
+            if blocked && unasked && entry.socket {
+                if let Some(hangup) = check_hangup(entry.source) {
+                    for op in entry.take_operations() {
+                        out.push(Event::new(op.key, Err(hangup.to_error(op.write))));
                         num_events += 1;
                     }
                 }
             }
 
-            // register again if we need to
-            if register {
-                self.poller.add(
-                    entry.source,
-                    PollEvent {
-                        key: poll_key,
-                        readable: entry.readable,
-                        writable: entry.writable,
-                    },
-                )?;
+            // re-arm for the interest the remaining operations need
+            if !self.edge && entry.needs_arming() {
+                entry.arm(&self.poller, poll_key)?;
             }
         }
 
+        num_events += self.adopt_deferred(sources, out)?;
         Ok(num_events)
     }
 
+    /// Add the operations that polling functions submitted during `wait`.
+    ///
+    /// Returns the number of events pushed for the ones that couldn't be
+    /// added.
+    fn adopt_deferred(&self, sources: &mut Sources, out: &mut Vec<Event>) -> Result<usize> {
+        let deferred = mem::take(&mut *lock!(self.deferred, self.poison));
+        let mut failed = 0;
+
+        for (raw, op) in deferred {
+            let key = op.key;
+            let poll_key = match sources.fd_to_key.get(&raw) {
+                Some(&poll_key) => poll_key,
+                None => {
+                    out.push(Event::new(key, Err(io::ErrorKind::NotFound.into())));
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            let entry = sources.sources.get_mut(poll_key).unwrap();
+            entry.push(op);
+            if !self.edge && entry.needs_arming() {
+                if let Err(e) = entry.arm(&self.poller, poll_key) {
+                    entry.swap_remove(entry.operations.len() - 1);
+                    out.push(Event::new(key, Err(e)));
+                    failed += 1;
+                }
+            }
+        }
+
+        Ok(failed)
+    }
+
+    /// The backend that performs operations on this kind of source.
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn backend_for(&self, _variant: SourceType) -> crate::Backend {
+        crate::Backend::Polling
+    }
+
     pub(crate) fn notify(&self) -> Result<()> {
         self.poller.notify()
     }
+
+    /// The number of events that a single `wait` has room for.
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity.load(Ordering::Relaxed)
+    }
+
+    /// Make room for `additional` more sources and events per `wait`.
+    pub(crate) fn reserve(&self, additional: usize) -> Result<()> {
+        let out_of_memory = |e| io::Error::new(io::ErrorKind::OutOfMemory, e);
+
+        // like `Vec::reserve`, this is on top of the sources there are now
+        let needed = {
+            let mut sources = lock!(self.sources, self.poison);
+            sources
+                .fd_to_key
+                .try_reserve(additional)
+                .map_err(out_of_memory)?;
+            sources.sources.reserve(additional);
+            sources.fd_to_key.len().saturating_add(additional)
+        };
+
+        // `wait` holds the buffer while it blocks, so it grows it itself
+        let capacity = self
+            .capacity
+            .fetch_max(needed, Ordering::Relaxed)
+            .max(needed);
+        let poll_events = match self.event_buffer.try_lock() {
+            Ok(poll_events) => Some(poll_events),
+            Err(TryLockError::Poisoned(e)) => Some(self.poison.handle(Err(e))?),
+            Err(TryLockError::WouldBlock) => None,
+        };
+        if let Some(mut poll_events) = poll_events {
+            let additional = capacity.saturating_sub(poll_events.len());
+            poll_events.try_reserve(additional).map_err(out_of_memory)?;
+        }
+
+        Ok(())
+    }
+
+    /// Give back the memory that isn't needed for the sources and
+    /// operations there are now.
+    pub(crate) fn shrink_to_fit(&self) -> Result<()> {
+        // the keys of the sources can only change while nothing waits on
+        // the poller, since its events would point to the wrong ones
+        let poll_events = match self.event_buffer.try_lock() {
+            Ok(poll_events) => Some(poll_events),
+            Err(TryLockError::Poisoned(e)) => Some(self.poison.handle(Err(e))?),
+            Err(TryLockError::WouldBlock) => None,
+        };
+
+        let mut sources = lock!(self.sources, self.poison);
+        let Sources {
+            sources,
+            fd_to_key,
+            backlog,
+        } = &mut *sources;
+
+        let mut result = Ok(());
+        if poll_events.is_some() {
+            // fill the gaps left by sources that were removed
+            sources.compact(|entry, from, to| {
+                if let Err(e) = entry.rekey(&self.poller, self.edge, to) {
+                    result = Err(e);
+                    return false;
+                }
+
+                fd_to_key.insert(entry.source, to);
+                for key in backlog.iter_mut().filter(|key| **key == from) {
+                    *key = to;
+                }
+                true
+            });
+        }
+        sources.shrink_to_fit();
+        for (_, entry) in sources.iter_mut() {
+            entry.operations.shrink_to_fit();
+        }
+        fd_to_key.shrink_to_fit();
+        backlog.shrink_to_fit();
+
+        if let Some(mut poll_events) = poll_events {
+            poll_events.shrink_to(self.capacity.load(Ordering::Relaxed));
+        }
+        lock!(self.deferred, self.poison).shrink_to_fit();
+        lock!(self.finished, self.poison).shrink_to_fit();
+
+        result
+    }
+
+    /// The handles that become readable when `wait` has something to do.
+    pub(crate) fn notifiers(&self) -> Result<Vec<Raw>> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(
+                target_os = "linux",
+                target_os = "android",
+                target_os = "illumos",
+                target_os = "solaris",
+                target_os = "macos",
+                target_os = "ios",
+                target_os = "tvos",
+                target_os = "watchos",
+                target_os = "freebsd",
+                target_os = "netbsd",
+                target_os = "openbsd",
+                target_os = "dragonfly",
+            ))] {
+                use std::os::unix::io::AsRawFd;
+                Ok(vec![self.poller.as_raw_fd()])
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the poller can't be waited on from outside",
+                ))
+            }
+        }
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        // put the sources that are still registered back how we found them
+        let sources = match self.sources.get_mut() {
+            Ok(sources) => sources,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for (_, entry) in sources.sources.iter() {
+            if let Some(flags) = entry.original_flags {
+                if let Err(e) = syscall!(fcntl(entry.source, libc::F_SETFL, flags)) {
+                    tracing::error!("Failed to restore file status flags: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Marks the current thread as polling the operations of a `Completion`,
+/// until it's dropped.
+struct PollingGuard {
+    previous: usize,
+}
+
+impl PollingGuard {
+    fn new(completion: &Completion) -> Self {
+        let previous = POLLING.with(|polling| polling.replace(completion as *const _ as usize));
+        PollingGuard { previous }
+    }
+}
+
+impl Drop for PollingGuard {
+    fn drop(&mut self) {
+        POLLING.with(|polling| polling.set(self.previous));
+    }
+}
+
+/// A reason why a source can no longer make progress.
+#[derive(Debug, Clone, Copy)]
+enum Hangup {
+    /// The source has a pending error.
+    Error(i32),
+    /// The peer closed the connection.
+    Closed,
+}
+
+impl Hangup {
+    /// Convert into an error for an operation.
+    fn to_error(self, write: bool) -> io::Error {
+        match self {
+            Hangup::Error(code) => io::Error::from_raw_os_error(code),
+            Hangup::Closed if write => io::ErrorKind::BrokenPipe.into(),
+            Hangup::Closed => io::ErrorKind::ConnectionReset.into(),
+        }
+    }
+}
+
+/// Check whether the socket has hung up or has an error.
+fn check_hangup(fd: Raw) -> Option<Hangup> {
+    // POLLHUP and POLLERR are always reported
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: 0,
+        revents: 0,
+    };
+
+    match syscall!(poll(&mut pollfd, 1, 0)) {
+        Ok(0) | Err(_) => return None,
+        Ok(_) => {}
+    }
+
+    if pollfd.revents & libc::POLLERR != 0 {
+        // get the actual error
+        let mut error: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let res = syscall!(getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ERROR,
+            &mut error as *mut _ as *mut _,
+            &mut len
+        ));
+
+        match res {
+            Ok(_) if error != 0 => Some(Hangup::Error(error)),
+            _ => Some(Hangup::Error(libc::ECONNRESET)),
+        }
+    } else if pollfd.revents & libc::POLLHUP != 0 {
+        Some(Hangup::Closed)
+    } else {
+        None
+    }
+}
+
+/// Returned by a polling function to finish the operation with its blocking
+/// function, on the blocking pool.
+///
+/// This is for operations that have to wait on something other than their
+/// source once they're underway.
+#[derive(Debug)]
+struct HandOff;
+
+impl fmt::Display for HandOff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("operation continues on the blocking pool")
+    }
+}
+
+impl std::error::Error for HandOff {}
+
+/// The error for operations that didn't provide a polling function.
+fn no_polling_function() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "No polling function provided")
+}
+
+/// Create the error that hands an operation off to the blocking pool.
+pub(crate) fn hand_off() -> io::Error {
+    io::Error::other(HandOff)
+}
+
+/// Is this error a request to hand the operation off?
+pub(crate) fn is_hand_off(err: &io::Error) -> bool {
+    matches!(err.get_ref(), Some(err) if err.is::<HandOff>())
+}
+
+/// Is this the standard input, output or error we inherited?
+///
+/// Their file status flags are shared with whoever else has them open,
+/// such as the shell.
+pub(crate) fn is_inherited_stdio(fd: Raw) -> bool {
+    matches!(
+        fd,
+        libc::STDIN_FILENO | libc::STDOUT_FILENO | libc::STDERR_FILENO
+    )
+}
+
+/// Is the file descriptor in non-blocking mode?
+fn is_nonblocking(fd: Raw) -> Result<bool> {
+    let flags = syscall!(fcntl(fd, libc::F_GETFL))?;
+    Ok(flags & libc::O_NONBLOCK != 0)
+}
+
+/// Is the file descriptor a regular file?
+fn is_regular_file(fd: Raw) -> Result<bool> {
+    let mut stat = mem::MaybeUninit::<libc::stat>::uninit();
+    syscall!(fstat(fd, stat.as_mut_ptr()))?;
+    let mode = unsafe { stat.assume_init() }.st_mode;
+    Ok(mode & libc::S_IFMT == libc::S_IFREG)
+}
+
+/// Put the file descriptor into non-blocking mode, returning its
+/// previous file status flags.
+pub(crate) fn set_nonblocking(fd: Raw) -> Result<libc::c_int> {
+    let flags = syscall!(fcntl(fd, libc::F_GETFL))?;
+    if flags & libc::O_NONBLOCK == 0 {
+        syscall!(fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+    }
+    Ok(flags)
 }
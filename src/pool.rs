@@ -0,0 +1,212 @@
+// GNU GPL v3 License
+
+use crate::PoisonPolicy;
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, Result},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// The default maximum number of threads that the pool will spawn.
+const MAX_THREADS: usize = 16;
+
+/// How long a thread will wait for new work before exiting, by default.
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+/// A job to run on the blocking pool.
+pub type BlockingJob = Box<dyn FnOnce() + Send + 'static>;
+
+/// Runs the jobs of the blocking pool somewhere else.
+///
+/// Operations that the OS can't run asynchronously, such as file I/O on
+/// the polling backend, are finished on a pool of threads. By default,
+/// every `Completion` has its own; implement this to use a pool you
+/// already have, such as `rayon`'s or the `blocking` crate's, and pass it
+/// to `CompletionBuilder::blocking_executor`.
+pub trait BlockingExecutor: Send + Sync {
+    /// Run `job` on another thread.
+    ///
+    /// The job may block for a while, and it must be run eventually for
+    /// the operation to complete. If it can't be run, return an error; the
+    /// operation fails with it.
+    fn execute(&self, job: BlockingJob) -> Result<()>;
+}
+
+/// How the blocking pool is set up.
+#[derive(Clone)]
+pub(crate) struct PoolConfig {
+    /// The most threads that are alive at once.
+    pub(crate) max_threads: usize,
+    /// The most jobs that wait for a thread, if limited.
+    pub(crate) max_queued: Option<usize>,
+    /// How long an idle thread waits for a job before exiting.
+    pub(crate) keep_alive: Duration,
+    /// Where jobs are run instead, if anywhere.
+    pub(crate) executor: Option<Arc<dyn BlockingExecutor>>,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            max_threads: MAX_THREADS,
+            max_queued: None,
+            keep_alive: KEEP_ALIVE,
+            executor: None,
+        }
+    }
+}
+
+impl fmt::Debug for PoolConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolConfig")
+            .field("max_threads", &self.max_threads)
+            .field("max_queued", &self.max_queued)
+            .field("keep_alive", &self.keep_alive)
+            .field("external", &self.executor.is_some())
+            .finish()
+    }
+}
+
+/// A pool of threads for running blocking operations.
+pub(crate) struct BlockingPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    /// The queue of jobs and the state of the threads.
+    state: Mutex<State>,
+    /// Used to wake up idle threads when a job is queued.
+    condvar: Condvar,
+    /// The most threads that are alive at once.
+    max_threads: usize,
+    /// The most jobs that wait for a thread, if limited.
+    max_queued: Option<usize>,
+    /// How long an idle thread waits for a job before exiting.
+    keep_alive: Duration,
+    /// Where jobs are run instead, if anywhere.
+    executor: Option<Arc<dyn BlockingExecutor>>,
+    /// What to do when `state` is poisoned.
+    poison: PoisonPolicy,
+}
+
+struct State {
+    /// Jobs that are waiting to be run.
+    queue: VecDeque<BlockingJob>,
+    /// The number of threads currently alive.
+    threads: usize,
+    /// The number of threads currently waiting for a job.
+    idle: usize,
+}
+
+impl fmt::Debug for BlockingPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.inner.executor.is_some() {
+            return f.write_str("BlockingPool { external }");
+        }
+
+        let state = lock!(self.inner.state, self.inner.poison, infallible);
+        f.debug_struct("BlockingPool")
+            .field("queued", &state.queue.len())
+            .field("threads", &state.threads)
+            .field("idle", &state.idle)
+            .finish()
+    }
+}
+
+impl BlockingPool {
+    /// Create a new, empty pool.
+    ///
+    /// Threads are spawned on demand.
+    pub(crate) fn new(config: &PoolConfig, poison: PoisonPolicy) -> Self {
+        BlockingPool {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    queue: VecDeque::new(),
+                    threads: 0,
+                    idle: 0,
+                }),
+                condvar: Condvar::new(),
+                max_threads: config.max_threads,
+                max_queued: config.max_queued,
+                keep_alive: config.keep_alive,
+                executor: config.executor.clone(),
+                poison,
+            }),
+        }
+    }
+
+    /// Run a job on the pool.
+    pub(crate) fn spawn(&self, job: impl FnOnce() + Send + 'static) -> Result<()> {
+        if let Some(executor) = &self.inner.executor {
+            return executor.execute(Box::new(job));
+        }
+
+        let mut state = lock!(self.inner.state, self.inner.poison);
+
+        // jobs beyond the idle threads and the ones we can spawn wait
+        let waiting = state.queue.len() + 1;
+        let room = state.idle + (self.inner.max_threads - state.threads);
+        if let Some(max_queued) = self.inner.max_queued {
+            if waiting > room + max_queued {
+                return Err(io::Error::other("blocking pool queue is full"));
+            }
+        }
+
+        state.queue.push_back(Box::new(job));
+
+        if state.idle >= state.queue.len() {
+            // an idle thread can pick this up; idle threads that were
+            // already woken up for the jobs ahead of it don't count
+            self.inner.condvar.notify_one();
+        } else if state.threads < self.inner.max_threads {
+            // spin up a new thread to run the job
+            let inner = self.inner.clone();
+            let spawned = thread::Builder::new()
+                .name("polldough-blocking".into())
+                .spawn(move || inner.run());
+            if let Err(e) = spawned {
+                // the operation fails, so nothing may run the job later
+                state.queue.pop_back();
+                return Err(e);
+            }
+            state.threads += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl Inner {
+    /// The main loop for a thread in the pool.
+    fn run(&self) {
+        let mut state = lock!(self.state, self.poison, infallible);
+
+        loop {
+            // run all of the jobs we can
+            while let Some(job) = state.queue.pop_front() {
+                drop(state);
+                job();
+                state = lock!(self.state, self.poison, infallible);
+            }
+
+            // wait for more jobs to come in
+            state.idle += 1;
+            let waited = self.condvar.wait_timeout(state, self.keep_alive);
+            let (new_state, timeout) = match self.poison.handle(waited) {
+                Ok(res) => res,
+                // there's no one to return the error to
+                Err(_) => std::process::abort(),
+            };
+            state = new_state;
+            state.idle -= 1;
+
+            if timeout.timed_out() && state.queue.is_empty() {
+                state.threads -= 1;
+                return;
+            }
+        }
+    }
+}
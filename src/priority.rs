@@ -0,0 +1,73 @@
+// GNU GPL v3 License
+
+use crate::Event;
+use std::collections::HashMap;
+
+/// The lane an operation is submitted in.
+///
+/// This is passed to `Completion::submit_with_priority`. High priority
+/// operations reach the OS before normal ones that are waiting to be
+/// submitted, and their events are delivered first when several arrive in
+/// the same `wait`. This lets control traffic overtake bulk transfers on a
+/// single `Completion`, but it doesn't make the OS perform the operations
+/// any sooner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// Submitted and delivered ahead of normal operations.
+    High,
+    /// The lane that `Completion::submit` uses.
+    #[default]
+    Normal,
+}
+
+impl Priority {
+    /// Is this the high priority lane?
+    pub(crate) fn is_high(self) -> bool {
+        self == Priority::High
+    }
+}
+
+/// The keys of high priority operations in flight.
+#[derive(Debug, Default)]
+pub(crate) struct Urgent {
+    /// The number of operations in flight with each key.
+    keys: HashMap<u64, usize>,
+}
+
+impl Urgent {
+    /// Is any high priority operation in flight?
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Record a high priority operation that is about to be submitted.
+    pub(crate) fn submitted(&mut self, key: u64) {
+        *self.keys.entry(key).or_default() += 1;
+    }
+
+    /// Forget an operation that won't deliver an event.
+    pub(crate) fn cancelled(&mut self, key: u64) {
+        if let Some(count) = self.keys.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                self.keys.remove(&key);
+            }
+        }
+    }
+
+    /// Move the events of high priority operations in `events[start..]`
+    /// to the front of that range, keeping the order within each lane.
+    pub(crate) fn prioritize(&mut self, events: &mut [Event], start: usize) {
+        let events = &mut events[start..];
+        let mut front = 0;
+
+        for i in 0..events.len() {
+            if self.keys.contains_key(&events[i].key) {
+                self.cancelled(events[i].key);
+                // shifting keeps the normal events in order
+                events[front..=i].rotate_right(1);
+                front += 1;
+            }
+        }
+    }
+}
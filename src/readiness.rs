@@ -0,0 +1,74 @@
+// GNU GPL v3 License
+
+use crate::{Raw, SourceType};
+use std::io::Result;
+
+/// Whether a source is ready for I/O, at one point in time.
+///
+/// This is returned by `Completion::poll_ready`. By the time it's looked
+/// at, the source may no longer be ready, so it's only a hint.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Readiness {
+    /// Reading wouldn't block.
+    pub readable: bool,
+    /// Writing wouldn't block.
+    pub writable: bool,
+    /// The peer hung up, or the source was otherwise closed.
+    pub hangup: bool,
+    /// An error is pending on the source.
+    pub error: bool,
+}
+
+/// Check whether a source is ready, without blocking.
+pub(crate) fn poll_ready(source: Raw, variant: SourceType) -> Result<Readiness> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let _ = variant;
+            let mut fd = libc::pollfd {
+                fd: source,
+                events: libc::POLLIN | libc::POLLOUT,
+                revents: 0,
+            };
+            syscall!(poll(&mut fd, 1, 0))?;
+
+            Ok(Readiness {
+                readable: fd.revents & libc::POLLIN != 0,
+                writable: fd.revents & libc::POLLOUT != 0,
+                hangup: fd.revents & libc::POLLHUP != 0,
+                error: fd.revents & (libc::POLLERR | libc::POLLNVAL) != 0,
+            })
+        } else if #[cfg(windows)] {
+            use windows_sys::Win32::Networking::WinSock::{
+                WSAPoll, POLLERR, POLLHUP, POLLNVAL, POLLRDNORM, POLLWRNORM, SOCKET_ERROR,
+                WSAPOLLFD,
+            };
+
+            if variant != SourceType::Socket {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "only sockets can be polled on Windows",
+                ));
+            }
+
+            let mut fd = WSAPOLLFD {
+                fd: source as _,
+                events: (POLLRDNORM | POLLWRNORM) as _,
+                revents: 0,
+            };
+            if unsafe { WSAPoll(&mut fd, 1, 0) } == SOCKET_ERROR {
+                return Err(std::io::Error::last_os_error());
+            }
+
+            let revents = fd.revents as i32;
+            Ok(Readiness {
+                readable: revents & POLLRDNORM as i32 != 0,
+                writable: revents & POLLWRNORM as i32 != 0,
+                hangup: revents & POLLHUP as i32 != 0,
+                error: revents & (POLLERR | POLLNVAL) as i32 != 0,
+            })
+        } else {
+            let _ = (source, variant);
+            Err(std::io::ErrorKind::Unsupported.into())
+        }
+    }
+}
@@ -0,0 +1,153 @@
+// GNU GPL v3 License
+
+use crate::{ops::Op, Completion, Event, Priority, SubmissionStatus};
+use std::{
+    collections::HashMap,
+    io::{self, Result},
+};
+
+/// Submits a rearmed operation again, given a pointer to it.
+type ResubmitFn = unsafe fn(*mut (), &Completion, u64, Priority) -> Result<SubmissionStatus>;
+
+/// Operations that are submitted again every time they complete with
+/// data, see `Read::rearm`.
+#[derive(Debug, Default)]
+pub(crate) struct Rearmed {
+    /// The operations, by key.
+    ops: HashMap<u64, Rearm>,
+    /// Operations whose events were handed out, to be submitted again by
+    /// the next `wait`.
+    parked: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Rearm {
+    /// The operation, which the user keeps in place until its last event.
+    op: *mut (),
+    resubmit: ResubmitFn,
+    priority: Priority,
+    /// Was the operation cancelled? If so, its next event is its last.
+    cancelled: bool,
+}
+
+// SAFETY: the operation is only touched by `resubmit`, while the user
+// isn't allowed to use it
+unsafe impl Send for Rearm {}
+
+impl Rearm {
+    /// Keep track of an operation that was just submitted.
+    ///
+    /// # Safety
+    ///
+    /// `op` must stay in place and unused until its last event.
+    pub(crate) unsafe fn new<O: Op>(op: &mut O, priority: Priority) -> Self {
+        unsafe fn resubmit<O: Op>(
+            op: *mut (),
+            completion: &Completion,
+            key: u64,
+            priority: Priority,
+        ) -> Result<SubmissionStatus> {
+            let op = &mut *op.cast::<O>();
+            op.reset();
+            completion.submit_once(op, key, priority)
+        }
+
+        Rearm {
+            op: (op as *mut O).cast(),
+            resubmit: resubmit::<O>,
+            priority,
+            cancelled: false,
+        }
+    }
+
+    /// Submit the operation again.
+    ///
+    /// # Safety
+    ///
+    /// The operation's last event must not have been handed out yet.
+    pub(crate) unsafe fn resubmit(
+        &self,
+        completion: &Completion,
+        key: u64,
+    ) -> Result<SubmissionStatus> {
+        (self.resubmit)(self.op, completion, key, self.priority)
+    }
+}
+
+impl Rearmed {
+    /// Is there any rearmed operation at all?
+    pub(crate) fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Keep track of an operation that's about to be submitted.
+    pub(crate) fn submitted(&mut self, key: u64, rearm: Rearm) {
+        self.ops.insert(key, rearm);
+    }
+
+    /// Forget an operation that couldn't be submitted.
+    pub(crate) fn remove(&mut self, key: u64) {
+        self.ops.remove(&key);
+    }
+
+    /// Look at events that are being handed out, parking the operations
+    /// that go on and forgetting the ones that are done.
+    ///
+    /// Returns the keys of the operations that are done.
+    pub(crate) fn completed(&mut self, events: &[Event]) -> Vec<u64> {
+        events
+            .iter()
+            .filter(|event| self.complete(event.key, &event.result))
+            .map(|event| event.key)
+            .collect()
+    }
+
+    /// Look at the result of an operation, parking it if it goes on.
+    ///
+    /// Returns `true` if this was its last event.
+    pub(crate) fn complete(&mut self, key: u64, result: &Result<usize>) -> bool {
+        let rearm = match self.ops.get(&key) {
+            Some(rearm) => rearm,
+            None => return false,
+        };
+
+        let goes_on = matches!(result, Ok(n) if *n > 0);
+        if goes_on && !rearm.cancelled {
+            self.parked.push(key);
+            false
+        } else {
+            self.ops.remove(&key);
+            true
+        }
+    }
+
+    /// Take the operations that are waiting to be submitted again.
+    ///
+    /// They're still kept track of, so they can be cancelled while they're
+    /// being submitted.
+    pub(crate) fn take_parked(&mut self) -> Vec<(u64, Rearm)> {
+        let ops = &self.ops;
+        self.parked
+            .drain(..)
+            .filter_map(|key| ops.get(&key).map(|&rearm| (key, rearm)))
+            .collect()
+    }
+
+    /// Cancel an operation.
+    ///
+    /// If it's waiting to be submitted again, it's forgotten, and its last
+    /// event is returned.
+    pub(crate) fn cancel(&mut self, key: u64) -> Option<Event> {
+        if let Some(i) = self.parked.iter().position(|&parked| parked == key) {
+            self.parked.swap_remove(i);
+            self.ops.remove(&key);
+            return Some(Event::new(key, Err(io::ErrorKind::Interrupted.into())));
+        }
+
+        if let Some(rearm) = self.ops.get_mut(&key) {
+            rearm.cancelled = true;
+        }
+
+        None
+    }
+}
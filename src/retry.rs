@@ -0,0 +1,33 @@
+// GNU GPL v3 License
+
+use std::{
+    io::{ErrorKind, Result},
+    time::{Duration, Instant},
+};
+
+/// Run a wait, retrying it with the remaining time if a signal interrupts
+/// it.
+///
+/// If `retry` is false, the interruption is returned as an error.
+pub(crate) fn retry_interrupted<T>(
+    timeout: Option<Duration>,
+    retry: bool,
+    mut wait: impl FnMut(Option<Duration>) -> Result<T>,
+) -> Result<T> {
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    let mut timeout = timeout;
+
+    loop {
+        match wait(timeout) {
+            Err(e) if retry && e.kind() == ErrorKind::Interrupted => {
+                tracing::debug!("Wait was interrupted, retrying");
+
+                // only wait for as long as was left
+                if let Some(deadline) = deadline {
+                    timeout = Some(deadline.saturating_duration_since(Instant::now()));
+                }
+            }
+            result => return result,
+        }
+    }
+}
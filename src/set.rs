@@ -0,0 +1,161 @@
+// GNU GPL v3 License
+
+use crate::{Completion, Op, SubmissionStatus};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    io::{self, Result},
+    mem,
+    time::{Duration, Instant},
+};
+
+/// A group of operations that can be waited on together.
+///
+/// Operations submitted through the set are given consecutive keys,
+/// starting at the key passed to `new`. Events for other operations are
+/// set aside and returned by later calls to `Completion::wait`.
+///
+/// If the set is dropped while operations are still in flight, those
+/// operations are leaked, since the OS may still be using their buffers.
+pub struct OpSet<'a, O: Op> {
+    /// The completion that operations are submitted to.
+    completion: &'a Completion,
+    /// The key for the next operation.
+    next_key: u64,
+    /// The operations in flight, by key.
+    ///
+    /// They're boxed so that they stay in place while in flight.
+    ops: HashMap<u64, Box<O>>,
+    /// Operations that completed, but haven't been returned yet.
+    ready: VecDeque<(u64, Result<O::Output>)>,
+}
+
+impl<O: Op> fmt::Debug for OpSet<'_, O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpSet")
+            .field("next_key", &self.next_key)
+            .field("in_flight", &self.ops.len())
+            .field("ready", &self.ready.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, O: Op> OpSet<'a, O> {
+    /// Create a new, empty set whose operations use keys starting at
+    /// `first_key`.
+    ///
+    /// # Safety
+    ///
+    /// None of the keys the set hands out may be used by another operation
+    /// in flight while the set is alive, and their events must not be
+    /// received by anything but the set. Otherwise, an operation would be
+    /// completed with another one's result.
+    pub unsafe fn new(completion: &'a Completion, first_key: u64) -> Self {
+        OpSet {
+            completion,
+            next_key: first_key,
+            ops: HashMap::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Submit an operation into the set, returning its key.
+    pub fn submit(&mut self, op: O) -> Result<u64> {
+        let key = self.next_key;
+        let mut op = Box::new(op);
+
+        // SAFETY: we own the operation, so it can't be submitted twice, and
+        // it's boxed so it won't move while in flight; the key is ours, by
+        // the contract of `new`
+        match unsafe { self.completion.submit(&mut *op, key)? } {
+            SubmissionStatus::AlreadyComplete(result) => {
+                let output = result.map(|result| unsafe { op.complete(result) });
+                self.ready.push_back((key, output));
+            }
+            SubmissionStatus::Submitted => {
+                self.ops.insert(key, op);
+            }
+        }
+
+        self.next_key = self.next_key.wrapping_add(1);
+        Ok(key)
+    }
+
+    /// The number of operations that haven't been returned yet.
+    pub fn len(&self) -> usize {
+        self.ops.len() + self.ready.len()
+    }
+
+    /// Tell whether every operation has been returned.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Wait for any operation in the set to complete.
+    ///
+    /// Returns the operation's key and its output, or `None` if the set is
+    /// empty. Returns an error of kind `TimedOut` if the timeout expires
+    /// first.
+    pub fn wait_any(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Option<(u64, Result<O::Output>)>> {
+        if let Some(ready) = self.ready.pop_front() {
+            return Ok(Some(ready));
+        }
+
+        if self.ops.is_empty() {
+            return Ok(None);
+        }
+
+        self.wait_one(timeout).map(Some)
+    }
+
+    /// Wait for every operation in the set to complete.
+    ///
+    /// Returns the keys and outputs of all operations, in the order they
+    /// completed. Returns an error of kind `TimedOut` if the timeout
+    /// expires first; operations that completed in the meantime are kept
+    /// and returned by the next call.
+    pub fn wait_all(&mut self, timeout: Option<Duration>) -> Result<Vec<(u64, Result<O::Output>)>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        while !self.ops.is_empty() {
+            let timeout = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) => Some(timeout),
+                    None => return Err(io::ErrorKind::TimedOut.into()),
+                },
+                None => None,
+            };
+
+            let ready = self.wait_one(timeout)?;
+            self.ready.push_back(ready);
+        }
+
+        Ok(self.ready.drain(..).collect())
+    }
+
+    /// Wait for an operation in flight to complete.
+    fn wait_one(&mut self, timeout: Option<Duration>) -> Result<(u64, Result<O::Output>)> {
+        let ops = &self.ops;
+        let event = self
+            .completion
+            .wait_matching(timeout, |key| ops.contains_key(&key))?;
+        let key = event.key;
+        let op = self.ops.remove(&key).unwrap();
+
+        // SAFETY: the event belongs to this operation
+        let output = unsafe { event.complete(*op) };
+        Ok((key, output))
+    }
+}
+
+impl<O: Op> Drop for OpSet<'_, O> {
+    fn drop(&mut self) {
+        // the OS may still write into these
+        for (_, op) in self.ops.drain() {
+            mem::forget(op);
+        }
+    }
+}
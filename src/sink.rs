@@ -0,0 +1,25 @@
+// GNU GPL v3 License
+
+use crate::Event;
+
+/// Something that events are reported to, one at a time.
+///
+/// This is used by `Completion::wait_sink`, as an alternative to
+/// collecting events into a `Vec`. It's implemented for `Vec<Event>`
+/// and for closures that take an `Event`.
+pub trait EventSink {
+    /// Report an event.
+    fn on_event(&mut self, event: Event);
+}
+
+impl EventSink for Vec<Event> {
+    fn on_event(&mut self, event: Event) {
+        self.push(event);
+    }
+}
+
+impl<F: FnMut(Event)> EventSink for F {
+    fn on_event(&mut self, event: Event) {
+        self(event)
+    }
+}
@@ -42,13 +42,68 @@ unsafe impl<T: AsSource> Source for T {
     }
 }
 
-/// Is this source a socket or a file?
+/// A batch of sources that are registered and deregistered together.
+///
+/// Registering a group takes the internal locks once for the entire
+/// batch, instead of once for each of thousands of sources. Every source
+/// must stay open for as long as it's registered.
+#[derive(Debug, Clone, Default)]
+pub struct SourceGroup {
+    pub(crate) sources: Vec<(Raw, SourceType)>,
+}
+
+impl SourceGroup {
+    /// Create a new, empty `SourceGroup`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a new, empty `SourceGroup` with room for `capacity` sources.
+    pub fn with_capacity(capacity: usize) -> Self {
+        SourceGroup {
+            sources: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Add a source to the group.
+    pub fn add<S: Source>(&mut self, source: &S) -> &mut Self {
+        self.sources.push((source.as_raw(), S::SOURCE_TYPE));
+        self
+    }
+
+    /// Remove a source from the group, returning whether it was there.
+    pub fn remove(&mut self, source: &impl Source) -> bool {
+        let raw = source.as_raw();
+        let len = self.sources.len();
+        self.sources.retain(|&(other, _)| other != raw);
+        self.sources.len() != len
+    }
+
+    /// The number of sources in the group.
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Whether the group is empty.
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+}
+
+/// Is this source a socket, a file or a terminal?
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum SourceType {
     /// A socket.
     Socket,
     /// A file.
     File,
+    /// A terminal, a pipe or another character device.
+    ///
+    /// These can't be seeked and, on Windows, can't be used with
+    /// overlapped I/O. The standard I/O types and child process pipes
+    /// are always declared as one, but readiness polling checks whether
+    /// they're regular files when they're registered.
+    Tty,
 }
 
 macro_rules! impl_source {
@@ -79,16 +134,34 @@ impl_source! {
     std::net::TcpListener, Socket, as_raw_socket,
     std::net::UdpSocket, Socket, as_raw_socket,
     std::fs::File, File, as_raw_handle,
-    std::io::Stderr, File, as_raw_handle,
-    std::io::Stdout, File, as_raw_handle,
-    std::io::Stdin, File, as_raw_handle,
-    std::io::StderrLock<'_>, File, as_raw_handle,
-    std::io::StdoutLock<'_>, File, as_raw_handle,
-    std::io::StdinLock<'_>, File, as_raw_handle,
-    std::process::ChildStdin, File, as_raw_handle,
-    std::process::ChildStdout, File, as_raw_handle,
-    std::process::ChildStderr, File, as_raw_handle,
-    #[cfg(unix)] std::os::unix::net::UnixStream, File, as_raw_fd,
-    #[cfg(unix)] std::os::unix::net::UnixListener, File, as_raw_fd,
-    #[cfg(unix)] std::os::unix::net::UnixDatagram, File, as_raw_fd
+    std::io::Stderr, Tty, as_raw_handle,
+    std::io::Stdout, Tty, as_raw_handle,
+    std::io::Stdin, Tty, as_raw_handle,
+    std::io::StderrLock<'_>, Tty, as_raw_handle,
+    std::io::StdoutLock<'_>, Tty, as_raw_handle,
+    std::io::StdinLock<'_>, Tty, as_raw_handle,
+    std::process::ChildStdin, Tty, as_raw_handle,
+    std::process::ChildStdout, Tty, as_raw_handle,
+    std::process::ChildStderr, Tty, as_raw_handle,
+    #[cfg(unix)] std::os::unix::net::UnixStream, Socket, as_raw_fd,
+    #[cfg(unix)] std::os::unix::net::UnixListener, Socket, as_raw_fd,
+    #[cfg(unix)] std::os::unix::net::UnixDatagram, Socket, as_raw_fd
+}
+
+// sockets configured before connecting
+#[cfg(feature = "socket2")]
+impl_source! {
+    socket2::Socket, Socket, as_raw_socket
+}
+
+// handles from readiness-based stacks; they shouldn't stay registered with
+// a `mio::Poll` at the same time
+#[cfg(feature = "mio")]
+impl_source! {
+    mio::net::TcpStream, Socket, as_raw_socket,
+    mio::net::TcpListener, Socket, as_raw_socket,
+    mio::net::UdpSocket, Socket, as_raw_socket,
+    #[cfg(unix)] mio::net::UnixStream, Socket, as_raw_fd,
+    #[cfg(unix)] mio::net::UnixListener, Socket, as_raw_fd,
+    #[cfg(unix)] mio::net::UnixDatagram, Socket, as_raw_fd
 }
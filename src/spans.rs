@@ -0,0 +1,43 @@
+// GNU GPL v3 License
+
+//! The spans of traced operations in flight.
+
+#![cfg(feature = "tracing-spans")]
+
+use crate::Event;
+use std::collections::HashMap;
+use tracing::Span;
+
+/// Keeps the span of every traced operation until its event is received.
+#[derive(Default)]
+pub(crate) struct Spans {
+    spans: HashMap<u64, Span>,
+}
+
+impl Spans {
+    /// A traced operation was submitted.
+    pub(crate) fn submitted(&mut self, key: u64, span: Span) {
+        self.spans.insert(key, span);
+    }
+
+    /// A traced operation couldn't be submitted, or completed right away.
+    pub(crate) fn cancelled(&mut self, key: u64) {
+        self.spans.remove(&key);
+    }
+
+    /// Are there any traced operations in flight?
+    pub(crate) fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    /// Log the events of traced operations within their spans.
+    pub(crate) fn completed(&mut self, events: &[Event]) {
+        for event in events {
+            if let Some(span) = self.spans.remove(&event.key) {
+                span.in_scope(|| {
+                    tracing::trace!(key = event.key, result = ?event.result, "operation completed")
+                });
+            }
+        }
+    }
+}
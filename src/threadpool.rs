@@ -0,0 +1,174 @@
+// GNU GPL v3 License
+
+//! Thread pool I/O, where completions are picked up by the Win32 thread
+//! pool instead of our own wait on the completion port.
+
+#![cfg(windows)]
+
+use crate::{PoisonPolicy, Raw};
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    fmt, io,
+    io::Result,
+    mem,
+    ptr::null_mut,
+    sync::{Arc, Mutex},
+};
+use windows_sys::Win32::{
+    Foundation::{FALSE, HANDLE, TRUE},
+    System::{
+        Threading::{
+            CancelThreadpoolIo, CloseThreadpoolIo, CreateThreadpoolIo, StartThreadpoolIo,
+            WaitForThreadpoolIoCallbacks, TP_CALLBACK_INSTANCE, TP_IO,
+        },
+        IO::{PostQueuedCompletionStatus, OVERLAPPED},
+    },
+};
+
+/// The completion key that the thread pool wakes up the port with.
+pub(crate) const THREAD_POOL_KEY: u64 = u64::MAX - 1;
+
+/// State shared with the thread pool's callbacks.
+struct Shared {
+    /// The `OVERLAPPED`s of operations that completed since the last wait.
+    completed: Mutex<Vec<usize>>,
+    /// The port that `wait` sleeps on.
+    port: HANDLE,
+    /// What to do when one of our mutexes is poisoned.
+    poison: PoisonPolicy,
+}
+
+// SAFETY: the port is a kernel handle, usable from any thread
+unsafe impl Send for Shared {}
+unsafe impl Sync for Shared {}
+
+/// Binds handles to the thread pool, and collects their completions.
+pub(crate) struct ThreadPool {
+    shared: Arc<Shared>,
+    /// The thread pool I/O object of every registered handle.
+    ios: Mutex<HashMap<Raw, usize>>,
+}
+
+impl fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadPool").finish_non_exhaustive()
+    }
+}
+
+impl ThreadPool {
+    /// Hand completions over to `wait` on `port`.
+    pub(crate) fn new(port: HANDLE, poison: PoisonPolicy) -> Self {
+        ThreadPool {
+            shared: Arc::new(Shared {
+                completed: Mutex::new(Vec::new()),
+                port,
+                poison,
+            }),
+            ios: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Bind a handle to the thread pool.
+    pub(crate) fn bind(&self, raw: Raw) -> Result<()> {
+        let mut ios = lock!(self.ios, self.shared.poison);
+        if ios.contains_key(&raw) {
+            return Ok(());
+        }
+
+        let context = Arc::as_ptr(&self.shared) as *mut c_void;
+        let io = unsafe { CreateThreadpoolIo(raw as _, Some(io_callback), context, null_mut()) };
+        if io.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+
+        ios.insert(raw, io as usize);
+        Ok(())
+    }
+
+    /// Unbind a handle, once its operations are done.
+    pub(crate) fn unbind(&self, raw: Raw) -> Result<()> {
+        if let Some(io) = lock!(self.ios, self.shared.poison).remove(&raw) {
+            unsafe {
+                WaitForThreadpoolIoCallbacks(io as *mut TP_IO, FALSE);
+                CloseThreadpoolIo(io as *mut TP_IO);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get ready for an operation on `raw`, if it's bound.
+    ///
+    /// This must happen before every overlapped operation on the handle.
+    /// Returns the I/O object to pass to `cancel` if the operation doesn't
+    /// end up queueing a completion.
+    pub(crate) fn start(&self, raw: Raw) -> Result<Option<usize>> {
+        let io = lock!(self.ios, self.shared.poison).get(&raw).copied();
+        if let Some(io) = io {
+            unsafe { StartThreadpoolIo(io as *mut TP_IO) };
+        }
+
+        Ok(io)
+    }
+
+    /// Undo `start` for an operation that won't queue a completion.
+    pub(crate) fn cancel(&self, io: usize) {
+        unsafe { CancelThreadpoolIo(io as *mut TP_IO) };
+    }
+
+    /// Take the `OVERLAPPED`s of the operations that completed.
+    pub(crate) fn take_completed(&self) -> Result<Vec<*mut OVERLAPPED>> {
+        let completed = mem::take(&mut *lock!(self.shared.completed, self.shared.poison));
+        Ok(completed
+            .into_iter()
+            .map(|o| o as *mut OVERLAPPED)
+            .collect())
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        let ios = match self.ios.get_mut() {
+            Ok(ios) => ios,
+            Err(e) => e.into_inner(),
+        };
+
+        // the callbacks use the shared state until they're done
+        for (_, io) in ios.drain() {
+            unsafe {
+                WaitForThreadpoolIoCallbacks(io as *mut TP_IO, TRUE);
+                CloseThreadpoolIo(io as *mut TP_IO);
+            }
+        }
+    }
+}
+
+/// Called on the thread pool once an operation completes.
+///
+/// The status and the number of bytes are already in the `OVERLAPPED`, so
+/// this hands it to `wait`, waking it up once for every batch.
+unsafe extern "system" fn io_callback(
+    _instance: *mut TP_CALLBACK_INSTANCE,
+    context: *mut c_void,
+    overlapped: *mut c_void,
+    _result: u32,
+    _transferred: usize,
+    _io: *mut TP_IO,
+) {
+    let shared = &*(context as *const Shared);
+    let mut completed = lock!(shared.completed, shared.poison, infallible);
+    completed.push(overlapped as usize);
+    let first = completed.len() == 1;
+    drop(completed);
+
+    if first {
+        let res = PostQueuedCompletionStatus(shared.port, 0, THREAD_POOL_KEY as _, null_mut());
+        if res == 0 {
+            tracing::error!(
+                "Failed to wake up the completion port: {:?}",
+                io::Error::last_os_error()
+            );
+        }
+    }
+}
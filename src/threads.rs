@@ -0,0 +1,402 @@
+// GNU GPL v3 License
+
+#![cfg(all(unix, feature = "fallback-threads"))]
+
+//! A backend that runs every operation on its own thread.
+//!
+//! This is the last resort for platforms without a way to poll many
+//! sources at once. Each operation is polled on a thread of the blocking
+//! pool, which waits for its source with `poll()` in between, and the
+//! results come back through a queue.
+
+pub use crate::polling::OpData;
+
+use crate::{
+    ops::Op,
+    polling,
+    pool::{BlockingPool, PoolConfig},
+    CompletionBuilder, Event, PoisonPolicy, PollingFn, Priority, Raw, Source, SourceGroup,
+    SourceType, SubmissionStatus,
+};
+use slab::Slab;
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Result},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+/// How long a thread waits for its source before checking whether its
+/// operation was cancelled, in milliseconds.
+const CANCEL_CHECK: libc::c_int = 50;
+
+pub(crate) struct Completion {
+    /// The threads that the operations run on.
+    pool: BlockingPool,
+    /// The state shared with the threads.
+    shared: Arc<Shared>,
+    /// The most events returned by a single `wait`.
+    capacity: usize,
+    /// Do we put registered sources into non-blocking mode?
+    nonblocking: bool,
+    /// Does that include the standard I/O we inherited?
+    nonblocking_stdio: bool,
+    /// The original file status flags of the sources we made non-blocking.
+    original_flags: Mutex<HashMap<Raw, libc::c_int>>,
+    /// What to do when one of our mutexes is poisoned.
+    poison: PoisonPolicy,
+}
+
+struct Shared {
+    /// Events for operations that finished.
+    finished: Mutex<Vec<Event>>,
+    /// Signalled when an event is queued or the waiter is notified.
+    condvar: Condvar,
+    /// Was the waiter notified since it last returned?
+    notified: AtomicBool,
+    /// The operations in flight.
+    in_flight: Mutex<Slab<InFlight>>,
+    /// What to do when one of our mutexes is poisoned.
+    poison: PoisonPolicy,
+}
+
+/// An operation that is running on a thread.
+struct InFlight {
+    key: u64,
+    source: Raw,
+    /// Set to make the operation give up.
+    cancelled: Arc<AtomicBool>,
+}
+
+/// The functions that perform an operation.
+struct Functions {
+    poll: PollingFn,
+    blocking: Option<PollingFn>,
+    read: bool,
+    write: bool,
+}
+
+impl fmt::Debug for Completion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Completion")
+            .field("pool", &self.pool)
+            .field(
+                "in_flight",
+                &lock!(self.shared.in_flight, self.poison, infallible).len(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl Completion {
+    pub(crate) fn new(builder: &CompletionBuilder) -> Result<Self> {
+        // every operation in flight holds a thread until it's done
+        let config = PoolConfig {
+            max_threads: usize::MAX,
+            ..builder.blocking.clone()
+        };
+
+        Ok(Completion {
+            pool: BlockingPool::new(&config, builder.poison),
+            shared: Arc::new(Shared {
+                finished: Mutex::new(Vec::new()),
+                condvar: Condvar::new(),
+                notified: AtomicBool::new(false),
+                in_flight: Mutex::new(Slab::new()),
+                poison: builder.poison,
+            }),
+            capacity: builder.capacity,
+            nonblocking: builder.nonblocking,
+            nonblocking_stdio: builder.nonblocking_stdio,
+            original_flags: Mutex::new(HashMap::new()),
+            poison: builder.poison,
+        })
+    }
+
+    pub(crate) fn register(&self, source: &impl Source) -> Result<()> {
+        self.add_source(source.as_raw())
+    }
+
+    pub(crate) fn deregister(&self, source: &impl Source) -> Result<()> {
+        self.remove_source(source.as_raw())
+    }
+
+    pub(crate) fn register_group(&self, group: &SourceGroup) -> Result<()> {
+        for (i, &(raw, _)) in group.sources.iter().enumerate() {
+            if let Err(e) = self.add_source(raw) {
+                for &(raw, _) in &group.sources[..i] {
+                    if let Err(e) = self.remove_source(raw) {
+                        tracing::error!("Failed to roll back registration: {:?}", e);
+                    }
+                }
+
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn deregister_group(&self, group: &SourceGroup) -> Result<()> {
+        let mut result = Ok(());
+
+        // keep going, so one bad source doesn't leak the others
+        for &(raw, _) in &group.sources {
+            if let Err(e) = self.remove_source(raw) {
+                result = Err(e);
+            }
+        }
+
+        result
+    }
+
+    /// Put the source into non-blocking mode, so that a thread waiting on
+    /// it can notice that its operation was cancelled.
+    fn add_source(&self, raw: Raw) -> Result<()> {
+        let mut original_flags = lock!(self.original_flags, self.poison);
+        let shared = !self.nonblocking_stdio && polling::is_inherited_stdio(raw);
+        if self.nonblocking && !shared && !original_flags.contains_key(&raw) {
+            original_flags.insert(raw, polling::set_nonblocking(raw)?);
+        }
+        Ok(())
+    }
+
+    /// Restore the source to how we found it.
+    fn remove_source(&self, raw: Raw) -> Result<()> {
+        if let Some(flags) = lock!(self.original_flags, self.poison).remove(&raw) {
+            syscall!(fcntl(raw, libc::F_SETFL, flags))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn cancel_group(&self, group: &SourceGroup) -> Result<()> {
+        self.cancel_where(|op| group.sources.iter().any(|&(raw, _)| raw == op.source))
+    }
+
+    /// Make the operation with `key` fail with `Interrupted`, unless it's
+    /// already running on the blocking pool.
+    pub(crate) fn cancel(&self, key: u64) -> Result<()> {
+        self.cancel_where(|op| op.key == key)
+    }
+
+    fn cancel_where(&self, mut f: impl FnMut(&InFlight) -> bool) -> Result<()> {
+        let in_flight = lock!(self.shared.in_flight, self.poison);
+        for (_, op) in in_flight.iter().filter(|(_, op)| f(op)) {
+            op.cancelled.store(true, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn submit(
+        &self,
+        op: &mut impl Op,
+        key: u64,
+        _priority: Priority,
+    ) -> Result<SubmissionStatus> {
+        let mut functions = functions(op)?;
+        let source = op.source();
+        let file = op.variant() == SourceType::File;
+
+        // operations that don't wait for anything complete right away
+        if !file && !functions.read && !functions.write {
+            return Ok(SubmissionStatus::AlreadyComplete(functions.poll.call()));
+        }
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let index = lock!(self.shared.in_flight, self.poison).insert(InFlight {
+            key,
+            source,
+            cancelled: cancelled.clone(),
+        });
+
+        let shared = self.shared.clone();
+        let spawned = self.pool.spawn(move || {
+            let result = run(functions, source, file, &cancelled);
+            lock!(shared.in_flight, shared.poison, infallible).remove(index);
+            lock!(shared.finished, shared.poison, infallible).push(Event::new(key, result));
+            shared.condvar.notify_all();
+        });
+
+        if let Err(e) = spawned {
+            lock!(self.shared.in_flight, self.poison).remove(index);
+            return Err(e);
+        }
+
+        Ok(SubmissionStatus::Submitted)
+    }
+
+    pub(crate) fn wait(&self, timeout: Option<Duration>, out: &mut Vec<Event>) -> Result<usize> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut finished = lock!(self.shared.finished, self.poison);
+
+        while finished.is_empty() && !self.shared.notified.swap(false, Ordering::AcqRel) {
+            finished = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(timeout) => wait_timeout(&self.shared.condvar, finished, timeout),
+                    None => break,
+                },
+                None => match self.shared.condvar.wait(finished) {
+                    Ok(guard) => guard,
+                    Err(e) => {
+                        tracing::error!("Mutex was poisoned: {:?}", &e);
+                        e.into_inner()
+                    }
+                },
+            };
+        }
+
+        let count = finished.len().min(self.capacity);
+        out.extend(finished.drain(..count));
+        Ok(count)
+    }
+
+    /// The backend that performs operations on this kind of source.
+    #[cfg(not(target_os = "linux"))]
+    pub(crate) fn backend_for(&self, _variant: SourceType) -> crate::Backend {
+        crate::Backend::Threads
+    }
+
+    pub(crate) fn notify(&self) -> Result<()> {
+        // hold the lock so the waiter can't miss the wakeup
+        let _finished = lock!(self.shared.finished, self.poison);
+        self.shared.notified.store(true, Ordering::Release);
+        self.shared.condvar.notify_all();
+        Ok(())
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn reserve(&self, _additional: usize) -> Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the thread-per-operation backend can't be resized",
+        ))
+    }
+
+    pub(crate) fn shrink_to_fit(&self) -> Result<()> {
+        lock!(self.shared.finished, self.poison).shrink_to_fit();
+        lock!(self.shared.in_flight, self.poison).shrink_to_fit();
+        lock!(self.original_flags, self.poison).shrink_to_fit();
+        Ok(())
+    }
+
+    pub(crate) fn notifiers(&self) -> Result<Vec<Raw>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "the thread backend can't be waited on from outside",
+        ))
+    }
+}
+
+impl Drop for Completion {
+    fn drop(&mut self) {
+        // put the sources that are still registered back how we found them
+        let original_flags = match self.original_flags.get_mut() {
+            Ok(original_flags) => original_flags,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for (&raw, &flags) in original_flags.iter() {
+            if let Err(e) = syscall!(fcntl(raw, libc::F_SETFL, flags)) {
+                tracing::error!("Failed to restore file status flags: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Have the operation fill in the functions that perform it.
+fn functions(op: &mut impl Op) -> Result<Functions> {
+    #[allow(unused_mut)]
+    let mut op_data = OpData::new();
+
+    #[cfg(target_os = "linux")]
+    let mut op_data = crate::OpData::Polling(op_data);
+
+    op.run(&mut op_data)?;
+
+    #[cfg(target_os = "linux")]
+    let op_data = match op_data {
+        crate::OpData::Polling(op_data) => op_data,
+        _ => unreachable!(),
+    };
+
+    match op_data.slot {
+        Some(poll) => Ok(Functions {
+            poll,
+            blocking: op_data.blocking,
+            read: op_data.read,
+            write: op_data.write,
+        }),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "No polling function provided",
+        )),
+    }
+}
+
+/// Perform the operation, waiting for its source whenever it blocks.
+fn run(mut functions: Functions, source: Raw, file: bool, cancelled: &AtomicBool) -> Result<usize> {
+    loop {
+        match functions.poll.call() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock && !file => {
+                wait_ready(source, functions.read, functions.write, cancelled)?;
+            }
+            // files can't be waited on, so they block instead
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock || polling::is_hand_off(&e) => {
+                return match functions.blocking.as_mut() {
+                    Some(blocking) => blocking.call(),
+                    None => Err(e),
+                };
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Wait until the source is ready, or the operation is cancelled.
+fn wait_ready(source: Raw, read: bool, write: bool, cancelled: &AtomicBool) -> Result<()> {
+    let mut pollfd = libc::pollfd {
+        fd: source,
+        events: 0,
+        revents: 0,
+    };
+    if read {
+        pollfd.events |= libc::POLLIN;
+    }
+    if write {
+        pollfd.events |= libc::POLLOUT;
+    }
+
+    loop {
+        if cancelled.load(Ordering::Acquire) {
+            return Err(io::ErrorKind::Interrupted.into());
+        }
+
+        match syscall!(poll(&mut pollfd, 1, CANCEL_CHECK)) {
+            Ok(0) => {}
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Wait on the condition variable for at most `timeout`.
+fn wait_timeout<'a>(
+    condvar: &Condvar,
+    guard: MutexGuard<'a, Vec<Event>>,
+    timeout: Duration,
+) -> MutexGuard<'a, Vec<Event>> {
+    match condvar.wait_timeout(guard, timeout) {
+        Ok((guard, _)) => guard,
+        Err(e) => {
+            tracing::error!("Mutex was poisoned: {:?}", &e);
+            e.into_inner().0
+        }
+    }
+}
@@ -0,0 +1,14 @@
+// GNU GPL v3 License
+
+use crate::Event;
+use std::time::Instant;
+
+/// An event, along with the time it was received.
+///
+/// This is what `Completion::wait_timed` hands out.
+#[derive(Debug)]
+pub struct TimedEvent {
+    pub event: Event,
+    /// When `wait_timed` received the event.
+    pub timestamp: Instant,
+}
@@ -0,0 +1,127 @@
+// GNU GPL v3 License
+
+#![cfg(feature = "rustls")]
+
+use crate::{Read, Source, Write};
+use rustls::Connection;
+use std::io::{self, Result};
+
+/// The size of the buffer used to read ciphertext.
+const READ_BUF_SIZE: usize = 16 * 1024;
+
+/// Couples a `rustls::Connection` with the operations needed to drive it.
+///
+/// This does not submit anything by itself. Instead, it hands out `Read`
+/// and `Write` operations for the ciphertext whenever the connection wants
+/// them; once those complete, their results are passed back in through
+/// `complete_read` and `complete_write`. The plaintext is then available
+/// through `reader` and `writer`.
+#[derive(Debug)]
+pub struct TlsAdapter {
+    /// The TLS connection.
+    conn: Connection,
+    /// A buffer for reading ciphertext, if it isn't in use.
+    read_buf: Option<Vec<u8>>,
+    /// A buffer for writing ciphertext, if it isn't in use.
+    write_buf: Option<Vec<u8>>,
+}
+
+impl TlsAdapter {
+    /// Create a new `TlsAdapter` around a connection.
+    pub fn new(conn: impl Into<Connection>) -> Self {
+        TlsAdapter {
+            conn: conn.into(),
+            read_buf: Some(Vec::new()),
+            write_buf: Some(Vec::new()),
+        }
+    }
+
+    /// Get the underlying connection.
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Get the underlying connection mutably.
+    pub fn connection_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+
+    /// Read decrypted data.
+    pub fn reader(&mut self) -> rustls::Reader<'_> {
+        self.conn.reader()
+    }
+
+    /// Write data to be encrypted.
+    ///
+    /// The data is only sent once the operation returned by `write_op`
+    /// completes.
+    pub fn writer(&mut self) -> rustls::Writer<'_> {
+        self.conn.writer()
+    }
+
+    /// Get an operation that reads ciphertext from `source`, if the
+    /// connection wants to read and no read is already in flight.
+    pub fn read_op<S: Source>(&mut self, source: &S) -> Option<Read<Vec<u8>>> {
+        if !self.conn.wants_read() {
+            return None;
+        }
+
+        let mut buf = self.read_buf.take()?;
+        buf.resize(READ_BUF_SIZE, 0);
+        Some(Read::new(source, buf))
+    }
+
+    /// Process the result of a completed read operation.
+    ///
+    /// Returns the state of the connection after processing any new
+    /// packets. A read of zero bytes is treated as the peer closing the
+    /// connection.
+    pub fn complete_read(&mut self, n: usize, buf: Vec<u8>) -> Result<rustls::IoState> {
+        let mut data = &buf[..n];
+        let result = loop {
+            // an empty read feeds an EOF into the connection
+            let read = self.conn.read_tls(&mut data);
+            let state = self
+                .conn
+                .process_new_packets()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+
+            match (read, state) {
+                (Err(e), _) | (_, Err(e)) => break Err(e),
+                (Ok(0), state) => break state,
+                (_, state) if data.is_empty() => break state,
+                _ => {}
+            }
+        };
+
+        self.read_buf = Some(buf);
+        result
+    }
+
+    /// Get an operation that writes pending ciphertext to `source`, if
+    /// there is any and no write is already in flight.
+    pub fn write_op<S: Source>(&mut self, source: &S) -> Option<Write<Vec<u8>>> {
+        let buf = self.write_buf.as_mut()?;
+        while self.conn.wants_write() {
+            if let Err(e) = self.conn.write_tls(buf) {
+                tracing::error!("Failed to buffer TLS data: {:?}", e);
+                break;
+            }
+        }
+
+        if buf.is_empty() {
+            return None;
+        }
+
+        let buf = self.write_buf.take()?;
+        Some(Write::new(source, buf))
+    }
+
+    /// Process the result of a completed write operation.
+    ///
+    /// Any data that wasn't written is sent by the next write operation.
+    pub fn complete_write(&mut self, n: usize, mut buf: Vec<u8>) {
+        buf.drain(..n.min(buf.len()));
+        self.write_buf = Some(buf);
+    }
+}
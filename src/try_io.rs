@@ -0,0 +1,83 @@
+// GNU GPL v3 License
+
+use crate::{Op, Raw, SourceType};
+use std::{fmt, io::Result, ptr::NonNull};
+
+/// The outcome of `Completion::try_read` and `Completion::try_write`.
+pub enum TryIo<O: Op> {
+    /// The transfer happened right away.
+    Done(O::Output),
+    /// The transfer would have blocked, so here's the operation to submit
+    /// instead.
+    WouldBlock(O),
+}
+
+impl<O: Op> fmt::Debug for TryIo<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryIo::Done(_) => f.write_str("Done(..)"),
+            TryIo::WouldBlock(_) => f.write_str("WouldBlock(..)"),
+        }
+    }
+}
+
+/// Receive into a buffer without blocking.
+///
+/// Returns `None` if the source can't be read without blocking, and a
+/// `WouldBlock` error if it's not ready.
+pub(crate) fn try_recv(
+    source: Raw,
+    variant: SourceType,
+    buf: NonNull<[u8]>,
+) -> Option<Result<usize>> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let (ptr, len) = (buf.as_ptr() as *mut u8, buf.len());
+            match variant {
+                SourceType::Socket => Some(
+                    syscall!(recv(source, ptr.cast(), len, libc::MSG_DONTWAIT)).map(|n| n as usize),
+                ),
+                SourceType::Tty if is_nonblocking(source) => {
+                    Some(syscall!(read(source, ptr.cast(), len)).map(|n| n as usize))
+                }
+                _ => None,
+            }
+        } else {
+            let _ = (source, variant, buf);
+            None
+        }
+    }
+}
+
+/// Send from a buffer without blocking.
+///
+/// See `try_recv`.
+pub(crate) fn try_send(
+    source: Raw,
+    variant: SourceType,
+    buf: NonNull<[u8]>,
+) -> Option<Result<usize>> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            let (ptr, len) = (buf.as_ptr() as *const u8, buf.len());
+            match variant {
+                SourceType::Socket => Some(
+                    syscall!(send(source, ptr.cast(), len, libc::MSG_DONTWAIT)).map(|n| n as usize),
+                ),
+                SourceType::Tty if is_nonblocking(source) => {
+                    Some(syscall!(write(source, ptr.cast(), len)).map(|n| n as usize))
+                }
+                _ => None,
+            }
+        } else {
+            let _ = (source, variant, buf);
+            None
+        }
+    }
+}
+
+/// Tell whether the source is in non-blocking mode.
+#[cfg(unix)]
+fn is_nonblocking(source: Raw) -> bool {
+    matches!(syscall!(fcntl(source, libc::F_GETFL)), Ok(flags) if flags & libc::O_NONBLOCK != 0)
+}
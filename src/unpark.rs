@@ -0,0 +1,150 @@
+// GNU GPL v3 License
+
+//! Unparking a thread when the backend has something to harvest.
+
+#![cfg(unix)]
+
+use crate::{PoisonPolicy, Raw};
+use std::{
+    io::{self, Result, Write as _},
+    os::unix::{io::AsRawFd, net::UnixStream},
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle, Thread},
+};
+
+/// Unparks a thread whenever the backend wakes up.
+///
+/// A watcher thread waits on the handles that wake the backend up, the same
+/// ones that `CompletionSet` waits on, and unparks the thread once one of
+/// them is signalled. They stay signalled until the events are harvested,
+/// so the watcher waits for the next harvest before it looks again.
+pub(crate) struct Unparker {
+    thread: Thread,
+    shared: Arc<Shared>,
+    /// Wakes the watcher up to stop it.
+    stop: UnixStream,
+    watcher: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    condvar: Condvar,
+    poison: PoisonPolicy,
+}
+
+#[derive(Default)]
+struct State {
+    /// The number of times the events were harvested.
+    harvests: u64,
+    /// Should the watcher stop?
+    stopped: bool,
+}
+
+impl Unparker {
+    /// Start watching `notifiers` on behalf of `thread`.
+    ///
+    /// The handles must stay open until the `Unparker` is dropped.
+    pub(crate) fn new(thread: Thread, notifiers: Vec<Raw>, poison: PoisonPolicy) -> Result<Self> {
+        let (stop, stopped) = UnixStream::pair()?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            condvar: Condvar::new(),
+            poison,
+        });
+
+        let watcher = {
+            let (thread, shared) = (thread.clone(), shared.clone());
+            thread::Builder::new()
+                .name("polldough-unpark".into())
+                .spawn(move || watch(&notifiers, &stopped, &thread, &shared))?
+        };
+
+        Ok(Unparker {
+            thread,
+            shared,
+            stop,
+            watcher: Some(watcher),
+        })
+    }
+
+    /// The thread that is unparked.
+    pub(crate) fn thread(&self) -> &Thread {
+        &self.thread
+    }
+
+    /// Let the watcher know that the events were harvested.
+    pub(crate) fn harvested(&self) {
+        let mut state = lock!(self.shared.state, self.shared.poison, infallible);
+        state.harvests = state.harvests.wrapping_add(1);
+        self.shared.condvar.notify_all();
+    }
+}
+
+impl Drop for Unparker {
+    fn drop(&mut self) {
+        lock!(self.shared.state, self.shared.poison, infallible).stopped = true;
+        self.shared.condvar.notify_all();
+
+        if let Err(e) = self.stop.write_all(&[1]) {
+            tracing::error!("Failed to stop the unpark watcher: {:?}", e);
+            return;
+        }
+
+        if let Some(watcher) = self.watcher.take() {
+            let _ = watcher.join();
+        }
+    }
+}
+
+/// Unpark `thread` every time one of the handles is signalled, until
+/// `stopped` is.
+fn watch(notifiers: &[Raw], stopped: &UnixStream, thread: &Thread, shared: &Shared) {
+    let mut fds: Vec<_> = notifiers
+        .iter()
+        .copied()
+        .chain(Some(stopped.as_raw_fd()))
+        .map(|fd| libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+
+    loop {
+        let seen = {
+            let state = lock!(shared.state, shared.poison, infallible);
+            if state.stopped {
+                return;
+            }
+            state.harvests
+        };
+
+        match syscall!(poll(fds.as_mut_ptr(), fds.len() as _, -1)) {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                // let the thread find out for itself on its next harvest
+                tracing::error!("Failed to watch the backend: {:?}", e);
+                thread.unpark();
+                return;
+            }
+        }
+
+        if fds[fds.len() - 1].revents != 0 {
+            return;
+        }
+        thread.unpark();
+
+        // the handles stay signalled until the events are harvested
+        let mut state = lock!(shared.state, shared.poison, infallible);
+        while state.harvests == seen && !state.stopped {
+            state = match shared.condvar.wait(state) {
+                Ok(guard) => guard,
+                Err(e) => {
+                    tracing::error!("Mutex was poisoned: {:?}", &e);
+                    e.into_inner()
+                }
+            };
+        }
+    }
+}
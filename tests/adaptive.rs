@@ -0,0 +1,79 @@
+// GNU GPL v3 License
+
+//! Reading into pooled buffers that grow to fit the data.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{backends, run};
+use polldough::{BufPool, ReadAdaptive};
+use std::{
+    io::Write as _,
+    os::unix::net::{UnixDatagram, UnixStream},
+};
+
+#[test]
+fn small_reads_stay_small() {
+    let pool = BufPool::new(16, 256);
+
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        client.write_all(b"hello").unwrap();
+        let buf = run(&completion, ReadAdaptive::new(&server, &pool), 1).unwrap();
+        assert_eq!(&*buf, b"hello");
+        assert_eq!(buf.capacity(), 16);
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn stream_grows_buffer() {
+    let pool = BufPool::new(16, 256);
+    let data: Vec<u8> = (0..100).collect();
+
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        // everything is there before the read starts, so it's taken in
+        // one go
+        client.write_all(&data).unwrap();
+        let buf = run(&completion, ReadAdaptive::new(&server, &pool), 1).unwrap();
+        assert_eq!(*buf, *data);
+        assert_eq!(buf.capacity(), 128);
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn datagram_gets_buffer_that_fits() {
+    let pool = BufPool::new(16, 256);
+    let data: Vec<u8> = (0..100).collect();
+    let large = vec![7u8; 1000];
+
+    for completion in backends() {
+        let (client, server) = UnixDatagram::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        client.send(&data).unwrap();
+        let buf = run(&completion, ReadAdaptive::new(&server, &pool), 1).unwrap();
+        assert_eq!(*buf, *data);
+        assert_eq!(buf.capacity(), 128);
+
+        // datagrams larger than the largest buffer are truncated, and the
+        // rest of them doesn't turn up in the next read
+        client.send(&large).unwrap();
+        client.send(b"next").unwrap();
+        let buf = run(&completion, ReadAdaptive::new(&server, &pool), 2).unwrap();
+        assert_eq!(*buf, large[..256]);
+        let buf = run(&completion, ReadAdaptive::new(&server, &pool), 3).unwrap();
+        assert_eq!(&*buf, b"next");
+
+        completion.deregister(&server).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+// GNU GPL v3 License
+
+//! Blocking `std::io` adaptors over a `Completion`.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::backends;
+use polldough::{CompletionRead, CompletionWrite, Read, SubmissionStatus};
+use std::{
+    fs,
+    io::{Read as _, Seek, SeekFrom, Write as _},
+    os::unix::net::UnixStream,
+    time::Duration,
+};
+
+#[test]
+fn file_round_trip() {
+    let path = std::env::temp_dir().join(format!("polldough-adaptor-{}", std::process::id()));
+
+    for completion in backends() {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        completion.register(&file).unwrap();
+
+        let mut writer = CompletionWrite::new(&completion, &file, 1);
+        writer.write_all(b"hello, world").unwrap();
+        writer.flush().unwrap();
+
+        let mut reader = CompletionRead::new(&completion, &file, 2);
+        reader.seek(SeekFrom::Start(7)).unwrap();
+        let mut buf = String::new();
+        reader.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "world");
+
+        completion.deregister(&file).unwrap();
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn other_events_are_kept() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        let (other_client, other_server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+        completion.register(&other_server).unwrap();
+
+        // this completes while the adaptor waits for its own read
+        let mut other = Box::new(Read::new(&other_server, vec![0u8; 16]));
+        let status = unsafe { completion.submit(&mut *other, 9).unwrap() };
+        assert!(matches!(status, SubmissionStatus::Submitted));
+        (&other_client).write_all(b"other").unwrap();
+        client.write_all(b"mine").unwrap();
+
+        let mut reader = CompletionRead::new(&completion, &server, 1);
+        let mut buf = [0u8; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"mine");
+
+        // the other event is handed out by the next wait
+        let mut events = Vec::new();
+        while events.is_empty() {
+            completion
+                .wait(Some(Duration::from_secs(5)), &mut events)
+                .unwrap();
+        }
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].key, 9);
+        let (n, buf) = unsafe { events.remove(0).complete(*other) }.unwrap();
+        assert_eq!(&buf[..n], b"other");
+
+        completion.deregister(&server).unwrap();
+        completion.deregister(&other_server).unwrap();
+    }
+}
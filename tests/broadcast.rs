@@ -0,0 +1,123 @@
+// GNU GPL v3 License
+
+//! Writing one buffer to several sources.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::backends;
+use polldough::SharedBuf;
+use std::{
+    io::{ErrorKind, Read as _, Write as _},
+    os::unix::net::UnixStream,
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn broadcast_to_every_source() {
+    for completion in backends() {
+        let (clients, servers): (Vec<_>, Vec<_>) =
+            (0..3).map(|_| UnixStream::pair().unwrap()).unzip();
+        for server in &servers {
+            completion.register(server).unwrap();
+        }
+
+        let data: Arc<[u8]> = Arc::from(&b"hello"[..]);
+        let mut handle =
+            unsafe { completion.broadcast(SharedBuf::from(data.clone()), &servers, 10) };
+        assert_eq!(handle.keys(), 10..13);
+
+        let results = handle.wait(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results.iter().all(|result| *result.as_ref().unwrap() == 5));
+        assert!(handle.is_complete());
+        assert!(handle.wait(None).unwrap().is_empty());
+
+        // the writes let go of the data once they're complete
+        drop(handle);
+        assert_eq!(Arc::strong_count(&data), 1);
+
+        for mut client in clients {
+            let mut buf = [0u8; 5];
+            client.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, b"hello");
+        }
+
+        for server in &servers {
+            completion.deregister(server).unwrap();
+        }
+    }
+}
+
+#[test]
+fn broadcast_reports_each_failure() {
+    for completion in backends() {
+        let (clients, servers): (Vec<_>, Vec<_>) =
+            (0..3).map(|_| UnixStream::pair().unwrap()).unzip();
+        for server in &servers {
+            completion.register(server).unwrap();
+        }
+
+        // the peer of the second one hangs up
+        let mut clients = clients.into_iter();
+        let first = clients.next().unwrap();
+        drop(clients.next());
+        let third = clients.next().unwrap();
+
+        let mut handle =
+            unsafe { completion.broadcast(SharedBuf::from(&b"data"[..]), &servers, 0) };
+        let results = handle.wait(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(*results[0].as_ref().unwrap(), 4);
+        assert_eq!(
+            results[1].as_ref().unwrap_err().kind(),
+            ErrorKind::BrokenPipe
+        );
+        assert_eq!(*results[2].as_ref().unwrap(), 4);
+
+        drop((first, third));
+        for server in &servers {
+            completion.deregister(server).unwrap();
+        }
+    }
+}
+
+#[test]
+fn wait_times_out_and_resumes() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        // fill the socket buffer, so that the write has to wait
+        server.set_nonblocking(true).unwrap();
+        let mut filled = 0;
+        loop {
+            match (&server).write(&[0; 4096]) {
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => panic!("{}", e),
+            }
+        }
+
+        let servers = [server];
+        let mut handle =
+            unsafe { completion.broadcast(SharedBuf::from(&b"late"[..]), &servers, 0) };
+        let err = handle.wait(Some(Duration::from_millis(50))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(!handle.is_complete());
+
+        let results = thread::scope(|scope| {
+            scope.spawn(|| {
+                let mut buf = vec![0u8; filled + 4];
+                client.read_exact(&mut buf).unwrap();
+                assert_eq!(&buf[filled..], b"late");
+            });
+            handle.wait(Some(Duration::from_secs(5))).unwrap()
+        });
+        assert_eq!(*results[0].as_ref().unwrap(), 4);
+
+        completion.deregister(&servers[0]).unwrap();
+    }
+}
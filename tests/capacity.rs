@@ -0,0 +1,78 @@
+// GNU GPL v3 License
+
+//! Going past the capacity given to `Completion::new`.
+
+#![cfg(windows)]
+
+use polldough::{Completion, Op, Read, SubmissionStatus};
+use std::{
+    collections::HashMap,
+    io::Write as _,
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+/// Connect a pair of sockets over loopback.
+fn pair(listener: &TcpListener) -> (TcpStream, TcpStream) {
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    (client, server)
+}
+
+#[test]
+fn active_ops_grow_in_chunks() {
+    // each chunk of the active operation list has room for two
+    const CAPACITY: usize = 2;
+    const READS: usize = 7;
+
+    let completion = Completion::new(CAPACITY).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let pairs: Vec<_> = (0..READS).map(|_| pair(&listener)).collect();
+
+    // a second round reuses the slots that the first one freed
+    for _ in 0..2 {
+        let mut reads: Vec<_> = pairs
+            .iter()
+            .map(|(_, server)| Read::new(server, vec![0u8; 16]))
+            .collect();
+        let mut done = HashMap::new();
+        for (key, ((_, server), read)) in pairs.iter().zip(&mut reads).enumerate() {
+            completion.register(server).unwrap();
+            match unsafe { completion.submit(read, key as u64).unwrap() } {
+                SubmissionStatus::AlreadyComplete(result) => {
+                    done.insert(key as u64, result.unwrap());
+                }
+                SubmissionStatus::Submitted => {}
+            }
+        }
+
+        // every read is in flight at once, across several chunks
+        assert_eq!(completion.len_in_flight(), READS - done.len());
+
+        // complete them in reverse, so later chunks empty out first
+        for (key, (client, _)) in pairs.iter().enumerate().rev() {
+            (&*client).write_all(&[key as u8; 4]).unwrap();
+        }
+
+        let mut events = Vec::new();
+        while done.len() < READS {
+            completion
+                .wait(Some(Duration::from_secs(5)), &mut events)
+                .unwrap();
+            for event in events.drain(..) {
+                let previous = done.insert(event.key, event.result.unwrap());
+                assert!(previous.is_none(), "{} completed twice", event.key);
+            }
+        }
+        assert_eq!(completion.len_in_flight(), 0);
+
+        // the events were received, so the reads are done with the buffers
+        for (key, read) in reads.into_iter().enumerate() {
+            let (n, buf) = unsafe { read.complete(done[&(key as u64)]) };
+            assert_eq!(&buf[..n], &[key as u8; 4]);
+        }
+        for (_, server) in &pairs {
+            completion.deregister(server).unwrap();
+        }
+    }
+}
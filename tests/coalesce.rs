@@ -0,0 +1,104 @@
+// GNU GPL v3 License
+
+//! Small writes merged into vectored writes.
+
+#![cfg(unix)]
+
+use polldough::{CompletionBuilder, Event, SubmissionStatus, Write};
+use std::{collections::HashMap, io::Read as _, os::unix::net::UnixStream, thread, time::Duration};
+
+/// The number of writes, and the size of each one.
+const WRITES: usize = 64;
+const LEN: usize = 16 * 1024;
+
+#[test]
+fn short_write_is_shared_out() {
+    // readiness polling writes without blocking, so a merged write larger
+    // than the socket buffer comes up short
+    let completion = CompletionBuilder::new(16)
+        .disable_io_uring()
+        .coalesce_writes(LEN)
+        .build()
+        .unwrap();
+    let (client, mut server) = UnixStream::pair().unwrap();
+    completion.register(&client).unwrap();
+
+    let mut writes: Vec<_> = (0..WRITES)
+        .map(|i| Box::new(Write::new(&client, vec![i as u8; LEN])))
+        .collect();
+    for (key, write) in writes.iter_mut().enumerate() {
+        let status = unsafe { completion.submit(&mut **write, key as u64).unwrap() };
+        assert!(matches!(status, SubmissionStatus::Submitted));
+    }
+
+    // nothing is read yet, so only the writes at the front are reached
+    let mut events = Vec::new();
+    completion
+        .wait(Some(Duration::from_secs(5)), &mut events)
+        .unwrap();
+    assert!(!events.is_empty());
+    assert!(events.len() < WRITES);
+
+    let reader = thread::spawn(move || {
+        let mut data = Vec::new();
+        server.read_to_end(&mut data).unwrap();
+        data
+    });
+
+    // the writes that weren't reached are written once there's room
+    while events.len() < WRITES {
+        completion
+            .wait(Some(Duration::from_secs(5)), &mut events)
+            .unwrap();
+    }
+
+    let written: HashMap<u64, usize> = events
+        .into_iter()
+        .map(|Event { key, result }| (key, result.unwrap()))
+        .collect();
+    assert_eq!(written.len(), WRITES);
+
+    completion.deregister(&client).unwrap();
+    drop(client);
+
+    // every write got the bytes that were actually sent from its buffer,
+    // in order
+    let expected: Vec<u8> = (0..WRITES)
+        .flat_map(|i| vec![i as u8; written[&(i as u64)]])
+        .collect();
+    assert_eq!(reader.join().unwrap(), expected);
+}
+
+#[test]
+fn held_write_wakes_waiter() {
+    let completion = CompletionBuilder::new(16)
+        .coalesce_writes(LEN)
+        .build()
+        .unwrap();
+    let (client, mut server) = UnixStream::pair().unwrap();
+    completion.register(&client).unwrap();
+
+    let mut write = Box::new(Write::new(&client, b"hello".to_vec()));
+    thread::scope(|scope| {
+        // the waiter blocks before there's anything to wait for
+        let waiter = scope.spawn(|| {
+            let mut events = Vec::new();
+            while events.is_empty() {
+                completion.wait(None, &mut events).unwrap();
+            }
+            events
+        });
+        thread::sleep(Duration::from_millis(50));
+
+        unsafe { completion.submit(&mut *write, 1).unwrap() };
+        let events = waiter.join().unwrap();
+        assert_eq!(events[0].key, 1);
+        assert_eq!(*events[0].result.as_ref().unwrap(), 5);
+    });
+
+    let mut buf = [0u8; 5];
+    server.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+
+    completion.deregister(&client).unwrap();
+}
@@ -0,0 +1,67 @@
+// GNU GPL v3 License
+
+//! Fixtures shared by the integration tests.
+
+// every test uses a different part of this
+#![allow(dead_code)]
+
+use polldough::{Completion, CompletionBuilder, Op, SubmissionStatus};
+use std::{io::Result, mem, time::Duration};
+
+/// The default backend, readiness polling, and a thread per operation.
+pub fn backends() -> Vec<Completion> {
+    vec![
+        Completion::new(16).unwrap(),
+        #[cfg(unix)]
+        CompletionBuilder::new(16)
+            .disable_io_uring()
+            .build()
+            .unwrap(),
+        #[cfg(feature = "fallback-threads")]
+        CompletionBuilder::new(16)
+            .fallback_threads()
+            .build()
+            .unwrap(),
+    ]
+}
+
+/// Readiness polling, and a thread per operation.
+///
+/// `io_uring` can't cancel by source.
+pub fn cancellable_backends() -> Vec<Completion> {
+    vec![
+        CompletionBuilder::new(16)
+            .disable_io_uring()
+            .build()
+            .unwrap(),
+        #[cfg(feature = "fallback-threads")]
+        CompletionBuilder::new(16)
+            .fallback_threads()
+            .build()
+            .unwrap(),
+    ]
+}
+
+/// Submit the operation and wait for it to complete.
+pub fn run<O: Op>(completion: &Completion, mut op: O, key: u64) -> Result<O::Output> {
+    match unsafe { completion.submit(&mut op, key)? } {
+        SubmissionStatus::AlreadyComplete(result) => result.map(|n| unsafe { op.complete(n) }),
+        SubmissionStatus::Submitted => {
+            match completion.wait_for_key(key, Some(Duration::from_secs(5))) {
+                Ok(event) => unsafe { event.complete(op) },
+                Err(e) => {
+                    // the operation may still be using its buffers, so it
+                    // can't be dropped before its event arrives
+                    let cancelled = completion
+                        .cancel(key)
+                        .and_then(|()| completion.wait_for_key(key, Some(Duration::from_secs(5))));
+                    if cancelled.is_err() {
+                        mem::forget(op);
+                    }
+
+                    Err(e)
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,7 @@
+// GNU GPL v3 License
+
+#[test]
+fn compile_fail() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,90 @@
+// GNU GPL v3 License
+
+//! Copying ranges between files.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{backends, run};
+use polldough::CopyFileRange;
+use std::{fs, path::PathBuf};
+
+/// A file in the temporary directory, removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str, contents: &[u8]) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("polldough-copy-{}-{}", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        TempFile(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn copy_range() {
+    // larger than the chunks that are copied through a buffer
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let from = TempFile::new("range-from", &data);
+    let to = TempFile::new("range-to", b"");
+
+    for completion in backends() {
+        let src = fs::File::open(&from.0).unwrap();
+        let dst = fs::OpenOptions::new().write(true).open(&to.0).unwrap();
+        dst.set_len(0).unwrap();
+        completion.register(&src).unwrap();
+
+        let mut op = CopyFileRange::new(&src, &dst, 150_000);
+        op.from_offset(10).unwrap().to_offset(5).unwrap();
+        assert_eq!(run(&completion, op, 1).unwrap(), 150_000);
+
+        let copied = fs::read(&to.0).unwrap();
+        assert_eq!(copied.len(), 150_005);
+        assert_eq!(copied[..5], [0; 5]);
+        assert!(copied[5..] == data[10..150_010]);
+
+        completion.deregister(&src).unwrap();
+    }
+}
+
+#[test]
+fn copy_stops_at_end() {
+    let from = TempFile::new("end-from", b"short file");
+    let to = TempFile::new("end-to", b"");
+
+    for completion in backends() {
+        let src = fs::File::open(&from.0).unwrap();
+        let dst = fs::OpenOptions::new().write(true).open(&to.0).unwrap();
+        dst.set_len(0).unwrap();
+        completion.register(&src).unwrap();
+
+        let mut op = CopyFileRange::new(&src, &dst, 100);
+        op.from_offset(6).unwrap();
+        assert_eq!(run(&completion, op, 1).unwrap(), 4);
+        assert_eq!(fs::read(&to.0).unwrap(), b"file");
+
+        // nothing is left past the end
+        let mut op = CopyFileRange::new(&src, &dst, 100);
+        op.from_offset(100).unwrap();
+        assert_eq!(run(&completion, op, 2).unwrap(), 0);
+
+        completion.deregister(&src).unwrap();
+    }
+}
+
+#[test]
+fn offset_out_of_range() {
+    let from = TempFile::new("offset", b"");
+    let src = fs::File::open(&from.0).unwrap();
+
+    let mut op = CopyFileRange::new(&src, &src, 1);
+    let err = op.from_offset(u64::MAX).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+}
@@ -0,0 +1,66 @@
+// GNU GPL v3 License
+
+//! Tuning the builder with environment variables.
+//!
+//! The variables are shared by the whole process, so every test that sets
+//! them lives here, in a single test.
+
+#![cfg(target_os = "linux")]
+
+use polldough::{Backend, CompletionBuilder, Read, SubmissionStatus};
+use std::{env, io::ErrorKind, os::unix::net::UnixStream};
+
+/// The backend that a read on a socket goes through.
+fn socket_backend(builder: &mut CompletionBuilder) -> Backend {
+    let completion = builder.track_pending(true).build().unwrap();
+    let (_client, server) = UnixStream::pair().unwrap();
+    completion.register(&server).unwrap();
+
+    let mut read = Box::new(Read::new(&server, vec![0u8; 16]));
+    let status = unsafe { completion.submit(&mut *read, 1).unwrap() };
+    assert!(matches!(status, SubmissionStatus::Submitted));
+    let backend = completion.debug_lookup(1).unwrap().backend;
+
+    completion.cancel(1).unwrap();
+    completion.wait_for_key(1, None).unwrap();
+    completion.deregister(&server).unwrap();
+    backend
+}
+
+#[test]
+fn no_uring() {
+    for value in ["1", "true", "YES", " on "] {
+        env::set_var("POLLDOUGH_NO_URING", value);
+        let mut builder = CompletionBuilder::from_env(16).unwrap();
+        assert_eq!(socket_backend(&mut builder), Backend::Polling);
+    }
+
+    // switching it off doesn't count as setting it
+    for value in ["0", "false", "no", "off", ""] {
+        env::set_var("POLLDOUGH_NO_URING", value);
+        let mut builder = CompletionBuilder::from_env(16).unwrap();
+        let expected = socket_backend(&mut CompletionBuilder::new(16));
+        assert_eq!(socket_backend(&mut builder), expected);
+    }
+
+    env::set_var("POLLDOUGH_NO_URING", "maybe");
+    let err = CompletionBuilder::from_env(16).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    // only the builder reads it
+    env::set_var("POLLDOUGH_NO_URING", "1");
+    let expected = socket_backend(&mut CompletionBuilder::new(16));
+    env::remove_var("POLLDOUGH_NO_URING");
+    assert_eq!(socket_backend(&mut CompletionBuilder::new(16)), expected);
+
+    // POSIX AIO is switched off the same way
+    for value in ["1", "0", "off", ""] {
+        env::set_var("POLLDOUGH_NO_AIO", value);
+        CompletionBuilder::from_env(16).unwrap();
+    }
+
+    env::set_var("POLLDOUGH_NO_AIO", "maybe");
+    let err = CompletionBuilder::from_env(16).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    env::remove_var("POLLDOUGH_NO_AIO");
+}
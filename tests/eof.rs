@@ -0,0 +1,154 @@
+// GNU GPL v3 License
+
+mod common;
+
+use common::{backends, run};
+use polldough::{CompletionKind, Read, ReadStream, SubmissionStatus, Write};
+use std::{
+    fs,
+    io::{ErrorKind, Write as _},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+fn pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    (client, server)
+}
+
+#[test]
+fn peer_closes_mid_read() {
+    for completion in backends() {
+        let (client, server) = pair();
+        completion.register(&server).unwrap();
+
+        let mut read = ReadStream::new(&server, vec![0u8; 16]);
+        let status = unsafe { completion.submit(&mut read, 1).unwrap() };
+        assert!(matches!(status, SubmissionStatus::Submitted));
+
+        drop(client);
+        let event = completion
+            .wait_for_key(1, Some(Duration::from_secs(5)))
+            .unwrap();
+        let (kind, _) = unsafe { event.complete(read) }.unwrap();
+        assert_eq!(kind, CompletionKind::Eof);
+    }
+}
+
+#[test]
+fn data_then_eof() {
+    for completion in backends() {
+        let (mut client, server) = pair();
+        completion.register(&server).unwrap();
+
+        client.write_all(b"hi").unwrap();
+        drop(client);
+
+        let (kind, buf) = run(&completion, ReadStream::new(&server, vec![0u8; 16]), 1).unwrap();
+        assert_eq!(kind, CompletionKind::Data(2));
+        assert_eq!(&buf[..2], b"hi");
+
+        let (kind, _) = run(&completion, ReadStream::new(&server, vec![0u8; 16]), 2).unwrap();
+        assert_eq!(kind, CompletionKind::Eof);
+    }
+}
+
+#[test]
+fn empty_buffer_is_not_eof() {
+    for completion in backends() {
+        let (mut client, server) = pair();
+        completion.register(&server).unwrap();
+        client.write_all(b"hi").unwrap();
+
+        let (kind, _) = run(&completion, ReadStream::new(&server, Vec::new()), 1).unwrap();
+        assert_eq!(kind, CompletionKind::Data(0));
+    }
+}
+
+#[test]
+fn file_end() {
+    let path = std::env::temp_dir().join(format!("polldough-eof-{}", std::process::id()));
+    fs::write(&path, b"hello").unwrap();
+
+    for completion in backends() {
+        let file = fs::File::open(&path).unwrap();
+        completion.register(&file).unwrap();
+
+        let mut read = ReadStream::new(&file, vec![0u8; 16]);
+        read.offset(5).unwrap();
+        let (kind, _) = run(&completion, read, 1).unwrap();
+        assert_eq!(kind, CompletionKind::Eof);
+
+        completion.deregister(&file).unwrap();
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn exact_read_past_file_end() {
+    let path = std::env::temp_dir().join(format!("polldough-exact-{}", std::process::id()));
+    fs::write(&path, b"hello").unwrap();
+
+    for completion in backends() {
+        let file = fs::File::open(&path).unwrap();
+        completion.register(&file).unwrap();
+
+        let mut read = Read::new(&file, vec![0u8; 5]);
+        read.exact();
+        let (n, buf) = run(&completion, read, 1).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+
+        // the first read comes up short, and the next one hits the end
+        let mut read = Read::new(&file, vec![0u8; 16]);
+        read.exact();
+        let err = run(&completion, read, 2).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        completion.deregister(&file).unwrap();
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn offset_out_of_range() {
+    let path = std::env::temp_dir().join(format!("polldough-offset-{}", std::process::id()));
+    fs::write(&path, b"hello").unwrap();
+    let file = fs::File::open(&path).unwrap();
+
+    // the OS takes signed offsets
+    let mut read = Read::new(&file, vec![0u8; 5]);
+    let err = read.offset(u64::MAX).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    let err = read.offset(i64::MAX as u64 + 1).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    read.offset(i64::MAX as u64).unwrap();
+
+    // the offset from before is kept
+    let mut write = Write::new(&file, vec![0u8; 5]);
+    write.offset(3).unwrap();
+    assert!(write.offset(u64::MAX).is_err());
+    assert_eq!(write.end_position(2), Some(5));
+
+    drop(file);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn exact_read_past_peer_close() {
+    for completion in backends() {
+        let (mut client, server) = pair();
+        completion.register(&server).unwrap();
+
+        client.write_all(b"hi").unwrap();
+        drop(client);
+
+        let mut read = Read::new(&server, vec![0u8; 16]);
+        read.exact();
+        let err = run(&completion, read, 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,137 @@
+// GNU GPL v3 License
+
+//! Registering fixed buffers with `io_uring` one slot at a time.
+
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::run;
+use polldough::{Completion, CompletionBuilder, Read};
+use std::{fs, io::ErrorKind};
+
+/// A `Completion` with two fixed buffer slots, if the kernel has them.
+fn with_slots() -> Option<Completion> {
+    let completion = CompletionBuilder::new(16).fixed_buffers(2).build().unwrap();
+    let capabilities = completion.capabilities();
+    assert_eq!(capabilities.fixed_buffers, capabilities.sparse_buffers);
+
+    if capabilities.fixed_buffers {
+        Some(completion)
+    } else {
+        eprintln!("skipping: no fixed buffer slots");
+        None
+    }
+}
+
+/// Read the file into `buf` through the fixed buffer at `index`.
+fn read_fixed(
+    completion: &Completion,
+    file: &fs::File,
+    buf: Vec<u8>,
+    index: u16,
+) -> std::io::Result<(usize, Vec<u8>)> {
+    let mut read = Read::new(file, buf);
+    read.fixed_buffer(index);
+    run(completion, read, 1)
+}
+
+#[test]
+fn register_read_unregister_reregister() {
+    let completion = match with_slots() {
+        Some(completion) => completion,
+        None => return,
+    };
+
+    let path = std::env::temp_dir().join(format!("polldough-fixed-{}", std::process::id()));
+    fs::write(&path, b"fixed contents").unwrap();
+    let file = fs::File::open(&path).unwrap();
+    completion.register(&file).unwrap();
+
+    // the buffer stays registered while the read owns it, since moving a
+    // `Vec` doesn't move its data
+    let mut buf = vec![0u8; 32];
+    let index = unsafe { completion.register_buffer(&mut buf).unwrap() };
+    let (n, mut buf) = read_fixed(&completion, &file, buf, index).unwrap();
+    assert_eq!(&buf[..n], b"fixed contents");
+
+    // a buffer outside of the slot is refused
+    let err = read_fixed(&completion, &file, vec![0u8; 32], index).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EFAULT));
+
+    // once it's unregistered, the slot is empty
+    completion.unregister_buffer(index).unwrap();
+    let err = completion.unregister_buffer(index).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    buf.fill(0);
+    let err = read_fixed(&completion, &file, buf, index).unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EFAULT));
+
+    // and it can be filled in again
+    let mut buf = vec![0u8; 32];
+    assert_eq!(
+        unsafe { completion.register_buffer(&mut buf).unwrap() },
+        index
+    );
+    let (n, buf) = read_fixed(&completion, &file, buf, index).unwrap();
+    assert_eq!(&buf[..n], b"fixed contents");
+
+    completion.unregister_buffer(index).unwrap();
+    completion.deregister(&file).unwrap();
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn slots_run_out() {
+    let completion = match with_slots() {
+        Some(completion) => completion,
+        None => return,
+    };
+
+    let mut bufs = [vec![0u8; 16], vec![0u8; 16], vec![0u8; 16]];
+    let first = unsafe { completion.register_buffer(&mut bufs[0]).unwrap() };
+    let second = unsafe { completion.register_buffer(&mut bufs[1]).unwrap() };
+    assert_ne!(first, second);
+
+    let err = unsafe { completion.register_buffer(&mut bufs[2]).unwrap_err() };
+    assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+
+    // emptying a slot makes room
+    completion.unregister_buffer(first).unwrap();
+    assert_eq!(
+        unsafe { completion.register_buffer(&mut bufs[2]).unwrap() },
+        first
+    );
+
+    completion.unregister_buffer(first).unwrap();
+    completion.unregister_buffer(second).unwrap();
+}
+
+#[test]
+fn sparse_buffers_without_slots() {
+    if with_slots().is_none() {
+        return;
+    }
+
+    // the kernel is checked even when no slots are set aside
+    let completion = Completion::new(16).unwrap();
+    assert!(completion.capabilities().sparse_buffers);
+    assert!(!completion.capabilities().fixed_buffers);
+
+    let err = unsafe { completion.register_buffer(&mut [0u8; 16]).unwrap_err() };
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn no_slots_without_io_uring() {
+    let completion = CompletionBuilder::new(16)
+        .fixed_buffers(2)
+        .disable_io_uring()
+        .build()
+        .unwrap();
+    assert!(!completion.capabilities().fixed_buffers);
+    assert!(!completion.capabilities().sparse_buffers);
+
+    let err = unsafe { completion.register_buffer(&mut [0u8; 16]).unwrap_err() };
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+}
@@ -0,0 +1,160 @@
+// GNU GPL v3 License
+
+//! Reading delimited and length-prefixed frames.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{backends, run};
+use polldough::{ReadFrame, ReadUntil};
+use std::{
+    io::{ErrorKind, Write as _},
+    os::unix::net::UnixStream,
+    thread,
+    time::Duration,
+};
+
+/// Write `chunks` to the stream one at a time, pausing in between.
+fn trickle(mut stream: &UnixStream, chunks: &[&[u8]]) {
+    for chunk in chunks {
+        thread::sleep(Duration::from_millis(20));
+        stream.write_all(chunk).unwrap();
+    }
+}
+
+/// A length-prefixed frame.
+fn frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = (data.len() as u32).to_be_bytes().to_vec();
+    frame.extend_from_slice(data);
+    frame
+}
+
+#[test]
+fn read_until_across_writes() {
+    for completion in backends() {
+        let (client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        let frame = thread::scope(|scope| {
+            scope.spawn(|| trickle(&client, &[b"hel", b"lo\nwor"]));
+            run(
+                &completion,
+                ReadUntil::new(&server, vec![0u8; 64], b'\n'),
+                1,
+            )
+            .unwrap()
+        });
+        assert_eq!(frame.frame(), b"hello\n");
+        assert_eq!(frame.remainder(), b"wor");
+
+        // the remainder is handed on to the next read
+        let end = frame.end();
+        let mut buf = frame.into_inner();
+        buf.copy_within(end..end + 3, 0);
+        let mut op = ReadUntil::new(&server, buf, b'\n');
+        op.filled(3);
+
+        let frame = thread::scope(|scope| {
+            scope.spawn(|| trickle(&client, &[b"ld\n"]));
+            run(&completion, op, 2).unwrap()
+        });
+        assert_eq!(frame.frame(), b"world\n");
+        assert!(frame.remainder().is_empty());
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn read_until_in_filled_data() {
+    for completion in backends() {
+        let (_client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        // nothing is written, so the frame must come from the buffer
+        let mut buf = vec![0u8; 16];
+        buf[..5].copy_from_slice(b"a\nb\nc");
+        let mut op = ReadUntil::new(&server, buf, b'\n');
+        op.filled(5);
+
+        let frame = run(&completion, op, 1).unwrap();
+        assert_eq!(frame.frame(), b"a\n");
+        assert_eq!(frame.remainder(), b"b\nc");
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn read_until_fails() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        // the buffer fills up before the delimiter comes up
+        client.write_all(b"abcdef\n").unwrap();
+        let err = run(&completion, ReadUntil::new(&server, vec![0u8; 4], b'\n'), 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        completion.deregister(&server).unwrap();
+
+        // the stream ends before the delimiter comes up
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+        client.write_all(b"abc").unwrap();
+        drop(client);
+        let err = run(
+            &completion,
+            ReadUntil::new(&server, vec![0u8; 16], b'\n'),
+            2,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn read_frame_across_writes() {
+    for completion in backends() {
+        let (client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        let mut data = frame(b"hello");
+        data.extend_from_slice(&frame(b"next")[..2]);
+        let frame = thread::scope(|scope| {
+            // split the length prefix too
+            scope.spawn(|| trickle(&client, &[&data[..2], &data[2..6], &data[6..]]));
+            run(&completion, ReadFrame::new(&server, vec![0u8; 64]), 1).unwrap()
+        });
+        assert_eq!(frame.frame(), b"hello");
+        assert_eq!(frame.remainder(), [0, 0]);
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn read_frame_fails() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        // the prefix says the frame won't fit, so it fails right away
+        client.write_all(&frame(&[7; 32])[..8]).unwrap();
+        let err = run(&completion, ReadFrame::new(&server, vec![0u8; 16]), 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        completion.deregister(&server).unwrap();
+
+        // the stream ends in the middle of the frame
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+        client.write_all(&frame(b"cut short")[..6]).unwrap();
+        drop(client);
+        let err = run(&completion, ReadFrame::new(&server, vec![0u8; 16]), 2).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+
+        completion.deregister(&server).unwrap();
+    }
+}
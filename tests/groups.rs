@@ -0,0 +1,106 @@
+// GNU GPL v3 License
+
+//! Registering and cancelling sources in groups.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::cancellable_backends;
+use polldough::{CompletionBuilder, Read, SourceGroup, SubmissionStatus};
+use std::{
+    collections::HashSet,
+    io::{ErrorKind, Write as _},
+    os::unix::net::UnixStream,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn cancel_group() {
+    for completion in cancellable_backends() {
+        let pairs: Vec<_> = (0..4).map(|_| UnixStream::pair().unwrap()).collect();
+        let mut group = SourceGroup::new();
+        for (_, server) in &pairs {
+            group.add(server);
+        }
+        completion.register_group(&group).unwrap();
+
+        let mut reads: Vec<_> = pairs
+            .iter()
+            .map(|(_, server)| Box::new(Read::new(server, vec![0u8; 16])))
+            .collect();
+        for (key, read) in reads.iter_mut().enumerate() {
+            let status = unsafe { completion.submit(&mut **read, key as u64).unwrap() };
+            assert!(matches!(status, SubmissionStatus::Submitted));
+        }
+
+        completion.cancel_group(&group).unwrap();
+        let mut events = Vec::new();
+        while events.len() < reads.len() {
+            completion
+                .wait(Some(Duration::from_secs(5)), &mut events)
+                .unwrap();
+        }
+
+        let keys: HashSet<_> = events.iter().map(|event| event.key).collect();
+        assert_eq!(keys.len(), reads.len());
+        for event in events {
+            assert_eq!(event.result.unwrap_err().kind(), ErrorKind::Interrupted);
+        }
+
+        completion.deregister_group(&group).unwrap();
+    }
+}
+
+#[test]
+fn failed_group_is_rolled_back() {
+    let completion = CompletionBuilder::new(16)
+        .disable_io_uring()
+        .build()
+        .unwrap();
+    let (_client, first) = UnixStream::pair().unwrap();
+    let (_peer, second) = UnixStream::pair().unwrap();
+
+    // the source is registered twice, which fails the second time
+    let mut group = SourceGroup::new();
+    group.add(&first).add(&second).add(&first);
+    let err = completion.register_group(&group).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+
+    // so none of them are registered anymore
+    completion.register(&first).unwrap();
+    completion.register(&second).unwrap();
+    completion.deregister(&first).unwrap();
+    completion.deregister(&second).unwrap();
+}
+
+#[test]
+fn cancel_group_drops_interest() {
+    let completion = CompletionBuilder::new(16)
+        .disable_io_uring()
+        .build()
+        .unwrap();
+    let (mut client, server) = UnixStream::pair().unwrap();
+    let mut group = SourceGroup::new();
+    group.add(&server);
+    completion.register_group(&group).unwrap();
+
+    let mut read = Box::new(Read::new(&server, vec![0u8; 16]));
+    unsafe { completion.submit(&mut *read, 1).unwrap() };
+    completion.cancel_group(&group).unwrap();
+    let event = completion
+        .wait_for_key(1, Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(event.result.unwrap_err().kind(), ErrorKind::Interrupted);
+
+    // nothing waits for the source anymore, so it becoming readable
+    // doesn't wake us up
+    client.write_all(b"hello").unwrap();
+    let timeout = Duration::from_millis(100);
+    let started = Instant::now();
+    let mut events = Vec::new();
+    assert_eq!(completion.wait(Some(timeout), &mut events).unwrap(), 0);
+    assert!(started.elapsed() >= timeout);
+
+    completion.deregister_group(&group).unwrap();
+}
@@ -0,0 +1,66 @@
+// GNU GPL v3 License
+
+//! Keeping count of the operations in flight.
+
+mod common;
+
+use common::backends;
+use polldough::{Event, Nop, SubmissionStatus};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn events_received_before_submit_returns() {
+    const OPS: usize = 4096;
+
+    for completion in backends() {
+        let completion = Arc::new(completion);
+        let stop = Arc::new(AtomicBool::new(false));
+        let received = Arc::new(AtomicUsize::new(0));
+
+        // another thread takes the events as soon as they arrive
+        let waiter = thread::spawn({
+            let (completion, stop, received) = (completion.clone(), stop.clone(), received.clone());
+            move || {
+                let mut events: Vec<Event> = Vec::new();
+                while !stop.load(Ordering::Acquire) {
+                    completion.wait(Some(Duration::ZERO), &mut events).unwrap();
+                    received.fetch_add(events.drain(..).count(), Ordering::AcqRel);
+                }
+            }
+        });
+
+        let mut nops: Vec<_> = (0..OPS).map(|_| Box::new(Nop::new())).collect();
+        let mut submitted = 0;
+        for (key, nop) in nops.iter_mut().enumerate() {
+            match unsafe { completion.submit(&mut **nop, key as u64).unwrap() } {
+                SubmissionStatus::Submitted => submitted += 1,
+                SubmissionStatus::AlreadyComplete(result) => {
+                    result.unwrap();
+                }
+            }
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while received.load(Ordering::Acquire) < submitted {
+            assert!(Instant::now() < deadline, "events went missing");
+            thread::sleep(Duration::from_millis(1));
+        }
+        stop.store(true, Ordering::Release);
+        waiter.join().unwrap();
+
+        // every operation is done, however its event was received
+        assert_eq!(completion.len_in_flight(), 0);
+        let mut events = Vec::new();
+        completion
+            .drain(Some(Duration::from_secs(1)), &mut events)
+            .unwrap();
+        drop(nops);
+    }
+}
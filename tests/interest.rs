@@ -0,0 +1,135 @@
+// GNU GPL v3 License
+
+use polldough::{Completion, CompletionBuilder, Read, SubmissionStatus, Write};
+use std::{
+    collections::HashSet,
+    io::{Read as _, Write as _},
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicBool, Ordering},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+fn pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    (client, server)
+}
+
+fn polling() -> Completion {
+    CompletionBuilder::new(16)
+        .disable_io_uring()
+        .build()
+        .unwrap()
+}
+
+/// Fill the send buffer of `socket` until writing would block.
+fn fill(socket: &TcpStream) {
+    socket.set_nonblocking(true).unwrap();
+    let chunk = [0u8; 65536];
+    while (&*socket).write(&chunk).is_ok() {}
+}
+
+#[test]
+fn read_event_keeps_write_interest() {
+    let completion = polling();
+    let (mut client, server) = pair();
+    completion.register(&server).unwrap();
+    fill(&server);
+
+    // both operations block, one for each direction
+    let mut write = Write::new(&server, vec![1u8; 16]);
+    let mut read = Read::new(&server, vec![0u8; 4]);
+    unsafe {
+        let status = completion.submit(&mut write, 1).unwrap();
+        assert!(matches!(status, SubmissionStatus::Submitted));
+        let status = completion.submit(&mut read, 2).unwrap();
+        assert!(matches!(status, SubmissionStatus::Submitted));
+    }
+
+    // the read completes first, the write is still waiting
+    client.write_all(b"ping").unwrap();
+    let event = completion
+        .wait_for_key(2, Some(Duration::from_secs(5)))
+        .unwrap();
+    event.result.unwrap();
+
+    // draining the client makes room, and the write must still be armed
+    client.set_nonblocking(true).unwrap();
+    let mut sink = vec![0u8; 65536];
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        while matches!(client.read(&mut sink), Ok(n) if n > 0) {}
+
+        let mut events = Vec::new();
+        completion
+            .wait(Some(Duration::from_millis(10)), &mut events)
+            .unwrap();
+        if let Some(event) = events.into_iter().find(|event| event.key == 1) {
+            event.result.unwrap();
+            break;
+        }
+
+        assert!(Instant::now() < deadline, "write never completed");
+    }
+}
+
+#[test]
+fn submit_during_wait() {
+    const OPS: u64 = 200;
+
+    let completion = polling();
+    let (mut client, server) = pair();
+    completion.register(&server).unwrap();
+
+    let mut ops = Vec::new();
+    let done = Mutex::new(HashSet::new());
+    let submitted = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        // keep waiting while the operations are submitted
+        s.spawn(|| {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            let mut events = Vec::new();
+            loop {
+                completion
+                    .wait(Some(Duration::from_millis(1)), &mut events)
+                    .unwrap();
+
+                let mut done = done.lock().unwrap();
+                for event in events.drain(..) {
+                    event.result.unwrap();
+                    done.insert(event.key);
+                }
+                if submitted.load(Ordering::Acquire) && done.len() as u64 == OPS {
+                    break;
+                }
+
+                assert!(Instant::now() < deadline, "operations never completed");
+            }
+        });
+
+        for key in 0..OPS {
+            // each operation reads one of the bytes written below
+            let mut read = Box::new(Read::new(&server, vec![0u8; 1]));
+            match unsafe { completion.submit(&mut *read, key).unwrap() } {
+                SubmissionStatus::AlreadyComplete(result) => {
+                    result.unwrap();
+                    done.lock().unwrap().insert(key);
+                }
+                _ => ops.push(read),
+            }
+
+            if key % 2 == 0 {
+                thread::yield_now();
+            }
+            client.write_all(&[key as u8]).unwrap();
+        }
+
+        submitted.store(true, Ordering::Release);
+    });
+
+    assert_eq!(done.into_inner().unwrap().len() as u64, OPS);
+}
@@ -0,0 +1,134 @@
+// GNU GPL v3 License
+
+//! Receiving and sending batches of datagrams.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{backends, run};
+use polldough::{RecvMMsg, SendMMsg};
+use std::{net::UdpSocket, time::Duration};
+
+/// Bind a socket that gives up on blocking reads after a while.
+fn receiver() -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    socket
+}
+
+/// Receive the next datagram.
+fn recv(socket: &UdpSocket) -> Vec<u8> {
+    let mut buf = [0u8; 64];
+    let len = socket.recv(&mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+#[test]
+fn recv_batch() {
+    for completion in backends() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        completion.register(&server).unwrap();
+
+        let sent: [&[u8]; 3] = [b"one", b"two", b"three"];
+        for datagram in &sent {
+            client.send(datagram).unwrap();
+        }
+
+        // io_uring receives a single datagram at a time, everything else
+        // takes all of them at once
+        let mut received = Vec::new();
+        while received.len() < sent.len() {
+            let bufs = vec![vec![0u8; 16]; 4];
+            let (datagrams, bufs) = run(&completion, RecvMMsg::new(&server, bufs), 1).unwrap();
+            assert!(!datagrams.is_empty());
+
+            for ((len, from), buf) in datagrams.into_iter().zip(bufs) {
+                assert_eq!(from, Some(client.local_addr().unwrap()));
+                received.push(buf[..len].to_vec());
+            }
+        }
+
+        assert_eq!(received, sent);
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn recv_truncates_to_buffer() {
+    for completion in backends() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        completion.register(&server).unwrap();
+
+        client.send(b"longer than the buffer").unwrap();
+        let (datagrams, bufs) =
+            run(&completion, RecvMMsg::new(&server, vec![vec![0u8; 6]]), 1).unwrap();
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].0, 6);
+        assert_eq!(bufs[0], b"longer");
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn send_batch() {
+    for completion in backends() {
+        let server = receiver();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server.local_addr().unwrap()).unwrap();
+        completion.register(&client).unwrap();
+
+        let bufs = vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()];
+        let (sent, bufs) = run(&completion, SendMMsg::new(&client, bufs), 1).unwrap();
+        assert_eq!(sent, [3, 3, 5]);
+        assert_eq!(bufs.len(), 3);
+
+        // the datagrams arrive whole, and in order
+        assert_eq!(recv(&server), b"one");
+        assert_eq!(recv(&server), b"two");
+        assert_eq!(recv(&server), b"three");
+
+        completion.deregister(&client).unwrap();
+    }
+}
+
+#[test]
+fn send_to_each() {
+    for completion in backends() {
+        let first = receiver();
+        let second = receiver();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        completion.register(&client).unwrap();
+
+        let bufs = vec![b"first".to_vec(), b"second".to_vec(), b"again".to_vec()];
+        let mut op = SendMMsg::new(&client, bufs);
+        op.to_each(vec![
+            first.local_addr().unwrap(),
+            second.local_addr().unwrap(),
+            first.local_addr().unwrap(),
+        ]);
+        let (sent, _) = run(&completion, op, 1).unwrap();
+        assert_eq!(sent, [5, 6, 5]);
+
+        assert_eq!(recv(&first), b"first");
+        assert_eq!(recv(&first), b"again");
+        assert_eq!(recv(&second), b"second");
+
+        // an unconnected socket can send everything to one address too
+        let mut op = SendMMsg::new(&client, vec![b"a".to_vec(), b"b".to_vec()]);
+        op.to(second.local_addr().unwrap());
+        let (sent, _) = run(&completion, op, 2).unwrap();
+        assert_eq!(sent, [1, 1]);
+        assert_eq!(recv(&second), b"a");
+        assert_eq!(recv(&second), b"b");
+
+        completion.deregister(&client).unwrap();
+    }
+}
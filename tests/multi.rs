@@ -0,0 +1,142 @@
+// GNU GPL v3 License
+
+//! Waiting on several completions at once.
+
+#![cfg(unix)]
+
+use polldough::{Completion, CompletionBuilder, CompletionSet, Event, Read, SubmissionStatus};
+use std::{
+    io::Write as _,
+    os::unix::net::UnixStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// The default backend and readiness polling.
+///
+/// The thread per operation backend can't be waited on from outside.
+fn backends() -> Vec<Completion> {
+    vec![
+        Completion::new(16).unwrap(),
+        CompletionBuilder::new(16)
+            .disable_io_uring()
+            .build()
+            .unwrap(),
+    ]
+}
+
+/// Wait on the set until some events arrive, or fail after five seconds.
+fn wait_for_events(completions: &[&Completion]) -> Vec<(usize, Event)> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut out = Vec::new();
+
+    // a completion can be woken up without having any events for us
+    while out.is_empty() {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        assert!(timeout > Duration::ZERO, "timed out waiting for events");
+        CompletionSet::wait_any(completions, Some(timeout), &mut out).unwrap();
+    }
+
+    out
+}
+
+#[test]
+fn wait_any_reports_completion() {
+    for (first, second) in backends().into_iter().zip(backends()) {
+        let (mut client1, server1) = UnixStream::pair().unwrap();
+        let (mut client2, server2) = UnixStream::pair().unwrap();
+        first.register(&server1).unwrap();
+        second.register(&server2).unwrap();
+
+        let mut read1 = Read::new(&server1, vec![0u8; 8]);
+        let mut read2 = Read::new(&server2, vec![0u8; 8]);
+        unsafe {
+            assert!(matches!(
+                first.submit(&mut read1, 1).unwrap(),
+                SubmissionStatus::Submitted
+            ));
+            assert!(matches!(
+                second.submit(&mut read2, 2).unwrap(),
+                SubmissionStatus::Submitted
+            ));
+        }
+
+        // the event comes with the index of its completion
+        client2.write_all(b"second").unwrap();
+        let events = wait_for_events(&[&first, &second]);
+        assert_eq!(events.len(), 1);
+        let (index, event) = events.into_iter().next().unwrap();
+        assert_eq!((index, event.key), (1, 2));
+        let (n, buf) = unsafe { event.complete(read2) }.unwrap();
+        assert_eq!(&buf[..n], b"second");
+
+        client1.write_all(b"first").unwrap();
+        let events = wait_for_events(&[&first, &second]);
+        assert_eq!(events.len(), 1);
+        let (index, event) = events.into_iter().next().unwrap();
+        assert_eq!((index, event.key), (0, 1));
+        let (n, buf) = unsafe { event.complete(read1) }.unwrap();
+        assert_eq!(&buf[..n], b"first");
+
+        first.deregister(&server1).unwrap();
+        second.deregister(&server2).unwrap();
+    }
+}
+
+#[test]
+fn wait_any_times_out() {
+    for (first, second) in backends().into_iter().zip(backends()) {
+        let mut out = Vec::new();
+        let start = Instant::now();
+        let count = CompletionSet::wait_any(
+            &[&first, &second],
+            Some(Duration::from_millis(50)),
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(count, 0);
+        assert!(out.is_empty());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+}
+
+#[test]
+fn notify_wakes_wait_any() {
+    for (first, second) in backends().into_iter().zip(backends()) {
+        let start = Instant::now();
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(100));
+                second.notify().unwrap();
+            });
+
+            let mut out = Vec::new();
+            let count =
+                CompletionSet::wait_any(&[&first, &second], Some(Duration::from_secs(5)), &mut out)
+                    .unwrap();
+            assert_eq!(count, 0);
+        });
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
+
+#[cfg(feature = "fallback-threads")]
+#[test]
+fn threads_are_unsupported() {
+    let threads = CompletionBuilder::new(16)
+        .fallback_threads()
+        .build()
+        .unwrap();
+    let polling = CompletionBuilder::new(16)
+        .disable_io_uring()
+        .build()
+        .unwrap();
+
+    let mut out = Vec::new();
+    let err =
+        CompletionSet::wait_any(&[&polling, &threads], Some(Duration::ZERO), &mut out).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+}
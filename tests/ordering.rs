@@ -0,0 +1,170 @@
+// GNU GPL v3 License
+
+use polldough::{Barrier, CompletionBuilder, OrderingMode, Read, SubmissionStatus, Write};
+use std::{
+    io::Write as _,
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+fn pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    (client, server)
+}
+
+/// Wait until `count` events arrived, returning their keys.
+fn keys(completion: &polldough::Completion, count: usize) -> Vec<u64> {
+    let mut events = Vec::new();
+    while events.len() < count {
+        completion
+            .wait(Some(Duration::from_secs(5)), &mut events)
+            .unwrap();
+    }
+
+    events
+        .into_iter()
+        .map(|event| {
+            event.result.unwrap();
+            event.key
+        })
+        .collect()
+}
+
+#[test]
+fn submission_order_per_source() {
+    let completion = CompletionBuilder::new(16)
+        .ordering(OrderingMode::SubmissionOrderPerSource)
+        .build()
+        .unwrap();
+    let (mut client, server) = pair();
+    completion.register(&server).unwrap();
+
+    // the read can't complete until the client writes, but the write can
+    let mut read = Read::new(&server, vec![0u8; 4]);
+    let mut write = Write::new(&server, b"pong".to_vec());
+    unsafe {
+        completion.submit(&mut read, 1).unwrap();
+        let status = completion.submit(&mut write, 2).unwrap();
+        assert!(matches!(status, SubmissionStatus::Submitted));
+    }
+
+    // the write is held back behind the read
+    let mut events = Vec::new();
+    completion
+        .wait(Some(Duration::from_millis(100)), &mut events)
+        .unwrap();
+    assert!(events.is_empty());
+
+    client.write_all(b"ping").unwrap();
+    assert_eq!(keys(&completion, 2), [1, 2]);
+}
+
+#[test]
+fn first_operation_completes_right_away() {
+    let completion = CompletionBuilder::new(16)
+        .ordering(OrderingMode::SubmissionOrderPerSource)
+        .build()
+        .unwrap();
+    let (_client, server) = pair();
+    completion.register(&server).unwrap();
+
+    // nothing is in flight before it, so it isn't held back
+    let mut write = Write::new(&server, b"pong".to_vec());
+    let status = unsafe { completion.submit(&mut write, 1).unwrap() };
+    let done = match status {
+        SubmissionStatus::AlreadyComplete(result) => {
+            result.unwrap();
+            true
+        }
+        SubmissionStatus::Submitted => false,
+    };
+
+    if !done {
+        assert_eq!(keys(&completion, 1), [1]);
+    }
+}
+
+#[test]
+fn unordered_by_default() {
+    let completion = CompletionBuilder::new(16).build().unwrap();
+    let (mut client, server) = pair();
+    completion.register(&server).unwrap();
+
+    let mut read = Read::new(&server, vec![0u8; 4]);
+    let mut write = Write::new(&server, b"pong".to_vec());
+    let write_done = unsafe {
+        completion.submit(&mut read, 1).unwrap();
+        matches!(
+            completion.submit(&mut write, 2).unwrap(),
+            SubmissionStatus::AlreadyComplete(_)
+        )
+    };
+
+    // the write doesn't wait for the read
+    if !write_done {
+        assert_eq!(keys(&completion, 1), [2]);
+    }
+
+    client.write_all(b"ping").unwrap();
+    assert_eq!(keys(&completion, 1), [1]);
+}
+
+#[test]
+fn barrier_holds_back_later_operations() {
+    let completion = CompletionBuilder::new(16).barriers(true).build().unwrap();
+    let (mut client, server) = pair();
+    completion.register(&server).unwrap();
+
+    // the barrier waits for the read, and the write waits for the barrier
+    let mut read = Read::new(&server, vec![0u8; 4]);
+    let mut barrier = Barrier::new(&server);
+    let mut write = Write::new(&server, b"pong".to_vec());
+    unsafe {
+        completion.submit(&mut read, 1).unwrap();
+        let status = completion.submit(&mut barrier, 2).unwrap();
+        assert!(matches!(status, SubmissionStatus::Submitted));
+        let status = completion.submit(&mut write, 3).unwrap();
+        assert!(matches!(status, SubmissionStatus::Submitted));
+    }
+
+    let mut events = Vec::new();
+    completion
+        .wait(Some(Duration::from_millis(100)), &mut events)
+        .unwrap();
+    assert!(events.is_empty());
+
+    client.write_all(b"ping").unwrap();
+    assert_eq!(keys(&completion, 3), [1, 2, 3]);
+}
+
+#[test]
+fn drain_counts_cancelled_held_operation() {
+    let completion = CompletionBuilder::new(16).barriers(true).build().unwrap();
+    let (mut client, server) = pair();
+    completion.register(&server).unwrap();
+
+    let mut read = Read::new(&server, vec![0u8; 4]);
+    let mut barrier = Barrier::new(&server);
+    let mut write = Write::new(&server, b"pong".to_vec());
+    unsafe {
+        completion.submit(&mut read, 1).unwrap();
+        completion.submit(&mut barrier, 2).unwrap();
+        completion.submit(&mut write, 3).unwrap();
+    }
+    assert_eq!(completion.len_in_flight(), 3);
+
+    // the write never reaches the backend, but it's still done
+    completion.cancel(3).unwrap();
+    client.write_all(b"ping").unwrap();
+
+    let mut events = Vec::new();
+    completion
+        .drain(Some(Duration::from_secs(5)), &mut events)
+        .unwrap();
+    let mut keys: Vec<_> = events.iter().map(|event| event.key).collect();
+    keys.sort_unstable();
+    assert_eq!(keys, [1, 2, 3]);
+    assert_eq!(completion.len_in_flight(), 0);
+}
@@ -0,0 +1,126 @@
+// GNU GPL v3 License
+
+//! The typed outputs that operations decode their results into.
+
+mod common;
+
+use common::{backends, run};
+use polldough::{Accept, Custom, CustomFn, CustomOp, Raw, Source, SourceType, SubmissionStatus};
+use std::{
+    io::{Read as _, Write as _},
+    mem,
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[test]
+fn accept() {
+    for completion in backends() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        completion.register(&listener).unwrap();
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, peer) = run(&completion, Accept::new(&listener), 1).unwrap();
+        assert_eq!(peer, client.local_addr().unwrap());
+
+        // the output is the connection itself, not a descriptor to wrap
+        stream.set_nonblocking(false).unwrap();
+        client.write_all(b"hello").unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        completion.deregister(&listener).unwrap();
+    }
+}
+
+#[test]
+fn accept_dropped_without_output() {
+    for completion in backends() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        completion.register(&listener).unwrap();
+
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut accept = Accept::new(&listener);
+        if let SubmissionStatus::Submitted = unsafe { completion.submit(&mut accept, 1).unwrap() } {
+            completion
+                .wait_for_key(1, Some(Duration::from_secs(5)))
+                .unwrap();
+        }
+
+        // the operation still owns the connection, and closes it
+        drop(accept);
+        let mut buf = [0u8; 1];
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+
+        completion.deregister(&listener).unwrap();
+    }
+}
+
+/// Reads with a plain non-blocking read whenever the stream is readable.
+struct PlainRead {
+    source: Raw,
+    stream: TcpStream,
+    data: Arc<Mutex<Vec<u8>>>,
+}
+
+unsafe impl CustomOp for PlainRead {
+    type Output = Vec<u8>;
+
+    const READABLE: bool = true;
+
+    fn source(&self) -> Raw {
+        self.source
+    }
+
+    fn variant(&self) -> SourceType {
+        SourceType::Socket
+    }
+
+    fn polling_function(&mut self) -> CustomFn {
+        let mut stream = self.stream.try_clone().unwrap();
+        let data = self.data.clone();
+        Box::new(move || {
+            let mut buf = [0u8; 16];
+            let n = stream.read(&mut buf)?;
+            data.lock().unwrap().extend_from_slice(&buf[..n]);
+            Ok(n)
+        })
+    }
+
+    fn finish(self, _result: usize) -> Vec<u8> {
+        mem::take(&mut *self.data.lock().unwrap())
+    }
+}
+
+#[test]
+fn custom_polling_function() {
+    for completion in backends() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server.set_nonblocking(true).unwrap();
+        completion.register(&server).unwrap();
+
+        // there's nothing to read yet, so it waits for the socket
+        let mut read = Box::new(Custom::new(PlainRead {
+            source: server.as_raw(),
+            stream: server.try_clone().unwrap(),
+            data: Arc::default(),
+        }));
+        let status = unsafe { completion.submit(&mut *read, 1).unwrap() };
+        assert!(matches!(status, SubmissionStatus::Submitted));
+
+        client.write_all(b"hello").unwrap();
+        let event = completion
+            .wait_for_key(1, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(unsafe { event.complete(*read) }.unwrap(), b"hello");
+
+        completion.deregister(&server).unwrap();
+    }
+}
@@ -0,0 +1,80 @@
+// GNU GPL v3 License
+
+//! Delivering high priority events ahead of normal ones.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::backends;
+use polldough::{Priority, Read, SubmissionStatus};
+use std::{
+    collections::HashSet, io::Write as _, os::unix::net::UnixStream, thread, time::Duration,
+};
+
+#[test]
+fn high_priority_events_come_first() {
+    for completion in backends() {
+        let pairs: Vec<_> = (0..6).map(|_| UnixStream::pair().unwrap()).collect();
+        for (_, server) in &pairs {
+            completion.register(server).unwrap();
+        }
+
+        // the last two reads are urgent, and submitted after the others
+        let mut reads: Vec<_> = pairs
+            .iter()
+            .map(|(_, server)| Read::new(server, vec![0u8; 8]))
+            .collect();
+        for (i, read) in reads.iter_mut().enumerate() {
+            let priority = if i >= 4 {
+                Priority::High
+            } else {
+                Priority::Normal
+            };
+            let status = unsafe { completion.submit_with_priority(read, i as u64, priority) };
+            assert!(matches!(status.unwrap(), SubmissionStatus::Submitted));
+        }
+
+        for (client, _) in &pairs {
+            (&*client).write_all(b"ready").unwrap();
+        }
+        // let every read finish, so that they're delivered together
+        thread::sleep(Duration::from_millis(100));
+
+        let mut events = Vec::new();
+        completion
+            .wait(Some(Duration::from_secs(5)), &mut events)
+            .unwrap();
+        assert_eq!(events.len(), pairs.len());
+
+        let first: HashSet<_> = events[..2].iter().map(|event| event.key).collect();
+        assert_eq!(first, HashSet::from([4, 5]));
+
+        for (_, server) in &pairs {
+            completion.deregister(server).unwrap();
+        }
+    }
+}
+
+#[test]
+fn normal_priority_is_plain_submit() {
+    assert_eq!(Priority::default(), Priority::Normal);
+
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        let mut read = Read::new(&server, vec![0u8; 8]);
+        let status = unsafe { completion.submit_with_priority(&mut read, 1, Priority::Normal) };
+        assert!(matches!(status.unwrap(), SubmissionStatus::Submitted));
+
+        client.write_all(b"normal").unwrap();
+        let event = completion
+            .wait_for_key(1, Some(Duration::from_secs(5)))
+            .unwrap();
+        let (n, buf) = unsafe { event.complete(read) }.unwrap();
+        assert_eq!(&buf[..n], b"normal");
+
+        completion.deregister(&server).unwrap();
+    }
+}
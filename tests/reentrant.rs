@@ -0,0 +1,235 @@
+// GNU GPL v3 License
+
+//! Submitting operations while events are being processed.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::backends;
+use polldough::{
+    Completion, Custom, CustomFn, CustomOp, Op, Raw, Read, SourceType, SubmissionStatus,
+};
+use std::{
+    io::{self, Write as _},
+    os::unix::{io::AsRawFd, net::UnixStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn submit_from_event_loop() {
+    const ROUNDS: u64 = 50;
+
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        // every event submits the next read before the byte it reads is
+        // written
+        let mut read = Read::new(&server, vec![0u8; 1]);
+        let mut key = 0;
+        let mut status = unsafe { completion.submit(&mut read, key).unwrap() };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut events = Vec::new();
+
+        while key < ROUNDS {
+            client.write_all(&[key as u8]).unwrap();
+
+            let result = match status {
+                SubmissionStatus::AlreadyComplete(result) => result,
+                SubmissionStatus::Submitted => loop {
+                    completion
+                        .wait(Some(Duration::from_millis(10)), &mut events)
+                        .unwrap();
+                    if let Some(event) = events.pop() {
+                        assert_eq!(event.key, key);
+                        break event.result;
+                    }
+                    assert!(Instant::now() < deadline, "read never completed");
+                },
+            };
+            let (n, buf) = unsafe { read.complete(result.unwrap()) };
+            assert_eq!(&buf[..n], &[key as u8]);
+
+            key += 1;
+            read = Read::new(&server, vec![0u8; 1]);
+            status = unsafe { completion.submit(&mut read, key).unwrap() };
+        }
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+/// Reads a byte, then submits another one of itself to the same
+/// `Completion`, from inside its polling function.
+struct Nested {
+    source: Raw,
+    depth: u64,
+    completion: Arc<Completion>,
+    /// The nested operations, kept alive until they complete.
+    ///
+    /// They're boxed before they're submitted, so that they don't move.
+    #[allow(clippy::vec_box)]
+    nested: Arc<Mutex<Vec<Box<Custom<Nested>>>>>,
+    /// The keys of nested operations that completed right away.
+    early: Arc<Mutex<Vec<u64>>>,
+}
+
+unsafe impl CustomOp for Nested {
+    type Output = usize;
+
+    const READABLE: bool = true;
+
+    fn source(&self) -> Raw {
+        self.source
+    }
+
+    fn variant(&self) -> SourceType {
+        SourceType::Socket
+    }
+
+    fn polling_function(&mut self) -> CustomFn {
+        let source = self.source;
+        let depth = self.depth;
+        let completion = self.completion.clone();
+        let nested = self.nested.clone();
+        let early = self.early.clone();
+
+        Box::new(move || {
+            let mut byte = 0u8;
+            let n = unsafe { libc::read(source, (&mut byte as *mut u8).cast(), 1) };
+            if n < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            if depth > 0 {
+                let mut op = Box::new(Custom::new(Nested {
+                    source,
+                    depth: depth - 1,
+                    completion: completion.clone(),
+                    nested: nested.clone(),
+                    early: early.clone(),
+                }));
+
+                match unsafe { completion.submit(&mut *op, depth - 1)? } {
+                    SubmissionStatus::AlreadyComplete(result) => {
+                        result?;
+                        early.lock().unwrap().push(depth - 1);
+                    }
+                    SubmissionStatus::Submitted => nested.lock().unwrap().push(op),
+                }
+            }
+
+            Ok(n as usize)
+        })
+    }
+
+    fn finish(self, result: usize) -> usize {
+        result
+    }
+}
+
+#[test]
+fn submit_from_polling_function() {
+    const DEPTH: u64 = 20;
+
+    for completion in backends() {
+        let completion = Arc::new(completion);
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        let nested = Arc::new(Mutex::new(Vec::new()));
+        let early = Arc::new(Mutex::new(Vec::new()));
+        let mut op = Custom::new(Nested {
+            source: server.as_raw_fd(),
+            depth: DEPTH,
+            completion: completion.clone(),
+            nested: nested.clone(),
+            early: early.clone(),
+        });
+        let mut done = Vec::new();
+        if let SubmissionStatus::AlreadyComplete(result) =
+            unsafe { completion.submit(&mut op, DEPTH).unwrap() }
+        {
+            result.unwrap();
+            done.push(DEPTH);
+        }
+
+        // one byte for each operation, written once the one before it
+        // completed
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut events = Vec::new();
+        let mut written = 0;
+        while done.len() as u64 <= DEPTH {
+            if written == done.len() {
+                client.write_all(&[0]).unwrap();
+                written += 1;
+            }
+
+            completion
+                .wait(Some(Duration::from_millis(10)), &mut events)
+                .unwrap();
+            for event in events.drain(..) {
+                assert_eq!(event.result.unwrap(), 1);
+                done.push(event.key);
+            }
+            done.append(&mut early.lock().unwrap());
+
+            assert!(
+                Instant::now() < deadline,
+                "nested operations never completed"
+            );
+        }
+
+        // every level completed once, outermost first
+        assert_eq!(done, (0..=DEPTH).rev().collect::<Vec<_>>());
+        completion.deregister(&server).unwrap();
+        nested.lock().unwrap().clear();
+    }
+}
+
+#[test]
+fn submit_while_waiting() {
+    for completion in backends() {
+        let completion = Arc::new(completion);
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        // another thread is blocked in `wait` with nothing to wait for
+        let waiter = {
+            let completion = completion.clone();
+            thread::spawn(move || {
+                let mut events = Vec::new();
+                while events.is_empty() {
+                    completion
+                        .wait(Some(Duration::from_secs(10)), &mut events)
+                        .unwrap();
+                }
+                events
+            })
+        };
+        thread::sleep(Duration::from_millis(100));
+
+        // which neither blocks submitting nor keeps the operation from
+        // reaching it
+        let started = Instant::now();
+        let mut read = Box::new(Read::new(&server, vec![0u8; 1]));
+        let status = unsafe { completion.submit(&mut *read, 1).unwrap() };
+        assert!(matches!(status, SubmissionStatus::Submitted));
+        client.write_all(&[7]).unwrap();
+
+        let mut events = waiter.join().unwrap();
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "the waiter didn't see the read"
+        );
+        let event = events.remove(0);
+        assert_eq!(event.key, 1);
+        let (n, buf) = unsafe { event.complete(*read) }.unwrap();
+        assert_eq!(&buf[..n], &[7]);
+
+        completion.deregister(&server).unwrap();
+    }
+}
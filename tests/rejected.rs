@@ -0,0 +1,39 @@
+// GNU GPL v3 License
+
+//! Entries that the kernel refuses to take when they're submitted.
+
+#![cfg(all(target_os = "linux", feature = "fault-injection"))]
+
+use polldough::{CompletionBuilder, Nop, SubmissionStatus};
+use std::time::Duration;
+
+#[test]
+fn refused_entry_fails_its_operation() {
+    let completion = CompletionBuilder::new(16)
+        .refuse_io_uring_key(1)
+        .build()
+        .unwrap();
+    if !completion.uses_io_uring() {
+        return;
+    }
+
+    let mut nops: Vec<_> = (0..3).map(|_| Box::new(Nop::new())).collect();
+    for (key, nop) in (1..).zip(&mut nops) {
+        let status = unsafe { completion.submit(&mut **nop, key).unwrap() };
+        assert!(matches!(status, SubmissionStatus::Submitted));
+    }
+
+    // the entry at the head is refused, and the ones behind it still go in
+    let mut events = Vec::new();
+    while events.len() < 3 {
+        completion
+            .wait(Some(Duration::from_secs(5)), &mut events)
+            .unwrap();
+    }
+    events.sort_by_key(|event| event.key);
+
+    let err = events[0].result.as_ref().unwrap_err();
+    assert_eq!(err.raw_os_error(), Some(libc::EINVAL));
+    assert!(events[1].result.is_ok());
+    assert!(events[2].result.is_ok());
+}
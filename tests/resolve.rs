@@ -0,0 +1,64 @@
+// GNU GPL v3 License
+
+//! Resolving host names on another thread.
+
+mod common;
+
+use common::{backends, run};
+use polldough::Resolve;
+use std::{
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[test]
+fn resolve_address() {
+    for completion in backends() {
+        // numeric hosts don't need a name server
+        let addrs = run(&completion, Resolve::new("127.0.0.1", 8080), 1).unwrap();
+        assert_eq!(addrs, [SocketAddr::from((Ipv4Addr::LOCALHOST, 8080))]);
+
+        let addrs = run(&completion, Resolve::new("localhost", 443), 2).unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs
+            .iter()
+            .all(|addr| addr.ip().is_loopback() && addr.port() == 443));
+    }
+}
+
+#[test]
+fn custom_resolver_runs_on_another_thread() {
+    for completion in backends() {
+        let seen = Arc::new(Mutex::new(None));
+        let ip = IpAddr::from([10, 0, 0, 1]);
+
+        let mut op = Resolve::new("example.test", 53);
+        op.resolver({
+            let seen = seen.clone();
+            move |host, port| {
+                *seen.lock().unwrap() = Some((host.to_string(), thread::current().id()));
+                Ok(vec![SocketAddr::new(ip, port)])
+            }
+        });
+
+        let addrs = run(&completion, op, 1).unwrap();
+        assert_eq!(addrs, [SocketAddr::new(ip, 53)]);
+
+        let (host, id) = seen.lock().unwrap().take().unwrap();
+        assert_eq!(host, "example.test");
+        assert_ne!(id, thread::current().id());
+    }
+}
+
+#[test]
+fn resolver_error_fails_operation() {
+    for completion in backends() {
+        let mut op = Resolve::new("example.test", 53);
+        op.resolver(|_, _| Err(io::Error::new(ErrorKind::NotFound, "no such host")));
+
+        let err = run(&completion, op, 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+}
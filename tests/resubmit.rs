@@ -0,0 +1,100 @@
+// GNU GPL v3 License
+
+//! Submitting operations again once they're complete.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::backends;
+use polldough::{Completion, Op, Read, ReadUntil, SubmissionStatus, Write};
+use std::{
+    io::{Read as _, Result, Write as _},
+    os::unix::net::UnixStream,
+    time::Duration,
+};
+
+/// Wait for the event of the operation submitted with `key`, and return
+/// its raw result.
+fn finish(completion: &Completion, status: Result<SubmissionStatus>, key: u64) -> usize {
+    match status.unwrap() {
+        SubmissionStatus::AlreadyComplete(result) => result.unwrap(),
+        SubmissionStatus::Submitted => completion
+            .wait_for_key(key, Some(Duration::from_secs(5)))
+            .unwrap()
+            .result
+            .unwrap(),
+    }
+}
+
+#[test]
+fn resubmit_read() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        let mut read = Read::new(&server, vec![0u8; 16]);
+        let status = unsafe { completion.submit(&mut read, 1) };
+        client.write_all(b"first").unwrap();
+        let n = finish(&completion, status, 1);
+        assert_eq!(unsafe { &read.buf()[..n] }, b"first");
+
+        // the same buffer is read into again
+        let status = unsafe { completion.resubmit(&mut read, 2) };
+        client.write_all(b"again").unwrap();
+        let n = finish(&completion, status, 2);
+        assert_eq!(n, 5);
+
+        let (n, buf) = unsafe { read.complete(n) };
+        assert_eq!(&buf[..n], b"again");
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn resubmit_write() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        let mut write = Write::new(&server, b"twice".to_vec());
+        let status = unsafe { completion.submit(&mut write, 1) };
+        assert_eq!(finish(&completion, status, 1), 5);
+
+        // refill the buffer before sending it again
+        unsafe { write.buf_mut() }.copy_from_slice(b"again");
+        let status = unsafe { completion.resubmit(&mut write, 2) };
+        assert_eq!(finish(&completion, status, 2), 5);
+
+        let mut buf = [0u8; 10];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"twiceagain");
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn resubmit_starts_over() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        let mut read = ReadUntil::new(&server, vec![0u8; 16], b'\n');
+        client.write_all(b"a\n").unwrap();
+        let status = unsafe { completion.submit(&mut read, 1) };
+        assert_eq!(finish(&completion, status, 1), 2);
+
+        // the first frame is forgotten, instead of being found again
+        client.write_all(b"bc\n").unwrap();
+        let status = unsafe { completion.resubmit(&mut read, 2) };
+        let n = finish(&completion, status, 2);
+        assert_eq!(n, 3);
+
+        let frame = unsafe { read.complete(n) };
+        assert_eq!(frame.frame(), b"bc\n");
+
+        completion.deregister(&server).unwrap();
+    }
+}
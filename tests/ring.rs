@@ -0,0 +1,93 @@
+// GNU GPL v3 License
+
+//! Reading into a ring buffer with vectored reads.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{backends, run};
+use polldough::{ReadVectored, RingBuf};
+use std::{io::Write as _, os::unix::net::UnixStream};
+
+/// The data in the ring, in one piece.
+fn contents(ring: &RingBuf) -> Vec<u8> {
+    let (first, second) = ring.as_slices();
+    [first, second].concat()
+}
+
+#[test]
+fn read_into_ring() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        client.write_all(b"hello world").unwrap();
+        let (n, mut ring) =
+            run(&completion, ReadVectored::new(&server, RingBuf::new(64)), 1).unwrap();
+        assert_eq!(n, 11);
+        ring.advance_tail(n);
+        assert_eq!(contents(&ring), b"hello world");
+
+        // the parsed data is dropped from the front, and the next read
+        // goes after the rest
+        ring.advance_head(6);
+        client.write_all(b"!").unwrap();
+        let (n, mut ring) = run(&completion, ReadVectored::new(&server, ring), 2).unwrap();
+        ring.advance_tail(n);
+        assert_eq!(contents(&ring), b"world!");
+
+        ring.clear();
+        assert!(ring.is_empty());
+        assert_eq!(ring.remaining(), ring.capacity());
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn read_wraps_around() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        // leave ten bytes of data right before the end of the buffer
+        let mut ring = RingBuf::new(64);
+        let capacity = ring.capacity();
+        let data: Vec<u8> = (0..capacity).map(|i| i as u8).collect();
+        client.write_all(&data[..capacity - 10]).unwrap();
+        let mut read = 0;
+        while read < capacity - 10 {
+            let (n, filled) = run(&completion, ReadVectored::new(&server, ring), 1).unwrap();
+            ring = filled;
+            ring.advance_tail(n);
+            read += n;
+        }
+        ring.advance_head(capacity - 20);
+
+        // the free space wraps around the end, and the read fills all of it
+        let tail = [0xaa; 30];
+        client.write_all(&tail).unwrap();
+        let (n, mut ring) = run(&completion, ReadVectored::new(&server, ring), 2).unwrap();
+        assert_eq!(n, 30);
+        ring.advance_tail(n);
+
+        let mut expected = data[capacity - 20..capacity - 10].to_vec();
+        expected.extend_from_slice(&tail);
+        assert_eq!(contents(&ring), expected);
+        if ring.is_mirrored() {
+            assert!(ring.as_slices().1.is_empty());
+        }
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+#[should_panic(expected = "only 4 are free")]
+fn advance_past_free_space() {
+    let mut ring = RingBuf::new(4);
+    let capacity = ring.capacity();
+    ring.advance_tail(capacity - 4);
+    ring.advance_tail(5);
+}
@@ -0,0 +1,225 @@
+// GNU GPL v3 License
+
+//! The example servers, driven by real clients on every backend.
+
+#[path = "../examples/echo.rs"]
+#[allow(dead_code)]
+mod echo;
+#[path = "../examples/file_server.rs"]
+#[allow(dead_code)]
+mod file_server;
+
+mod common;
+
+use common::backends;
+use polldough::Completion;
+use std::{
+    fs,
+    io::{Read as _, Write as _},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Run a server on its own thread while `f` talks to it.
+fn with_server(
+    completion: Completion,
+    serve: impl FnOnce(&TcpListener, &Completion, &AtomicBool) -> std::io::Result<()> + Send + 'static,
+    f: impl FnOnce(SocketAddr),
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let server = thread::spawn({
+        let stop = stop.clone();
+        move || serve(&listener, &completion, &stop)
+    });
+
+    f(addr);
+
+    stop.store(true, Ordering::Release);
+    server.join().unwrap().unwrap();
+}
+
+/// The echo server, hanging up on clients that are quiet for `idle`.
+fn echo_server(
+    idle: Duration,
+) -> impl FnOnce(&TcpListener, &Completion, &AtomicBool) -> std::io::Result<()> + Send + 'static {
+    move |listener, completion, stop| echo::serve(listener, completion, idle, stop)
+}
+
+/// Connect to a server, giving up if it doesn't answer in time.
+fn connect(addr: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    stream
+}
+
+#[test]
+fn echo_many_clients() {
+    const CLIENTS: usize = 8;
+    const ROUNDS: usize = 16;
+
+    for completion in backends() {
+        with_server(completion, echo_server(Duration::from_secs(30)), |addr| {
+            let clients = (0..CLIENTS)
+                .map(|i| {
+                    thread::spawn(move || {
+                        let mut stream = connect(addr);
+                        for round in 0..ROUNDS {
+                            let message = format!("client {} round {}", i, round);
+                            stream.write_all(message.as_bytes()).unwrap();
+
+                            let mut echoed = vec![0; message.len()];
+                            stream.read_exact(&mut echoed).unwrap();
+                            assert_eq!(echoed, message.as_bytes());
+                        }
+
+                        // the server hangs up after we do
+                        stream.shutdown(Shutdown::Write).unwrap();
+                        let mut rest = Vec::new();
+                        stream.read_to_end(&mut rest).unwrap();
+                        assert!(rest.is_empty());
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for client in clients {
+                client.join().unwrap();
+            }
+        });
+    }
+}
+
+#[test]
+fn echo_large_message() {
+    for completion in backends() {
+        with_server(completion, echo_server(Duration::from_secs(30)), |addr| {
+            let mut stream = connect(addr);
+            let message = (0..256 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+
+            // write from another thread, so that neither side blocks
+            let writer = thread::spawn({
+                let mut stream = stream.try_clone().unwrap();
+                let message = message.clone();
+                move || stream.write_all(&message).unwrap()
+            });
+
+            let mut echoed = vec![0; message.len()];
+            stream.read_exact(&mut echoed).unwrap();
+            assert!(echoed == message);
+            writer.join().unwrap();
+        });
+    }
+}
+
+#[test]
+fn echo_hangs_up_on_idle_client() {
+    let idle = Duration::from_millis(200);
+
+    for completion in backends() {
+        with_server(completion, echo_server(idle), |addr| {
+            let mut stream = connect(addr);
+            stream.write_all(b"hello").unwrap();
+            let mut echoed = [0; 5];
+            stream.read_exact(&mut echoed).unwrap();
+            assert_eq!(&echoed, b"hello");
+
+            // then say nothing, until the server hangs up
+            let started = Instant::now();
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).unwrap();
+            assert!(rest.is_empty());
+            assert!(started.elapsed() >= idle / 2);
+        });
+    }
+}
+
+/// A directory with a couple of files in it.
+struct Root(PathBuf);
+
+impl Root {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("polldough-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("hello.txt"), b"Hello, world!\n").unwrap();
+        fs::write(
+            path.join("large.bin"),
+            (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>(),
+        )
+        .unwrap();
+        Root(path)
+    }
+}
+
+impl Drop for Root {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Ask the file server for `path`, returning the status line and the body.
+fn get(addr: SocketAddr, path: &str) -> (String, Vec<u8>) {
+    let mut stream = connect(addr);
+    // the server only looks at what arrives with the connection
+    let request = format!("GET {} HTTP/1.0\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+
+    let end = response
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .expect("no end of header");
+    let header = String::from_utf8(response[..end].to_vec()).unwrap();
+    let body = response[end + 4..].to_vec();
+
+    let status = header.lines().next().unwrap().to_string();
+    let len = header
+        .lines()
+        .find_map(|line| line.strip_prefix("Content-Length: "))
+        .expect("no content length")
+        .parse::<usize>()
+        .unwrap();
+    assert_eq!(len, body.len());
+
+    (status, body)
+}
+
+#[test]
+fn file_server() {
+    let root = Root::new("file-server");
+
+    for completion in backends() {
+        let dir = root.0.clone();
+        let serve = move |listener: &TcpListener, completion: &Completion, stop: &AtomicBool| {
+            file_server::serve(listener, completion, &dir, stop)
+        };
+
+        with_server(completion, serve, |addr| {
+            let (status, body) = get(addr, "/hello.txt");
+            assert_eq!(status, "HTTP/1.0 200 OK");
+            assert_eq!(body, b"Hello, world!\n");
+
+            let (status, body) = get(addr, "/large.bin");
+            assert_eq!(status, "HTTP/1.0 200 OK");
+            assert_eq!(body, fs::read(root.0.join("large.bin")).unwrap());
+
+            let (status, _) = get(addr, "/missing.txt");
+            assert_eq!(status, "HTTP/1.0 404 Not Found");
+
+            // nothing outside of the root
+            let (status, _) = get(addr, "/../Cargo.toml");
+            assert_eq!(status, "HTTP/1.0 404 Not Found");
+        });
+    }
+}
@@ -0,0 +1,94 @@
+// GNU GPL v3 License
+
+//! Waiting on a set of operations.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::backends;
+use polldough::{OpSet, Read, SubmissionStatus};
+use std::{
+    collections::HashSet,
+    io::{ErrorKind, Write as _},
+    os::unix::net::UnixStream,
+    time::Duration,
+};
+
+#[test]
+fn wait_all() {
+    for completion in backends() {
+        let pairs: Vec<_> = (0..4).map(|_| UnixStream::pair().unwrap()).collect();
+        for (_, server) in &pairs {
+            completion.register(server).unwrap();
+        }
+
+        let mut set = unsafe { OpSet::new(&completion, 10) };
+        let keys: Vec<_> = pairs
+            .iter()
+            .map(|(_, server)| set.submit(Read::new(server, vec![0u8; 16])).unwrap())
+            .collect();
+        assert_eq!(keys, [10, 11, 12, 13]);
+        assert_eq!(set.len(), 4);
+
+        // nothing has been written yet
+        let err = set.wait_all(Some(Duration::from_millis(50))).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert_eq!(set.len(), 4);
+
+        for (i, (client, _)) in pairs.iter().enumerate().rev() {
+            (&*client).write_all(&[i as u8]).unwrap();
+        }
+
+        let done = set.wait_all(Some(Duration::from_secs(5))).unwrap();
+        assert!(set.is_empty());
+        let mut seen = HashSet::new();
+        for (key, output) in done {
+            let (n, buf) = output.unwrap();
+            assert_eq!(&buf[..n], &[(key - 10) as u8]);
+            assert!(seen.insert(key));
+        }
+        assert_eq!(seen.len(), 4);
+
+        for (_, server) in &pairs {
+            completion.deregister(server).unwrap();
+        }
+    }
+}
+
+#[test]
+fn wait_any_leaves_other_events() {
+    for completion in backends() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (other_client, other_server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+        completion.register(&other_server).unwrap();
+
+        // an operation outside of the set completes first
+        let mut other = Box::new(Read::new(&other_server, vec![0u8; 16]));
+        let status = unsafe { completion.submit(&mut *other, 1).unwrap() };
+        assert!(matches!(status, SubmissionStatus::Submitted));
+        (&other_client).write_all(b"other").unwrap();
+
+        let mut set = unsafe { OpSet::new(&completion, 10) };
+        assert!(set.wait_any(Some(Duration::ZERO)).unwrap().is_none());
+        let key = set.submit(Read::new(&server, vec![0u8; 16])).unwrap();
+        (&client).write_all(b"set").unwrap();
+
+        let (done, output) = set.wait_any(Some(Duration::from_secs(5))).unwrap().unwrap();
+        assert_eq!(done, key);
+        let (n, buf) = output.unwrap();
+        assert_eq!(&buf[..n], b"set");
+        assert!(set.wait_any(None).unwrap().is_none());
+
+        // the other event is still there for whoever asks for it
+        let event = completion
+            .wait_for_key(1, Some(Duration::from_secs(5)))
+            .unwrap();
+        let (n, buf) = unsafe { event.complete(*other) }.unwrap();
+        assert_eq!(&buf[..n], b"other");
+
+        completion.deregister(&server).unwrap();
+        completion.deregister(&other_server).unwrap();
+    }
+}
@@ -0,0 +1,51 @@
+// GNU GPL v3 License
+
+//! Timestamping events as they're received.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::backends;
+use polldough::{Read, SubmissionStatus};
+use std::{
+    io::Write as _,
+    os::unix::net::UnixStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn events_are_stamped_when_received() {
+    for completion in backends() {
+        let (client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        let mut read = Box::new(Read::new(&server, vec![0u8; 16]));
+        let status = unsafe { completion.submit(&mut *read, 7).unwrap() };
+        assert!(matches!(status, SubmissionStatus::Submitted));
+
+        thread::sleep(Duration::from_millis(50));
+        let written = Instant::now();
+        (&client).write_all(b"hello").unwrap();
+
+        let mut events = Vec::new();
+        while events.is_empty() {
+            completion
+                .wait_timed(Some(Duration::from_secs(5)), &mut events)
+                .unwrap();
+        }
+        let waited = Instant::now();
+
+        assert_eq!(events.len(), 1);
+        let timed = events.pop().unwrap();
+        assert_eq!(timed.event.key, 7);
+        assert_eq!(timed.event.result.unwrap(), 5);
+        assert!(timed.timestamp >= written);
+        assert!(timed.timestamp <= waited);
+
+        // the event was received, so the read is done with the buffer
+        drop(read);
+        completion.deregister(&server).unwrap();
+    }
+}
@@ -0,0 +1,75 @@
+// GNU GPL v3 License
+
+//! Reading and writing right away, before submitting.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::backends;
+use polldough::{OpHandle, TryIo};
+use std::{
+    io::{Read as _, Write as _},
+    os::unix::net::UnixStream,
+};
+
+#[test]
+fn try_read() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        // nothing is there yet, so the read is handed back
+        let read = match completion.try_read(&server, vec![0u8; 16]).unwrap() {
+            TryIo::WouldBlock(read) => read,
+            TryIo::Done(_) => panic!("read without any data"),
+        };
+        let handle = unsafe { OpHandle::submit(&completion, read, 1).unwrap() };
+        client.write_all(b"later").unwrap();
+        let (n, buf) = handle.output().unwrap();
+        assert_eq!(&buf[..n], b"later");
+
+        // now it's waiting
+        client.write_all(b"now").unwrap();
+        match completion.try_read(&server, vec![0u8; 16]).unwrap() {
+            TryIo::Done((n, buf)) => assert_eq!(&buf[..n], b"now"),
+            TryIo::WouldBlock(_) => panic!("data was waiting"),
+        }
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn try_write() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        match completion.try_write(&server, b"now".to_vec()).unwrap() {
+            TryIo::Done((n, _)) => assert_eq!(n, 3),
+            TryIo::WouldBlock(_) => panic!("there was room"),
+        }
+        let mut buf = [0u8; 3];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"now");
+
+        // fill the socket up until a write is handed back
+        let chunk = vec![0u8; 64 * 1024];
+        let mut written = 0;
+        let write = loop {
+            match completion.try_write(&server, chunk.clone()).unwrap() {
+                TryIo::Done((n, _)) => written += n,
+                TryIo::WouldBlock(write) => break write,
+            }
+        };
+
+        let handle = unsafe { OpHandle::submit(&completion, write, 1).unwrap() };
+        let mut drained = vec![0u8; written];
+        client.read_exact(&mut drained).unwrap();
+        let (n, _) = handle.output().unwrap();
+        assert!(n > 0);
+
+        completion.deregister(&server).unwrap();
+    }
+}
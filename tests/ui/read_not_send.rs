@@ -0,0 +1,21 @@
+// GNU GPL v3 License
+
+// the buffer is used from the blocking pool, so it must be `Send`
+
+use polldough::{Buf, BufMut, Read};
+use std::{net::UdpSocket, ptr::NonNull, rc::Rc};
+
+struct Shared(Rc<[u8; 16]>);
+
+unsafe impl Buf for Shared {
+    fn pointer(&self) -> NonNull<[u8]> {
+        NonNull::from(&self.0[..])
+    }
+}
+
+unsafe impl BufMut for Shared {}
+
+fn main() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let _read = Read::new(&socket, Shared(Rc::new([0; 16])));
+}
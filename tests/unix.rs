@@ -0,0 +1,792 @@
+// GNU GPL v3 License
+
+//! Sources that every Unix platform has, on every backend.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{backends, cancellable_backends, run};
+use polldough::{
+    fs::OpenOptions, CompletionBuilder, Custom, CustomFn, CustomOp, Hints, Op, OpHandle, Raw, Read,
+    Source, SourceType, SubmissionStatus, Write,
+};
+use std::{
+    fs,
+    io::{ErrorKind, Read as _, Write as _},
+    os::unix::{
+        io::AsRawFd,
+        net::{UnixDatagram, UnixStream},
+    },
+    process::{ChildStdout, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn unix_stream() {
+    for completion in backends() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        // the read is in flight before there's anything to read
+        let mut read = Read::new(&server, vec![0u8; 16]);
+        let status = unsafe { completion.submit(&mut read, 1).unwrap() };
+
+        client.write_all(b"hello").unwrap();
+        let (n, buf) = match status {
+            SubmissionStatus::AlreadyComplete(result) => unsafe { read.complete(result.unwrap()) },
+            SubmissionStatus::Submitted => {
+                let event = completion
+                    .wait_for_key(1, Some(Duration::from_secs(5)))
+                    .unwrap();
+                unsafe { event.complete(read) }.unwrap()
+            }
+        };
+        assert_eq!(&buf[..n], b"hello");
+
+        let (n, _) = run(&completion, Write::new(&server, b"world".to_vec()), 2).unwrap();
+        assert_eq!(n, 5);
+        let mut buf = [0u8; 5];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"world");
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn unix_datagram() {
+    for completion in backends() {
+        let (a, b) = UnixDatagram::pair().unwrap();
+        completion.register(&b).unwrap();
+
+        a.send(b"ping").unwrap();
+        let (n, buf) = run(&completion, Read::new(&b, vec![0u8; 16]), 1).unwrap();
+        assert_eq!(&buf[..n], b"ping");
+
+        completion.deregister(&b).unwrap();
+    }
+}
+
+#[test]
+fn regular_file() {
+    let path = std::env::temp_dir().join(format!("polldough-unix-{}", std::process::id()));
+    fs::write(&path, b"file contents").unwrap();
+
+    for completion in backends() {
+        let file = fs::File::open(&path).unwrap();
+        completion.register(&file).unwrap();
+
+        let (n, buf) = run(&completion, Read::new(&file, vec![0u8; 32]), 1).unwrap();
+        assert_eq!(&buf[..n], b"file contents");
+
+        completion.deregister(&file).unwrap();
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn file_with_hints() {
+    let path = std::env::temp_dir().join(format!("polldough-hints-{}", std::process::id()));
+    let contents = (0..64 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    fs::write(&path, &contents).unwrap();
+
+    let hints = Hints {
+        sequential: true,
+        size_hint: Some(contents.len() as u64),
+        ..Hints::default()
+    };
+    let mut completions = backends();
+    completions.push(CompletionBuilder::new(16).fixed_files(4).build().unwrap());
+
+    for completion in completions {
+        let file = fs::File::open(&path).unwrap();
+        completion.register_with_hints(&file, &hints).unwrap();
+
+        // read it from start to end, like the hints say
+        let mut read = Vec::new();
+        while read.len() < contents.len() {
+            let mut op = Read::new(&file, vec![0u8; 4096]);
+            op.offset(read.len() as u64).unwrap();
+            let (n, buf) = run(&completion, op, 1).unwrap();
+            assert!(n > 0);
+            read.extend_from_slice(&buf[..n]);
+        }
+        assert!(read == contents);
+
+        // the slot is free for the next file
+        completion.deregister(&file).unwrap();
+        let file = fs::File::open(&path).unwrap();
+        completion.register_with_hints(&file, &hints).unwrap();
+        let (n, buf) = run(&completion, Read::new(&file, vec![0u8; 16]), 2).unwrap();
+        assert_eq!(&buf[..n], &contents[..16]);
+        completion.deregister(&file).unwrap();
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fixed_file_closed_without_deregister() {
+    let dir = std::env::temp_dir();
+    let old_path = dir.join(format!("polldough-closed-old-{}", std::process::id()));
+    let new_path = dir.join(format!("polldough-closed-new-{}", std::process::id()));
+    fs::write(&old_path, b"old file").unwrap();
+    fs::write(&new_path, b"new file").unwrap();
+
+    let completion = CompletionBuilder::new(16).fixed_files(4).build().unwrap();
+    let old = fs::File::open(&old_path).unwrap();
+    completion
+        .register_with_hints(&old, &Hints::default())
+        .unwrap();
+    let fd = old.as_raw_fd();
+    drop(old);
+
+    // the new file gets the same descriptor, and registering it takes the
+    // old one out of its slot
+    let new = fs::File::open(&new_path).unwrap();
+    assert_eq!(new.as_raw_fd(), fd);
+    completion.register(&new).unwrap();
+    let (n, buf) = run(&completion, Read::new(&new, vec![0u8; 16]), 1).unwrap();
+    assert_eq!(&buf[..n], b"new file");
+    completion.deregister(&new).unwrap();
+
+    fs::remove_file(&old_path).unwrap();
+    fs::remove_file(&new_path).unwrap();
+}
+
+/// A pipe passed off as a file, which the OS won't take hints for.
+struct NotAFile(ChildStdout);
+
+unsafe impl Source for NotAFile {
+    const SOURCE_TYPE: SourceType = SourceType::File;
+
+    fn as_raw(&self) -> Raw {
+        self.0.as_raw_fd()
+    }
+}
+
+#[test]
+fn refused_hints_deregister() {
+    let hints = Hints {
+        sequential: true,
+        ..Hints::default()
+    };
+
+    for completion in backends() {
+        let mut child = Command::new("true").stdout(Stdio::piped()).spawn().unwrap();
+        let source = NotAFile(child.stdout.take().unwrap());
+        let err = completion.register_with_hints(&source, &hints).unwrap_err();
+        assert!(err.raw_os_error().is_some());
+
+        // it isn't left registered
+        completion.register(&source).unwrap();
+        completion.deregister(&source).unwrap();
+        child.wait().unwrap();
+    }
+}
+
+#[test]
+fn child_pipe() {
+    for completion in backends() {
+        let mut child = Command::new("echo")
+            .arg("piped")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        completion.register(&stdout).unwrap();
+
+        let (n, buf) = run(&completion, Read::new(&stdout, vec![0u8; 16]), 1).unwrap();
+        assert_eq!(&buf[..n], b"piped\n");
+
+        completion.deregister(&stdout).unwrap();
+        child.wait().unwrap();
+    }
+}
+
+/// Tell whether standard input is in non-blocking mode.
+fn stdin_nonblocking() -> bool {
+    let flags = unsafe { libc::fcntl(libc::STDIN_FILENO, libc::F_GETFL) };
+    assert_ne!(flags, -1);
+    flags & libc::O_NONBLOCK != 0
+}
+
+#[test]
+fn inherited_stdin() {
+    let path = std::env::temp_dir().join(format!("polldough-stdin-{}", std::process::id()));
+    fs::write(&path, b"from a file").unwrap();
+    let file = fs::File::open(&path).unwrap();
+    let saved = unsafe { libc::dup(libc::STDIN_FILENO) };
+    assert_ne!(saved, -1);
+    let stdin = std::io::stdin();
+
+    for completion in backends() {
+        // a socket is left blocking, and still read
+        let (mut client, server) = UnixStream::pair().unwrap();
+        assert_ne!(
+            unsafe { libc::dup2(server.as_raw_fd(), libc::STDIN_FILENO) },
+            -1
+        );
+        completion.register(&stdin).unwrap();
+        assert!(!stdin_nonblocking());
+
+        let mut read = Box::new(Read::new(&stdin, vec![0u8; 16]));
+        let status = unsafe { completion.submit(&mut *read, 1).unwrap() };
+        client.write_all(b"from a socket").unwrap();
+        let (n, buf) = match status {
+            SubmissionStatus::AlreadyComplete(result) => unsafe { read.complete(result.unwrap()) },
+            SubmissionStatus::Submitted => {
+                let event = completion
+                    .wait_for_key(1, Some(Duration::from_secs(5)))
+                    .unwrap();
+                unsafe { event.complete(*read) }.unwrap()
+            }
+        };
+        assert_eq!(&buf[..n], b"from a socket");
+        completion.deregister(&stdin).unwrap();
+
+        // a file, which can't be polled for readiness
+        assert_ne!(
+            unsafe { libc::dup2(file.as_raw_fd(), libc::STDIN_FILENO) },
+            -1
+        );
+        assert_eq!(
+            unsafe { libc::lseek(file.as_raw_fd(), 0, libc::SEEK_SET) },
+            0
+        );
+        completion.register(&stdin).unwrap();
+        let (n, buf) = run(&completion, Read::new(&stdin, vec![0u8; 16]), 2).unwrap();
+        assert_eq!(&buf[..n], b"from a file");
+        completion.deregister(&stdin).unwrap();
+    }
+
+    // unless it's asked for
+    let completion = CompletionBuilder::new(16)
+        .disable_io_uring()
+        .nonblocking_stdio(true)
+        .build()
+        .unwrap();
+    let (_client, server) = UnixStream::pair().unwrap();
+    assert_ne!(
+        unsafe { libc::dup2(server.as_raw_fd(), libc::STDIN_FILENO) },
+        -1
+    );
+    completion.register(&stdin).unwrap();
+    assert!(stdin_nonblocking());
+    completion.deregister(&stdin).unwrap();
+    assert!(!stdin_nonblocking());
+
+    assert_ne!(unsafe { libc::dup2(saved, libc::STDIN_FILENO) }, -1);
+    unsafe { libc::close(saved) };
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn drop_restores_flags() {
+    let nonblocking = |fd| {
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+        assert_ne!(flags, -1);
+        flags & libc::O_NONBLOCK != 0
+    };
+
+    for completion in cancellable_backends() {
+        let (_client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+        assert!(nonblocking(server.as_raw_fd()));
+
+        // the source is still registered when the completion goes away
+        drop(completion);
+        assert!(!nonblocking(server.as_raw_fd()));
+    }
+}
+
+#[test]
+fn busy_poll() {
+    let path = std::env::temp_dir().join(format!("polldough-busy-{}", std::process::id()));
+    fs::write(&path, b"spun").unwrap();
+    let file = fs::File::open(&path).unwrap();
+
+    for io_uring in [true, false] {
+        let mut builder = CompletionBuilder::new(16);
+        builder.busy_poll(Duration::from_millis(20));
+        if !io_uring {
+            builder.disable_io_uring();
+        }
+        let completion = builder.build().unwrap();
+
+        // nothing comes in, so it spins and then sleeps until the timeout
+        let start = Instant::now();
+        let mut events = Vec::new();
+        let count = completion
+            .wait(Some(Duration::from_millis(50)), &mut events)
+            .unwrap();
+        assert_eq!(count, 0);
+        assert!(events.is_empty());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(5));
+            client.write_all(b"while spinning").unwrap();
+            client
+        });
+        let (n, buf) = run(&completion, Read::new(&server, vec![0u8; 32]), 1).unwrap();
+        assert_eq!(&buf[..n], b"while spinning");
+        writer.join().unwrap();
+        completion.deregister(&server).unwrap();
+
+        completion.register(&file).unwrap();
+        let (n, buf) = run(&completion, Read::new(&file, vec![0u8; 16]), 2).unwrap();
+        assert_eq!(&buf[..n], b"spun");
+        completion.deregister(&file).unwrap();
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn shrink_to_fit() {
+    for completion in backends() {
+        let pairs: Vec<_> = (0..64).map(|_| UnixStream::pair().unwrap()).collect();
+        for (_, server) in &pairs {
+            completion.register(server).unwrap();
+        }
+
+        // the read waits on a source that moves when the gaps are filled
+        let (client, server) = &pairs[60];
+        let mut read = Read::new(server, vec![0u8; 16]);
+        let status = unsafe { completion.submit(&mut read, 1).unwrap() };
+
+        for (_, server) in &pairs[..56] {
+            completion.deregister(server).unwrap();
+        }
+        completion.shrink_to_fit().unwrap();
+
+        (&*client).write_all(b"still here").unwrap();
+        let (n, buf) = match status {
+            SubmissionStatus::AlreadyComplete(result) => unsafe { read.complete(result.unwrap()) },
+            SubmissionStatus::Submitted => {
+                let event = completion
+                    .wait_for_key(1, Some(Duration::from_secs(5)))
+                    .unwrap();
+                unsafe { event.complete(read) }.unwrap()
+            }
+        };
+        assert_eq!(&buf[..n], b"still here");
+
+        for (_, server) in &pairs[56..] {
+            completion.deregister(server).unwrap();
+        }
+    }
+}
+
+#[test]
+fn reserve_is_relative() {
+    let completion = CompletionBuilder::new(16)
+        .disable_io_uring()
+        .build()
+        .unwrap();
+    let pairs: Vec<_> = (0..8).map(|_| UnixStream::pair().unwrap()).collect();
+    for (_, server) in &pairs {
+        completion.register(server).unwrap();
+    }
+
+    // there's room for 8 more already
+    completion.reserve(8).unwrap();
+    assert_eq!(completion.capacity(), 16);
+    completion.reserve(8).unwrap();
+    assert_eq!(completion.capacity(), 16);
+
+    // and this is on top of the 8 that are registered
+    completion.reserve(24).unwrap();
+    assert_eq!(completion.capacity(), 32);
+
+    for (_, server) in &pairs {
+        completion.deregister(server).unwrap();
+    }
+}
+
+#[test]
+fn recover_cancelled_read() {
+    for completion in backends() {
+        let (_client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        // nothing is ever written, so the read only ends when cancelled
+        let mut buf = vec![0u8; 16];
+        buf[0] = 42;
+        let handle = unsafe { OpHandle::submit(&completion, Read::new(&server, buf), 1).unwrap() };
+        assert!(!handle.is_complete());
+
+        let buf = handle.recover().unwrap();
+        assert_eq!(buf.len(), 16);
+        assert_eq!(buf[0], 42);
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn watchdog_keeps_waiting() {
+    let completion = CompletionBuilder::new(16)
+        .watchdog(Duration::from_millis(20))
+        .build()
+        .unwrap();
+    let (_client, server) = UnixStream::pair().unwrap();
+    completion.register(&server).unwrap();
+
+    let mut read = Read::new(&server, vec![0u8; 16]);
+    unsafe { completion.submit(&mut read, 1).unwrap() };
+
+    // the watchdog wakes up to flag the read, but has no event to deliver
+    let started = Instant::now();
+    let mut events = Vec::new();
+    let count = completion
+        .wait(Some(Duration::from_millis(200)), &mut events)
+        .unwrap();
+    assert_eq!(count, 0);
+    assert!(started.elapsed() >= Duration::from_millis(200));
+
+    completion.cancel(1).unwrap();
+    completion
+        .wait_for_key(1, Some(Duration::from_secs(5)))
+        .unwrap();
+    completion.deregister(&server).unwrap();
+}
+
+#[test]
+fn completion_unparks_thread() {
+    let hybrid = CompletionBuilder::new(16).hybrid(true).build().unwrap();
+    for completion in backends().into_iter().chain(Some(hybrid)) {
+        if let Err(e) = completion.unpark_on_notify(Some(thread::current())) {
+            // the thread per operation backend can't be watched
+            assert_eq!(e.kind(), ErrorKind::Unsupported);
+            continue;
+        }
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+        let mut read = Box::new(Read::new(&server, vec![0u8; 16]));
+        let status = unsafe { completion.submit(&mut *read, 1).unwrap() };
+        assert!(matches!(status, SubmissionStatus::Submitted));
+
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(2));
+            client.write_all(b"hello").unwrap();
+            client
+        });
+
+        // nothing calls notify, so only the read completing unparks us
+        let started = Instant::now();
+        let mut events = Vec::new();
+        while events.is_empty() {
+            thread::park_timeout(Duration::from_secs(10));
+            assert!(started.elapsed() < Duration::from_secs(5));
+            completion.harvest_nonblocking(&mut events).unwrap();
+        }
+        assert_eq!(events[0].key, 1);
+        let (n, buf) = unsafe { events.remove(0).complete(*read) }.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+
+        completion.unpark_on_notify(None).unwrap();
+        drop(writer.join().unwrap());
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "unstable-uring"))]
+#[test]
+fn adopted_ring_must_be_fresh() {
+    let fresh = io_uring::IoUring::new(16).unwrap();
+    let completion = unsafe { CompletionBuilder::new(16).build_from_uring(fresh) }.unwrap();
+    let (mut client, server) = UnixStream::pair().unwrap();
+    completion.register(&server).unwrap();
+    client.write_all(b"hello").unwrap();
+    let (n, buf) = run(&completion, Read::new(&server, vec![0u8; 16]), 1).unwrap();
+    assert_eq!(&buf[..n], b"hello");
+    completion.deregister(&server).unwrap();
+
+    // the tail has moved on, even though the queue is empty again
+    let mut used = io_uring::IoUring::new(16).unwrap();
+    let nop = io_uring::opcode::Nop::new().build().user_data(7);
+    unsafe { used.submission().push(&nop).unwrap() };
+    used.submit_and_wait(1).unwrap();
+    assert_eq!(used.completion().count(), 1);
+
+    let err = unsafe { CompletionBuilder::new(16).build_from_uring(used) }.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn failed_deregister_interrupts() {
+    let completion = CompletionBuilder::new(16)
+        .disable_io_uring()
+        .build()
+        .unwrap();
+    let (_client, server) = UnixStream::pair().unwrap();
+    completion.register(&server).unwrap();
+    let mut read = Box::new(Read::new(&server, vec![0u8; 16]));
+    let status = unsafe { completion.submit(&mut *read, 1).unwrap() };
+    assert!(matches!(status, SubmissionStatus::Submitted));
+
+    // the descriptor now refers to a socket the poller has never seen
+    let (other, _peer) = UnixStream::pair().unwrap();
+    let fd = server.as_raw_fd();
+    assert_eq!(unsafe { libc::dup2(other.as_raw_fd(), fd) }, fd);
+    completion.deregister(&server).unwrap_err();
+
+    // the read is still handed back
+    let event = completion
+        .wait_for_key(1, Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(event.result.unwrap_err().kind(), ErrorKind::Interrupted);
+}
+
+/// Waits for the source to be readable, but never gets anywhere.
+struct Stalled {
+    source: Raw,
+}
+
+unsafe impl CustomOp for Stalled {
+    type Output = usize;
+
+    const READABLE: bool = true;
+
+    fn source(&self) -> Raw {
+        self.source
+    }
+
+    fn variant(&self) -> SourceType {
+        SourceType::Socket
+    }
+
+    fn polling_function(&mut self) -> CustomFn {
+        Box::new(|| Err(ErrorKind::WouldBlock.into()))
+    }
+
+    fn finish(self, result: usize) -> usize {
+        result
+    }
+}
+
+#[test]
+fn hangup_fails_stalled_operation() {
+    for edge_triggered in [false, true] {
+        let completion = CompletionBuilder::new(16)
+            .disable_io_uring()
+            .edge_triggered(edge_triggered)
+            .build()
+            .unwrap();
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+        let mut stalled = Box::new(Custom::new(Stalled {
+            source: server.as_raw_fd(),
+        }));
+        unsafe { completion.submit(&mut *stalled, 1).unwrap() };
+
+        // plain readiness leaves it waiting
+        client.write_all(b"hello").unwrap();
+        let mut events = Vec::new();
+        completion
+            .wait(Some(Duration::from_millis(100)), &mut events)
+            .unwrap();
+        assert!(events.is_empty());
+
+        // but it would wait forever once the peer is gone
+        drop(client);
+        let event = completion
+            .wait_for_key(1, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(event.result.unwrap_err().kind(), ErrorKind::ConnectionReset);
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn rearmed_read_stays_pinned() {
+    for builder in [
+        CompletionBuilder::new(16).memory_limit(64),
+        CompletionBuilder::new(16)
+            .memory_limit(64)
+            .disable_io_uring(),
+    ] {
+        let completion = builder.build().unwrap();
+        let (mut client, server) = UnixStream::pair().unwrap();
+        completion.register(&server).unwrap();
+
+        let mut read = Box::new(Read::new(&server, vec![0u8; 16]));
+        unsafe {
+            read.rearm(true);
+            completion.submit(&mut *read, 1).unwrap();
+        }
+        assert_eq!(completion.memory_in_use(), Some(16));
+
+        // the read is submitted again, so its buffer is still in use
+        client.write_all(b"hello").unwrap();
+        let event = completion
+            .wait_for_key(1, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(event.result.unwrap(), 5);
+        assert_eq!(completion.memory_in_use(), Some(16));
+
+        // reading nothing is its last event
+        drop(client);
+        let event = completion
+            .wait_for_key(1, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(event.result.unwrap(), 0);
+        assert_eq!(completion.memory_in_use(), Some(0));
+
+        completion.deregister(&server).unwrap();
+    }
+}
+
+#[test]
+fn open_registers_file() {
+    let path = std::env::temp_dir().join(format!("polldough-open-{}", std::process::id()));
+    fs::write(&path, b"hello").unwrap();
+
+    for completion in backends() {
+        let open = OpenOptions::new()
+            .read(true)
+            .open(&completion, &path)
+            .unwrap();
+        let file = run(&completion, open, 1).unwrap().unwrap();
+
+        // it's ready to be used without registering it first
+        let (n, buf) = run(&completion, Read::new(&file, vec![0u8; 16]), 2).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+
+        completion.deregister(&file).unwrap();
+    }
+
+    // readiness polling keeps track of what's registered
+    let completion = CompletionBuilder::new(16)
+        .disable_io_uring()
+        .build()
+        .unwrap();
+    let open = OpenOptions::new()
+        .read(true)
+        .open(&completion, &path)
+        .unwrap();
+    let file = run(&completion, open, 1).unwrap().unwrap();
+    let err = completion.register(&file).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    completion.deregister(&file).unwrap();
+
+    fs::remove_file(&path).unwrap();
+}
+
+/// A file operation that runs its blocking function on the pool.
+struct Blocking {
+    source: Raw,
+    job: Option<CustomFn>,
+}
+
+unsafe impl CustomOp for Blocking {
+    type Output = usize;
+
+    const READABLE: bool = true;
+
+    fn source(&self) -> Raw {
+        self.source
+    }
+
+    fn variant(&self) -> SourceType {
+        SourceType::File
+    }
+
+    fn polling_function(&mut self) -> CustomFn {
+        Box::new(|| Err(ErrorKind::WouldBlock.into()))
+    }
+
+    fn blocking_function(&mut self) -> Option<CustomFn> {
+        self.job.take()
+    }
+
+    fn finish(self, result: usize) -> usize {
+        result
+    }
+}
+
+/// Keep the calling thread on `cpu`, behind every other thread there.
+#[cfg(target_os = "linux")]
+fn pin(cpu: usize, idle: bool) {
+    unsafe {
+        let mut set = std::mem::zeroed::<libc::cpu_set_t>();
+        libc::CPU_SET(cpu, &mut set);
+        assert_eq!(
+            libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set),
+            0
+        );
+        if idle {
+            let param = libc::sched_param { sched_priority: 0 };
+            assert_eq!(libc::sched_setscheduler(0, libc::SCHED_IDLE, &param), 0);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn blocking_pool_wakes_enough_threads() {
+    let path = std::env::temp_dir().join(format!("polldough-pool-{}", std::process::id()));
+    fs::write(&path, b"").unwrap();
+    let file = fs::File::open(&path).unwrap();
+
+    let completion = CompletionBuilder::new(16)
+        .disable_io_uring()
+        .blocking_threads(4)
+        .build()
+        .unwrap();
+    completion.register(&file).unwrap();
+    let blocking = |job: CustomFn| {
+        Custom::new(Blocking {
+            source: file.as_raw_fd(),
+            job: Some(job),
+        })
+    };
+
+    // leave the pool's only thread waiting for work, on this CPU, where
+    // it only gets to run once this thread blocks
+    let cpu = unsafe { libc::sched_getcpu() } as usize;
+    pin(cpu, false);
+    let warm_up = blocking(Box::new(move || {
+        pin(cpu, true);
+        Ok(0)
+    }));
+    run(&completion, warm_up, 0).unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    // the first job only finishes once the second one has run, so both
+    // need a thread, even though there's only one that's idle
+    let (tx, rx) = std::sync::mpsc::sync_channel::<()>(1);
+    let rx = std::sync::Mutex::new(rx);
+    let mut first = blocking(Box::new(move || {
+        let rx = rx.lock().unwrap();
+        rx.recv_timeout(Duration::from_secs(1))
+            .map(|()| 1)
+            .map_err(|_| ErrorKind::TimedOut.into())
+    }));
+    let mut second = blocking(Box::new(move || {
+        tx.send(()).unwrap();
+        Ok(2)
+    }));
+    unsafe {
+        completion.submit(&mut first, 1).unwrap();
+        completion.submit(&mut second, 2).unwrap();
+    }
+
+    for key in [1, 2] {
+        let event = completion
+            .wait_for_key(key, Some(Duration::from_secs(5)))
+            .unwrap();
+        assert_eq!(event.result.unwrap() as u64, key);
+    }
+
+    completion.deregister(&file).unwrap();
+    fs::remove_file(&path).unwrap();
+}
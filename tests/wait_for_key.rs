@@ -0,0 +1,62 @@
+// GNU GPL v3 License
+
+//! Waiting for particular operations from several threads.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::backends;
+use polldough::Read;
+use std::{
+    io::Write as _,
+    os::unix::net::UnixStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn events_reach_other_waiters() {
+    for completion in backends() {
+        let (first_client, first) = UnixStream::pair().unwrap();
+        let (second_client, second) = UnixStream::pair().unwrap();
+        completion.register(&first).unwrap();
+        completion.register(&second).unwrap();
+
+        let mut reads = [
+            Box::new(Read::new(&first, vec![0u8; 16])),
+            Box::new(Read::new(&second, vec![0u8; 16])),
+        ];
+        for (key, read) in reads.iter_mut().enumerate() {
+            unsafe { completion.submit(&mut **read, key as u64).unwrap() };
+        }
+
+        let completion = &completion;
+        thread::scope(|scope| {
+            // the thread waiting for the second read gets to the backend
+            // first, so it receives the event for the first one
+            let second_waiter =
+                scope.spawn(move || completion.wait_for_key(1, Some(Duration::from_secs(5))));
+            thread::sleep(Duration::from_millis(50));
+            let first_waiter = scope.spawn(move || {
+                let start = Instant::now();
+                let event = completion.wait_for_key(0, Some(Duration::from_secs(5)));
+                (event, start.elapsed())
+            });
+            thread::sleep(Duration::from_millis(50));
+
+            (&first_client).write_all(b"first").unwrap();
+            let (event, elapsed) = first_waiter.join().unwrap();
+            assert_eq!(event.unwrap().key, 0);
+            assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+
+            (&second_client).write_all(b"second").unwrap();
+            assert_eq!(second_waiter.join().unwrap().unwrap().key, 1);
+        });
+
+        // the events were received, so the reads are done with the buffers
+        drop(reads);
+        completion.deregister(&first).unwrap();
+        completion.deregister(&second).unwrap();
+    }
+}
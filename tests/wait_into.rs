@@ -0,0 +1,173 @@
+// GNU GPL v3 License
+
+//! Waiting into buffers other than a `Vec<Event>`.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::backends;
+use polldough::{Completion, Event, Read};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    io::Write as _,
+    mem::MaybeUninit,
+    os::unix::net::UnixStream,
+    thread,
+    time::Duration,
+};
+
+const READS: u64 = 3;
+
+/// Reads that completed, along with the sockets they read from.
+struct Ready {
+    streams: Vec<(UnixStream, UnixStream)>,
+    reads: Vec<Read<Vec<u8>>>,
+}
+
+impl Ready {
+    /// Submit a read on each of `READS` pairs of sockets, and write to all
+    /// of them so that every read completes.
+    fn new(completion: &Completion) -> Self {
+        let streams: Vec<_> = (0..READS).map(|_| UnixStream::pair().unwrap()).collect();
+        let mut reads: Vec<_> = streams
+            .iter()
+            .map(|(_, server)| Read::new(server, vec![0u8; 16]))
+            .collect();
+
+        // the reads stay in place until they're finished
+        for (key, ((client, server), read)) in streams.iter().zip(&mut reads).enumerate() {
+            completion.register(server).unwrap();
+            unsafe { completion.submit(read, key as u64).unwrap() };
+            (&*client).write_all(b"ready").unwrap();
+        }
+
+        // give every read time to complete, so they're received together
+        thread::sleep(Duration::from_millis(50));
+        Ready { streams, reads }
+    }
+
+    /// Clean up, once every event was received and the reads are done
+    /// with their buffers.
+    fn finish(self, completion: &Completion) {
+        drop(self.reads);
+        for (_, server) in &self.streams {
+            completion.deregister(server).unwrap();
+        }
+    }
+}
+
+#[test]
+fn wait_into_keeps_leftovers() {
+    for completion in backends() {
+        let ready = Ready::new(&completion);
+
+        // only one event fits, so the rest are kept for later
+        let mut out = [MaybeUninit::<Event>::uninit()];
+        let mut count = 0;
+        while count == 0 {
+            count = completion
+                .wait_into(Some(Duration::from_secs(5)), &mut out)
+                .unwrap();
+        }
+        assert_eq!(count, 1);
+        let first = unsafe { out[0].assume_init_read() };
+        assert_eq!(first.result.unwrap(), 5);
+
+        let mut keys = BTreeSet::new();
+        keys.insert(first.key);
+        for key in 0..READS {
+            if keys.contains(&key) {
+                continue;
+            }
+            let event = completion
+                .wait_for_key(key, Some(Duration::from_secs(5)))
+                .unwrap();
+            assert_eq!(event.result.unwrap(), 5);
+            keys.insert(key);
+        }
+        assert_eq!(keys.len(), READS as usize);
+
+        ready.finish(&completion);
+    }
+}
+
+#[test]
+fn wait_into_hands_leftovers_to_wait() {
+    for completion in backends() {
+        let ready = Ready::new(&completion);
+
+        let mut keys = BTreeSet::new();
+        let mut out = [MaybeUninit::<Event>::uninit()];
+        while keys.is_empty() {
+            let count = completion
+                .wait_into(Some(Duration::from_secs(5)), &mut out)
+                .unwrap();
+            if count > 0 {
+                keys.insert(unsafe { out[0].assume_init_read() }.key);
+            }
+        }
+
+        let mut events = Vec::new();
+        while keys.len() < READS as usize {
+            completion
+                .wait(Some(Duration::from_secs(5)), &mut events)
+                .unwrap();
+            for event in events.drain(..) {
+                assert_eq!(event.result.unwrap(), 5);
+                assert!(keys.insert(event.key), "{} was received twice", event.key);
+            }
+        }
+
+        ready.finish(&completion);
+    }
+}
+
+#[test]
+fn wait_extend_pushes_every_event() {
+    for completion in backends() {
+        let ready = Ready::new(&completion);
+
+        let mut events = VecDeque::new();
+        while events.len() < READS as usize {
+            let before = events.len();
+            let count = completion
+                .wait_extend(Some(Duration::from_secs(5)), &mut events)
+                .unwrap();
+            assert_eq!(events.len() - before, count);
+        }
+
+        let keys: BTreeSet<_> = events
+            .into_iter()
+            .map(|event| {
+                assert_eq!(event.result.unwrap(), 5);
+                event.key
+            })
+            .collect();
+        assert_eq!(keys, (0..READS).collect());
+
+        ready.finish(&completion);
+    }
+}
+
+#[test]
+fn wait_sink_reports_every_event() {
+    for completion in backends() {
+        let ready = Ready::new(&completion);
+
+        let mut keys = BTreeSet::new();
+        let mut sink = |event: Event| {
+            assert_eq!(event.result.unwrap(), 5);
+            assert!(keys.insert(event.key), "{} was reported twice", event.key);
+        };
+        let mut reported = 0;
+        while reported < READS as usize {
+            reported += completion
+                .wait_sink(Some(Duration::from_secs(5)), &mut sink)
+                .unwrap();
+        }
+        assert_eq!(keys, (0..READS).collect());
+
+        ready.finish(&completion);
+    }
+}
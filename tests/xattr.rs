@@ -0,0 +1,142 @@
+// GNU GPL v3 License
+
+//! Reading and writing extended attributes.
+
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use common::{backends, run};
+use polldough::{GetXattr, SetXattr};
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+/// A file in the temporary directory, removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("polldough-xattr-{}-{}", name, std::process::id()));
+        fs::write(&path, b"").unwrap();
+        TempFile(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn set_and_get() {
+    let path = TempFile::new("roundtrip");
+
+    for completion in backends() {
+        let file = fs::OpenOptions::new().write(true).open(&path.0).unwrap();
+        completion.register(&file).unwrap();
+
+        let value = b"\"v1\"".to_vec();
+        match run(&completion, SetXattr::new(&file, "user.etag", value), 1) {
+            Ok(value) => assert_eq!(value, b"\"v1\""),
+            Err(e) if e.kind() == ErrorKind::Unsupported => {
+                // the file system doesn't take user attributes
+                completion.deregister(&file).unwrap();
+                return;
+            }
+            Err(e) => panic!("{}", e),
+        }
+
+        let (n, buf) = run(
+            &completion,
+            GetXattr::new(&file, "user.etag", vec![0u8; 16]),
+            2,
+        )
+        .unwrap();
+        assert_eq!(&buf[..n], b"\"v1\"");
+
+        // an empty buffer asks for the length of the value
+        let (n, _) = run(
+            &completion,
+            GetXattr::new(&file, "user.etag", Vec::new()),
+            3,
+        )
+        .unwrap();
+        assert_eq!(n, 4);
+
+        let mut op = SetXattr::new(&file, "user.etag", b"\"v2\"".to_vec());
+        op.replace();
+        run(&completion, op, 4).unwrap();
+        let (n, buf) = run(
+            &completion,
+            GetXattr::new(&file, "user.etag", vec![0u8; 16]),
+            5,
+        )
+        .unwrap();
+        assert_eq!(&buf[..n], b"\"v2\"");
+
+        completion.deregister(&file).unwrap();
+    }
+}
+
+#[test]
+fn errors() {
+    for (i, completion) in backends().into_iter().enumerate() {
+        // the attributes stay on the file, so every backend gets its own
+        let path = TempFile::new(&format!("errors-{}", i));
+        let file = fs::OpenOptions::new().write(true).open(&path.0).unwrap();
+        completion.register(&file).unwrap();
+
+        let err = run(
+            &completion,
+            GetXattr::new(&file, "user.missing", vec![0u8; 16]),
+            1,
+        )
+        .unwrap_err();
+        if err.kind() == ErrorKind::Unsupported {
+            completion.deregister(&file).unwrap();
+            return;
+        }
+        assert_eq!(err.raw_os_error(), Some(libc::ENODATA));
+
+        // replacing needs the attribute to be there already
+        let mut op = SetXattr::new(&file, "user.missing", b"x".to_vec());
+        op.replace();
+        let err = run(&completion, op, 2).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENODATA));
+
+        // and creating needs it not to be
+        let mut op = SetXattr::new(&file, "user.once", b"x".to_vec());
+        op.create();
+        run(&completion, op, 3).unwrap();
+        let mut op = SetXattr::new(&file, "user.once", b"y".to_vec());
+        op.create();
+        let err = run(&completion, op, 4).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EEXIST));
+
+        // the value doesn't fit into the buffer
+        run(
+            &completion,
+            SetXattr::new(&file, "user.long", vec![b'z'; 32]),
+            5,
+        )
+        .unwrap();
+        let err = run(
+            &completion,
+            GetXattr::new(&file, "user.long", vec![0u8; 8]),
+            6,
+        )
+        .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ERANGE));
+
+        completion.deregister(&file).unwrap();
+    }
+}
+
+#[test]
+#[should_panic(expected = "contains a null byte")]
+fn name_with_null_byte() {
+    let path = TempFile::new("null");
+    let file = fs::File::open(&path.0).unwrap();
+    GetXattr::new(&file, "user.\0", Vec::new());
+}